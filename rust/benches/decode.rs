@@ -0,0 +1,57 @@
+//! Compares decoding through the `match` of `OpCode::from_byte` with the
+//! lookup in the static opcode table, then times a tight loop on the CPU.
+//!
+//! Run with `cargo bench`.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use rust::cpu::{CpuVariant, Easy6502};
+use rust::instructions::OpCode;
+
+const ROUNDS: usize = 20_000;
+
+fn time(name: &str, mut routine: impl FnMut()) -> Duration {
+    let start = Instant::now();
+    routine();
+    let elapsed = start.elapsed();
+
+    println!("{:<24} {:>10.3} ms", name, elapsed.as_secs_f64() * 1000.0);
+
+    elapsed
+}
+
+fn main() {
+    let matched = time("decode by match", || {
+        for _ in 0..ROUNDS {
+            for code in 0..=0xFF {
+                black_box(OpCode::from_byte_w65c02(black_box(code)));
+            }
+        }
+    });
+
+    let table = time("decode by table", || {
+        for _ in 0..ROUNDS {
+            for code in 0..=0xFF {
+                black_box(OpCode::decode(black_box(code), &CpuVariant::Wdc65C02));
+            }
+        }
+    });
+
+    println!(
+        "table lookup speedup     {:>10.1}x",
+        matched.as_secs_f64() / table.as_secs_f64()
+    );
+
+    // DEX / BNE / JMP: a tight loop spends most of its time fetching and decoding
+    let mut nes = Easy6502::default();
+    nes.load_instructions(vec![
+        0xCA, // DEX
+        0xD0, 0xFD, // BNE $0600
+        0x4C, 0x00, 0x06, // JMP $0600
+    ]);
+
+    time("tight loop, 10M cycles", || {
+        nes.run_for_cycles(10_000_000).unwrap();
+    });
+}
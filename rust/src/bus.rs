@@ -0,0 +1,479 @@
+/// The address and data buses the CPU is connected to. A board implements it
+/// with its own memory map, the CPU core only sees reads, writes and time.
+pub trait Bus {
+    /// Reads a byte as the CPU does, which may have side effects on hardware
+    /// registers, like acknowledging an interrupt
+    fn read(&mut self, address: u16) -> u8 {
+        self.peek(address)
+    }
+
+    fn write(&mut self, address: u16, data: u8);
+
+    /// Reads a byte without any side effect, for debuggers and disassemblers
+    fn peek(&self, address: u16) -> u8;
+
+    /// Reads a byte from a 24-bit address of the 65C816. The boards of the
+    /// 6502 have no bank lines, by default every bank is the same 64 KiB.
+    fn read_long(&mut self, address: u32) -> u8 {
+        self.read(address as u16)
+    }
+
+    fn write_long(&mut self, address: u32, data: u8) {
+        self.write(address as u16, data)
+    }
+
+    fn peek_long(&self, address: u32) -> u8 {
+        self.peek(address as u16)
+    }
+
+    /// Lets the rest of the board catch up after the CPU has spent some cycles
+    fn tick(&mut self, _cycles: u64) {}
+
+    /// Whether a device on the board pulls the IRQ line low
+    fn irq(&self) -> bool {
+        false
+    }
+
+    /// Whether a device pulled the NMI line low since the last call. NMI is
+    /// edge-triggered, so the device keeps the edge until the CPU takes it.
+    fn take_nmi(&mut self) -> bool {
+        false
+    }
+}
+
+/// 64 KiB of RAM on the whole address space, like the Easy6502 board. With
+/// banks, the RAM goes on over the 16 MiB that the 65C816 addresses.
+pub struct FlatMemory {
+    /// Bank 0
+    pub memory: [u8; 0x10000],
+    /// Banks $01-$FF, empty when every bank mirrors bank 0
+    banks: Vec<u8>,
+}
+
+impl Default for FlatMemory {
+    fn default() -> Self {
+        FlatMemory {
+            memory: [0; 0x10000],
+            banks: Vec::new(),
+        }
+    }
+}
+
+impl FlatMemory {
+    pub fn with_banks() -> Self {
+        FlatMemory {
+            banks: vec![0; 0xFF0000],
+            ..FlatMemory::default()
+        }
+    }
+
+    // The offset in `banks` of an address above bank 0
+    fn bank_offset(&self, address: u32) -> Option<usize> {
+        let offset = (address as usize & 0xFFFFFF).checked_sub(0x10000)?;
+
+        (offset < self.banks.len()).then_some(offset)
+    }
+}
+
+impl Bus for FlatMemory {
+    fn write(&mut self, address: u16, data: u8) {
+        self.memory[address as usize] = data;
+    }
+
+    fn peek(&self, address: u16) -> u8 {
+        self.memory[address as usize]
+    }
+
+    fn read_long(&mut self, address: u32) -> u8 {
+        self.peek_long(address)
+    }
+
+    fn write_long(&mut self, address: u32, data: u8) {
+        match self.bank_offset(address) {
+            Some(offset) => self.banks[offset] = data,
+            None => self.write(address as u16, data),
+        }
+    }
+
+    fn peek_long(&self, address: u32) -> u8 {
+        match self.bank_offset(address) {
+            Some(offset) => self.banks[offset],
+            None => self.peek(address as u16),
+        }
+    }
+}
+
+/// A chip mapped on a range of the address space. It sees the offset of the
+/// access from the start of its range, after mirroring.
+pub trait Device {
+    fn read(&mut self, offset: u16) -> u8 {
+        self.peek(offset)
+    }
+
+    fn write(&mut self, offset: u16, data: u8);
+
+    fn peek(&self, offset: u16) -> u8;
+
+    fn tick(&mut self, _cycles: u64) {}
+}
+
+/// Read/write memory
+pub struct Ram {
+    data: Vec<u8>,
+}
+
+impl Ram {
+    pub fn new(size: usize) -> Self {
+        Ram {
+            data: vec![0; size],
+        }
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn data_mut(&mut self) -> &mut [u8] {
+        &mut self.data
+    }
+}
+
+impl Device for Ram {
+    fn write(&mut self, offset: u16, data: u8) {
+        let size = self.data.len().max(1);
+        if let Some(byte) = self.data.get_mut(offset as usize % size) {
+            *byte = data;
+        }
+    }
+
+    fn peek(&self, offset: u16) -> u8 {
+        self.data
+            .get(offset as usize % self.data.len().max(1))
+            .copied()
+            .unwrap_or(0)
+    }
+}
+
+/// Read-only memory, the writes are ignored
+pub struct Rom {
+    data: Vec<u8>,
+}
+
+impl Rom {
+    pub fn new(data: Vec<u8>) -> Self {
+        Rom { data }
+    }
+}
+
+impl Device for Rom {
+    fn write(&mut self, _offset: u16, _data: u8) {}
+
+    fn peek(&self, offset: u16) -> u8 {
+        self.data
+            .get(offset as usize % self.data.len().max(1))
+            .copied()
+            .unwrap_or(0)
+    }
+}
+
+struct Mapping<D> {
+    start: u16,
+    end: u16,
+    mirror_mask: u16,
+    device: D,
+}
+
+impl<D> Mapping<D> {
+    fn offset(&self, address: u16) -> Option<u16> {
+        (self.start..=self.end)
+            .contains(&address)
+            .then(|| (address - self.start) & self.mirror_mask)
+    }
+}
+
+/// Decodes the addresses to the devices mapped on them. Nothing drives the data
+/// bus on unmapped addresses, so they read back the last value it carried.
+///
+/// The devices are boxed [`Device`]s, or any handle that a board resolves to
+/// its own chips with the `*_with` accessors.
+pub struct MemoryMap<D = Box<dyn Device>> {
+    mappings: Vec<Mapping<D>>,
+    open_bus: u8,
+}
+
+impl<D> Default for MemoryMap<D> {
+    fn default() -> Self {
+        MemoryMap {
+            mappings: Vec::new(),
+            open_bus: 0,
+        }
+    }
+}
+
+impl MemoryMap {
+    /// Maps a device on `start..=end`. The offset from `start` is ANDed with
+    /// `mirror_mask`, e.g. 2 KiB of RAM repeated over 8 KiB uses `0x07FF`.
+    /// A device mapped later hides the ones below it.
+    pub fn map(&mut self, start: u16, end: u16, mirror_mask: u16, device: impl Device + 'static) {
+        self.map_handle(start, end, mirror_mask, Box::new(device));
+    }
+}
+
+impl<D> MemoryMap<D> {
+    /// Maps a handle on `start..=end` like [`MemoryMap::map`]
+    pub fn map_handle(&mut self, start: u16, end: u16, mirror_mask: u16, device: D) {
+        self.mappings.push(Mapping {
+            start,
+            end,
+            mirror_mask,
+            device,
+        });
+    }
+
+    /// The last value carried by the data bus
+    pub fn open_bus(&self) -> u8 {
+        self.open_bus
+    }
+
+    pub fn set_open_bus(&mut self, data: u8) {
+        self.open_bus = data;
+    }
+
+    /// Reads through the device mapped on `address` and its offset. The
+    /// device answers `None` to leave the data bus floating.
+    pub fn read_with(&mut self, address: u16, read: impl FnOnce(&mut D, u16) -> Option<u8>) -> u8 {
+        if let Some((index, offset)) = self.mapping(address) {
+            if let Some(data) = read(&mut self.mappings[index].device, offset) {
+                self.open_bus = data;
+            }
+        }
+
+        self.open_bus
+    }
+
+    pub fn write_with(&mut self, address: u16, data: u8, write: impl FnOnce(&mut D, u16)) {
+        self.open_bus = data;
+
+        if let Some((index, offset)) = self.mapping(address) {
+            write(&mut self.mappings[index].device, offset);
+        }
+    }
+
+    pub fn peek_with(&self, address: u16, peek: impl FnOnce(&D, u16) -> Option<u8>) -> u8 {
+        self.mapping(address)
+            .and_then(|(index, offset)| peek(&self.mappings[index].device, offset))
+            .unwrap_or(self.open_bus)
+    }
+
+    fn mapping(&self, address: u16) -> Option<(usize, u16)> {
+        self.mappings
+            .iter()
+            .enumerate()
+            .rev()
+            .find_map(|(index, mapping)| mapping.offset(address).map(|offset| (index, offset)))
+    }
+}
+
+impl Bus for MemoryMap {
+    fn read(&mut self, address: u16) -> u8 {
+        self.read_with(address, |device, offset| Some(device.read(offset)))
+    }
+
+    fn write(&mut self, address: u16, data: u8) {
+        self.write_with(address, data, |device, offset| device.write(offset, data));
+    }
+
+    fn peek(&self, address: u16) -> u8 {
+        self.peek_with(address, |device, offset| Some(device.peek(offset)))
+    }
+
+    fn tick(&mut self, cycles: u64) {
+        for mapping in &mut self.mappings {
+            mapping.device.tick(cycles);
+        }
+    }
+}
+
+#[cfg(test)]
+mod bus_tests {
+    use super::{Bus, Device, FlatMemory, MemoryMap, Ram, Rom};
+    use crate::cpu::{Cpu, Machine};
+
+    // RAM, a keyboard register that is cleared when read, and a cycle counter
+    struct Board {
+        ram: [u8; 0x10000],
+        key: u8,
+        cycles: u64,
+    }
+
+    impl Bus for Board {
+        fn read(&mut self, address: u16) -> u8 {
+            match address {
+                0xD010 => std::mem::take(&mut self.key),
+                _ => self.peek(address),
+            }
+        }
+
+        fn write(&mut self, address: u16, data: u8) {
+            self.ram[address as usize] = data;
+        }
+
+        fn peek(&self, address: u16) -> u8 {
+            match address {
+                0xD010 => self.key,
+                _ => self.ram[address as usize],
+            }
+        }
+
+        fn tick(&mut self, cycles: u64) {
+            self.cycles += cycles;
+        }
+    }
+
+    #[test]
+    fn custom_board_test() {
+        let board = Board {
+            ram: [0; 0x10000],
+            key: b'A',
+            cycles: 0,
+        };
+        let mut machine = Machine::with_bus(Cpu::default(), board);
+
+        machine.load_instructions(vec![
+            0xAD, 0x10, 0xD0, // LDA $D010
+            0x4C, 0x03, 0x06, // JMP *
+        ]);
+
+        assert_eq!(machine.mem_peek_8(0xD010), b'A');
+
+        machine.step().unwrap();
+
+        assert_eq!(machine.cpu.accumulator, b'A');
+        assert_eq!(machine.bus.key, 0);
+        assert_eq!(machine.bus.cycles, 4);
+    }
+
+    // A status register that clears its flag when it is read
+    struct Status {
+        flag: u8,
+    }
+
+    impl Device for Status {
+        fn read(&mut self, _offset: u16) -> u8 {
+            std::mem::take(&mut self.flag)
+        }
+
+        fn write(&mut self, _offset: u16, data: u8) {
+            self.flag = data;
+        }
+
+        fn peek(&self, _offset: u16) -> u8 {
+            self.flag
+        }
+    }
+
+    fn memory_map() -> MemoryMap {
+        let mut map = MemoryMap::default();
+
+        map.map(0x0000, 0x1FFF, 0x07FF, Ram::new(0x0800));
+        map.map(0x2000, 0x2000, 0x0000, Status { flag: 0x80 });
+        map.map(0x8000, 0xFFFF, 0x7FFF, Rom::new(vec![0xEA; 0x8000]));
+        map
+    }
+
+    #[test]
+    fn mirroring_test() {
+        let mut map = memory_map();
+
+        map.write(0x0042, 0x11);
+
+        assert_eq!(map.read(0x0842), 0x11);
+        assert_eq!(map.read(0x1842), 0x11);
+    }
+
+    #[test]
+    fn rom_write_protection_test() {
+        let mut map = memory_map();
+
+        map.write(0x8000, 0x00);
+
+        assert_eq!(map.read(0x8000), 0xEA);
+    }
+
+    #[test]
+    fn open_bus_test() {
+        let mut map = memory_map();
+
+        assert_eq!(map.read(0x8000), 0xEA);
+        assert_eq!(map.read(0x5000), 0xEA);
+
+        map.write(0x6000, 0x33);
+
+        assert_eq!(map.read(0x5000), 0x33);
+    }
+
+    #[test]
+    fn io_register_test() {
+        let mut map = memory_map();
+
+        assert_eq!(map.peek(0x2000), 0x80);
+        assert_eq!(map.read(0x2000), 0x80);
+        assert_eq!(map.read(0x2000), 0x00);
+    }
+
+    #[test]
+    fn later_mapping_hides_earlier_test() {
+        let mut map = memory_map();
+
+        map.map(0xFFFC, 0xFFFD, 0x0001, Ram::new(2));
+        map.write(0xFFFC, 0x00);
+
+        assert_eq!(map.read(0xFFFC), 0x00);
+        assert_eq!(map.read(0xFFFE), 0xEA);
+    }
+
+    #[test]
+    fn handle_map_test() {
+        // The board keeps its chips and the map only tells which one answers
+        let mut map = MemoryMap::<char>::default();
+        map.map_handle(0x0000, 0x1FFF, 0x07FF, 'r');
+        map.map_handle(0x4000, 0x4000, 0x0000, 'w');
+
+        let read = |chip: &mut char, offset: u16| (*chip == 'r').then_some(offset as u8);
+        assert_eq!(map.read_with(0x0842, read), 0x42);
+        assert_eq!(map.read_with(0x4000, read), 0x42);
+        assert_eq!(map.read_with(0x5000, read), 0x42);
+
+        let mut written = None;
+        map.write_with(0x4000, 0x33, |chip, offset| written = Some((*chip, offset)));
+        assert_eq!(written, Some(('w', 0)));
+        assert_eq!(map.peek_with(0x4000, |_, _| None), 0x33);
+        assert_eq!(map.open_bus(), 0x33);
+    }
+
+    #[test]
+    fn empty_memory_test() {
+        let mut ram = Ram::new(0);
+        ram.write(0x0042, 0x11);
+        assert_eq!(ram.peek(0x0042), 0x00);
+
+        assert_eq!(Rom::new(Vec::new()).peek(0x0042), 0x00);
+    }
+
+    #[test]
+    fn long_address_test() {
+        // Without banks, the bank byte is not decoded
+        let mut memory = FlatMemory::default();
+        memory.write_long(0x7E0400, 0x5A);
+        assert_eq!(memory.peek(0x0400), 0x5A);
+        assert_eq!(memory.read_long(0x120400), 0x5A);
+
+        let mut memory = FlatMemory::with_banks();
+        memory.write_long(0x7E0400, 0x5A);
+        memory.write_long(0x000400, 0xA5);
+        assert_eq!(memory.read_long(0x7E0400), 0x5A);
+        assert_eq!(memory.peek(0x0400), 0xA5);
+        assert_eq!(memory.read_long(0x120400), 0x00);
+        assert_eq!(memory.peek_long(0xFFFFFF), 0x00);
+    }
+}
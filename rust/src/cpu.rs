@@ -1,58 +1,111 @@
-use std::{fs, io::Read};
+use std::{error, fmt};
 use strum_macros::EnumIter;
 
+use crate::bus::{Bus, FlatMemory};
 use crate::instructions::{Instruction, OpCode};
 
+mod wdc65c816;
+
 const STACK_START: u16 = 0x0100;
 
-pub struct Nes {
+/// A CPU connected to the bus of a board. The instructions are executed
+/// here, the board decides what every address means.
+pub struct Machine<B> {
     pub cpu: Cpu,
-    pub memory: [u8; 0xFFFF], // 64 Kib
+    pub bus: B,
+    /// Every bus cycle of the CPU in bus-accurate mode, when recording is enabled
+    pub bus_trace: Option<Vec<BusCycle>>,
+    operand_address: Option<u32>,
+    branch_taken: bool,
 }
 
-impl Default for Nes {
+/// A 6502 with 64 KiB of flat RAM, programs are loaded at $0600. The 65C816
+/// gets RAM in all of its 256 banks.
+pub type Easy6502 = Machine<FlatMemory>;
+
+impl Default for Easy6502 {
     fn default() -> Self {
-        Nes {
-            cpu: Cpu::default(),
-            memory: [0; 0xFFFF],
-        }
+        Easy6502::new(Cpu::default())
     }
 }
 
-pub trait NesMemory {
-    fn mem_read_8(&self, address: u16) -> u8;
+/// A single CPU cycle on the bus, with the address and the data that was transferred
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BusCycle {
+    Read(u16, u8),
+    Write(u16, u8),
+}
 
-    fn mem_write_8(&mut self, address: u16, data: u8);
+/// What the CPU did during one call to [`Machine::step`]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct StepResult {
+    /// Address of the executed instruction, after any interrupt was serviced
+    pub program_counter: u16,
+    /// The executed instruction, `None` when the clock is halted by WAI or STP
+    pub opcode: Option<OpCode>,
+    /// Effective address of the operand, or the target of a branch.
+    /// It is a 24-bit address on the 65C816.
+    pub operand_address: Option<u32>,
+    /// Cycles consumed by the instruction and the interrupt sequence before it
+    pub cycles: u64,
+    pub branch_taken: bool,
+    /// The NMI or IRQ that was serviced before the instruction
+    pub interrupt: Option<Interrupt>,
+}
 
-    fn mem_read_16(&self, address: u16) -> u16 {
-        let low = self.mem_read_8(address) as u16;
-        let high = self.mem_read_8(address.wrapping_add(1)) as u16;
+// How an instruction accesses its operand decides the dummy cycles of the indexed modes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OperandAccess {
+    Read,
+    Write,
+    Modify,
+}
 
-        (high << 8) | low
+impl Easy6502 {
+    pub fn new(cpu: Cpu) -> Self {
+        let memory = match cpu.variant {
+            CpuVariant::Wdc65C816 => FlatMemory::with_banks(),
+            _ => FlatMemory::default(),
+        };
+
+        Machine::with_bus(cpu, memory)
     }
 
-    fn mem_write_16(&mut self, address: u16, data: u16) {
-        let [high, low] = [(data >> 8) as u8, (data & 0xFF) as u8];
+    pub fn with_variant(variant: CpuVariant) -> Self {
+        Easy6502::new(Cpu {
+            variant,
+            ..Cpu::default()
+        })
+    }
 
-        self.mem_write_8(address, low);
-        self.mem_write_8(address.wrapping_add(1), high);
+    pub fn load(&mut self, data: [u8; 0x10000]) {
+        self.bus.memory = data;
     }
 }
 
-impl Nes {
-    pub fn new(cpu: Cpu) -> Self {
-        Nes {
+impl<B: Bus> Machine<B> {
+    pub fn with_bus(cpu: Cpu, bus: B) -> Self {
+        Machine {
             cpu,
-            memory: [0; 0xFFFF],
+            bus,
+            bus_trace: None,
+            operand_address: None,
+            branch_taken: false,
         }
     }
 
     pub fn reset(&mut self) {
+        self.cpu.set_emulation_mode(true);
         self.cpu.accumulator = 0;
         self.cpu.register_x = 0;
         self.cpu.register_y = 0;
-        self.cpu.status = 0;
+        self.cpu.status = 0b00100100;
         self.cpu.stack_pointer = 0xFD;
+        self.cpu.cycles = 0;
+        self.cpu.state = CpuState::Running;
+        self.cpu.direct_page = 0;
+        self.cpu.data_bank = 0;
+        self.cpu.program_bank = 0;
 
         // Reset vector: read from $FFFC and $FFFD
         self.cpu.program_counter = self.mem_read_16(0xFFFC);
@@ -62,48 +115,76 @@ impl Nes {
         self.cpu.program_counter = address;
     }
 
-    pub fn load(&mut self, data: [u8; 0xFFFF]) {
-        self.memory = data;
-    }
-
     pub fn load_instructions(&mut self, program_mem: Vec<u8>) {
         program_mem.iter().enumerate().for_each(|(index, &code)| {
             self.mem_write_8(0x0600 + index as u16, code);
         })
     }
 
-    pub fn load_rom_from_bytes(&mut self, data: &[u8]) {
-        // TODO: fix overflow
-        self.memory[0x8000..0x8000 + data.len()].copy_from_slice(data);
-    }
+    pub fn mem_read_8(&mut self, address: u16) -> u8 {
+        let address = address & self.cpu.variant.address_mask();
 
-    pub fn load_rom_from_file(&mut self, filename: String) {
-        let file = fs::File::open(&filename).expect("File not found");
+        self.read_io_port(address)
+            .unwrap_or_else(|| self.bus.read(address))
+    }
 
-        let data: Vec<u8> = file
-            .bytes()
-            .take(0x8000)
-            .collect::<Result<Vec<u8>, _>>()
-            .expect("Error processing byte stream for ROM");
+    /// Reads like `mem_read_8` but without side effects on the bus
+    pub fn mem_peek_8(&self, address: u16) -> u8 {
+        let address = address & self.cpu.variant.address_mask();
 
-        self.load_rom_from_bytes(&data);
+        self.read_io_port(address)
+            .unwrap_or_else(|| self.bus.peek(address))
     }
 
-    pub fn mem_read_8(&self, address: u16) -> u8 {
-        self.memory[address as usize]
+    // The 6510 answers the reads of its on-chip port itself
+    fn read_io_port(&self, address: u16) -> Option<u8> {
+        match (self.cpu.variant, address) {
+            (CpuVariant::Mos6510, 0x0000) => Some(self.cpu.io_port.direction),
+            (CpuVariant::Mos6510, 0x0001) => Some(self.cpu.io_port.read()),
+            _ => None,
+        }
     }
 
     pub fn mem_write_8(&mut self, address: u16, data: u8) {
-        self.memory[address as usize] = data;
+        let address = address & self.cpu.variant.address_mask();
+
+        // The on-chip port is written along with the RAM underneath it
+        match (self.cpu.variant, address) {
+            (CpuVariant::Mos6510, 0x0000) => self.cpu.io_port.direction = data,
+            (CpuVariant::Mos6510, 0x0001) => self.cpu.io_port.output = data,
+            _ => {}
+        }
+
+        self.bus.write(address, data);
+    }
+
+    /// Reads from a 24-bit address of the 65C816, the board decodes the bank
+    pub fn mem_read_long(&mut self, address: u32) -> u8 {
+        self.bus.read_long(address & 0xFFFFFF)
+    }
+
+    pub fn mem_peek_long(&self, address: u32) -> u8 {
+        self.bus.peek_long(address & 0xFFFFFF)
+    }
+
+    pub fn mem_write_long(&mut self, address: u32, data: u8) {
+        self.bus.write_long(address & 0xFFFFFF, data)
     }
 
-    pub fn mem_read_16(&self, address: u16) -> u16 {
+    pub fn mem_read_16(&mut self, address: u16) -> u16 {
         let low = self.mem_read_8(address) as u16;
         let high = self.mem_read_8(address.wrapping_add(1)) as u16;
 
         (high << 8) | low
     }
 
+    pub fn mem_peek_16(&self, address: u16) -> u16 {
+        let low = self.mem_peek_8(address);
+        let high = self.mem_peek_8(address.wrapping_add(1));
+
+        u16::from_le_bytes([low, high])
+    }
+
     pub fn mem_write_16(&mut self, address: u16, data: u16) {
         let [high, low] = [(data >> 8) as u8, (data & 0xFF) as u8];
 
@@ -111,14 +192,75 @@ impl Nes {
         self.mem_write_8(address.wrapping_add(1), high);
     }
 
+    // A read cycle of the CPU: in bus-accurate mode every access costs one cycle
+    // and is recorded, otherwise it is a plain memory read
+    fn bus_read(&mut self, address: u16) -> u8 {
+        let data = self.mem_read_8(address);
+
+        if self.cpu.is_bus_accurate() {
+            self.cpu.cycles += 1;
+            self.bus.tick(1);
+            if let Some(trace) = &mut self.bus_trace {
+                trace.push(BusCycle::Read(address, data));
+            }
+        }
+
+        data
+    }
+
+    fn bus_write(&mut self, address: u16, data: u8) {
+        self.mem_write_8(address, data);
+
+        if self.cpu.is_bus_accurate() {
+            self.cpu.cycles += 1;
+            self.bus.tick(1);
+            if let Some(trace) = &mut self.bus_trace {
+                trace.push(BusCycle::Write(address, data));
+            }
+        }
+    }
+
+    fn bus_read_16(&mut self, address: u16) -> u16 {
+        let low = self.bus_read(address);
+        let high = self.bus_read(address.wrapping_add(1));
+
+        u16::from_le_bytes([low, high])
+    }
+
+    // Accesses whose data is thrown away. They are part of the cycle counts of
+    // the opcode table, so they only reach the bus in bus-accurate mode.
+    fn dummy_read(&mut self, address: u16) {
+        if self.cpu.is_bus_accurate() {
+            self.bus_read(address);
+        }
+    }
+
+    fn dummy_write(&mut self, address: u16, data: u8) {
+        if self.cpu.is_bus_accurate() {
+            self.bus_write(address, data);
+        }
+    }
+
+    // A cycle that the opcode table does not count, like the page-cross penalty:
+    // a dummy read in bus-accurate mode, one more cycle otherwise
+    fn penalty_cycle(&mut self, address: u16) {
+        if self.cpu.is_bus_accurate() {
+            self.bus_read(address);
+        } else {
+            self.cpu.cycles += 1;
+        }
+    }
+
     pub fn pop_stack(&mut self) -> u8 {
-        self.cpu.stack_pointer = self.cpu.stack_pointer.wrapping_add(1);
-        self.mem_read_8(STACK_START + self.cpu.stack_pointer as u16)
+        self.cpu
+            .set_stack_pointer_16(self.cpu.stack_pointer_16().wrapping_add(1));
+        self.bus_read(self.cpu.stack_pointer_16())
     }
 
     pub fn push_stack(&mut self, data: u8) {
-        self.mem_write_8(STACK_START + self.cpu.stack_pointer as u16, data);
-        self.cpu.stack_pointer = self.cpu.stack_pointer.wrapping_sub(1);
+        self.bus_write(self.cpu.stack_pointer_16(), data);
+        self.cpu
+            .set_stack_pointer_16(self.cpu.stack_pointer_16().wrapping_sub(1));
     }
 
     pub fn pop_stack_16(&mut self) -> u16 {
@@ -135,57 +277,178 @@ impl Nes {
         self.push_stack(low);
     }
 
-    pub fn get_operand_address(&self, mode: &AddressingMode) -> u16 {
+    pub fn get_operand_address(&self, mode: &AddressingMode) -> Result<u16, CpuError> {
         let program_counter = self.cpu.program_counter;
 
-        match mode {
+        let address = match mode {
             AddressingMode::Accumulator => self.cpu.accumulator as u16,
-            AddressingMode::Immediate => program_counter,
-            AddressingMode::ZeroPage => self.mem_read_8(program_counter) as u16,
+            AddressingMode::Immediate | AddressingMode::Relative => program_counter,
+            AddressingMode::ZeroPage | AddressingMode::ZeroPageRelative => {
+                self.mem_peek_8(program_counter) as u16
+            }
             AddressingMode::ZeroPageX => {
-                let position = self.mem_read_8(program_counter);
+                let position = self.mem_peek_8(program_counter);
                 position.wrapping_add(self.cpu.register_x) as u16
             }
             AddressingMode::ZeroPageY => {
-                let position = self.mem_read_8(program_counter);
+                let position = self.mem_peek_8(program_counter);
                 position.wrapping_add(self.cpu.register_y) as u16
             }
-            AddressingMode::Absolute => self.mem_read_16(program_counter),
+            AddressingMode::Absolute => self.mem_peek_16(program_counter),
             AddressingMode::AbsoluteX => {
-                let position = self.mem_read_16(program_counter);
+                let position = self.mem_peek_16(program_counter);
                 position.wrapping_add(self.cpu.register_x as u16)
             }
             AddressingMode::AbsoluteY => {
-                let position = self.mem_read_16(program_counter);
+                let position = self.mem_peek_16(program_counter);
                 position.wrapping_add(self.cpu.register_y as u16)
             }
             AddressingMode::Indirect => {
-                let address = self.mem_read_16(program_counter);
+                let address = self.mem_peek_16(program_counter);
 
                 u16::from_le(address)
             }
             AddressingMode::IndexedIndirectX => {
-                let start_address = self.mem_read_8(program_counter);
-                let address = start_address.wrapping_add(self.cpu.register_x) as u16;
+                let start_address = self.mem_peek_8(program_counter);
+                let address = start_address.wrapping_add(self.cpu.register_x);
 
-                let low = self.mem_read_8(address);
-                let high = self.mem_read_8(address.wrapping_add(1));
+                self.mem_peek_zero_page_16(address)
+            }
+            AddressingMode::IndirectIndexedY => {
+                let address = self.mem_peek_8(program_counter);
 
-                u16::from_le_bytes([low, high])
+                self.mem_peek_zero_page_16(address)
+                    .wrapping_add(self.cpu.register_y as u16)
+            }
+            AddressingMode::ZeroPageIndirect => {
+                let address = self.mem_peek_8(program_counter);
+
+                self.mem_peek_zero_page_16(address)
+            }
+            AddressingMode::AbsoluteIndexedIndirect => {
+                let address = self.mem_peek_16(program_counter);
+
+                address.wrapping_add(self.cpu.register_x as u16)
+            }
+            _ => return Err(CpuError::UnsupportedAddressingMode(*mode)),
+        };
+
+        Ok(address)
+    }
+
+    // Pointers stored in the zero page wrap around to $00 instead of crossing into $0100
+    fn mem_peek_zero_page_16(&self, address: u8) -> u16 {
+        let low = self.mem_peek_8(address as u16);
+        let high = self.mem_peek_8(address.wrapping_add(1) as u16);
+
+        u16::from_le_bytes([low, high])
+    }
+
+    // Fetches the operand bytes and computes the effective address cycle by cycle,
+    // including the dummy reads of the indexed and indirect modes
+    fn resolve_operand_address(&mut self, mode: &AddressingMode, access: OperandAccess) -> u16 {
+        let program_counter = self.cpu.program_counter;
+
+        let address = match mode {
+            // Branches report their target instead
+            AddressingMode::Relative => return program_counter,
+            AddressingMode::Immediate => program_counter,
+            AddressingMode::ZeroPage | AddressingMode::ZeroPageRelative => {
+                self.bus_read(program_counter) as u16
+            }
+            AddressingMode::ZeroPageX => self.zero_page_indexed(self.cpu.register_x),
+            AddressingMode::ZeroPageY => self.zero_page_indexed(self.cpu.register_y),
+            AddressingMode::Absolute | AddressingMode::Indirect => {
+                self.bus_read_16(program_counter)
+            }
+            AddressingMode::AbsoluteX => {
+                let base = self.bus_read_16(program_counter);
+                self.index_address(base, self.cpu.register_x, access)
+            }
+            AddressingMode::AbsoluteY => {
+                let base = self.bus_read_16(program_counter);
+                self.index_address(base, self.cpu.register_y, access)
+            }
+            AddressingMode::IndexedIndirectX => {
+                let pointer = self.bus_read(program_counter);
+                self.dummy_read(pointer as u16);
+
+                self.read_zero_page_pointer(pointer.wrapping_add(self.cpu.register_x))
             }
             AddressingMode::IndirectIndexedY => {
-                let address = self.mem_read_8(program_counter) as u16;
+                let pointer = self.bus_read(program_counter);
+                let base = self.read_zero_page_pointer(pointer);
+
+                self.index_address(base, self.cpu.register_y, access)
+            }
+            AddressingMode::ZeroPageIndirect => {
+                let pointer = self.bus_read(program_counter);
+
+                self.read_zero_page_pointer(pointer)
+            }
+            AddressingMode::AbsoluteIndexedIndirect => {
+                let base = self.bus_read_16(program_counter);
+                self.dummy_read(program_counter.wrapping_add(1));
+
+                base.wrapping_add(self.cpu.register_x as u16)
+            }
+            // The opcode tables only pair these modes with other instructions
+            _ => unreachable!("{:?} has no operand address on the 6502", mode),
+        };
+
+        self.operand_address = Some(address as u32);
 
-                let low = self.mem_read_8(address);
-                let high = self.mem_read_8(address.wrapping_add(1));
+        address
+    }
+
+    // The CPU reads the unindexed zero page address while it adds the index
+    fn zero_page_indexed(&mut self, index: u8) -> u16 {
+        let position = self.bus_read(self.cpu.program_counter);
+        self.dummy_read(position as u16);
+
+        position.wrapping_add(index) as u16
+    }
+
+    fn read_zero_page_pointer(&mut self, pointer: u8) -> u16 {
+        let low = self.bus_read(pointer as u16);
+        let high = self.bus_read(pointer.wrapping_add(1) as u16);
+
+        u16::from_le_bytes([low, high])
+    }
+
+    // Indexing first reads from the address with the high byte not fixed up yet.
+    // Reads that stay on the same page are done with it, other reads pay one extra
+    // cycle, and writes always spend that cycle because they cannot be undone.
+    fn index_address(&mut self, base: u16, index: u8, access: OperandAccess) -> u16 {
+        let address = base.wrapping_add(index as u16);
+        let is_page_crossed = base & 0xFF00 != address & 0xFF00;
+        let unfixed_address = (base & 0xFF00) | (address & 0x00FF);
 
-                u16::from_le_bytes([low, high]).wrapping_add(self.cpu.register_y as u16)
+        if access == OperandAccess::Read {
+            if is_page_crossed {
+                self.penalty_cycle(unfixed_address);
             }
-            _ => panic!("Addressing mode not implemented!"),
+        } else {
+            self.dummy_read(unfixed_address);
         }
+
+        address
+    }
+
+    // Reads the operand of an instruction marked with `*` in the opcode table
+    fn read_operand(&mut self, mode: &AddressingMode) -> u8 {
+        let address = self.resolve_operand_address(mode, OperandAccess::Read);
+
+        self.bus_read(address)
+    }
+
+    fn write_operand(&mut self, mode: &AddressingMode, value: u8) {
+        let address = self.resolve_operand_address(mode, OperandAccess::Write);
+
+        self.bus_write(address, value)
     }
 
-    pub fn run_with_reset_pc(&mut self, reset_program_counter: bool) {
+    pub fn run_with_reset_pc(&mut self, reset_program_counter: bool) -> Result<(), CpuError> {
         self.reset();
 
         if reset_program_counter {
@@ -195,199 +458,396 @@ impl Nes {
         self.run()
     }
 
-    #[allow(unused)]
-    fn run(&mut self) {
+    // Runs until the program traps itself, i.e. an instruction leaves
+    // the program counter where it was (`JMP *`, `BNE *`, BRK to itself),
+    // or until the clock is halted by STP, or by WAI with no interrupt to wake it up
+    fn run(&mut self) -> Result<(), CpuError> {
         // Main loop
         loop {
-            let code = self.mem_read_8(self.cpu.program_counter);
-
-            self.cpu.program_counter += 1;
-
-            let current_pc = self.cpu.program_counter;
-            let opcode = OpCode::from_byte(code);
-
-            match (&opcode.instruction, code) {
-                // Stop code
-                (Instruction::Brk, _) => return,
-                // ADC
-                (Instruction::Adc, _) => self.adc(&opcode),
-                // AND
-                (Instruction::And, _) => self.and(&opcode),
-                // ASL
-                (Instruction::Asl, _) => self.asl(&opcode),
-                // CMP
-                (Instruction::Cmp, _) => self.cmp(&opcode),
-                // CPX
-                (Instruction::Cpx, _) => self.cpx(&opcode),
-                // CPY
-                (Instruction::Cpy, _) => self.cpy(&opcode),
-                // DEC
-                (Instruction::Dec, _) => self.dec(&opcode),
-                // EOR
-                (Instruction::Eor, _) => self.eor(&opcode),
-                // INC
-                (Instruction::Inc, _) => self.inc(&opcode),
-                // JMP
-                (Instruction::Jmp, _) => self.jmp(&opcode),
-                // JSR
-                (Instruction::Jsr, _) => self.jsr(&opcode),
-                // LDA
-                (Instruction::Lda, _) => self.lda(&opcode),
-                // LDX
-                (Instruction::Ldx, _) => self.ldx(&opcode),
-                // LDY
-                (Instruction::Ldy, _) => self.ldy(&opcode),
-                // LSR
-                (Instruction::Lsr, _) => self.lsr(&opcode),
-                // ORA
-                (Instruction::Ora, _) => self.ora(&opcode),
-                // ROL
-                (Instruction::Rol, _) => self.rol(&opcode),
-                // ROR
-                (Instruction::Ror, _) => self.ror(&opcode),
-                // SBC
-                (Instruction::Sbc, _) => self.sbc(&opcode),
-                // STA
-                (Instruction::Sta, _) => self.sta(&opcode),
-                // STX
-                (Instruction::Stx, _) => self.stx(&opcode),
-                // STY
-                (Instruction::Sty, _) => self.sty(&opcode),
-                // SEC
-                (Instruction::Sec, _) => self.sec(),
-                // SED
-                (Instruction::Sed, _) => self.sed(),
-                // SEI
-                (Instruction::Sei, _) => self.sei(),
-                // CLC
-                (Instruction::Clc, _) => self.clc(),
-                // CLD
-                (Instruction::Cld, _) => self.cld(),
-                // CLI
-                (Instruction::Cli, _) => self.cli(),
-                // CLV
-                (Instruction::Clv, _) => self.clv(),
-                // BMI
-                (Instruction::Bmi, _) => self.bmi(&opcode),
-                // BPL
-                (Instruction::Bpl, _) => self.bpl(&opcode),
-                // BVS
-                (Instruction::Bvs, _) => self.bvs(&opcode),
-                // BVC
-                (Instruction::Bvc, _) => self.bvc(&opcode),
-                // BCS
-                (Instruction::Bcs, _) => self.bcs(&opcode),
-                // BCC
-                (Instruction::Bcc, _) => self.bcc(&opcode),
-                // BEQ
-                (Instruction::Beq, _) => self.beq(&opcode),
-                // BNE
-                (Instruction::Bne, _) => self.bne(&opcode),
-                // TAX
-                (Instruction::Tax, _) => self.tax(),
-                // TAY
-                (Instruction::Tay, _) => self.tay(),
-                // TYA
-                (Instruction::Tya, _) => self.tya(),
-                // TXA
-                (Instruction::Txa, _) => self.txa(),
-                // TXS
-                (Instruction::Txs, _) => self.txs(),
-                // TSX
-                (Instruction::Tsx, _) => self.tsx(),
-                // INY
-                (Instruction::Iny, _) => self.iny(),
-                // INX
-                (Instruction::Inx, _) => self.inx(),
-                // DEY
-                (Instruction::Dey, _) => self.dey(),
-                // DEX
-                (Instruction::Dex, _) => self.dex(),
-                // BMI
-                (Instruction::Bmi, _) => self.bmi(&opcode),
-                // BPL
-                (Instruction::Bpl, _) => self.bpl(&opcode),
-                // BVS
-                (Instruction::Bvs, _) => self.bvs(&opcode),
-                // BVC
-                (Instruction::Bvc, _) => self.bvc(&opcode),
-                // BCS
-                (Instruction::Bcs, _) => self.bcs(&opcode),
-                // BCC
-                (Instruction::Bcc, _) => self.bcc(&opcode),
-                // BEQ
-                (Instruction::Beq, _) => self.beq(&opcode),
-                // BNE
-                (Instruction::Bne, _) => self.bne(&opcode),
-                // BIT
-                (Instruction::Bit, _) => self.bit(&opcode),
-                // RTS
-                (Instruction::Rts, _) => self.rts(),
-                // PHA
-                (Instruction::Pha, _) => self.pha(),
-                // PHP
-                (Instruction::Php, _) => self.php(),
-                // PLA
-                (Instruction::Pla, _) => self.pla(),
-                // PLP
-                (Instruction::Plp, _) => self.plp(),
-                // BIT
-                (Instruction::Bit, _) => self.bit(&opcode),
-                // Other
-                _ => todo!("Code: {:x?} not implemented!", code),
+            let step = self.step()?;
+
+            let Some(opcode) = step.opcode else {
+                return Ok(());
             };
 
-            self.update_pc(current_pc, opcode.bytes);
+            // Block moves repeat themselves until the whole block is copied
+            if self.cpu.program_counter == step.program_counter
+                && !matches!(opcode.instruction, Instruction::Mvn | Instruction::Mvp)
+            {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Services a pending interrupt and executes one instruction.
+    /// A CPU halted by STP, or by WAI with no interrupt request, executes nothing.
+    /// Fails when an illegal opcode halts the CPU or the policy callback fails.
+    pub fn step(&mut self) -> Result<StepResult, CpuError> {
+        if self.bus.take_nmi() {
+            self.cpu.trigger_nmi();
+        }
+
+        match self.cpu.state {
+            CpuState::Stopped => return Ok(self.halted_step()),
+            CpuState::Waiting if !self.cpu.has_interrupt_request() && !self.bus.irq() => {
+                return Ok(self.halted_step())
+            }
+            CpuState::Waiting => self.cpu.state = CpuState::Running,
+            CpuState::Running => {}
+        }
+
+        let start_cycles = self.cpu.cycles;
+
+        self.operand_address = None;
+        self.branch_taken = false;
+
+        let interrupt = self.poll_interrupts();
+
+        let opcode_address = self.cpu.program_counter;
+        let interrupt_disable = self.cpu.has_flag(&StatusFlag::Interrupt);
+        let code = self.fetch_opcode();
+
+        self.cpu.program_counter = self.cpu.program_counter.wrapping_add(1);
+
+        let current_pc = self.cpu.program_counter;
+        let opcode = OpCode::decode(code, &self.cpu.variant);
+        let bytes = self.instruction_length(opcode);
+
+        // The second cycle always reads the next byte, one-byte instructions ignore it
+        if opcode.bytes == 1 && opcode.cycles > 1 {
+            self.dummy_read(self.cpu.program_counter);
         }
+
+        match (&opcode.instruction, code) {
+            // The 65C816 has its own register widths and 24-bit addressing
+            _ if self.cpu.variant == CpuVariant::Wdc65C816 => self.execute_65c816(opcode),
+            // BRK
+            (Instruction::Brk, _) => self.brk(),
+            // RTI
+            (Instruction::Rti, _) => self.rti(),
+            // ADC
+            (Instruction::Adc, _) => self.adc(opcode),
+            // AND
+            (Instruction::And, _) => self.and(opcode),
+            // ASL
+            (Instruction::Asl, _) => self.asl(opcode),
+            // CMP
+            (Instruction::Cmp, _) => self.cmp(opcode),
+            // CPX
+            (Instruction::Cpx, _) => self.cpx(opcode),
+            // CPY
+            (Instruction::Cpy, _) => self.cpy(opcode),
+            // DEC
+            (Instruction::Dec, _) => self.dec(opcode),
+            // EOR
+            (Instruction::Eor, _) => self.eor(opcode),
+            // INC
+            (Instruction::Inc, _) => self.inc(opcode),
+            // JMP
+            (Instruction::Jmp, _) => self.jmp(opcode),
+            // JSR
+            (Instruction::Jsr, _) => self.jsr(),
+            // LDA
+            (Instruction::Lda, _) => self.lda(opcode),
+            // LDX
+            (Instruction::Ldx, _) => self.ldx(opcode),
+            // LDY
+            (Instruction::Ldy, _) => self.ldy(opcode),
+            // LSR
+            (Instruction::Lsr, _) => self.lsr(opcode),
+            // ORA
+            (Instruction::Ora, _) => self.ora(opcode),
+            // ROL
+            (Instruction::Rol, _) => self.rol(opcode),
+            // ROR
+            (Instruction::Ror, _) => self.ror(opcode),
+            // SBC
+            (Instruction::Sbc, _) => self.sbc(opcode),
+            // STA
+            (Instruction::Sta, _) => self.sta(opcode),
+            // STX
+            (Instruction::Stx, _) => self.stx(opcode),
+            // STY
+            (Instruction::Sty, _) => self.sty(opcode),
+            // SEC
+            (Instruction::Sec, _) => self.sec(),
+            // SED
+            (Instruction::Sed, _) => self.sed(),
+            // SEI
+            (Instruction::Sei, _) => self.sei(),
+            // CLC
+            (Instruction::Clc, _) => self.clc(),
+            // CLD
+            (Instruction::Cld, _) => self.cld(),
+            // CLI
+            (Instruction::Cli, _) => self.cli(),
+            // CLV
+            (Instruction::Clv, _) => self.clv(),
+            // BMI
+            (Instruction::Bmi, _) => self.bmi(),
+            // BPL
+            (Instruction::Bpl, _) => self.bpl(),
+            // BVS
+            (Instruction::Bvs, _) => self.bvs(),
+            // BVC
+            (Instruction::Bvc, _) => self.bvc(),
+            // BCS
+            (Instruction::Bcs, _) => self.bcs(),
+            // BCC
+            (Instruction::Bcc, _) => self.bcc(),
+            // BEQ
+            (Instruction::Beq, _) => self.beq(),
+            // BNE
+            (Instruction::Bne, _) => self.bne(),
+            // TAX
+            (Instruction::Tax, _) => self.tax(),
+            // TAY
+            (Instruction::Tay, _) => self.tay(),
+            // TYA
+            (Instruction::Tya, _) => self.tya(),
+            // TXA
+            (Instruction::Txa, _) => self.txa(),
+            // TXS
+            (Instruction::Txs, _) => self.txs(),
+            // TSX
+            (Instruction::Tsx, _) => self.tsx(),
+            // INY
+            (Instruction::Iny, _) => self.iny(),
+            // INX
+            (Instruction::Inx, _) => self.inx(),
+            // DEY
+            (Instruction::Dey, _) => self.dey(),
+            // DEX
+            (Instruction::Dex, _) => self.dex(),
+            // BIT
+            (Instruction::Bit, _) => self.bit(opcode),
+            // RTS
+            (Instruction::Rts, _) => self.rts(),
+            // PHA
+            (Instruction::Pha, _) => self.pha(),
+            // PHP
+            (Instruction::Php, _) => self.php(),
+            // PLA
+            (Instruction::Pla, _) => self.pla(),
+            // PLP
+            (Instruction::Plp, _) => self.plp(),
+            // NOP
+            (Instruction::Nop, _) => self.nop(opcode),
+            // 65C02 opcodes
+            (Instruction::Bra, _) => self.bra(),
+            (Instruction::Stz, _) => self.stz(opcode),
+            (Instruction::Phx, _) => self.phx(),
+            (Instruction::Plx, _) => self.plx(),
+            (Instruction::Phy, _) => self.phy(),
+            (Instruction::Ply, _) => self.ply(),
+            (Instruction::Trb, _) => self.trb(opcode),
+            (Instruction::Tsb, _) => self.tsb(opcode),
+            (Instruction::Rmb, code) => self.rmb(opcode, code),
+            (Instruction::Smb, code) => self.smb(opcode, code),
+            (Instruction::Bbr, code) => self.bbr(code),
+            (Instruction::Bbs, code) => self.bbs(code),
+            (Instruction::Wai, _) => self.wai(),
+            (Instruction::Stp, _) => self.stp(),
+            // Unofficial opcodes
+            (Instruction::Slo, _) => self.slo(opcode),
+            (Instruction::Rla, _) => self.rla(opcode),
+            (Instruction::Sre, _) => self.sre(opcode),
+            (Instruction::Rra, _) => self.rra(opcode),
+            (Instruction::Sax, _) => self.sax(opcode),
+            (Instruction::Lax, _) => self.lax(opcode),
+            (Instruction::Dcp, _) => self.dcp(opcode),
+            (Instruction::Isc, _) => self.isc(opcode),
+            (Instruction::Anc, _) => self.anc(opcode),
+            (Instruction::Alr, _) => self.alr(opcode),
+            (Instruction::Arr, _) => self.arr(opcode),
+            (Instruction::Sbx, _) => self.sbx(opcode),
+            (Instruction::Xaa, _) => self.xaa(opcode),
+            (Instruction::Lxa, _) => self.lxa(opcode),
+            (Instruction::Ahx, _) => self.ahx(opcode),
+            (Instruction::Tas, _) => self.tas(opcode),
+            (Instruction::Shx, _) => self.shx(opcode),
+            (Instruction::Shy, _) => self.shy(opcode),
+            (Instruction::Las, _) => self.las(opcode),
+            (Instruction::Jam, code) => self.jam(code, opcode_address)?,
+            // The remaining opcodes are only decoded on the 65C816
+            (_, code) => unreachable!("Opcode {:02X} is only defined on the 65C816", code),
+        };
+
+        // In bus-accurate mode the cycles were counted as they happened
+        if !self.cpu.is_bus_accurate() {
+            self.cpu.cycles += opcode.cycles as u64;
+        }
+        self.update_pc(current_pc, bytes);
+
+        // CLI, SEI and PLP change the I flag after the interrupt polling,
+        // so the next instruction still sees the previous value
+        if let Instruction::Cli | Instruction::Sei | Instruction::Plp = opcode.instruction {
+            self.cpu.delayed_interrupt_disable = Some(interrupt_disable);
+        }
+
+        let cycles = self.cpu.cycles - start_cycles;
+
+        // In bus-accurate mode the bus was ticked on every access
+        if !self.cpu.is_bus_accurate() {
+            self.bus.tick(cycles);
+        }
+
+        Ok(StepResult {
+            program_counter: opcode_address,
+            opcode: Some(*opcode),
+            operand_address: self.operand_address,
+            cycles,
+            branch_taken: self.branch_taken,
+            interrupt,
+        })
+    }
+
+    fn halted_step(&self) -> StepResult {
+        StepResult {
+            program_counter: self.cpu.program_counter,
+            ..StepResult::default()
+        }
+    }
+
+    /// Executes instructions until at least `cycles` cycles have passed or the clock
+    /// is halted. Returns the cycles actually spent, the last instruction may overshoot.
+    pub fn run_for_cycles(&mut self, cycles: u64) -> Result<u64, CpuError> {
+        let start_cycles = self.cpu.cycles;
+
+        while self.cpu.cycles - start_cycles < cycles {
+            if self.step()?.opcode.is_none() {
+                break;
+            }
+        }
+
+        Ok(self.cpu.cycles - start_cycles)
+    }
+
+    /// Executes instructions until the condition holds or the clock is halted.
+    /// Returns the cycles spent.
+    pub fn run_until(&mut self, mut condition: impl FnMut(&Self) -> bool) -> Result<u64, CpuError> {
+        let start_cycles = self.cpu.cycles;
+
+        while !condition(self) {
+            if self.step()?.opcode.is_none() {
+                break;
+            }
+        }
+
+        Ok(self.cpu.cycles - start_cycles)
+    }
+
+    // The 65C816 fetches from the program bank, the other CPUs go through the bus
+    fn fetch_opcode(&mut self) -> u8 {
+        match self.cpu.variant {
+            _ if self.cpu.is_bus_accurate() => self.bus_read(self.cpu.program_counter),
+            CpuVariant::Wdc65C816 => self.mem_read_long(self.cpu.program_address()),
+            _ => self.mem_read_8(self.cpu.program_counter),
+        }
+    }
+
+    // Interrupts are recognised only between instructions, NMI takes priority over IRQ.
+    // The IRQ line is shared, the bus devices pull it along with `set_irq_line`.
+    fn poll_interrupts(&mut self) -> Option<Interrupt> {
+        let interrupt_disable = self
+            .cpu
+            .delayed_interrupt_disable
+            .take()
+            .unwrap_or_else(|| self.cpu.has_flag(&StatusFlag::Interrupt));
+
+        let interrupt = if self.cpu.nmi_pending {
+            self.cpu.nmi_pending = false;
+            Interrupt::Nmi
+        } else if (self.cpu.irq_line || self.bus.irq()) && !interrupt_disable {
+            Interrupt::Irq
+        } else {
+            return None;
+        };
+
+        self.hardware_interrupt(&interrupt);
+
+        Some(interrupt)
+    }
+
+    // NMI and IRQ spend two cycles reading the opcode they replace before the
+    // BRK sequence. The 65C816 native mode needs one more to push the program bank.
+    fn hardware_interrupt(&mut self, interrupt: &Interrupt) {
+        if self.cpu.is_bus_accurate() {
+            self.dummy_read(self.cpu.program_counter);
+            self.dummy_read(self.cpu.program_counter);
+        } else {
+            self.cpu.cycles += if self.cpu.emulation { 7 } else { 8 };
+        }
+
+        self.interrupt(interrupt);
+    }
+
+    // In 65C816 native mode the program bank is saved too, the status is pushed
+    // as it is because bit 4 is the X flag, and the handlers have their own vectors
+    fn interrupt(&mut self, interrupt: &Interrupt) {
+        if self.cpu.emulation {
+            self.push_stack_16(self.cpu.program_counter);
+            self.push_stack(self.cpu.status_to_push(interrupt.sets_break_flag()));
+        } else {
+            self.push_stack(self.cpu.program_bank);
+            self.push_stack_16(self.cpu.program_counter);
+            self.push_stack(self.cpu.status);
+        }
+
+        self.cpu.enable_flag(&StatusFlag::Interrupt);
+
+        if self.cpu.variant.is_cmos() {
+            self.cpu.disable_flag(&StatusFlag::Decimal);
+        }
+
+        let vector = if self.cpu.emulation {
+            interrupt.vector()
+        } else {
+            interrupt.native_vector()
+        };
+
+        self.cpu.program_bank = 0;
+        self.cpu.program_counter = self.bus_read_16(vector);
     }
 
     fn update_pc(&mut self, current_pc: u16, bytes: u8) {
         if current_pc == self.cpu.program_counter {
-            self.cpu.program_counter += (bytes - 1) as u16;
+            self.cpu.program_counter = self.cpu.program_counter.wrapping_add((bytes - 1) as u16);
         }
     }
 
     //Operations for transferring bytes of data
     fn lda(&mut self, opcode: &OpCode) {
-        let address = self.get_operand_address(&opcode.address_mode);
-        let value = self.mem_read_8(address);
+        let value = self.read_operand(&opcode.address_mode);
 
         self.cpu.accumulator = value;
         self.cpu.update_zero_and_negative_flags(value);
     }
 
     fn ldx(&mut self, opcode: &OpCode) {
-        let adderss = self.get_operand_address(&opcode.address_mode);
-        let value = self.mem_read_8(adderss);
+        let value = self.read_operand(&opcode.address_mode);
 
         self.cpu.register_x = value;
         self.cpu.update_zero_and_negative_flags(value);
     }
 
     fn ldy(&mut self, opcode: &OpCode) {
-        let address = self.get_operand_address(&opcode.address_mode);
-        let value = self.mem_read_8(address);
+        let value = self.read_operand(&opcode.address_mode);
 
         self.cpu.register_y = value;
         self.cpu.update_zero_and_negative_flags(value);
     }
 
     fn sta(&mut self, opcode: &OpCode) {
-        let address = self.get_operand_address(&opcode.address_mode);
-
-        self.mem_write_8(address, self.cpu.accumulator);
+        self.write_operand(&opcode.address_mode, self.cpu.accumulator);
     }
 
     fn stx(&mut self, opcode: &OpCode) {
-        let address = self.get_operand_address(&opcode.address_mode);
-
-        self.mem_write_8(address, self.cpu.register_x)
+        self.write_operand(&opcode.address_mode, self.cpu.register_x)
     }
 
     fn sty(&mut self, opcode: &OpCode) {
-        let address = self.get_operand_address(&opcode.address_mode);
-
-        self.mem_write_8(address, self.cpu.register_y)
+        self.write_operand(&opcode.address_mode, self.cpu.register_y)
     }
 
     fn tax(&mut self) {
@@ -421,13 +881,39 @@ impl Nes {
         self.cpu.update_zero_and_negative_flags(self.cpu.register_x);
     }
 
-    // TODO: Refactor adc & sbc methods (same structure)
     // Addition
     fn adc(&mut self, opcode: &OpCode) {
-        let has_carry_flag = self.cpu.has_flag(&StatusFlag::Carry) as u8;
+        let value = self.read_operand(&opcode.address_mode);
+
+        self.add_decimal_cycle();
+        self.add_to_accumulator(value);
+    }
+
+    // Substract
+    fn sbc(&mut self, opcode: &OpCode) {
+        let value = self.read_operand(&opcode.address_mode);
+
+        self.add_decimal_cycle();
+        self.subtract_from_accumulator(value);
+    }
+
+    // The 65C02 spends one more cycle to fix up the flags in decimal mode
+    fn add_decimal_cycle(&mut self) {
+        if self.cpu.variant.is_cmos() && self.cpu.is_decimal_mode() {
+            self.penalty_cycle(self.cpu.program_counter);
+        }
+    }
+
+    fn add_to_accumulator(&mut self, value: u8) {
+        if self.cpu.is_decimal_mode() {
+            self.add_decimal(value)
+        } else {
+            self.add_binary(value)
+        }
+    }
 
-        let address = self.get_operand_address(&opcode.address_mode);
-        let value = self.mem_read_8(address);
+    fn add_binary(&mut self, value: u8) {
+        let has_carry_flag = self.cpu.has_flag(&StatusFlag::Carry) as u8;
 
         let result_with_carry =
             (value as u16) + (self.cpu.accumulator as u16) + (has_carry_flag as u16);
@@ -443,33 +929,70 @@ impl Nes {
         self.cpu.update_zero_and_negative_flags(result);
     }
 
-    // Substract
-    fn sbc(&mut self, opcode: &OpCode) {
-        let address = self.get_operand_address(&opcode.address_mode);
-        let value = self.mem_read_8(address);
+    // A - M - (1 - C) is the same as A + !M + C
+    fn subtract_from_accumulator(&mut self, value: u8) {
+        let accumulator = self.cpu.accumulator;
+        let has_carry_flag = self.cpu.has_flag(&StatusFlag::Carry);
 
-        let sub_result = (value as i8).wrapping_neg().wrapping_sub(1) as u8;
+        // In decimal mode the NMOS 6502 sets all flags as if the subtraction was binary
+        self.add_binary(!value);
 
-        let has_carry_flag = self.cpu.has_flag(&StatusFlag::Carry) as u8;
+        if !self.cpu.is_decimal_mode() {
+            return;
+        }
 
-        let result_with_carry =
-            (sub_result as u16) + (self.cpu.accumulator as u16) + (has_carry_flag as u16);
-        let result = result_with_carry as u8;
+        if self.cpu.variant.is_cmos() {
+            self.cpu.accumulator = cmos_decimal_difference(accumulator, value, has_carry_flag);
+            self.cpu
+                .update_zero_and_negative_flags(self.cpu.accumulator);
+        } else {
+            self.cpu.accumulator = decimal_difference(accumulator, value, has_carry_flag);
+        }
+    }
 
-        let has_carry = result_with_carry > 0xFF;
-        let has_overflow = (sub_result ^ result) & (self.cpu.accumulator ^ result) & 0x80 != 0;
+    // In decimal mode the NMOS 6502 computes N and V before the high digit is
+    // adjusted, and Z from the binary sum, so only A and C hold a valid BCD result
+    fn add_decimal(&mut self, value: u8) {
+        let accumulator = self.cpu.accumulator;
+        let has_carry_flag = self.cpu.has_flag(&StatusFlag::Carry) as u16;
 
-        self.cpu.accumulator = result;
+        let binary_result = (accumulator as u16 + value as u16 + has_carry_flag) as u8;
 
-        self.cpu.update_flag(&StatusFlag::Carry, has_carry);
-        self.cpu.update_flag(&StatusFlag::Overflow, has_overflow);
-        self.cpu.update_zero_and_negative_flags(result);
+        let mut low = (accumulator & 0x0F) as u16 + (value & 0x0F) as u16 + has_carry_flag;
+
+        if low >= 0x0A {
+            low = ((low + 0x06) & 0x0F) + 0x10;
+        }
+
+        let mut result = (accumulator & 0xF0) as u16 + (value & 0xF0) as u16 + low;
+
+        let signed_result =
+            (accumulator & 0xF0) as i8 as i16 + (value & 0xF0) as i8 as i16 + low as i16;
+
+        self.cpu
+            .update_flag(&StatusFlag::Negative, result & 0x80 != 0);
+        self.cpu.update_flag(
+            &StatusFlag::Overflow,
+            !(-128..=127).contains(&signed_result),
+        );
+        self.cpu.update_flag(&StatusFlag::Zero, binary_result == 0);
+
+        if result >= 0xA0 {
+            result += 0x60;
+        }
+
+        self.cpu.accumulator = result as u8;
+        self.cpu.update_flag(&StatusFlag::Carry, result > 0xFF);
+
+        // The 65C02 sets N and Z from the decimal result
+        if self.cpu.variant.is_cmos() {
+            self.cpu.update_zero_and_negative_flags(result as u8);
+        }
     }
 
     // Bitwise operations
     fn and(&mut self, opcode: &OpCode) {
-        let address = self.get_operand_address(&opcode.address_mode);
-        let value = self.mem_read_8(address);
+        let value = self.read_operand(&opcode.address_mode);
 
         self.cpu.accumulator &= value;
         self.cpu
@@ -477,8 +1000,7 @@ impl Nes {
     }
 
     fn ora(&mut self, opcode: &OpCode) {
-        let address = self.get_operand_address(&opcode.address_mode);
-        let value = self.mem_read_8(address);
+        let value = self.read_operand(&opcode.address_mode);
 
         self.cpu.accumulator |= value;
         self.cpu
@@ -486,8 +1008,7 @@ impl Nes {
     }
 
     fn eor(&mut self, opcode: &OpCode) {
-        let address = self.get_operand_address(&opcode.address_mode);
-        let value = self.mem_read_8(address);
+        let value = self.read_operand(&opcode.address_mode);
 
         self.cpu.accumulator ^= value;
         self.cpu
@@ -525,135 +1046,167 @@ impl Nes {
 
     // Operations for incrementing and decrementing memory
     fn inc(&mut self, opcode: &OpCode) {
-        let address = self.get_operand_address(&opcode.address_mode);
-        let value = self.mem_read_8(address);
-        let (result, _) = value.overflowing_add(1);
+        let result = self.modify_operand(&opcode.address_mode, |_, value| value.wrapping_add(1));
 
-        self.mem_write_8(address, result);
         self.cpu.update_zero_and_negative_flags(result);
     }
 
     fn dec(&mut self, opcode: &OpCode) {
-        let address = self.get_operand_address(&opcode.address_mode);
-        let value = self.mem_read_8(address);
-        let (result, _) = value.overflowing_sub(1);
+        let result = self.modify_operand(&opcode.address_mode, |_, value| value.wrapping_sub(1));
 
-        self.mem_write_8(address, result);
         self.cpu.update_zero_and_negative_flags(result);
     }
 
     // Operations for byte comparison
     fn cmp(&mut self, opcode: &OpCode) {
-        let address = self.get_operand_address(&opcode.address_mode);
-        let value = self.mem_read_8(address);
-        let result = self.cpu.accumulator.wrapping_sub(value);
+        let value = self.read_operand(&opcode.address_mode);
 
-        self.cpu
-            .update_flag(&StatusFlag::Carry, self.cpu.accumulator >= value);
-        self.cpu.update_zero_and_negative_flags(result);
+        self.cpu.compare(self.cpu.accumulator, value);
     }
 
     fn cpx(&mut self, opcode: &OpCode) {
-        let address = self.get_operand_address(&opcode.address_mode);
-        let value = self.mem_read_8(address);
-        let result = self.cpu.register_x.wrapping_sub(value);
+        let value = self.read_operand(&opcode.address_mode);
 
-        self.cpu
-            .update_flag(&StatusFlag::Carry, self.cpu.register_x >= value);
-        self.cpu.update_zero_and_negative_flags(result);
+        self.cpu.compare(self.cpu.register_x, value);
     }
 
     fn cpy(&mut self, opcode: &OpCode) {
-        let address = self.get_operand_address(&opcode.address_mode);
-        let value = self.mem_read_8(address);
-        let result = self.cpu.register_y.wrapping_sub(value);
+        let value = self.read_operand(&opcode.address_mode);
 
-        self.cpu
-            .update_flag(&StatusFlag::Carry, self.cpu.register_y >= value);
-        self.cpu.update_zero_and_negative_flags(result);
+        self.cpu.compare(self.cpu.register_y, value);
     }
 
     // The BIT operation
+    // N and V are copied from bits 7 and 6 of the operand,
+    // except for the 65C02 immediate mode that only affects Z
     fn bit(&mut self, opcode: &OpCode) {
-        let address = self.get_operand_address(&opcode.address_mode);
-        let value = self.mem_read_8(address);
+        let value = self.read_operand(&opcode.address_mode);
         let result = self.cpu.accumulator & value;
 
-        self.cpu.update_flag(&StatusFlag::Overflow, value >> 6 == 1);
-        self.cpu.update_zero_and_negative_flags(result);
+        self.cpu.update_flag(&StatusFlag::Zero, result == 0);
+
+        if !matches!(opcode.address_mode, AddressingMode::Immediate) {
+            self.cpu
+                .update_flag(&StatusFlag::Overflow, (value >> 6) & 1 == 1);
+            self.cpu.update_flag(&StatusFlag::Negative, value >> 7 == 1);
+        }
     }
 
     // Bit shift operations
     fn lsr(&mut self, opcode: &OpCode) {
-        let address = self.get_operand_address(&opcode.address_mode);
-        let value = self.mem_read_8(address);
-
-        let (result, _) = value.overflowing_shr(1);
-
-        self.mem_write_8(address, result);
+        let result = self.modify_operand(&opcode.address_mode, Cpu::shift_right);
 
-        self.cpu.update_flag(&StatusFlag::Zero, value >> 7 == 0);
-        self.cpu.update_flag(&StatusFlag::Negative, false);
-        self.cpu.update_flag(&StatusFlag::Carry, value & 1 == 1);
+        self.cpu.update_zero_and_negative_flags(result);
     }
 
     fn asl(&mut self, opcode: &OpCode) {
-        let address = self.get_operand_address(&opcode.address_mode);
-        let value = self.mem_read_8(address);
-
-        let (result, _) = value.overflowing_shl(1);
+        let result = self.modify_operand(&opcode.address_mode, Cpu::shift_left);
 
-        self.mem_write_8(address, result);
-
-        self.cpu.update_flag(&StatusFlag::Zero, value >> 7 == 0);
-        self.cpu
-            .update_flag(&StatusFlag::Negative, result >> 7 == 1);
-        self.cpu.update_flag(&StatusFlag::Carry, value >> 7 == 1);
+        self.cpu.update_zero_and_negative_flags(result);
     }
 
     fn ror(&mut self, opcode: &OpCode) {
-        let address = self.get_operand_address(&opcode.address_mode);
-        let value = self.mem_read_8(address);
-
-        let mut result = value.rotate_right(1);
-
-        self.cpu.update_flag(&StatusFlag::Carry, value >> 7 == 1);
-
-        if self.cpu.has_flag(&StatusFlag::Carry) {
-            result |= 0b10000000;
-        }
+        let result = self.modify_operand(&opcode.address_mode, Cpu::rotate_right);
 
-        self.cpu.update_flag(&StatusFlag::Zero, value >> 7 == 0);
-        self.cpu
-            .update_flag(&StatusFlag::Negative, result >> 7 == 1);
+        self.cpu.update_zero_and_negative_flags(result);
     }
 
     fn rol(&mut self, opcode: &OpCode) {
-        let address = self.get_operand_address(&opcode.address_mode);
-        let value = self.mem_read_8(address);
+        let result = self.modify_operand(&opcode.address_mode, Cpu::rotate_left);
 
-        let mut result = value.rotate_left(1);
+        self.cpu.update_zero_and_negative_flags(result);
+    }
 
-        self.cpu.update_flag(&StatusFlag::Carry, value >> 7 == 1);
+    // Read-modify-write instructions work either on the accumulator or on a memory cell
+    fn modify_operand(
+        &mut self,
+        mode: &AddressingMode,
+        operation: impl FnOnce(&mut Cpu, u8) -> u8,
+    ) -> u8 {
+        match mode {
+            AddressingMode::Accumulator => {
+                let value = self.cpu.accumulator;
+                let result = operation(&mut self.cpu, value);
+                self.cpu.accumulator = result;
 
-        if self.cpu.has_flag(&StatusFlag::Carry) {
-            result |= 1;
+                result
+            }
+            // The NMOS 6502 writes the unmodified value back while it computes
+            // the result, the 65C02 reads it once more instead
+            _ => {
+                let address = self.resolve_operand_address(mode, OperandAccess::Modify);
+                let value = self.bus_read(address);
+
+                if self.cpu.variant.is_cmos() {
+                    self.dummy_read(address);
+                } else {
+                    self.dummy_write(address, value);
+                }
+
+                let result = operation(&mut self.cpu, value);
+                self.bus_write(address, result);
+
+                result
+            }
         }
-
-        self.cpu.update_flag(&StatusFlag::Zero, value >> 7 == 0);
-        self.cpu
-            .update_flag(&StatusFlag::Negative, result >> 7 == 1);
     }
 
     // The Jump operation
     fn jmp(&mut self, opcode: &OpCode) {
-        let address = self.get_operand_address(&opcode.address_mode);
-        let value = self.mem_read_16(address);
+        let address = self.resolve_operand_address(&opcode.address_mode, OperandAccess::Read);
 
-        self.cpu.program_counter = value;
+        self.cpu.program_counter = match opcode.address_mode {
+            // The NMOS 6502 does not carry into the high byte of the pointer,
+            // so JMP ($xxFF) reads the high byte from $xx00
+            AddressingMode::Indirect if !self.cpu.variant.is_cmos() => {
+                let low = self.bus_read(address);
+                let high = self.bus_read((address & 0xFF00) | (address.wrapping_add(1) & 0x00FF));
+
+                u16::from_le_bytes([low, high])
+            }
+            // The 65C02 spends one cycle to fix that
+            AddressingMode::Indirect => {
+                self.dummy_read(self.cpu.program_counter.wrapping_add(1));
+                self.bus_read_16(address)
+            }
+            AddressingMode::AbsoluteIndexedIndirect => self.bus_read_16(address),
+            _ => address,
+        };
     }
 
-    // Operations for setting and clearing the Processor Status register flags
+    fn branch(&mut self, condition: bool) {
+        let address = self.resolve_operand_address(&AddressingMode::Relative, OperandAccess::Read);
+
+        self.branch_with_offset_at(address, condition);
+    }
+
+    // A taken branch costs one extra cycle, or two if it lands on another page.
+    // The offset is the last byte of the instruction.
+    fn branch_with_offset_at(&mut self, address: u16, condition: bool) {
+        let offset = self.bus_read(address) as i8;
+
+        let next_instruction = address.wrapping_add(1);
+        let target = next_instruction.wrapping_add(offset as u16);
+
+        // BBR and BBS keep the address of the tested zero page byte
+        self.operand_address.get_or_insert(target as u32);
+
+        if !condition {
+            return;
+        }
+
+        self.branch_taken = true;
+
+        self.penalty_cycle(next_instruction);
+
+        if next_instruction & 0xFF00 != target & 0xFF00 {
+            self.penalty_cycle((next_instruction & 0xFF00) | (target & 0x00FF));
+        }
+
+        self.cpu.program_counter = target;
+    }
+
+    // Operations for setting and clearing the Processor Status register flags
     fn sec(&mut self) {
         self.cpu.enable_flag(&StatusFlag::Carry);
     }
@@ -682,64 +1235,73 @@ impl Nes {
         self.cpu.disable_flag(&StatusFlag::Overflow);
     }
 
-    fn bmi(&mut self, opcode: &OpCode) {
-        if self.cpu.has_flag(&StatusFlag::Negative) {
-            self.jmp(opcode)
-        }
+    fn bmi(&mut self) {
+        self.branch(self.cpu.has_flag(&StatusFlag::Negative));
     }
 
-    fn bpl(&mut self, opcode: &OpCode) {
-        if !self.cpu.has_flag(&StatusFlag::Negative) {
-            self.jmp(opcode)
-        }
+    fn bpl(&mut self) {
+        self.branch(!self.cpu.has_flag(&StatusFlag::Negative));
     }
 
-    fn bvs(&mut self, opcode: &OpCode) {
-        if self.cpu.has_flag(&StatusFlag::Overflow) {
-            self.jmp(opcode)
-        }
+    fn bvs(&mut self) {
+        self.branch(self.cpu.has_flag(&StatusFlag::Overflow));
     }
 
-    fn bvc(&mut self, opcode: &OpCode) {
-        if !self.cpu.has_flag(&StatusFlag::Overflow) {
-            self.jmp(opcode)
-        }
+    fn bvc(&mut self) {
+        self.branch(!self.cpu.has_flag(&StatusFlag::Overflow));
     }
 
-    fn bcs(&mut self, opcode: &OpCode) {
-        if self.cpu.has_flag(&StatusFlag::Carry) {
-            self.jmp(opcode)
-        }
+    fn bcs(&mut self) {
+        self.branch(self.cpu.has_flag(&StatusFlag::Carry));
     }
 
-    fn bcc(&mut self, opcode: &OpCode) {
-        if !self.cpu.has_flag(&StatusFlag::Carry) {
-            self.jmp(opcode)
-        }
+    fn bcc(&mut self) {
+        self.branch(!self.cpu.has_flag(&StatusFlag::Carry));
     }
 
-    fn beq(&mut self, opcode: &OpCode) {
-        if self.cpu.has_flag(&StatusFlag::Zero) {
-            self.jmp(opcode)
-        }
+    fn beq(&mut self) {
+        self.branch(self.cpu.has_flag(&StatusFlag::Zero));
     }
 
-    fn bne(&mut self, opcode: &OpCode) {
-        if !self.cpu.has_flag(&StatusFlag::Zero) {
-            self.jmp(opcode)
-        }
+    fn bne(&mut self) {
+        self.branch(!self.cpu.has_flag(&StatusFlag::Zero));
     }
 
-    fn jsr(&mut self, opcode: &OpCode) {
-        let address = self.get_operand_address(&opcode.address_mode);
-        let value = self.mem_read_16(address);
+    // JSR pushes the return address before it has read the high byte of the target
+    fn jsr(&mut self) {
+        let low = self.bus_read(self.cpu.program_counter);
+        self.dummy_read(self.cpu.stack_pointer_16());
+
+        // The return address points to the last byte of the JSR instruction
+        self.push_stack_16(self.cpu.program_counter.wrapping_add(1));
 
-        self.cpu.program_counter = value;
+        let high = self.bus_read(self.cpu.program_counter.wrapping_add(1));
 
-        self.push_stack_16(self.cpu.program_counter);
+        self.cpu.program_counter = u16::from_le_bytes([low, high]);
+        self.operand_address = Some(self.cpu.program_counter as u32);
     }
 
     fn rts(&mut self) {
+        self.dummy_read(self.cpu.stack_pointer_16());
+        let address = self.pop_stack_16();
+        self.dummy_read(address);
+
+        self.cpu.program_counter = address.wrapping_add(1);
+    }
+
+    fn brk(&mut self) {
+        // BRK skips a padding byte, so the handler returns past it
+        self.cpu.program_counter = self.cpu.program_counter.wrapping_add(1);
+
+        self.interrupt(&Interrupt::Brk);
+    }
+
+    // Pulling spends one cycle incrementing the stack pointer
+    fn rti(&mut self) {
+        self.dummy_read(self.cpu.stack_pointer_16());
+        let status = self.pop_stack();
+        self.cpu.set_status_from_stack(status);
+
         self.cpu.program_counter = self.pop_stack_16();
     }
 
@@ -748,28 +1310,386 @@ impl Nes {
     }
 
     fn php(&mut self) {
-        self.push_stack(self.cpu.stack_pointer);
+        self.push_stack(self.cpu.status_to_push(true));
     }
 
     fn pla(&mut self) {
+        self.dummy_read(self.cpu.stack_pointer_16());
         self.cpu.accumulator = self.pop_stack();
         self.cpu
             .update_zero_and_negative_flags(self.cpu.accumulator);
     }
 
     fn plp(&mut self) {
-        self.cpu.stack_pointer = self.pop_stack();
+        self.dummy_read(self.cpu.stack_pointer_16());
+        let status = self.pop_stack();
+        self.cpu.set_status_from_stack(status);
+    }
+
+    // 65C02 operations
+    fn bra(&mut self) {
+        self.branch(true);
+    }
+
+    fn stz(&mut self, opcode: &OpCode) {
+        self.write_operand(&opcode.address_mode, 0);
+    }
+
+    fn phx(&mut self) {
+        self.push_stack(self.cpu.register_x);
+    }
+
+    fn plx(&mut self) {
+        self.dummy_read(self.cpu.stack_pointer_16());
+        self.cpu.register_x = self.pop_stack();
+        self.cpu.update_zero_and_negative_flags(self.cpu.register_x);
+    }
+
+    fn phy(&mut self) {
+        self.push_stack(self.cpu.register_y);
+    }
+
+    fn ply(&mut self) {
+        self.dummy_read(self.cpu.stack_pointer_16());
+        self.cpu.register_y = self.pop_stack();
+        self.cpu.update_zero_and_negative_flags(self.cpu.register_y);
+    }
+
+    // TRB and TSB set Z like BIT does, then clear or set the accumulator bits in memory
+    fn trb(&mut self, opcode: &OpCode) {
+        let accumulator = self.cpu.accumulator;
+
+        self.modify_operand(&opcode.address_mode, |cpu, value| {
+            cpu.update_flag(&StatusFlag::Zero, value & accumulator == 0);
+
+            value & !accumulator
+        });
+    }
+
+    fn tsb(&mut self, opcode: &OpCode) {
+        let accumulator = self.cpu.accumulator;
+
+        self.modify_operand(&opcode.address_mode, |cpu, value| {
+            cpu.update_flag(&StatusFlag::Zero, value & accumulator == 0);
+
+            value | accumulator
+        });
+    }
+
+    // Rockwell and WDC bit operations
+    fn rmb(&mut self, opcode: &OpCode, code: u8) {
+        let mask = opcode_bit_mask(code);
+
+        self.modify_operand(&opcode.address_mode, |_, value| value & !mask);
+    }
+
+    fn smb(&mut self, opcode: &OpCode, code: u8) {
+        let mask = opcode_bit_mask(code);
+
+        self.modify_operand(&opcode.address_mode, |_, value| value | mask);
+    }
+
+    fn bbr(&mut self, code: u8) {
+        let (value, offset_address) = self.read_zero_page_relative();
+
+        self.branch_with_offset_at(offset_address, value & opcode_bit_mask(code) == 0);
+    }
+
+    fn bbs(&mut self, code: u8) {
+        let (value, offset_address) = self.read_zero_page_relative();
+
+        self.branch_with_offset_at(offset_address, value & opcode_bit_mask(code) != 0);
+    }
+
+    // The tested zero page byte and the address of the branch offset
+    fn read_zero_page_relative(&mut self) -> (u8, u16) {
+        let address =
+            self.resolve_operand_address(&AddressingMode::ZeroPageRelative, OperandAccess::Read);
+        let value = self.bus_read(address);
+        self.dummy_read(address);
+
+        (value, self.cpu.program_counter.wrapping_add(1))
+    }
+
+    // WAI and STP leave the program counter on the next instruction
+    fn wai(&mut self) {
+        self.dummy_read(self.cpu.program_counter);
+        self.cpu.state = CpuState::Waiting;
+    }
+
+    fn stp(&mut self) {
+        self.dummy_read(self.cpu.program_counter);
+        self.cpu.state = CpuState::Stopped;
+    }
+
+    // NOPs with an operand still read it, including the page-cross penalty
+    fn nop(&mut self, opcode: &OpCode) {
+        if !matches!(opcode.address_mode, AddressingMode::Implied) {
+            self.read_operand(&opcode.address_mode);
+        }
+
+        // The 65C02 NOP $5C keeps the bus busy for four more cycles
+        if self.cpu.variant.is_cmos() && opcode.code == 0x5C {
+            for _ in 0..4 {
+                self.dummy_read(0xFFFF);
+            }
+        }
+    }
+
+    // Unofficial read-modify-write operations
+    fn slo(&mut self, opcode: &OpCode) {
+        let result = self.modify_operand(&opcode.address_mode, Cpu::shift_left);
+
+        self.cpu.accumulator |= result;
+        self.cpu
+            .update_zero_and_negative_flags(self.cpu.accumulator);
+    }
+
+    fn rla(&mut self, opcode: &OpCode) {
+        let result = self.modify_operand(&opcode.address_mode, Cpu::rotate_left);
+
+        self.cpu.accumulator &= result;
+        self.cpu
+            .update_zero_and_negative_flags(self.cpu.accumulator);
+    }
+
+    fn sre(&mut self, opcode: &OpCode) {
+        let result = self.modify_operand(&opcode.address_mode, Cpu::shift_right);
+
+        self.cpu.accumulator ^= result;
+        self.cpu
+            .update_zero_and_negative_flags(self.cpu.accumulator);
+    }
+
+    fn rra(&mut self, opcode: &OpCode) {
+        let result = self.modify_operand(&opcode.address_mode, Cpu::rotate_right);
+
+        self.add_to_accumulator(result);
+    }
+
+    fn dcp(&mut self, opcode: &OpCode) {
+        let result = self.modify_operand(&opcode.address_mode, |_, value| value.wrapping_sub(1));
+
+        self.cpu.compare(self.cpu.accumulator, result);
+    }
+
+    fn isc(&mut self, opcode: &OpCode) {
+        let result = self.modify_operand(&opcode.address_mode, |_, value| value.wrapping_add(1));
+
+        self.subtract_from_accumulator(result);
+    }
+
+    // Unofficial load and store operations
+    fn sax(&mut self, opcode: &OpCode) {
+        self.write_operand(
+            &opcode.address_mode,
+            self.cpu.accumulator & self.cpu.register_x,
+        );
+    }
+
+    fn lax(&mut self, opcode: &OpCode) {
+        let value = self.read_operand(&opcode.address_mode);
+
+        self.cpu.accumulator = value;
+        self.cpu.register_x = value;
+        self.cpu.update_zero_and_negative_flags(value);
+    }
+
+    // Unofficial immediate operations
+    fn anc(&mut self, opcode: &OpCode) {
+        let value = self.read_operand(&opcode.address_mode);
+
+        self.cpu.accumulator &= value;
+        self.cpu
+            .update_zero_and_negative_flags(self.cpu.accumulator);
+        self.cpu
+            .update_flag(&StatusFlag::Carry, self.cpu.accumulator >> 7 == 1);
+    }
+
+    fn alr(&mut self, opcode: &OpCode) {
+        let value = self.read_operand(&opcode.address_mode);
+        let result = self.cpu.shift_right(self.cpu.accumulator & value);
+
+        self.cpu.accumulator = result;
+        self.cpu.update_zero_and_negative_flags(result);
+    }
+
+    // Carry is taken from bit 6 of the result and Overflow from bit 6 XOR bit 5
+    fn arr(&mut self, opcode: &OpCode) {
+        let value = self.read_operand(&opcode.address_mode);
+        let carry = self.cpu.has_flag(&StatusFlag::Carry) as u8;
+        let and_result = self.cpu.accumulator & value;
+        let result = (and_result >> 1) | (carry << 7);
+
+        if self.cpu.is_decimal_mode() {
+            self.arr_decimal(and_result, result);
+            return;
+        }
+
+        self.cpu.accumulator = result;
+        self.cpu.update_zero_and_negative_flags(result);
+        self.cpu
+            .update_flag(&StatusFlag::Carry, (result >> 6) & 1 == 1);
+        self.cpu.update_flag(
+            &StatusFlag::Overflow,
+            ((result >> 6) ^ (result >> 5)) & 1 == 1,
+        );
+    }
+
+    // In decimal mode ARR fixes up each digit of the rotated value like ADC does
+    fn arr_decimal(&mut self, and_result: u8, result: u8) {
+        self.cpu.update_zero_and_negative_flags(result);
+        self.cpu
+            .update_flag(&StatusFlag::Overflow, (and_result ^ result) & 0x40 != 0);
+
+        let mut adjusted = result;
+
+        if (and_result & 0x0F) + (and_result & 0x01) > 0x05 {
+            adjusted = (adjusted & 0xF0) | (adjusted.wrapping_add(0x06) & 0x0F);
+        }
+
+        let has_carry = (and_result & 0xF0) as u16 + (and_result & 0x10) as u16 > 0x50;
+
+        if has_carry {
+            adjusted = adjusted.wrapping_add(0x60);
+        }
+
+        self.cpu.accumulator = adjusted;
+        self.cpu.update_flag(&StatusFlag::Carry, has_carry);
+    }
+
+    fn sbx(&mut self, opcode: &OpCode) {
+        let value = self.read_operand(&opcode.address_mode);
+        let register = self.cpu.accumulator & self.cpu.register_x;
+
+        self.cpu.compare(register, value);
+        self.cpu.register_x = register.wrapping_sub(value);
+    }
+
+    // Unstable operations
+    fn xaa(&mut self, opcode: &OpCode) {
+        let value = self.read_operand(&opcode.address_mode);
+        let magic = self.cpu.unstable_opcodes.xaa_magic;
+
+        self.cpu.accumulator = (self.cpu.accumulator | magic) & self.cpu.register_x & value;
+        self.cpu
+            .update_zero_and_negative_flags(self.cpu.accumulator);
+    }
+
+    fn lxa(&mut self, opcode: &OpCode) {
+        let value = self.read_operand(&opcode.address_mode);
+        let magic = self.cpu.unstable_opcodes.lxa_magic;
+        let result = (self.cpu.accumulator | magic) & value;
+
+        self.cpu.accumulator = result;
+        self.cpu.register_x = result;
+        self.cpu.update_zero_and_negative_flags(result);
+    }
+
+    fn ahx(&mut self, opcode: &OpCode) {
+        self.store_with_high_byte(
+            &opcode.address_mode,
+            self.cpu.accumulator & self.cpu.register_x,
+        );
+    }
+
+    fn tas(&mut self, opcode: &OpCode) {
+        self.cpu.stack_pointer = self.cpu.accumulator & self.cpu.register_x;
+
+        self.store_with_high_byte(&opcode.address_mode, self.cpu.stack_pointer);
+    }
+
+    fn shx(&mut self, opcode: &OpCode) {
+        self.store_with_high_byte(&opcode.address_mode, self.cpu.register_x);
+    }
+
+    fn shy(&mut self, opcode: &OpCode) {
+        self.store_with_high_byte(&opcode.address_mode, self.cpu.register_y);
+    }
+
+    fn las(&mut self, opcode: &OpCode) {
+        let value = self.read_operand(&opcode.address_mode) & self.cpu.stack_pointer;
+
+        self.cpu.accumulator = value;
+        self.cpu.register_x = value;
+        self.cpu.stack_pointer = value;
+        self.cpu.update_zero_and_negative_flags(value);
+    }
+
+    // AHX, TAS, SHX and SHY store the value ANDed with the high byte of the
+    // base address plus one. On a page crossing the stored value may also
+    // replace the high byte of the target address.
+    fn store_with_high_byte(&mut self, mode: &AddressingMode, value: u8) {
+        let index = match mode {
+            AddressingMode::AbsoluteX => self.cpu.register_x,
+            _ => self.cpu.register_y,
+        };
+
+        let mut address = self.resolve_operand_address(mode, OperandAccess::Write);
+        let base = address.wrapping_sub(index as u16);
+        let result = value & ((base >> 8) as u8).wrapping_add(1);
+
+        if self.cpu.unstable_opcodes.corrupt_address_on_page_cross
+            && base & 0xFF00 != address & 0xFF00
+        {
+            address = ((result as u16) << 8) | (address & 0x00FF);
+        }
+
+        self.bus_write(address, result);
+    }
+
+    // JAM locks up the real chip, the policy decides what the emulator does with it
+    fn jam(&mut self, code: u8, address: u16) -> Result<(), CpuError> {
+        match self.cpu.illegal_opcode_policy {
+            // The CPU stops fetching instructions and stays on the JAM opcode
+            IllegalOpcodePolicy::Halt => {
+                self.cpu.program_counter = address;
+                self.cpu.state = CpuState::Stopped;
+
+                Err(CpuError::IllegalOpcode { code, address })
+            }
+            IllegalOpcodePolicy::Nop => Ok(()),
+            IllegalOpcodePolicy::Callback(callback) => callback(&mut self.cpu, code),
+        }
+    }
+}
+
+// RMB, SMB, BBR and BBS encode the bit number in bits 4-6 of the opcode
+fn opcode_bit_mask(code: u8) -> u8 {
+    1 << ((code >> 4) & 0x07)
+}
+
+// The 65C02 subtracts in binary and then adjusts both digits
+fn cmos_decimal_difference(accumulator: u8, value: u8, has_carry_flag: bool) -> u8 {
+    let low = (accumulator & 0x0F) as i16 - (value & 0x0F) as i16 + has_carry_flag as i16 - 1;
+    let mut result = accumulator as i16 - value as i16 + has_carry_flag as i16 - 1;
+
+    if result < 0 {
+        result -= 0x60;
+    }
+
+    if low < 0 {
+        result -= 0x06;
     }
+
+    result as u8
 }
 
-impl NesMemory for Nes {
-    fn mem_read_8(&self, address: u16) -> u8 {
-        self.memory[address as usize]
+// Digit-wise subtraction of the NMOS 6502 decimal mode
+fn decimal_difference(accumulator: u8, value: u8, has_carry_flag: bool) -> u8 {
+    let mut low = (accumulator & 0x0F) as i16 - (value & 0x0F) as i16 + has_carry_flag as i16 - 1;
+
+    if low < 0 {
+        low = ((low - 0x06) & 0x0F) - 0x10;
     }
 
-    fn mem_write_8(&mut self, address: u16, data: u8) {
-        self.memory[address as usize] = data;
+    let mut result = (accumulator & 0xF0) as i16 - (value & 0xF0) as i16 + low;
+
+    if result < 0 {
+        result -= 0x60;
     }
+
+    result as u8
 }
 
 #[derive(Debug)]
@@ -780,6 +1700,37 @@ pub struct Cpu {
     pub program_counter: u16,
     pub status: u8,
     pub stack_pointer: u8,
+    /// Total number of cycles the CPU has spent since the last reset
+    pub cycles: u64,
+    pub variant: CpuVariant,
+    pub unstable_opcodes: UnstableOpcodeModel,
+    /// What to do when the CPU fetches a JAM opcode
+    pub illegal_opcode_policy: IllegalOpcodePolicy,
+    /// Data direction and data registers of the 6510 mapped at $00 and $01
+    pub io_port: IoPort,
+    /// Whether the clock is halted by WAI or STP
+    pub state: CpuState,
+    /// Runs every instruction as the bus cycles of the real chip, dummy accesses
+    /// included, and counts one cycle per access. The 65C816 always runs whole
+    /// instructions with the cycle counts of the opcode table.
+    pub bus_accurate: bool,
+    /// 65C816 emulation flag, the 8-bit CPUs always run in emulation mode
+    pub emulation: bool,
+    /// 65C816 high byte of the accumulator, together with A it forms the 16-bit C
+    pub accumulator_b: u8,
+    /// 65C816 high bytes of the index registers, zero while X and Y are 8-bit
+    pub register_x_high: u8,
+    pub register_y_high: u8,
+    /// 65C816 high byte of the stack pointer, fixed to page 1 in emulation mode
+    pub stack_pointer_high: u8,
+    /// 65C816 base address of the zero page (direct page) addressing modes
+    pub direct_page: u16,
+    /// 65C816 bank of the data operands and of the running program
+    pub data_bank: u8,
+    pub program_bank: u8,
+    nmi_pending: bool,
+    irq_line: bool,
+    delayed_interrupt_disable: Option<bool>,
 }
 
 impl Default for Cpu {
@@ -791,6 +1742,24 @@ impl Default for Cpu {
             program_counter: 0x0600,
             status: 0b00100100,
             stack_pointer: 0xfd,
+            cycles: 0,
+            variant: CpuVariant::default(),
+            unstable_opcodes: UnstableOpcodeModel::default(),
+            illegal_opcode_policy: IllegalOpcodePolicy::default(),
+            io_port: IoPort::default(),
+            state: CpuState::Running,
+            bus_accurate: false,
+            emulation: true,
+            accumulator_b: 0,
+            register_x_high: 0,
+            register_y_high: 0,
+            stack_pointer_high: (STACK_START >> 8) as u8,
+            direct_page: 0,
+            data_bank: 0,
+            program_bank: 0,
+            nmi_pending: false,
+            irq_line: false,
+            delayed_interrupt_disable: None,
         }
     }
 }
@@ -811,6 +1780,24 @@ impl Cpu {
             program_counter,
             status,
             stack_pointer,
+            cycles: 0,
+            variant: CpuVariant::default(),
+            unstable_opcodes: UnstableOpcodeModel::default(),
+            illegal_opcode_policy: IllegalOpcodePolicy::default(),
+            io_port: IoPort::default(),
+            state: CpuState::Running,
+            bus_accurate: false,
+            emulation: true,
+            accumulator_b: 0,
+            register_x_high: 0,
+            register_y_high: 0,
+            stack_pointer_high: (STACK_START >> 8) as u8,
+            direct_page: 0,
+            data_bank: 0,
+            program_bank: 0,
+            nmi_pending: false,
+            irq_line: false,
+            delayed_interrupt_disable: None,
         }
     }
 
@@ -821,6 +1808,10 @@ impl Cpu {
         self.status = 0;
     }
 
+    fn is_bus_accurate(&self) -> bool {
+        self.bus_accurate && self.variant != CpuVariant::Wdc65C816
+    }
+
     pub fn has_flag(&self, flag: &StatusFlag) -> bool {
         (self.status & flag.bit_shift()) != 0
     }
@@ -830,7 +1821,7 @@ impl Cpu {
     }
 
     pub fn disable_flag(&mut self, flag: &StatusFlag) {
-        self.status ^= flag.bit_shift();
+        self.status &= !flag.bit_shift();
     }
 
     pub fn update_flag(&mut self, flag: &StatusFlag, is_enable: bool) {
@@ -845,71 +1836,410 @@ impl Cpu {
         self.update_flag(&StatusFlag::Zero, value == 0);
         self.update_flag(&StatusFlag::Negative, value >> 7 == 1);
     }
-}
 
-#[derive(EnumIter, Debug)]
-pub enum StatusFlag {
-    Carry,
-    Zero,
-    Interrupt,
-    Decimal,
-    Break,
-    Constant,
-    Overflow,
-    Negative,
-}
+    fn is_decimal_mode(&self) -> bool {
+        self.variant.has_decimal_mode() && self.has_flag(&StatusFlag::Decimal)
+    }
 
-impl StatusFlag {
-    pub fn bit_shift(&self) -> u8 {
-        match self {
-            StatusFlag::Carry => 0x01,
-            StatusFlag::Zero => 0x02,
-            StatusFlag::Interrupt => 0x04,
-            StatusFlag::Decimal => 0x08,
-            StatusFlag::Break => 0x10,
-            StatusFlag::Constant => 0x20,
-            StatusFlag::Overflow => 0x40,
-            StatusFlag::Negative => 0x80,
-        }
+    pub fn compare(&mut self, register: u8, value: u8) {
+        self.update_flag(&StatusFlag::Carry, register >= value);
+        self.update_zero_and_negative_flags(register.wrapping_sub(value));
     }
-}
 
-/// Instructions need operands to work on.
-/// Addressing modes are various ways that indicate where
-/// the processor should receive these operands.
-///
-/// The list includes only modes that are common to many instructions.
-///
-/// Other modes are specific to specific instructions, namely:
-/// - Implicit: In this mode the operand's value is given in the instruction itself;
-/// - Accumulator: In this mode the instruction operates on data in the
-/// accumulator, so no operands are needed;
-/// - Relative: This mode is used with Branch-on-Condition instructions.
-/// - Indirect: This mode applies only to the JMP instruction - JuMP to new location.
-pub enum AddressingMode {
-    Implied,
-    Accumulator,
-    Immediate,
-    Relative,
-    ZeroPage,
-    ZeroPageX,
-    ZeroPageY,
-    Absolute,
-    AbsoluteX,
-    AbsoluteY,
-    Indirect,
-    IndexedIndirectX,
-    IndirectIndexedY,
-}
+    pub fn shift_left(&mut self, value: u8) -> u8 {
+        self.update_flag(&StatusFlag::Carry, value >> 7 == 1);
 
-#[cfg(test)]
-mod nes_test {
-    use super::{Cpu, Nes, StatusFlag};
-    use std::slice;
-    use strum::IntoEnumIterator;
+        value << 1
+    }
 
-    #[test]
-    fn cpu_status_test() {
+    pub fn shift_right(&mut self, value: u8) -> u8 {
+        self.update_flag(&StatusFlag::Carry, value & 1 == 1);
+
+        value >> 1
+    }
+
+    pub fn rotate_left(&mut self, value: u8) -> u8 {
+        let carry = self.has_flag(&StatusFlag::Carry) as u8;
+
+        self.update_flag(&StatusFlag::Carry, value >> 7 == 1);
+
+        (value << 1) | carry
+    }
+
+    pub fn rotate_right(&mut self, value: u8) -> u8 {
+        let carry = self.has_flag(&StatusFlag::Carry) as u8;
+
+        self.update_flag(&StatusFlag::Carry, value & 1 == 1);
+
+        (value >> 1) | (carry << 7)
+    }
+
+    /// Requests a non-maskable interrupt. NMI is edge-triggered,
+    /// so it is serviced once before the next instruction.
+    pub fn trigger_nmi(&mut self) {
+        self.nmi_pending = true;
+    }
+
+    /// Drives the level-triggered IRQ line. The interrupt is serviced
+    /// for as long as the line is held and the I flag is clear.
+    pub fn set_irq_line(&mut self, is_active: bool) {
+        self.irq_line = is_active;
+    }
+
+    /// Address of the next instruction including the 65C816 program bank
+    pub fn program_address(&self) -> u32 {
+        ((self.program_bank as u32) << 16) | self.program_counter as u32
+    }
+
+    pub fn stack_pointer_16(&self) -> u16 {
+        if self.emulation {
+            STACK_START | self.stack_pointer as u16
+        } else {
+            u16::from_le_bytes([self.stack_pointer, self.stack_pointer_high])
+        }
+    }
+
+    fn set_stack_pointer_16(&mut self, value: u16) {
+        let [low, high] = value.to_le_bytes();
+
+        self.stack_pointer = low;
+
+        if !self.emulation {
+            self.stack_pointer_high = high;
+        }
+    }
+
+    /// Switches the 65C816 between the emulation and the native mode. Both ways the
+    /// registers come out 8-bit, and emulation mode moves the stack back to page 1.
+    pub fn set_emulation_mode(&mut self, emulation: bool) {
+        self.emulation = emulation;
+        self.status |= StatusFlag::MemorySelect.bit_shift() | StatusFlag::IndexSelect.bit_shift();
+        self.register_x_high = 0;
+        self.register_y_high = 0;
+
+        if emulation {
+            self.stack_pointer_high = (STACK_START >> 8) as u8;
+        }
+    }
+
+    /// The 16-bit C accumulator of the 65C816: B in the high byte and A in the low byte
+    pub fn accumulator_16(&self) -> u16 {
+        u16::from_le_bytes([self.accumulator, self.accumulator_b])
+    }
+
+    pub fn set_accumulator_16(&mut self, value: u16) {
+        [self.accumulator, self.accumulator_b] = value.to_le_bytes();
+    }
+
+    pub fn register_x_16(&self) -> u16 {
+        u16::from_le_bytes([self.register_x, self.register_x_high])
+    }
+
+    pub fn register_y_16(&self) -> u16 {
+        u16::from_le_bytes([self.register_y, self.register_y_high])
+    }
+
+    /// The M flag selects a 16-bit accumulator and memory in 65C816 native mode
+    pub fn is_accumulator_16bit(&self) -> bool {
+        !self.emulation && !self.has_flag(&StatusFlag::MemorySelect)
+    }
+
+    /// The X flag selects 16-bit index registers in 65C816 native mode
+    pub fn is_index_16bit(&self) -> bool {
+        !self.emulation && !self.has_flag(&StatusFlag::IndexSelect)
+    }
+
+    // WAI is released by any interrupt request, even an IRQ masked by the I flag
+    fn has_interrupt_request(&self) -> bool {
+        self.nmi_pending || self.irq_line
+    }
+
+    // The Break flag only exists on the stack: it is set by BRK and PHP
+    // and cleared by NMI and IRQ. Bit 5 is always pushed as 1.
+    fn status_to_push(&self, has_break_flag: bool) -> u8 {
+        let mut status = self.status | StatusFlag::Constant.bit_shift();
+
+        if has_break_flag {
+            status |= StatusFlag::Break.bit_shift();
+        } else {
+            status &= !StatusFlag::Break.bit_shift();
+        }
+
+        status
+    }
+
+    fn set_status_from_stack(&mut self, status: u8) {
+        self.status = (status & !StatusFlag::Break.bit_shift()) | StatusFlag::Constant.bit_shift();
+    }
+}
+
+/// Whether the CPU is executing instructions
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum CpuState {
+    #[default]
+    Running,
+    /// Halted by WAI until an interrupt is requested
+    Waiting,
+    /// Halted by STP until the next reset
+    Stopped,
+}
+
+/// Members of the 6502 family that can run on this core
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum CpuVariant {
+    /// The original MOS 6502
+    #[default]
+    Nmos6502,
+    /// The NES CPU: an NMOS 6502 without decimal mode
+    Ricoh2A03,
+    /// The Atari 2600 CPU: an NMOS 6502 with only 13 address lines
+    Mos6507,
+    /// The Commodore 64 CPU: an NMOS 6502 with an I/O port at $00/$01
+    Mos6510,
+    /// The Rockwell R65C02: a 65C02 with the RMB, SMB, BBR and BBS bit operations
+    Rockwell65C02,
+    /// The WDC W65C02S: the Rockwell instruction set plus WAI and STP
+    Wdc65C02,
+    /// The WDC 65C816: a 65C02 with 16-bit registers and 24-bit addresses in native mode
+    Wdc65C816,
+}
+
+impl CpuVariant {
+    pub fn has_decimal_mode(&self) -> bool {
+        !matches!(self, CpuVariant::Ricoh2A03)
+    }
+
+    pub fn is_cmos(&self) -> bool {
+        matches!(
+            self,
+            CpuVariant::Rockwell65C02 | CpuVariant::Wdc65C02 | CpuVariant::Wdc65C816
+        )
+    }
+
+    /// Mask of the address lines that are connected to the bus
+    pub fn address_mask(&self) -> u16 {
+        match self {
+            CpuVariant::Mos6507 => 0x1FFF,
+            _ => 0xFFFF,
+        }
+    }
+}
+
+/// The bidirectional I/O port of the 6510. Each bit of `direction` selects
+/// whether the pin is driven by `output` (1) or read from `input` (0).
+#[derive(Debug, Clone, Copy)]
+pub struct IoPort {
+    pub direction: u8,
+    pub output: u8,
+    /// Levels applied to the pins by the outside world
+    pub input: u8,
+}
+
+impl Default for IoPort {
+    fn default() -> Self {
+        // All pins are inputs after reset and the pull-ups read as 1
+        Self {
+            direction: 0x00,
+            output: 0x00,
+            input: 0xFF,
+        }
+    }
+}
+
+impl IoPort {
+    pub fn read(&self) -> u8 {
+        (self.output & self.direction) | (self.input & !self.direction)
+    }
+}
+
+/// XAA, LXA, AHX, TAS, SHX and SHY depend on analog effects inside the chip,
+/// so their results differ between CPUs and even between production runs.
+#[derive(Debug, Clone, Copy)]
+pub struct UnstableOpcodeModel {
+    /// XAA computes `A = (A | magic) & X & #imm`
+    pub xaa_magic: u8,
+    /// LXA computes `A = X = (A | magic) & #imm`
+    pub lxa_magic: u8,
+    /// Whether AHX, TAS, SHX and SHY write to the page given by the stored
+    /// value instead of the indexed page when the indexing crosses a page
+    pub corrupt_address_on_page_cross: bool,
+}
+
+impl Default for UnstableOpcodeModel {
+    fn default() -> Self {
+        Self {
+            xaa_magic: 0xEE,
+            lxa_magic: 0xEE,
+            corrupt_address_on_page_cross: true,
+        }
+    }
+}
+
+/// How the CPU reacts to an illegal opcode. On the NMOS 6502 these are the
+/// JAM opcodes, the CMOS CPUs define every opcode.
+#[derive(Debug, Default, Clone, Copy)]
+pub enum IllegalOpcodePolicy {
+    /// Stop the CPU on the opcode like the real chip and report an error
+    #[default]
+    Halt,
+    /// Skip the opcode as a one-byte NOP
+    Nop,
+    /// Call a handler with the opcode, the program counter already points past it.
+    /// Execution continues unless the handler returns an error.
+    Callback(fn(&mut Cpu, u8) -> Result<(), CpuError>),
+}
+
+/// Errors raised while executing a program
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CpuError {
+    /// The CPU fetched an illegal opcode and was halted by the policy
+    IllegalOpcode { code: u8, address: u16 },
+    /// The addressing mode has no operand address, like Implied
+    UnsupportedAddressingMode(AddressingMode),
+}
+
+impl fmt::Display for CpuError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CpuError::IllegalOpcode { code, address } => {
+                write!(f, "illegal opcode {:02X} at {:04X}", code, address)
+            }
+            CpuError::UnsupportedAddressingMode(mode) => {
+                write!(f, "addressing mode {:?} has no operand address", mode)
+            }
+        }
+    }
+}
+
+impl error::Error for CpuError {}
+
+/// Events that suspend the program and transfer control to a handler
+/// whose address is stored in one of the vectors at the top of memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interrupt {
+    Nmi,
+    Irq,
+    Brk,
+    /// The 65C816 co-processor software interrupt
+    Cop,
+}
+
+impl Interrupt {
+    pub fn vector(&self) -> u16 {
+        match self {
+            Interrupt::Nmi => 0xFFFA,
+            Interrupt::Irq | Interrupt::Brk => 0xFFFE,
+            Interrupt::Cop => 0xFFF4,
+        }
+    }
+
+    /// Vectors used by the 65C816 in native mode
+    pub fn native_vector(&self) -> u16 {
+        match self {
+            Interrupt::Nmi => 0xFFEA,
+            Interrupt::Irq => 0xFFEE,
+            Interrupt::Brk => 0xFFE6,
+            Interrupt::Cop => 0xFFE4,
+        }
+    }
+
+    pub fn sets_break_flag(&self) -> bool {
+        matches!(self, Interrupt::Brk)
+    }
+}
+
+#[derive(EnumIter, Debug)]
+pub enum StatusFlag {
+    Carry,
+    Zero,
+    Interrupt,
+    Decimal,
+    Break,
+    Constant,
+    Overflow,
+    Negative,
+    /// 65C816 native mode X flag in place of Break: index registers are 8-bit when set
+    IndexSelect,
+    /// 65C816 native mode M flag in place of bit 5: the accumulator is 8-bit when set
+    MemorySelect,
+}
+
+impl StatusFlag {
+    pub const fn bit_shift(&self) -> u8 {
+        match self {
+            StatusFlag::Carry => 0x01,
+            StatusFlag::Zero => 0x02,
+            StatusFlag::Interrupt => 0x04,
+            StatusFlag::Decimal => 0x08,
+            StatusFlag::Break => 0x10,
+            StatusFlag::Constant => 0x20,
+            StatusFlag::Overflow => 0x40,
+            StatusFlag::Negative => 0x80,
+            StatusFlag::IndexSelect => 0x10,
+            StatusFlag::MemorySelect => 0x20,
+        }
+    }
+}
+
+/// Instructions need operands to work on.
+/// Addressing modes are various ways that indicate where
+/// the processor should receive these operands.
+///
+/// The list includes only modes that are common to many instructions.
+///
+/// Other modes are specific to specific instructions, namely:
+/// - Implicit: In this mode the operand's value is given in the instruction itself;
+/// - Accumulator: In this mode the instruction operates on data in the
+///   accumulator, so no operands are needed;
+/// - Relative: This mode is used with Branch-on-Condition instructions.
+/// - Indirect: This mode applies only to the JMP instruction - JuMP to new location.
+///
+/// The 65C02 adds the Zero Page Indirect mode `(zp)` for the accumulator
+/// operations and the Absolute Indexed Indirect mode `(abs,X)` for JMP.
+/// BBR and BBS use Zero Page Relative: a zero page address to test followed
+/// by a branch offset.
+///
+/// On the 65C816 the zero page modes are relative to the direct page register
+/// and 24-bit addresses bring the long modes `long`, `long,X`, `[dp]`, `[dp],Y`
+/// and `[abs]`, the stack relative modes `sr,S` and `(sr,S),Y`, the 16-bit
+/// branch offset of BRL and PER and the two bank operands of the block moves.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressingMode {
+    Implied,
+    Accumulator,
+    Immediate,
+    Relative,
+    ZeroPage,
+    ZeroPageX,
+    ZeroPageY,
+    Absolute,
+    AbsoluteX,
+    AbsoluteY,
+    Indirect,
+    IndexedIndirectX,
+    IndirectIndexedY,
+    ZeroPageIndirect,
+    AbsoluteIndexedIndirect,
+    ZeroPageRelative,
+    AbsoluteLong,
+    AbsoluteLongX,
+    ZeroPageIndirectLong,
+    ZeroPageIndirectLongY,
+    AbsoluteIndirectLong,
+    StackRelative,
+    StackRelativeIndirectY,
+    RelativeLong,
+    BlockMove,
+}
+
+#[cfg(test)]
+mod nes_test {
+    use super::{Cpu, Easy6502, StatusFlag};
+    use strum::IntoEnumIterator;
+
+    #[test]
+    #[allow(clippy::field_reassign_with_default)]
+    fn cpu_status_test() {
         for case in StatusFlag::iter() {
             let mut cpu = Cpu::default();
             cpu.status = 0b00000000;
@@ -925,48 +2255,18 @@ mod nes_test {
         }
     }
 
-    #[test]
-    fn load_to_rom_from_bytes_test() {
-        let mut nes = Nes::default();
-
-        // Check that the default memory is empty
-        assert_eq!(nes.memory, [0; 0xFFFF]);
-
-        // Simulation of game data
-        const TEST_ROM_SIZE: usize = 0x0700;
-        let test_rom = [0x08; TEST_ROM_SIZE];
-
-        // Load catridge data to ROM
-        // ROM is in the range 0x8000..0xFFFF
-        nes.load_rom_from_bytes(&test_rom);
-
-        // Check the range in memory to which data is being loaded
-        assert_eq!(
-            nes.memory[0x8000..0x8000 + TEST_ROM_SIZE],
-            test_rom,
-            "The data in the ROM was loaded incorrectly"
-        );
-
-        // Check the range that should have remained untouched
-        assert_eq!(
-            nes.memory[0..0x7FFF],
-            [0; 0x7FFF],
-            "The first 32 KiB should be empty"
-        );
-    }
-
     #[test]
     fn mem_write_read_8_test() {
         const ADDRESS: usize = 0x00FF;
         const VALUE: u8 = 0x1F;
 
-        let mut nes = Nes::default();
+        let mut nes = Easy6502::default();
 
-        assert_eq!(nes.memory[ADDRESS], 0);
+        assert_eq!(nes.bus.memory[ADDRESS], 0);
 
         nes.mem_write_8(ADDRESS as u16, VALUE);
 
-        assert_eq!(nes.memory[ADDRESS], VALUE);
+        assert_eq!(nes.bus.memory[ADDRESS], VALUE);
     }
 
     #[test]
@@ -974,11 +2274,11 @@ mod nes_test {
         const ADDRESS: usize = 0x00FF;
         const VALUE: u8 = 0x1F;
 
-        let mut nes = Nes::default();
+        let mut nes = Easy6502::default();
 
-        assert_eq!(nes.memory[ADDRESS], 0);
+        assert_eq!(nes.bus.memory[ADDRESS], 0);
 
-        nes.memory[ADDRESS] = VALUE;
+        nes.bus.memory[ADDRESS] = VALUE;
 
         assert_eq!(nes.mem_read_8(ADDRESS as u16), VALUE);
     }
@@ -988,17 +2288,17 @@ mod nes_test {
         const ADDRESS: usize = 0xFF1F;
         const VALUE: u16 = 0x7F1F;
 
-        let mut nes = Nes::default();
+        let mut nes = Easy6502::default();
 
-        assert_eq!(nes.memory[ADDRESS], 0);
-        assert_eq!(nes.memory[ADDRESS + 1], 0);
+        assert_eq!(nes.bus.memory[ADDRESS], 0);
+        assert_eq!(nes.bus.memory[ADDRESS + 1], 0);
 
         nes.mem_write_16(ADDRESS as u16, VALUE);
 
         let [high, low] = VALUE.to_be_bytes();
 
-        assert_eq!(nes.memory[ADDRESS], low);
-        assert_eq!(nes.memory[ADDRESS + 1], high);
+        assert_eq!(nes.bus.memory[ADDRESS], low);
+        assert_eq!(nes.bus.memory[ADDRESS + 1], high);
     }
 
     #[test]
@@ -1007,15 +2307,15 @@ mod nes_test {
         const VALUE_HIGH: u8 = 0x23;
         const VALUE_LOW: u8 = 0x1F;
 
-        let mut nes = Nes::default();
+        let mut nes = Easy6502::default();
 
-        assert_eq!(nes.memory[ADDRESS], 0);
-        assert_eq!(nes.memory[ADDRESS + 1], 0);
+        assert_eq!(nes.bus.memory[ADDRESS], 0);
+        assert_eq!(nes.bus.memory[ADDRESS + 1], 0);
 
         // Little endian: the 8 least significant bits of an address will be stored
         // before the 8 most significant bits
-        nes.memory[ADDRESS] = VALUE_LOW;
-        nes.memory[ADDRESS + 1] = VALUE_HIGH;
+        nes.bus.memory[ADDRESS] = VALUE_LOW;
+        nes.bus.memory[ADDRESS + 1] = VALUE_HIGH;
 
         let data = nes.mem_read_16(ADDRESS as u16);
         let [low, high] = data.to_le_bytes();
@@ -1027,31 +2327,40 @@ mod nes_test {
 
 #[cfg(test)]
 mod addressing_mode_tests {
-    use crate::{cpu::Cpu, instructions::OpCode};
+    use crate::cpu::Cpu;
 
-    use super::{AddressingMode, Nes};
+    use super::{AddressingMode, CpuError, Easy6502};
 
     #[test]
     fn addr_mode_accumulator_test() {
-        todo!("Implement Accumulator addr. mode");
+        let mut nes = Easy6502::default();
+
+        nes.load_instructions(vec![
+            0xA9, 0x41, // LDA #$41
+            0x0A, // ASL A
+        ]);
+        nes.run_with_reset_pc(true).unwrap();
+
+        assert_eq!(nes.cpu.accumulator, 0x82);
+        assert_eq!(nes.mem_read_8(0x41), 0x00, "ASL A must not touch memory");
     }
 
     #[test]
     fn addr_mode_immediate_test() {
-        let mut nes = Nes::default();
+        let mut nes = Easy6502::default();
         let program_counter = 0xA080;
 
         nes.set_program_counter(program_counter);
 
         assert_eq!(
-            nes.get_operand_address(&AddressingMode::Immediate),
+            nes.get_operand_address(&AddressingMode::Immediate).unwrap(),
             program_counter
         );
     }
 
     #[test]
     fn addr_mode_absolute_test() {
-        let mut nes = Nes::default();
+        let mut nes = Easy6502::default();
         let program_counter = 0xA123;
         let expected_result = 0xF1;
 
@@ -1059,14 +2368,14 @@ mod addressing_mode_tests {
         nes.mem_write_8(program_counter, expected_result);
 
         assert_eq!(
-            nes.get_operand_address(&AddressingMode::Absolute),
+            nes.get_operand_address(&AddressingMode::Absolute).unwrap(),
             expected_result as u16
         );
     }
 
     #[test]
     fn addr_mode_zero_page_test() {
-        let mut nes = Nes::default();
+        let mut nes = Easy6502::default();
         let program_counter = 0x8001;
         let rom_data = 0x05;
         let expected_result = 0x43;
@@ -1075,7 +2384,7 @@ mod addressing_mode_tests {
         nes.mem_write_8(program_counter, rom_data);
         nes.mem_write_8(rom_data as u16, expected_result);
 
-        let result = nes.mem_read_8(nes.get_operand_address(&AddressingMode::ZeroPage));
+        let result = nes.mem_read_8(nes.get_operand_address(&AddressingMode::ZeroPage).unwrap());
 
         assert_eq!(result, expected_result);
     }
@@ -1084,14 +2393,14 @@ mod addressing_mode_tests {
     fn addr_mode_zero_page_x_test() {
         let register_x = 0x02;
         let cpu = Cpu::new(0x0, register_x, 0x0, 0x8001, 0x0, 0x0);
-        let mut nes = Nes::new(cpu);
+        let mut nes = Easy6502::new(cpu);
         let rom_data = 0x05;
         let expected_result = 0x43;
 
         nes.mem_write_8(nes.cpu.program_counter, rom_data);
         nes.mem_write_8(rom_data.wrapping_add(register_x) as u16, expected_result);
 
-        let result = nes.mem_read_8(nes.get_operand_address(&AddressingMode::ZeroPageX));
+        let result = nes.mem_read_8(nes.get_operand_address(&AddressingMode::ZeroPageX).unwrap());
 
         assert_eq!(result, expected_result);
     }
@@ -1100,14 +2409,14 @@ mod addressing_mode_tests {
     fn addr_mode_zero_page_y_test() {
         let register_y = 0x04;
         let cpu = Cpu::new(0x0, 0x0, register_y, 0x8001, 0x0, 0x0);
-        let mut nes = Nes::new(cpu);
+        let mut nes = Easy6502::new(cpu);
         let rom_data = 0x05;
         let expected_result = 0x43;
 
         nes.mem_write_8(nes.cpu.program_counter, rom_data);
         nes.mem_write_8(rom_data.wrapping_add(register_y) as u16, expected_result);
 
-        let result = nes.mem_read_8(nes.get_operand_address(&AddressingMode::ZeroPageY));
+        let result = nes.mem_read_8(nes.get_operand_address(&AddressingMode::ZeroPageY).unwrap());
 
         assert_eq!(result, expected_result);
     }
@@ -1116,14 +2425,14 @@ mod addressing_mode_tests {
     fn addr_mode_absolute_x_test() {
         let register_x = 0x01;
         let cpu = Cpu::new(0x0, register_x, 0x0, 0x8001, 0x0, 0x0);
-        let mut nes = Nes::new(cpu);
+        let mut nes = Easy6502::new(cpu);
         let rom_data: u16 = 0x0200;
         let expected_result = 0x43;
 
         nes.mem_write_16(nes.cpu.program_counter, rom_data);
         nes.mem_write_8(rom_data.wrapping_add(register_x as u16), expected_result);
 
-        let result = nes.mem_read_8(nes.get_operand_address(&AddressingMode::AbsoluteX));
+        let result = nes.mem_read_8(nes.get_operand_address(&AddressingMode::AbsoluteX).unwrap());
 
         assert_eq!(result, expected_result);
     }
@@ -1132,21 +2441,41 @@ mod addressing_mode_tests {
     fn addr_mode_absolute_y_test() {
         let register_y = 0x04;
         let cpu = Cpu::new(0x0, 0x0, register_y, 0x8001, 0x0, 0x0);
-        let mut nes = Nes::new(cpu);
+        let mut nes = Easy6502::new(cpu);
         let rom_data: u16 = 0x0200;
         let expected_resukt = 0x43;
 
         nes.mem_write_16(nes.cpu.program_counter, rom_data);
         nes.mem_write_8(rom_data.wrapping_add(register_y as u16), expected_resukt);
 
-        let result = nes.mem_read_8(nes.get_operand_address(&AddressingMode::AbsoluteY));
+        let result = nes.mem_read_8(nes.get_operand_address(&AddressingMode::AbsoluteY).unwrap());
 
         assert_eq!(result, expected_resukt);
     }
 
     #[test]
     fn addr_mode_indirect_test() {
-        todo!("Implement Indirect addr. mode");
+        let mut nes = Easy6502::default();
+        let program_counter = 0x8001;
+        let pointer: u16 = 0x0120;
+
+        nes.set_program_counter(program_counter);
+        nes.mem_write_16(program_counter, pointer);
+
+        assert_eq!(
+            nes.get_operand_address(&AddressingMode::Indirect).unwrap(),
+            pointer
+        );
+    }
+
+    #[test]
+    fn addr_mode_unsupported_test() {
+        let nes = Easy6502::default();
+
+        assert_eq!(
+            nes.get_operand_address(&AddressingMode::Implied),
+            Err(CpuError::UnsupportedAddressingMode(AddressingMode::Implied))
+        );
     }
 
     #[test]
@@ -1154,7 +2483,7 @@ mod addressing_mode_tests {
         let register_x = 0x01;
         let program_counter = 0x8001;
         let cpu = Cpu::new(0x0, register_x, 0x0, program_counter, 0x0, 0x0);
-        let mut nes = Nes::new(cpu);
+        let mut nes = Easy6502::new(cpu);
         let rom_data = 0x05;
         let stored_address = 0x0705;
         let expected_result = 0x1A;
@@ -1166,7 +2495,10 @@ mod addressing_mode_tests {
         );
         nes.mem_write_8(stored_address, expected_result);
 
-        let result = nes.mem_read_8(nes.get_operand_address(&AddressingMode::IndexedIndirectX));
+        let result = nes.mem_read_8(
+            nes.get_operand_address(&AddressingMode::IndexedIndirectX)
+                .unwrap(),
+        );
 
         assert_eq!(result, expected_result);
     }
@@ -1176,7 +2508,7 @@ mod addressing_mode_tests {
         let register_y = 0x02;
         let program_counter = 0x8001;
         let cpu = Cpu::new(0x0, 0x0, register_y, program_counter, 0x0, 0x0);
-        let mut nes = Nes::new(cpu);
+        let mut nes = Easy6502::new(cpu);
         let rom_data = 0x05;
         let stored_address = 0x0703;
         let expected_result = 0x1A;
@@ -1188,26 +2520,29 @@ mod addressing_mode_tests {
             expected_result,
         );
 
-        let result = nes.mem_read_8(nes.get_operand_address(&AddressingMode::IndirectIndexedY));
+        let result = nes.mem_read_8(
+            nes.get_operand_address(&AddressingMode::IndirectIndexedY)
+                .unwrap(),
+        );
 
         assert_eq!(result, expected_result);
     }
 
     #[test]
     fn lda_immediate_test() {
-        let mut nes = Nes::default();
+        let mut nes = Easy6502::default();
 
         nes.mem_write_8(nes.cpu.program_counter + 1, 0x80);
 
         nes.load_instructions(vec![0xA9]);
-        nes.run_with_reset_pc(true);
+        nes.run_with_reset_pc(true).unwrap();
 
         assert_eq!(nes.cpu.accumulator, 0x80);
     }
 
     #[test]
     fn load_to_and_store_to_zero_page_test() {
-        let mut nes = Nes::default();
+        let mut nes = Easy6502::default();
 
         // 0080: F1, F2, F3, 00,  00
         nes.mem_write_8(0x80, 0xF1);
@@ -1223,7 +2558,7 @@ mod addressing_mode_tests {
             0x84, 0x22, // STY $22
         ]);
 
-        nes.run_with_reset_pc(true);
+        nes.run_with_reset_pc(true).unwrap();
 
         let sta_result = nes.mem_read_8(0x20);
         let stx_result = nes.mem_read_8(0x21);
@@ -1234,3 +2569,1150 @@ mod addressing_mode_tests {
         assert_eq!(sty_result, 0xF3);
     }
 }
+
+#[cfg(test)]
+mod cycle_tests {
+    use super::Easy6502;
+
+    const TRAP_CYCLES: u64 = 3;
+
+    // Runs the program followed by a `JMP *` trap and returns
+    // the number of cycles spent before reaching the trap
+    fn count_cycles(nes: &mut Easy6502, mut program: Vec<u8>) -> u64 {
+        let [low, high] = (0x0600 + program.len() as u16).to_le_bytes();
+
+        program.extend([0x4C, low, high]);
+
+        nes.load_instructions(program);
+        nes.run_with_reset_pc(true).unwrap();
+
+        nes.cpu.cycles - TRAP_CYCLES
+    }
+
+    #[test]
+    fn base_cycles_test() {
+        let mut nes = Easy6502::default();
+
+        let cycles = count_cycles(
+            &mut nes,
+            vec![
+                0xA9, 0x01, // LDA #$01 (2)
+                0x85, 0x10, // STA $10 (3)
+                0xEE, 0x00, 0x02, // INC $0200 (6)
+            ],
+        );
+
+        assert_eq!(cycles, 11);
+    }
+
+    #[test]
+    fn page_cross_penalty_test() {
+        let mut nes = Easy6502::default();
+
+        let cycles = count_cycles(
+            &mut nes,
+            vec![
+                0xA2, 0x01, // LDX #$01 (2)
+                0xBD, 0xFF, 0x02, // LDA $02FF,X (4 + 1)
+                0xBD, 0x00, 0x02, // LDA $0200,X (4)
+                0x9D, 0xFF, 0x02, // STA $02FF,X (5, no penalty for stores)
+            ],
+        );
+
+        assert_eq!(cycles, 16);
+    }
+
+    #[test]
+    fn indirect_indexed_page_cross_penalty_test() {
+        let mut nes = Easy6502::default();
+
+        nes.mem_write_16(0x10, 0x02F0);
+
+        let cycles = count_cycles(
+            &mut nes,
+            vec![
+                0xA0, 0x20, // LDY #$20 (2)
+                0xB1, 0x10, // LDA ($10),Y (5 + 1)
+            ],
+        );
+
+        assert_eq!(cycles, 8);
+    }
+
+    #[test]
+    fn branch_penalty_test() {
+        let mut nes = Easy6502::default();
+
+        let cycles = count_cycles(
+            &mut nes,
+            vec![
+                0xA9, 0x01, // LDA #$01 (2)
+                0xF0, 0x10, // BEQ +$10 (2, not taken)
+                0xD0, 0x00, // BNE +$00 (2 + 1, same page)
+            ],
+        );
+
+        assert_eq!(cycles, 7);
+        assert_eq!(nes.cpu.program_counter, 0x0606);
+    }
+
+    #[test]
+    fn branch_page_cross_penalty_test() {
+        let mut nes = Easy6502::default();
+
+        // JMP $05F4 trap at the branch target
+        nes.mem_write_8(0x05F4, 0x4C);
+        nes.mem_write_16(0x05F5, 0x05F4);
+
+        nes.load_instructions(vec![
+            0xA9, 0x00, // LDA #$00 (2)
+            0xF0, 0xF0, // BEQ -$10 (2 + 2, lands on $05F4)
+        ]);
+        nes.run_with_reset_pc(true).unwrap();
+
+        assert_eq!(nes.cpu.cycles - TRAP_CYCLES, 6);
+        assert_eq!(nes.cpu.program_counter, 0x05F4);
+    }
+}
+
+#[cfg(test)]
+mod bus_cycle_tests {
+    use super::{BusCycle, Cpu, CpuVariant, Easy6502};
+    use crate::instructions::{Instruction, OpCode};
+
+    fn bus_accurate_nes() -> Easy6502 {
+        let mut nes = Easy6502::new(Cpu {
+            bus_accurate: true,
+            ..Cpu::default()
+        });
+        nes.bus_trace = Some(Vec::new());
+        nes
+    }
+
+    // Executes the first instruction of the program and returns its bus cycles
+    fn trace_instruction(nes: &mut Easy6502, program: Vec<u8>) -> Vec<BusCycle> {
+        nes.load_instructions(program);
+        nes.step().unwrap();
+
+        nes.bus_trace.take().unwrap()
+    }
+
+    // Runs a single instruction with the given index registers and returns its cycles
+    fn instruction_cycles(variant: CpuVariant, bus_accurate: bool, code: u8, index: u8) -> u64 {
+        let mut nes = Easy6502::new(Cpu {
+            variant,
+            bus_accurate,
+            ..Cpu::default()
+        });
+
+        nes.mem_write_16(0x10, 0x0280);
+        nes.load_instructions(vec![code, 0x10, 0x02]);
+        nes.cpu.register_x = index;
+        nes.cpu.register_y = index;
+        nes.step().unwrap();
+
+        nes.cpu.cycles
+    }
+
+    #[test]
+    fn cycle_counts_match_opcode_table_test() {
+        for variant in [CpuVariant::Nmos6502, CpuVariant::Wdc65C02] {
+            for code in 0..=0xFF {
+                if let Instruction::Jam = OpCode::decode(code, &variant).instruction {
+                    continue;
+                }
+
+                for index in [0x00, 0xFF] {
+                    assert_eq!(
+                        instruction_cycles(variant, true, code, index),
+                        instruction_cycles(variant, false, code, index),
+                        "{:?} opcode {:02X} with index {:02X}",
+                        variant,
+                        code,
+                        index
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn page_cross_dummy_read_test() {
+        let mut nes = bus_accurate_nes();
+        nes.cpu.register_x = 0x20;
+
+        let trace = trace_instruction(&mut nes, vec![0xBD, 0xF0, 0x02]); // LDA $02F0,X
+
+        assert_eq!(
+            trace,
+            vec![
+                BusCycle::Read(0x0600, 0xBD),
+                BusCycle::Read(0x0601, 0xF0),
+                BusCycle::Read(0x0602, 0x02),
+                BusCycle::Read(0x0210, 0x00),
+                BusCycle::Read(0x0310, 0x00),
+            ]
+        );
+    }
+
+    #[test]
+    fn read_modify_write_double_write_test() {
+        let mut nes = bus_accurate_nes();
+        nes.mem_write_8(0x10, 0x05);
+
+        let trace = trace_instruction(&mut nes, vec![0xE6, 0x10]); // INC $10
+
+        assert_eq!(
+            trace,
+            vec![
+                BusCycle::Read(0x0600, 0xE6),
+                BusCycle::Read(0x0601, 0x10),
+                BusCycle::Read(0x0010, 0x05),
+                BusCycle::Write(0x0010, 0x05),
+                BusCycle::Write(0x0010, 0x06),
+            ]
+        );
+    }
+
+    #[test]
+    fn stack_dummy_read_test() {
+        let mut nes = bus_accurate_nes();
+        nes.mem_write_8(0x01FE, 0x42);
+
+        let trace = trace_instruction(&mut nes, vec![0x68]); // PLA
+
+        assert_eq!(
+            trace,
+            vec![
+                BusCycle::Read(0x0600, 0x68),
+                BusCycle::Read(0x0601, 0x00),
+                BusCycle::Read(0x01FD, 0x00),
+                BusCycle::Read(0x01FE, 0x42),
+            ]
+        );
+        assert_eq!(nes.cpu.accumulator, 0x42);
+    }
+}
+
+#[cfg(test)]
+mod step_tests {
+    use super::{AddressingMode, CpuVariant, Easy6502, Interrupt};
+    use crate::instructions::Instruction;
+
+    #[test]
+    fn step_result_test() {
+        let mut nes = Easy6502::default();
+        nes.cpu.register_x = 0x20;
+        nes.mem_write_8(0x0310, 0x42);
+        nes.load_instructions(vec![0xBD, 0xF0, 0x02]); // LDA $02F0,X
+
+        let step = nes.step().unwrap();
+        let opcode = step.opcode.unwrap();
+
+        assert_eq!(opcode.instruction, Instruction::Lda);
+        assert_eq!(opcode.address_mode, AddressingMode::AbsoluteX);
+        assert_eq!(step.program_counter, 0x0600);
+        assert_eq!(step.operand_address, Some(0x0310));
+        assert_eq!(step.cycles, 5);
+        assert!(!step.branch_taken);
+        assert_eq!(step.interrupt, None);
+        assert_eq!(nes.cpu.accumulator, 0x42);
+        assert_eq!(nes.cpu.program_counter, 0x0603);
+    }
+
+    #[test]
+    fn branch_step_test() {
+        let mut nes = Easy6502::default();
+        nes.load_instructions(vec![
+            0xD0, 0x02, // BNE +2
+            0xF0, 0x00, // (skipped)
+            0xF0, 0xFE, // BEQ *
+        ]);
+
+        let taken = nes.step().unwrap();
+        let not_taken = nes.step().unwrap();
+
+        assert!(taken.branch_taken);
+        assert_eq!(taken.operand_address, Some(0x0604));
+        assert_eq!(taken.cycles, 3);
+        assert!(!not_taken.branch_taken);
+        assert_eq!(not_taken.cycles, 2);
+    }
+
+    #[test]
+    fn interrupt_step_test() {
+        let mut nes = Easy6502::default();
+        nes.mem_write_16(0xFFFA, 0x0700);
+        nes.mem_write_8(0x0700, 0xEA); // NOP
+        nes.load_instructions(vec![0xEA]);
+
+        nes.cpu.trigger_nmi();
+        let step = nes.step().unwrap();
+
+        assert_eq!(step.interrupt, Some(Interrupt::Nmi));
+        assert_eq!(step.program_counter, 0x0700);
+        assert_eq!(step.cycles, 7 + 2);
+    }
+
+    #[test]
+    fn halted_step_test() {
+        let mut nes = Easy6502::with_variant(CpuVariant::Wdc65C02);
+        nes.load_instructions(vec![0xDB]); // STP
+
+        assert!(nes.step().unwrap().opcode.is_some());
+
+        let step = nes.step().unwrap();
+
+        assert_eq!(step.opcode, None);
+        assert_eq!(step.cycles, 0);
+    }
+
+    #[test]
+    fn address_space_wrap_step_test() {
+        let mut nes = Easy6502::default();
+        // LDA #$EA, whose operand is also a NOP
+        nes.mem_write_8(0xFFFE, 0xA9);
+        nes.mem_write_8(0xFFFF, 0xEA);
+
+        // The program counter wraps to $0000 after the operand, then after the opcode
+        nes.set_program_counter(0xFFFE);
+        nes.step().unwrap();
+        assert_eq!(nes.cpu.accumulator, 0xEA);
+        assert_eq!(nes.cpu.program_counter, 0x0000);
+
+        nes.set_program_counter(0xFFFF);
+        nes.step().unwrap();
+        assert_eq!(nes.cpu.program_counter, 0x0000);
+    }
+
+    #[test]
+    fn run_for_cycles_test() {
+        let mut nes = Easy6502::default();
+        nes.load_instructions(vec![
+            0xE8, // INX
+            0x4C, 0x00, 0x06, // JMP $0600
+        ]);
+
+        let cycles = nes.run_for_cycles(100).unwrap();
+
+        // Every loop takes 5 cycles
+        assert_eq!(cycles, 100);
+        assert_eq!(nes.cpu.register_x, 20);
+
+        // The last instruction is completed even if it overshoots
+        assert_eq!(nes.run_for_cycles(1).unwrap(), 2);
+    }
+
+    #[test]
+    fn run_until_test() {
+        let mut nes = Easy6502::default();
+        nes.load_instructions(vec![
+            0xE8, // INX
+            0x4C, 0x00, 0x06, // JMP $0600
+        ]);
+
+        let cycles = nes.run_until(|nes| nes.cpu.register_x == 3).unwrap();
+
+        assert_eq!(cycles, 2 + 3 + 2 + 3 + 2);
+        assert_eq!(nes.cpu.program_counter, 0x0601);
+    }
+}
+
+#[cfg(test)]
+mod interrupt_tests {
+    use super::{Easy6502, StatusFlag};
+
+    const HANDLER: u16 = 0x0700;
+
+    // Places a `JMP *` trap at the given address
+    fn write_trap(nes: &mut Easy6502, address: u16) {
+        nes.mem_write_8(address, 0x4C);
+        nes.mem_write_16(address + 1, address);
+    }
+
+    #[test]
+    fn brk_test() {
+        let mut nes = Easy6502::default();
+
+        nes.mem_write_16(0xFFFE, HANDLER);
+        write_trap(&mut nes, HANDLER);
+
+        nes.load_instructions(vec![
+            0x38, // SEC
+            0x00, 0xFF, // BRK + padding byte
+        ]);
+        nes.run_with_reset_pc(true).unwrap();
+
+        assert_eq!(nes.cpu.program_counter, HANDLER);
+        assert_eq!(nes.cpu.stack_pointer, 0xFA);
+        assert!(nes.cpu.has_flag(&StatusFlag::Interrupt));
+
+        // Return address skips the padding byte
+        assert_eq!(nes.mem_read_16(0x01FC), 0x0603);
+        // Carry | Interrupt | Break | Constant
+        assert_eq!(nes.mem_read_8(0x01FB), 0b0011_0101);
+    }
+
+    #[test]
+    fn rti_test() {
+        let mut nes = Easy6502::default();
+
+        nes.mem_write_16(0xFFFE, HANDLER);
+        nes.mem_write_8(HANDLER, 0x40); // RTI
+
+        nes.load_instructions(vec![
+            0x58, // CLI
+            0x00, 0xFF, // BRK + padding byte
+            0xA2, 0x42, // LDX #$42
+            0x4C, 0x05, 0x06, // JMP $0605
+        ]);
+        nes.run_with_reset_pc(true).unwrap();
+
+        assert_eq!(nes.cpu.register_x, 0x42);
+        assert_eq!(nes.cpu.stack_pointer, 0xFD);
+        // The I flag is restored and Break is not a real flag
+        assert!(!nes.cpu.has_flag(&StatusFlag::Interrupt));
+        assert!(!nes.cpu.has_flag(&StatusFlag::Break));
+    }
+
+    #[test]
+    fn nmi_test() {
+        let mut nes = Easy6502::default();
+
+        nes.mem_write_16(0xFFFA, HANDLER);
+        write_trap(&mut nes, HANDLER);
+        write_trap(&mut nes, 0x0600);
+
+        nes.cpu.trigger_nmi();
+        nes.run_with_reset_pc(true).unwrap();
+
+        assert_eq!(nes.cpu.program_counter, HANDLER);
+        assert_eq!(nes.mem_read_16(0x01FC), 0x0600);
+        // Interrupt | Constant, the Break flag is clear for hardware interrupts
+        assert_eq!(nes.mem_read_8(0x01FB), 0b0010_0100);
+        assert_eq!(nes.cpu.cycles, 7 + 3);
+    }
+
+    #[test]
+    fn irq_masked_test() {
+        let mut nes = Easy6502::default();
+
+        nes.mem_write_16(0xFFFE, HANDLER);
+        write_trap(&mut nes, HANDLER);
+        write_trap(&mut nes, 0x0600);
+
+        // The I flag is set after reset
+        nes.cpu.set_irq_line(true);
+        nes.run_with_reset_pc(true).unwrap();
+
+        assert_eq!(nes.cpu.program_counter, 0x0600);
+    }
+
+    #[test]
+    fn irq_after_cli_test() {
+        let mut nes = Easy6502::default();
+
+        nes.mem_write_16(0xFFFE, HANDLER);
+        nes.mem_write_8(HANDLER, 0x86); // STX $10
+        nes.mem_write_8(HANDLER + 1, 0x10);
+        write_trap(&mut nes, HANDLER + 2);
+
+        nes.load_instructions(vec![
+            0xA2, 0x01, // LDX #$01
+            0x58, // CLI
+            0xA2, 0x02, // LDX #$02
+            0xA2, 0x03, // LDX #$03
+        ]);
+
+        nes.cpu.set_irq_line(true);
+        nes.run_with_reset_pc(true).unwrap();
+
+        // The instruction following CLI runs before the interrupt is taken
+        assert_eq!(nes.mem_read_8(0x10), 0x02);
+        assert_eq!(nes.mem_read_16(0x01FC), 0x0605);
+    }
+
+    #[test]
+    fn jsr_rts_test() {
+        let mut nes = Easy6502::default();
+
+        nes.mem_write_8(HANDLER, 0xE8); // INX
+        nes.mem_write_8(HANDLER + 1, 0x60); // RTS
+
+        nes.load_instructions(vec![
+            0x20, 0x00, 0x07, // JSR $0700
+            0x20, 0x00, 0x07, // JSR $0700
+            0x4C, 0x06, 0x06, // JMP $0606
+        ]);
+        nes.run_with_reset_pc(true).unwrap();
+
+        assert_eq!(nes.cpu.register_x, 2);
+        assert_eq!(nes.cpu.stack_pointer, 0xFD);
+    }
+
+    #[test]
+    fn php_plp_test() {
+        let mut nes = Easy6502::default();
+
+        nes.load_instructions(vec![
+            0x08, // PHP
+            0x68, // PLA
+            0x09, 0x01, // ORA #$01
+            0x48, // PHA
+            0x28, // PLP
+        ]);
+        nes.run_with_reset_pc(true).unwrap();
+
+        // Break and bit 5 are set in the pushed copy only
+        assert_eq!(nes.cpu.accumulator, 0b0011_0101);
+        assert!(nes.cpu.has_flag(&StatusFlag::Carry));
+    }
+}
+
+#[cfg(test)]
+mod unofficial_opcode_tests {
+    use super::{CpuError, CpuState, Easy6502, IllegalOpcodePolicy, StatusFlag};
+
+    fn run_program(nes: &mut Easy6502, program: Vec<u8>) {
+        nes.load_instructions(program);
+        nes.run_with_reset_pc(true).unwrap();
+    }
+
+    #[test]
+    fn lax_sax_test() {
+        let mut nes = Easy6502::default();
+
+        nes.mem_write_8(0x10, 0xF3);
+
+        run_program(
+            &mut nes,
+            vec![
+                0xA7, 0x10, // LAX $10
+                0xA9, 0x3C, // LDA #$3C
+                0x87, 0x11, // SAX $11
+            ],
+        );
+
+        assert_eq!(nes.cpu.register_x, 0xF3);
+        assert_eq!(nes.mem_read_8(0x11), 0x30);
+    }
+
+    #[test]
+    fn read_modify_write_test() {
+        let mut nes = Easy6502::default();
+
+        nes.mem_write_8(0x10, 0x41);
+        nes.mem_write_8(0x11, 0x05);
+        nes.mem_write_8(0x12, 0x0F);
+
+        run_program(
+            &mut nes,
+            vec![
+                0xA9, 0x04, // LDA #$04
+                0x07, 0x10, // SLO $10
+                0x85, 0x20, // STA $20
+                0xA9, 0x04, // LDA #$04
+                0xC7, 0x11, // DCP $11
+                0x08, // PHP
+                0x38, // SEC
+                0xA9, 0x20, // LDA #$20
+                0xE7, 0x12, // ISC $12
+            ],
+        );
+
+        assert_eq!(nes.mem_read_8(0x10), 0x82);
+        assert_eq!(nes.mem_read_8(0x20), 0x86);
+
+        // DCP: $05 - 1 = $04 equals the accumulator
+        assert_eq!(nes.mem_read_8(0x11), 0x04);
+        assert_eq!(nes.mem_read_8(0x01FD) & 0b11, 0b11);
+
+        // ISC: $20 - $10
+        assert_eq!(nes.mem_read_8(0x12), 0x10);
+        assert_eq!(nes.cpu.accumulator, 0x10);
+    }
+
+    #[test]
+    fn immediate_test() {
+        let mut nes = Easy6502::default();
+
+        run_program(
+            &mut nes,
+            vec![
+                0xA9, 0xF0, // LDA #$F0
+                0x0B, 0x80, // ANC #$80
+                0x85, 0x10, // STA $10
+                0xA9, 0xFF, // LDA #$FF
+                0x4B, 0x0F, // ALR #$0F
+                0x85, 0x11, // STA $11
+                0xA9, 0xFF, // LDA #$FF
+                0xA2, 0x0F, // LDX #$0F
+                0xCB, 0x03, // SBX #$03
+            ],
+        );
+
+        assert_eq!(nes.mem_read_8(0x10), 0x80);
+        assert_eq!(nes.mem_read_8(0x11), 0x07);
+        assert_eq!(nes.cpu.register_x, 0x0C);
+        assert!(nes.cpu.has_flag(&StatusFlag::Carry));
+    }
+
+    #[test]
+    fn arr_test() {
+        let mut nes = Easy6502::default();
+
+        run_program(
+            &mut nes,
+            vec![
+                0x38, // SEC
+                0xA9, 0xC0, // LDA #$C0
+                0x6B, 0xFF, // ARR #$FF
+            ],
+        );
+
+        assert_eq!(nes.cpu.accumulator, 0xE0);
+        assert!(nes.cpu.has_flag(&StatusFlag::Carry));
+        assert!(!nes.cpu.has_flag(&StatusFlag::Overflow));
+        assert!(nes.cpu.has_flag(&StatusFlag::Negative));
+    }
+
+    #[test]
+    fn nop_page_cross_test() {
+        let mut nes = Easy6502::default();
+
+        run_program(
+            &mut nes,
+            vec![
+                0xA2, 0x01, // LDX #$01 (2)
+                0x1C, 0xFF, 0x02, // NOP $02FF,X (4 + 1)
+                0x80, 0xFF, // NOP #$FF (2)
+                0x1A, // NOP (2)
+                0x4C, 0x08, 0x06, // JMP $0608 (3)
+            ],
+        );
+
+        assert_eq!(nes.cpu.cycles, 14);
+        assert_eq!(nes.cpu.program_counter, 0x0608);
+    }
+
+    #[test]
+    fn jam_test() {
+        let mut nes = Easy6502::default();
+
+        nes.load_instructions(vec![
+            0xA9, 0x01, // LDA #$01
+            0x02, // JAM
+            0xA9, 0x02, // LDA #$02
+        ]);
+
+        assert_eq!(
+            nes.run_with_reset_pc(true),
+            Err(CpuError::IllegalOpcode {
+                code: 0x02,
+                address: 0x0602
+            })
+        );
+        assert_eq!(nes.cpu.accumulator, 0x01);
+        assert_eq!(nes.cpu.program_counter, 0x0602);
+        assert_eq!(nes.cpu.state, CpuState::Stopped);
+    }
+
+    #[test]
+    fn jam_nop_policy_test() {
+        let mut nes = Easy6502::default();
+
+        nes.cpu.illegal_opcode_policy = IllegalOpcodePolicy::Nop;
+
+        run_program(
+            &mut nes,
+            vec![
+                0x02, // JAM
+                0xA9, 0x02, // LDA #$02
+            ],
+        );
+
+        assert_eq!(nes.cpu.accumulator, 0x02);
+    }
+
+    #[test]
+    fn jam_callback_policy_test() {
+        let mut nes = Easy6502::default();
+
+        nes.cpu.illegal_opcode_policy = IllegalOpcodePolicy::Callback(|cpu, code| {
+            cpu.register_x = code;
+            Ok(())
+        });
+
+        run_program(
+            &mut nes,
+            vec![
+                0x12, // JAM
+                0xA9, 0x02, // LDA #$02
+            ],
+        );
+
+        assert_eq!(nes.cpu.register_x, 0x12);
+        assert_eq!(nes.cpu.accumulator, 0x02);
+    }
+
+    #[test]
+    fn xaa_magic_test() {
+        let mut nes = Easy6502::default();
+
+        nes.cpu.unstable_opcodes.xaa_magic = 0x00;
+
+        run_program(
+            &mut nes,
+            vec![
+                0xA9, 0x0F, // LDA #$0F
+                0xA2, 0xFF, // LDX #$FF
+                0x8B, 0xFF, // XAA #$FF
+            ],
+        );
+
+        assert_eq!(nes.cpu.accumulator, 0x0F);
+
+        nes.cpu.unstable_opcodes.xaa_magic = 0xFF;
+        nes.run_with_reset_pc(true).unwrap();
+
+        assert_eq!(nes.cpu.accumulator, 0xFF);
+    }
+
+    #[test]
+    fn shx_test() {
+        let mut nes = Easy6502::default();
+
+        run_program(
+            &mut nes,
+            vec![
+                0xA2, 0xFF, // LDX #$FF
+                0xA0, 0x01, // LDY #$01
+                0x9E, 0x00, 0x02, // SHX $0200,Y
+            ],
+        );
+
+        // $FF & ($02 + 1)
+        assert_eq!(nes.mem_read_8(0x0201), 0x03);
+    }
+
+    #[test]
+    fn shx_page_cross_test() {
+        let mut nes = Easy6502::default();
+
+        let program = vec![
+            0xA2, 0x05, // LDX #$05
+            0xA0, 0x02, // LDY #$02
+            0x9E, 0xFF, 0x02, // SHX $02FF,Y
+        ];
+
+        run_program(&mut nes, program.clone());
+
+        // $05 & ($02 + 1) = $01 replaces the high byte of $0301
+        assert_eq!(nes.mem_read_8(0x0101), 0x01);
+        assert_eq!(nes.mem_read_8(0x0301), 0x00);
+
+        let mut nes = Easy6502::default();
+
+        nes.cpu.unstable_opcodes.corrupt_address_on_page_cross = false;
+        run_program(&mut nes, program);
+
+        assert_eq!(nes.mem_read_8(0x0301), 0x01);
+    }
+}
+
+#[cfg(test)]
+mod decimal_mode_tests {
+    use super::{CpuVariant, Easy6502, StatusFlag};
+
+    // Runs SED, optionally SEC, LDA #a and the given operation with #b
+    fn run_decimal(nes: &mut Easy6502, code: u8, a: u8, b: u8, carry: bool) {
+        let carry_code = if carry { 0x38 } else { 0x18 };
+
+        nes.load_instructions(vec![0xF8, carry_code, 0xA9, a, code, b]);
+        nes.run_with_reset_pc(true).unwrap();
+    }
+
+    fn adc(nes: &mut Easy6502, a: u8, b: u8, carry: bool) {
+        run_decimal(nes, 0x69, a, b, carry);
+    }
+
+    fn sbc(nes: &mut Easy6502, a: u8, b: u8, carry: bool) {
+        run_decimal(nes, 0xE9, a, b, carry);
+    }
+
+    #[test]
+    fn adc_decimal_test() {
+        let mut nes = Easy6502::default();
+
+        adc(&mut nes, 0x15, 0x27, false);
+        assert_eq!(nes.cpu.accumulator, 0x42);
+        assert!(!nes.cpu.has_flag(&StatusFlag::Carry));
+
+        adc(&mut nes, 0x81, 0x92, false);
+        assert_eq!(nes.cpu.accumulator, 0x73);
+        assert!(nes.cpu.has_flag(&StatusFlag::Carry));
+
+        adc(&mut nes, 0x58, 0x46, true);
+        assert_eq!(nes.cpu.accumulator, 0x05);
+        assert!(nes.cpu.has_flag(&StatusFlag::Carry));
+    }
+
+    #[test]
+    fn adc_decimal_flags_test() {
+        let mut nes = Easy6502::default();
+
+        // Z comes from the binary sum $9A
+        adc(&mut nes, 0x99, 0x01, false);
+        assert_eq!(nes.cpu.accumulator, 0x00);
+        assert!(nes.cpu.has_flag(&StatusFlag::Carry));
+        assert!(!nes.cpu.has_flag(&StatusFlag::Zero));
+
+        // N and V come from the intermediate result
+        adc(&mut nes, 0x79, 0x00, true);
+        assert_eq!(nes.cpu.accumulator, 0x80);
+        assert!(nes.cpu.has_flag(&StatusFlag::Negative));
+        assert!(nes.cpu.has_flag(&StatusFlag::Overflow));
+    }
+
+    #[test]
+    fn sbc_decimal_test() {
+        let mut nes = Easy6502::default();
+
+        sbc(&mut nes, 0x42, 0x15, true);
+        assert_eq!(nes.cpu.accumulator, 0x27);
+        assert!(nes.cpu.has_flag(&StatusFlag::Carry));
+
+        sbc(&mut nes, 0x00, 0x01, true);
+        assert_eq!(nes.cpu.accumulator, 0x99);
+        assert!(!nes.cpu.has_flag(&StatusFlag::Carry));
+        assert!(nes.cpu.has_flag(&StatusFlag::Negative));
+
+        sbc(&mut nes, 0x32, 0x02, false);
+        assert_eq!(nes.cpu.accumulator, 0x29);
+        assert!(nes.cpu.has_flag(&StatusFlag::Carry));
+    }
+
+    #[test]
+    fn decimal_mode_disabled_test() {
+        let mut nes = Easy6502::with_variant(CpuVariant::Ricoh2A03);
+
+        adc(&mut nes, 0x15, 0x27, false);
+        assert_eq!(nes.cpu.accumulator, 0x3C);
+
+        sbc(&mut nes, 0x42, 0x15, true);
+        assert_eq!(nes.cpu.accumulator, 0x2D);
+    }
+
+    #[test]
+    fn arr_decimal_test() {
+        let mut nes = Easy6502::default();
+
+        run_decimal(&mut nes, 0x6B, 0xFF, 0xFF, false);
+
+        // $7F fixed up to $75 (low digit) and $D5 (high digit)
+        assert_eq!(nes.cpu.accumulator, 0xD5);
+        assert!(nes.cpu.has_flag(&StatusFlag::Carry));
+    }
+}
+
+#[cfg(test)]
+mod variant_tests {
+    use super::{CpuState, CpuVariant, Easy6502, StatusFlag};
+
+    fn run_program(nes: &mut Easy6502, program: Vec<u8>) {
+        nes.load_instructions(program);
+        nes.run_with_reset_pc(true).unwrap();
+    }
+
+    #[test]
+    fn mos_6507_address_bus_test() {
+        let mut nes = Easy6502::with_variant(CpuVariant::Mos6507);
+
+        // $F080 and $1080 select the same cell on a 13-bit bus
+        nes.mem_write_8(0xF080, 0x42);
+
+        assert_eq!(nes.bus.memory[0x1080], 0x42);
+        assert_eq!(nes.mem_read_8(0x3080), 0x42);
+    }
+
+    #[test]
+    fn mos_6507_fetch_test() {
+        let mut nes = Easy6502::with_variant(CpuVariant::Mos6507);
+
+        // A cartridge at $F000 is the same memory as $1000
+        nes.mem_write_8(0xF000, 0xA9); // LDA #$42
+        nes.mem_write_8(0xF001, 0x42);
+        nes.set_program_counter(0xF000);
+
+        nes.step().unwrap();
+
+        assert_eq!(nes.bus.memory[0x1000], 0xA9);
+        assert_eq!(nes.cpu.accumulator, 0x42);
+        assert_eq!(nes.cpu.program_counter, 0xF002);
+    }
+
+    #[test]
+    fn mos_6510_io_port_fetch_test() {
+        let mut nes = Easy6502::with_variant(CpuVariant::Mos6510);
+
+        // The port answers the fetches at $0000 and $0001, not the RAM beneath
+        nes.cpu.io_port.input = 0x00;
+        nes.mem_write_8(0x0000, 0xEA); // NOP
+        nes.mem_write_8(0x0001, 0xE8); // INX
+        nes.bus.memory[..2].copy_from_slice(&[0x00, 0x00]);
+        nes.set_program_counter(0x0000);
+
+        nes.step().unwrap();
+        nes.step().unwrap();
+
+        assert_eq!(nes.cpu.register_x, 1);
+        assert_eq!(nes.cpu.program_counter, 0x0002);
+    }
+
+    #[test]
+    fn mos_6510_io_port_test() {
+        let mut nes = Easy6502::with_variant(CpuVariant::Mos6510);
+
+        nes.cpu.io_port.input = 0b1010_0000;
+
+        run_program(
+            &mut nes,
+            vec![
+                0xA9, 0x0F, // LDA #$0F
+                0x85, 0x00, // STA $00
+                0xA9, 0x35, // LDA #$35
+                0x85, 0x01, // STA $01
+                0xA5, 0x01, // LDA $01
+                0x4C, 0x0A, 0x06, // JMP $060A
+            ],
+        );
+
+        // Output pins return the latch, input pins the external levels
+        assert_eq!(nes.cpu.accumulator, 0b1010_0101);
+        assert_eq!(nes.mem_read_8(0x00), 0x0F);
+        assert_eq!(nes.bus.memory[0x01], 0x35);
+    }
+
+    #[test]
+    fn nmos_jmp_indirect_bug_test() {
+        let program = vec![
+            0x6C, 0xFF, 0x02, // JMP ($02FF)
+        ];
+
+        let mut nes = Easy6502::default();
+
+        nes.mem_write_8(0x02FF, 0x00);
+        nes.mem_write_8(0x0300, 0x07);
+        nes.mem_write_8(0x0200, 0x08);
+
+        run_program(&mut nes, program.clone());
+        assert_eq!(nes.mem_read_16(0x01FC), 0x0802);
+
+        let mut nes = Easy6502::with_variant(CpuVariant::Wdc65C02);
+
+        nes.mem_write_8(0x02FF, 0x00);
+        nes.mem_write_8(0x0300, 0x07);
+        nes.mem_write_8(0x0200, 0x08);
+
+        run_program(&mut nes, program);
+        assert_eq!(nes.mem_read_16(0x01FC), 0x0702);
+    }
+
+    #[test]
+    fn ricoh_2a03_test() {
+        let mut nes = Easy6502::with_variant(CpuVariant::Ricoh2A03);
+
+        run_program(
+            &mut nes,
+            vec![
+                0xF8, // SED
+                0x18, // CLC
+                0xA9, 0x09, // LDA #$09
+                0x69, 0x01, // ADC #$01
+            ],
+        );
+
+        assert_eq!(nes.cpu.accumulator, 0x0A);
+    }
+
+    #[test]
+    fn cmos_stack_and_store_test() {
+        let mut nes = Easy6502::with_variant(CpuVariant::Wdc65C02);
+
+        nes.mem_write_8(0x10, 0xFF);
+
+        run_program(
+            &mut nes,
+            vec![
+                0xA2, 0x11, // LDX #$11
+                0xA0, 0x22, // LDY #$22
+                0xDA, // PHX
+                0x5A, // PHY
+                0xFA, // PLX
+                0x7A, // PLY
+                0x64, 0x10, // STZ $10
+                0x1A, // INC A
+                0x1A, // INC A
+                0x3A, // DEC A
+            ],
+        );
+
+        assert_eq!(nes.cpu.register_x, 0x22);
+        assert_eq!(nes.cpu.register_y, 0x11);
+        assert_eq!(nes.mem_read_8(0x10), 0x00);
+        assert_eq!(nes.cpu.accumulator, 0x01);
+    }
+
+    #[test]
+    fn cmos_bit_operations_test() {
+        let mut nes = Easy6502::with_variant(CpuVariant::Wdc65C02);
+
+        nes.mem_write_8(0x10, 0b1100_0011);
+        nes.mem_write_8(0x11, 0b1100_0011);
+        nes.mem_write_16(0x20, 0x0300);
+        nes.mem_write_8(0x0300, 0x5A);
+
+        run_program(
+            &mut nes,
+            vec![
+                0xA9, 0x0F, // LDA #$0F
+                0x14, 0x10, // TRB $10
+                0x04, 0x11, // TSB $11
+                0xB2, 0x20, // LDA ($20)
+                0x89, 0xA5, // BIT #$A5
+            ],
+        );
+
+        assert_eq!(nes.mem_read_8(0x10), 0b1100_0000);
+        assert_eq!(nes.mem_read_8(0x11), 0b1100_1111);
+        assert_eq!(nes.cpu.accumulator, 0x5A);
+        // BIT #imm only affects Z
+        assert!(nes.cpu.has_flag(&StatusFlag::Zero));
+        assert!(!nes.cpu.has_flag(&StatusFlag::Negative));
+    }
+
+    #[test]
+    fn cmos_branch_always_test() {
+        let mut nes = Easy6502::with_variant(CpuVariant::Wdc65C02);
+
+        run_program(
+            &mut nes,
+            vec![
+                0x80, 0x02, // BRA +2 (3)
+                0xA2, 0x01, // LDX #$01
+                0xA0, 0x01, // LDY #$01 (2)
+                0x7C, 0x00, 0x07, // JMP ($0700,X) (6)
+            ],
+        );
+
+        assert_eq!(nes.cpu.register_x, 0x00);
+        assert_eq!(nes.cpu.register_y, 0x01);
+    }
+
+    #[test]
+    fn cmos_decimal_mode_test() {
+        let mut nes = Easy6502::with_variant(CpuVariant::Wdc65C02);
+
+        run_program(
+            &mut nes,
+            vec![
+                0xF8, // SED (2)
+                0x18, // CLC (2)
+                0xA9, 0x99, // LDA #$99 (2)
+                0x69, 0x01, // ADC #$01 (2 + 1)
+                0x4C, 0x06, 0x06, // JMP $0606 (3)
+            ],
+        );
+
+        assert_eq!(nes.cpu.accumulator, 0x00);
+        assert!(nes.cpu.has_flag(&StatusFlag::Carry));
+        // Unlike the NMOS 6502, Z reflects the decimal result
+        assert!(nes.cpu.has_flag(&StatusFlag::Zero));
+        assert_eq!(nes.cpu.cycles, 12);
+    }
+
+    #[test]
+    fn rockwell_bit_operations_test() {
+        let mut nes = Easy6502::with_variant(CpuVariant::Rockwell65C02);
+
+        nes.mem_write_8(0x10, 0b0000_0001);
+
+        run_program(
+            &mut nes,
+            vec![
+                0xF7, 0x10, // SMB7 $10
+                0x07, 0x10, // RMB0 $10
+                0xFF, 0x10, 0x02, // BBS7 $10, +2
+                0xA2, 0x01, // LDX #$01
+                0x7F, 0x10, 0x02, // BBR7 $10, +2
+                0xA0, 0x01, // LDY #$01
+            ],
+        );
+
+        assert_eq!(nes.mem_read_8(0x10), 0b1000_0000);
+        assert_eq!(nes.cpu.register_x, 0x00);
+        assert_eq!(nes.cpu.register_y, 0x01);
+    }
+
+    #[test]
+    fn wai_test() {
+        let mut nes = Easy6502::with_variant(CpuVariant::Wdc65C02);
+
+        // NMI handler: JMP $0700
+        nes.mem_write_16(0xFFFA, 0x0700);
+        nes.mem_write_8(0x0700, 0x4C);
+        nes.mem_write_16(0x0701, 0x0700);
+
+        run_program(
+            &mut nes,
+            vec![
+                0xCB, // WAI
+                0xA9, 0x42, // LDA #$42
+                0x4C, 0x03, 0x06, // JMP $0603
+            ],
+        );
+
+        assert_eq!(nes.cpu.state, CpuState::Waiting);
+        assert_eq!(nes.cpu.program_counter, 0x0601);
+
+        nes.cpu.trigger_nmi();
+        nes.run().unwrap();
+
+        assert_eq!(nes.cpu.state, CpuState::Running);
+        assert_eq!(nes.cpu.program_counter, 0x0700);
+        assert_eq!(nes.mem_read_16(0x01FC), 0x0601);
+
+        // A masked IRQ releases WAI without being serviced
+        nes.reset();
+        nes.set_program_counter(0x0600);
+        nes.run().unwrap();
+        nes.cpu.set_irq_line(true);
+        nes.run().unwrap();
+
+        assert_eq!(nes.cpu.accumulator, 0x42);
+        assert_eq!(nes.cpu.program_counter, 0x0603);
+    }
+
+    #[test]
+    fn stp_test() {
+        let mut nes = Easy6502::with_variant(CpuVariant::Wdc65C02);
+
+        run_program(
+            &mut nes,
+            vec![
+                0xDB, // STP
+                0xA9, 0x42, // LDA #$42
+            ],
+        );
+
+        nes.cpu.trigger_nmi();
+        nes.run().unwrap();
+
+        assert_eq!(nes.cpu.state, CpuState::Stopped);
+        assert_eq!(nes.cpu.accumulator, 0x00);
+
+        nes.reset();
+
+        assert_eq!(nes.cpu.state, CpuState::Running);
+    }
+}
@@ -0,0 +1,1256 @@
+use super::{AddressingMode, Cpu, Interrupt, Machine, StatusFlag};
+use crate::bus::Bus;
+use crate::instructions::{Instruction, OpCode};
+
+/// Size of the accumulator, index registers and memory operands selected by the M and X flags
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Width {
+    Byte,
+    Word,
+}
+
+impl Width {
+    fn mask(self) -> u16 {
+        match self {
+            Width::Byte => 0x00FF,
+            Width::Word => 0xFFFF,
+        }
+    }
+
+    fn sign(self) -> u16 {
+        match self {
+            Width::Byte => 0x0080,
+            Width::Word => 0x8000,
+        }
+    }
+
+    fn digits(self) -> u32 {
+        match self {
+            Width::Byte => 2,
+            Width::Word => 4,
+        }
+    }
+
+    // 16-bit operands take one more byte, and so one more bus cycle
+    fn extra_bytes(self) -> u8 {
+        match self {
+            Width::Byte => 0,
+            Width::Word => 1,
+        }
+    }
+}
+
+impl<B: Bus> Machine<B> {
+    // Immediate operands of the 16-bit registers are one byte longer
+    pub(super) fn instruction_length(&self, opcode: &OpCode) -> u8 {
+        let width = match (&opcode.address_mode, &opcode.instruction) {
+            (
+                AddressingMode::Immediate,
+                Instruction::Lda
+                | Instruction::Adc
+                | Instruction::Sbc
+                | Instruction::And
+                | Instruction::Ora
+                | Instruction::Eor
+                | Instruction::Cmp
+                | Instruction::Bit,
+            ) => self.cpu.accumulator_width(),
+            (
+                AddressingMode::Immediate,
+                Instruction::Ldx | Instruction::Ldy | Instruction::Cpx | Instruction::Cpy,
+            ) => self.cpu.index_width(),
+            _ => Width::Byte,
+        };
+
+        opcode.bytes + width.extra_bytes()
+    }
+
+    pub(super) fn execute_65c816(&mut self, opcode: &OpCode) {
+        let mode = &opcode.address_mode;
+        let a = self.cpu.accumulator_width();
+        let x = self.cpu.index_width();
+
+        match opcode.instruction {
+            // Loads and stores
+            Instruction::Lda => {
+                let value = self.read_operand_65c816(mode, a);
+                self.cpu.set_accumulator_value(value);
+                self.cpu.update_zero_and_negative_flags_for(value, a);
+            }
+            Instruction::Ldx => {
+                let value = self.read_operand_65c816(mode, x);
+                self.cpu.set_register_x_value(value);
+                self.cpu.update_zero_and_negative_flags_for(value, x);
+            }
+            Instruction::Ldy => {
+                let value = self.read_operand_65c816(mode, x);
+                self.cpu.set_register_y_value(value);
+                self.cpu.update_zero_and_negative_flags_for(value, x);
+            }
+            Instruction::Sta => self.write_operand_65c816(mode, self.cpu.accumulator_value(), a),
+            Instruction::Stx => self.write_operand_65c816(mode, self.cpu.register_x_16(), x),
+            Instruction::Sty => self.write_operand_65c816(mode, self.cpu.register_y_16(), x),
+            Instruction::Stz => self.write_operand_65c816(mode, 0, a),
+
+            // Transfers, the width of the destination decides how much is copied
+            Instruction::Tax => {
+                let value = self.cpu.accumulator_16();
+                self.cpu.set_register_x_value(value);
+                self.cpu.update_zero_and_negative_flags_for(value, x);
+            }
+            Instruction::Tay => {
+                let value = self.cpu.accumulator_16();
+                self.cpu.set_register_y_value(value);
+                self.cpu.update_zero_and_negative_flags_for(value, x);
+            }
+            Instruction::Txa => {
+                let value = self.cpu.register_x_16();
+                self.cpu.set_accumulator_value(value);
+                self.cpu.update_zero_and_negative_flags_for(value, a);
+            }
+            Instruction::Tya => {
+                let value = self.cpu.register_y_16();
+                self.cpu.set_accumulator_value(value);
+                self.cpu.update_zero_and_negative_flags_for(value, a);
+            }
+            Instruction::Txy => {
+                let value = self.cpu.register_x_16();
+                self.cpu.set_register_y_value(value);
+                self.cpu.update_zero_and_negative_flags_for(value, x);
+            }
+            Instruction::Tyx => {
+                let value = self.cpu.register_y_16();
+                self.cpu.set_register_x_value(value);
+                self.cpu.update_zero_and_negative_flags_for(value, x);
+            }
+            Instruction::Tsx => {
+                let value = self.cpu.stack_pointer_16();
+                self.cpu.set_register_x_value(value);
+                self.cpu.update_zero_and_negative_flags_for(value, x);
+            }
+            Instruction::Txs => self.cpu.set_stack_pointer_16(self.cpu.register_x_16()),
+            Instruction::Tcs => self.cpu.set_stack_pointer_16(self.cpu.accumulator_16()),
+            Instruction::Tsc => {
+                let value = self.cpu.stack_pointer_16();
+                self.cpu.set_accumulator_16(value);
+                self.cpu
+                    .update_zero_and_negative_flags_for(value, Width::Word);
+            }
+            Instruction::Tcd => {
+                self.cpu.direct_page = self.cpu.accumulator_16();
+                self.cpu
+                    .update_zero_and_negative_flags_for(self.cpu.direct_page, Width::Word);
+            }
+            Instruction::Tdc => {
+                let value = self.cpu.direct_page;
+                self.cpu.set_accumulator_16(value);
+                self.cpu
+                    .update_zero_and_negative_flags_for(value, Width::Word);
+            }
+            Instruction::Xba => {
+                let value = self.cpu.accumulator_16().swap_bytes();
+                self.cpu.set_accumulator_16(value);
+                self.cpu
+                    .update_zero_and_negative_flags_for(value, Width::Byte);
+            }
+
+            // Arithmetic and logical operations
+            Instruction::Adc => {
+                let value = self.read_operand_65c816(mode, a);
+                self.add_with_carry_65c816(value, a, false);
+            }
+            Instruction::Sbc => {
+                let value = self.read_operand_65c816(mode, a);
+                self.add_with_carry_65c816(value, a, true);
+            }
+            Instruction::And => {
+                let value = self.read_operand_65c816(mode, a) & self.cpu.accumulator_value();
+                self.cpu.set_accumulator_value(value);
+                self.cpu.update_zero_and_negative_flags_for(value, a);
+            }
+            Instruction::Ora => {
+                let value = self.read_operand_65c816(mode, a) | self.cpu.accumulator_value();
+                self.cpu.set_accumulator_value(value);
+                self.cpu.update_zero_and_negative_flags_for(value, a);
+            }
+            Instruction::Eor => {
+                let value = self.read_operand_65c816(mode, a) ^ self.cpu.accumulator_value();
+                self.cpu.set_accumulator_value(value);
+                self.cpu.update_zero_and_negative_flags_for(value, a);
+            }
+            Instruction::Cmp => {
+                let value = self.read_operand_65c816(mode, a);
+                self.cpu
+                    .compare_sized(self.cpu.accumulator_value(), value, a);
+            }
+            Instruction::Cpx => {
+                let value = self.read_operand_65c816(mode, x);
+                self.cpu.compare_sized(self.cpu.register_x_16(), value, x);
+            }
+            Instruction::Cpy => {
+                let value = self.read_operand_65c816(mode, x);
+                self.cpu.compare_sized(self.cpu.register_y_16(), value, x);
+            }
+            // N and V come from the two top bits of the operand, except in immediate mode
+            Instruction::Bit => {
+                let value = self.read_operand_65c816(mode, a);
+
+                self.cpu
+                    .update_flag(&StatusFlag::Zero, value & self.cpu.accumulator_value() == 0);
+
+                if !matches!(mode, AddressingMode::Immediate) {
+                    self.cpu
+                        .update_flag(&StatusFlag::Negative, value & a.sign() != 0);
+                    self.cpu
+                        .update_flag(&StatusFlag::Overflow, value & (a.sign() >> 1) != 0);
+                }
+            }
+
+            // Increments, decrements, shifts and rotations
+            Instruction::Inc => {
+                self.modify_operand_65c816(mode, a, |cpu, value| {
+                    let result = value.wrapping_add(1) & a.mask();
+                    cpu.update_zero_and_negative_flags_for(result, a);
+
+                    result
+                });
+            }
+            Instruction::Dec => {
+                self.modify_operand_65c816(mode, a, |cpu, value| {
+                    let result = value.wrapping_sub(1) & a.mask();
+                    cpu.update_zero_and_negative_flags_for(result, a);
+
+                    result
+                });
+            }
+            Instruction::Inx => {
+                let value = self.cpu.register_x_16().wrapping_add(1) & x.mask();
+                self.cpu.set_register_x_value(value);
+                self.cpu.update_zero_and_negative_flags_for(value, x);
+            }
+            Instruction::Iny => {
+                let value = self.cpu.register_y_16().wrapping_add(1) & x.mask();
+                self.cpu.set_register_y_value(value);
+                self.cpu.update_zero_and_negative_flags_for(value, x);
+            }
+            Instruction::Dex => {
+                let value = self.cpu.register_x_16().wrapping_sub(1) & x.mask();
+                self.cpu.set_register_x_value(value);
+                self.cpu.update_zero_and_negative_flags_for(value, x);
+            }
+            Instruction::Dey => {
+                let value = self.cpu.register_y_16().wrapping_sub(1) & x.mask();
+                self.cpu.set_register_y_value(value);
+                self.cpu.update_zero_and_negative_flags_for(value, x);
+            }
+            Instruction::Asl => {
+                self.modify_operand_65c816(mode, a, |cpu, value| cpu.shift_left_sized(value, a));
+            }
+            Instruction::Lsr => {
+                self.modify_operand_65c816(mode, a, |cpu, value| cpu.shift_right_sized(value, a));
+            }
+            Instruction::Rol => {
+                self.modify_operand_65c816(mode, a, |cpu, value| cpu.rotate_left_sized(value, a));
+            }
+            Instruction::Ror => {
+                self.modify_operand_65c816(mode, a, |cpu, value| cpu.rotate_right_sized(value, a));
+            }
+            Instruction::Trb => {
+                let accumulator = self.cpu.accumulator_value();
+
+                self.modify_operand_65c816(mode, a, |cpu, value| {
+                    cpu.update_flag(&StatusFlag::Zero, value & accumulator == 0);
+
+                    value & !accumulator
+                });
+            }
+            Instruction::Tsb => {
+                let accumulator = self.cpu.accumulator_value();
+
+                self.modify_operand_65c816(mode, a, |cpu, value| {
+                    cpu.update_flag(&StatusFlag::Zero, value & accumulator == 0);
+
+                    value | accumulator
+                });
+            }
+
+            // Status flags, register widths and the emulation mode
+            Instruction::Sec => self.sec(),
+            Instruction::Sed => self.sed(),
+            Instruction::Sei => self.sei(),
+            Instruction::Clc => self.clc(),
+            Instruction::Cld => self.cld(),
+            Instruction::Cli => self.cli(),
+            Instruction::Clv => self.clv(),
+            Instruction::Rep => {
+                let mask = self.read_program_8(0);
+                self.cpu.set_status_65c816(self.cpu.status & !mask);
+            }
+            Instruction::Sep => {
+                let mask = self.read_program_8(0);
+                self.cpu.set_status_65c816(self.cpu.status | mask);
+            }
+            // XCE exchanges the carry and the emulation flags
+            Instruction::Xce => {
+                let carry = self.cpu.has_flag(&StatusFlag::Carry);
+
+                self.cpu.update_flag(&StatusFlag::Carry, self.cpu.emulation);
+                self.cpu.set_emulation_mode(carry);
+            }
+
+            // Branches
+            Instruction::Bmi => self.branch_65c816(self.cpu.has_flag(&StatusFlag::Negative)),
+            Instruction::Bpl => self.branch_65c816(!self.cpu.has_flag(&StatusFlag::Negative)),
+            Instruction::Bvs => self.branch_65c816(self.cpu.has_flag(&StatusFlag::Overflow)),
+            Instruction::Bvc => self.branch_65c816(!self.cpu.has_flag(&StatusFlag::Overflow)),
+            Instruction::Bcs => self.branch_65c816(self.cpu.has_flag(&StatusFlag::Carry)),
+            Instruction::Bcc => self.branch_65c816(!self.cpu.has_flag(&StatusFlag::Carry)),
+            Instruction::Beq => self.branch_65c816(self.cpu.has_flag(&StatusFlag::Zero)),
+            Instruction::Bne => self.branch_65c816(!self.cpu.has_flag(&StatusFlag::Zero)),
+            Instruction::Bra => self.branch_65c816(true),
+            Instruction::Brl => {
+                let offset = self.read_program_16(0);
+                let next_instruction = self.cpu.program_counter.wrapping_add(2);
+
+                self.cpu.program_counter = next_instruction.wrapping_add(offset);
+                self.operand_address = Some(self.cpu.program_address());
+                self.branch_taken = true;
+            }
+
+            // Jumps and subroutines
+            Instruction::Jmp => self.cpu.program_counter = self.jump_target_65c816(mode),
+            Instruction::Jml => {
+                let address = match mode {
+                    AddressingMode::AbsoluteIndirectLong => {
+                        let pointer = self.read_program_16(0);
+                        self.read_long_24(pointer as u32)
+                    }
+                    _ => self.read_program_24(0),
+                };
+
+                self.jump_long(address);
+            }
+            // The return address points to the last byte of the instruction
+            Instruction::Jsr => {
+                let address = self.jump_target_65c816(mode);
+
+                self.push_stack_16(self.cpu.program_counter.wrapping_add(1));
+                self.cpu.program_counter = address;
+            }
+            Instruction::Jsl => {
+                let address = self.read_program_24(0);
+
+                self.push_stack(self.cpu.program_bank);
+                self.push_stack_16(self.cpu.program_counter.wrapping_add(2));
+                self.jump_long(address);
+            }
+            Instruction::Rts => self.rts(),
+            Instruction::Rtl => {
+                self.cpu.program_counter = self.pop_stack_16().wrapping_add(1);
+                self.cpu.program_bank = self.pop_stack();
+            }
+
+            // Interrupts, which take one more cycle in native mode to save the program bank
+            Instruction::Brk => {
+                self.cpu.cycles += !self.cpu.emulation as u64;
+                self.brk();
+            }
+            Instruction::Cop => {
+                self.cpu.cycles += !self.cpu.emulation as u64;
+                self.cpu.program_counter = self.cpu.program_counter.wrapping_add(1);
+                self.interrupt(&Interrupt::Cop);
+            }
+            Instruction::Rti if self.cpu.emulation => self.rti(),
+            Instruction::Rti => {
+                let status = self.pop_stack();
+                self.cpu.set_status_65c816(status);
+
+                self.cpu.program_counter = self.pop_stack_16();
+                self.cpu.program_bank = self.pop_stack();
+                self.cpu.cycles += 1;
+            }
+
+            // Stack operations
+            Instruction::Pha => self.push_sized(self.cpu.accumulator_value(), a),
+            Instruction::Phx => self.push_sized(self.cpu.register_x_16(), x),
+            Instruction::Phy => self.push_sized(self.cpu.register_y_16(), x),
+            Instruction::Pla => {
+                let value = self.pop_sized(a);
+                self.cpu.set_accumulator_value(value);
+                self.cpu.update_zero_and_negative_flags_for(value, a);
+            }
+            Instruction::Plx => {
+                let value = self.pop_sized(x);
+                self.cpu.set_register_x_value(value);
+                self.cpu.update_zero_and_negative_flags_for(value, x);
+            }
+            Instruction::Ply => {
+                let value = self.pop_sized(x);
+                self.cpu.set_register_y_value(value);
+                self.cpu.update_zero_and_negative_flags_for(value, x);
+            }
+            Instruction::Php if self.cpu.emulation => self.php(),
+            Instruction::Php => self.push_stack(self.cpu.status),
+            Instruction::Plp => {
+                let status = self.pop_stack();
+                self.cpu.set_status_65c816(status);
+            }
+            Instruction::Phb => self.push_stack(self.cpu.data_bank),
+            Instruction::Phk => self.push_stack(self.cpu.program_bank),
+            Instruction::Phd => self.push_stack_16(self.cpu.direct_page),
+            Instruction::Plb => {
+                self.cpu.data_bank = self.pop_stack();
+                self.cpu
+                    .update_zero_and_negative_flags_for(self.cpu.data_bank as u16, Width::Byte);
+            }
+            Instruction::Pld => {
+                self.cpu.direct_page = self.pop_stack_16();
+                self.cpu
+                    .update_zero_and_negative_flags_for(self.cpu.direct_page, Width::Word);
+            }
+            Instruction::Pea => {
+                let value = self.read_program_16(0);
+                self.push_stack_16(value);
+            }
+            Instruction::Pei => {
+                self.cpu.cycles += self.direct_page_cycles(mode);
+
+                let address = self.direct_operand_address(0);
+                let value = self.read_direct_pointer(address);
+                self.push_stack_16(value);
+            }
+            Instruction::Per => {
+                let offset = self.read_program_16(0);
+                let next_instruction = self.cpu.program_counter.wrapping_add(2);
+
+                self.push_stack_16(next_instruction.wrapping_add(offset));
+            }
+
+            // Block moves
+            Instruction::Mvn => self.block_move(1),
+            Instruction::Mvp => self.block_move(0xFFFF),
+
+            // WDM is reserved for future extensions and skips its operand
+            Instruction::Nop | Instruction::Wdm => {}
+            Instruction::Wai => self.wai(),
+            Instruction::Stp => self.stp(),
+
+            _ => unreachable!("Opcode {:02X} is not defined on the 65C816", opcode.code),
+        }
+    }
+
+    // Zero page operands are offsets into the direct page. In emulation mode
+    // with a page aligned direct page the indexing wraps like on the 6502.
+    fn direct_address(&self, offset: u8, index: u16) -> u16 {
+        let direct_page = self.cpu.direct_page;
+
+        if self.cpu.emulation && direct_page & 0xFF == 0 {
+            direct_page | offset.wrapping_add(index as u8) as u16
+        } else {
+            direct_page.wrapping_add(offset as u16).wrapping_add(index)
+        }
+    }
+
+    fn read_direct_pointer(&mut self, address: u16) -> u16 {
+        let high_address = if self.cpu.emulation && self.cpu.direct_page & 0xFF == 0 {
+            (address & 0xFF00) | (address.wrapping_add(1) & 0x00FF)
+        } else {
+            address.wrapping_add(1)
+        };
+
+        u16::from_le_bytes([
+            self.mem_read_long(address as u32),
+            self.mem_read_long(high_address as u32),
+        ])
+    }
+
+    // A direct page that is not aligned to a page costs one more cycle
+    fn direct_page_cycles(&self, mode: &AddressingMode) -> u64 {
+        let is_direct_page = matches!(
+            mode,
+            AddressingMode::ZeroPage
+                | AddressingMode::ZeroPageX
+                | AddressingMode::ZeroPageY
+                | AddressingMode::IndexedIndirectX
+                | AddressingMode::IndirectIndexedY
+                | AddressingMode::ZeroPageIndirect
+                | AddressingMode::ZeroPageIndirectLong
+                | AddressingMode::ZeroPageIndirectLongY
+        );
+
+        (is_direct_page && self.cpu.direct_page & 0xFF != 0) as u64
+    }
+
+    fn data_address(&self, address: u16) -> u32 {
+        ((self.cpu.data_bank as u32) << 16) | address as u32
+    }
+
+    // Indexing pays one extra cycle on reads when it crosses a page, and always with 16-bit indexes
+    fn indexed_address(&self, base: u32, index: u16) -> (u32, bool) {
+        let address = base.wrapping_add(index as u32) & 0xFFFFFF;
+
+        (
+            address,
+            self.cpu.is_index_16bit() || base >> 8 != address >> 8,
+        )
+    }
+
+    // Effective 24-bit address of the operand and whether indexing crossed a page
+    fn operand_address_65c816(&mut self, mode: &AddressingMode) -> (u32, bool) {
+        let register_x = self.cpu.register_x_16();
+        let register_y = self.cpu.register_y_16();
+
+        match mode {
+            AddressingMode::Immediate => (self.cpu.program_address(), false),
+            AddressingMode::ZeroPage => (self.direct_operand_address(0) as u32, false),
+            AddressingMode::ZeroPageX => (self.direct_operand_address(register_x) as u32, false),
+            AddressingMode::ZeroPageY => (self.direct_operand_address(register_y) as u32, false),
+            AddressingMode::Absolute => {
+                let address = self.read_program_16(0);
+
+                (self.data_address(address), false)
+            }
+            AddressingMode::AbsoluteX => {
+                let address = self.read_program_16(0);
+
+                self.indexed_address(self.data_address(address), register_x)
+            }
+            AddressingMode::AbsoluteY => {
+                let address = self.read_program_16(0);
+
+                self.indexed_address(self.data_address(address), register_y)
+            }
+            AddressingMode::AbsoluteLong => (self.read_program_24(0), false),
+            AddressingMode::AbsoluteLongX => {
+                let address = self.read_program_24(0).wrapping_add(register_x as u32);
+
+                (address & 0xFFFFFF, false)
+            }
+            AddressingMode::IndexedIndirectX => {
+                let address = self.direct_operand_address(register_x);
+                let pointer = self.read_direct_pointer(address);
+
+                (self.data_address(pointer), false)
+            }
+            AddressingMode::IndirectIndexedY => {
+                let address = self.direct_operand_address(0);
+                let pointer = self.read_direct_pointer(address);
+
+                self.indexed_address(self.data_address(pointer), register_y)
+            }
+            AddressingMode::ZeroPageIndirect => {
+                let address = self.direct_operand_address(0);
+                let pointer = self.read_direct_pointer(address);
+
+                (self.data_address(pointer), false)
+            }
+            AddressingMode::ZeroPageIndirectLong => {
+                let address = self.direct_operand_address(0);
+
+                (self.read_long_24(address as u32), false)
+            }
+            AddressingMode::ZeroPageIndirectLongY => {
+                let address = self.direct_operand_address(0);
+                let base = self.read_long_24(address as u32);
+
+                (base.wrapping_add(register_y as u32) & 0xFFFFFF, false)
+            }
+            AddressingMode::StackRelative => {
+                let offset = self.read_program_8(0) as u16;
+
+                (
+                    self.cpu.stack_pointer_16().wrapping_add(offset) as u32,
+                    false,
+                )
+            }
+            AddressingMode::StackRelativeIndirectY => {
+                let offset = self.read_program_8(0) as u16;
+                let address = self.cpu.stack_pointer_16().wrapping_add(offset);
+                let pointer = self.read_long_16(address as u32);
+
+                (
+                    self.data_address(pointer).wrapping_add(register_y as u32) & 0xFFFFFF,
+                    false,
+                )
+            }
+            // The opcode table only pairs these modes with other instructions
+            _ => unreachable!("{:?} has no operand address on the 65C816", mode),
+        }
+    }
+
+    // The direct page address of the operand byte, plus an index
+    fn direct_operand_address(&mut self, index: u16) -> u16 {
+        let offset = self.read_program_8(0);
+
+        self.direct_address(offset, index)
+    }
+
+    // JMP and JSR stay in the program bank, JMP (abs) reads its pointer from bank 0
+    fn jump_target_65c816(&mut self, mode: &AddressingMode) -> u16 {
+        let address = self.read_program_16(0);
+
+        match mode {
+            AddressingMode::Indirect => self.read_long_16(address as u32),
+            AddressingMode::AbsoluteIndexedIndirect => {
+                let pointer = address.wrapping_add(self.cpu.register_x_16());
+
+                self.read_long_16(self.program_bank_address(pointer))
+            }
+            _ => address,
+        }
+    }
+
+    fn jump_long(&mut self, address: u32) {
+        self.cpu.program_bank = (address >> 16) as u8;
+        self.cpu.program_counter = address as u16;
+    }
+
+    fn program_bank_address(&self, address: u16) -> u32 {
+        ((self.cpu.program_bank as u32) << 16) | address as u32
+    }
+
+    // Operand bytes following the opcode in the program bank, read on the bus
+    // like the pointers and the data operands
+    fn read_program_8(&mut self, offset: u16) -> u8 {
+        let address = self.cpu.program_counter.wrapping_add(offset);
+
+        self.mem_read_long(self.program_bank_address(address))
+    }
+
+    fn read_program_16(&mut self, offset: u16) -> u16 {
+        u16::from_le_bytes([
+            self.read_program_8(offset),
+            self.read_program_8(offset.wrapping_add(1)),
+        ])
+    }
+
+    fn read_program_24(&mut self, offset: u16) -> u32 {
+        u32::from_le_bytes([
+            self.read_program_8(offset),
+            self.read_program_8(offset.wrapping_add(1)),
+            self.read_program_8(offset.wrapping_add(2)),
+            0,
+        ])
+    }
+
+    fn read_long_16(&mut self, address: u32) -> u16 {
+        u16::from_le_bytes([
+            self.mem_read_long(address),
+            self.mem_read_long(address.wrapping_add(1) & 0xFFFFFF),
+        ])
+    }
+
+    fn read_long_24(&mut self, address: u32) -> u32 {
+        u32::from_le_bytes([
+            self.mem_read_long(address),
+            self.mem_read_long(address.wrapping_add(1) & 0xFFFFFF),
+            self.mem_read_long(address.wrapping_add(2) & 0xFFFFFF),
+            0,
+        ])
+    }
+
+    fn read_sized(&mut self, address: u32, width: Width) -> u16 {
+        match width {
+            Width::Byte => self.mem_read_long(address) as u16,
+            Width::Word => u16::from_le_bytes([
+                self.mem_read_long(address),
+                self.mem_read_long(address.wrapping_add(1) & 0xFFFFFF),
+            ]),
+        }
+    }
+
+    fn write_sized(&mut self, address: u32, value: u16, width: Width) {
+        let [low, high] = value.to_le_bytes();
+
+        self.mem_write_long(address, low);
+
+        if width == Width::Word {
+            self.mem_write_long(address.wrapping_add(1) & 0xFFFFFF, high);
+        }
+    }
+
+    fn read_operand_65c816(&mut self, mode: &AddressingMode, width: Width) -> u16 {
+        let (address, is_page_crossed) = self.operand_address_65c816(mode);
+        self.operand_address = Some(address);
+
+        self.cpu.cycles +=
+            self.direct_page_cycles(mode) + is_page_crossed as u64 + width.extra_bytes() as u64;
+
+        self.read_sized(address, width)
+    }
+
+    fn write_operand_65c816(&mut self, mode: &AddressingMode, value: u16, width: Width) {
+        let (address, _) = self.operand_address_65c816(mode);
+        self.operand_address = Some(address);
+
+        self.cpu.cycles += self.direct_page_cycles(mode) + width.extra_bytes() as u64;
+
+        self.write_sized(address, value, width);
+    }
+
+    // Read-modify-write operations read and write the extra byte of a 16-bit operand
+    fn modify_operand_65c816(
+        &mut self,
+        mode: &AddressingMode,
+        width: Width,
+        operation: impl FnOnce(&mut Cpu, u16) -> u16,
+    ) -> u16 {
+        match mode {
+            AddressingMode::Accumulator => {
+                let value = self.cpu.accumulator_value();
+                let result = operation(&mut self.cpu, value);
+                self.cpu.set_accumulator_value(result);
+
+                result
+            }
+            _ => {
+                let (address, _) = self.operand_address_65c816(mode);
+                self.operand_address = Some(address);
+
+                self.cpu.cycles += self.direct_page_cycles(mode) + 2 * width.extra_bytes() as u64;
+
+                let value = self.read_sized(address, width);
+                let result = operation(&mut self.cpu, value);
+                self.write_sized(address, result, width);
+
+                result
+            }
+        }
+    }
+
+    fn push_sized(&mut self, value: u16, width: Width) {
+        match width {
+            Width::Byte => self.push_stack(value as u8),
+            Width::Word => self.push_stack_16(value),
+        }
+
+        self.cpu.cycles += width.extra_bytes() as u64;
+    }
+
+    fn pop_sized(&mut self, width: Width) -> u16 {
+        self.cpu.cycles += width.extra_bytes() as u64;
+
+        match width {
+            Width::Byte => self.pop_stack() as u16,
+            Width::Word => self.pop_stack_16(),
+        }
+    }
+
+    // Only the emulation mode pays one more cycle when a branch lands on another page
+    fn branch_65c816(&mut self, condition: bool) {
+        let offset = self.read_program_8(0) as i8;
+
+        let next_instruction = self.cpu.program_counter.wrapping_add(1);
+        let target = next_instruction.wrapping_add(offset as u16);
+
+        self.operand_address = Some(self.program_bank_address(target));
+
+        if !condition {
+            return;
+        }
+
+        self.branch_taken = true;
+
+        let is_page_crossed = next_instruction & 0xFF00 != target & 0xFF00;
+
+        self.cpu.cycles += 1 + (self.cpu.emulation && is_page_crossed) as u64;
+        self.cpu.program_counter = target;
+    }
+
+    // SBC adds the complement of the operand. In decimal mode every digit is adjusted
+    // on its own, and V is taken before the adjustment of the top digit.
+    fn add_with_carry_65c816(&mut self, value: u16, width: Width, subtract: bool) {
+        let accumulator = self.cpu.accumulator_value() as i32;
+        let value = if subtract {
+            !value & width.mask()
+        } else {
+            value
+        } as i32;
+        let carry = self.cpu.has_flag(&StatusFlag::Carry) as i32;
+        let sign = width.sign() as i32;
+
+        let (result, overflow) = if self.cpu.is_decimal_mode() {
+            decimal_sum(accumulator, value, carry, width, subtract)
+        } else {
+            let result = accumulator + value + carry;
+
+            (
+                result,
+                !(accumulator ^ value) & (accumulator ^ result) & sign != 0,
+            )
+        };
+
+        let result_value = result as u16 & width.mask();
+
+        self.cpu
+            .update_flag(&StatusFlag::Carry, result > width.mask() as i32);
+        self.cpu.update_flag(&StatusFlag::Overflow, overflow);
+        self.cpu.set_accumulator_value(result_value);
+        self.cpu
+            .update_zero_and_negative_flags_for(result_value, width);
+    }
+
+    // MVN and MVP copy one byte from the source bank at X to the destination bank
+    // at Y, and run again until the byte counter in C wraps around to $FFFF
+    fn block_move(&mut self, step: u16) {
+        let destination = self.read_program_8(0);
+        let source = self.read_program_8(1);
+
+        let register_x = self.cpu.register_x_16();
+        let register_y = self.cpu.register_y_16();
+
+        let value = self.mem_read_long(((source as u32) << 16) | register_x as u32);
+        self.mem_write_long(((destination as u32) << 16) | register_y as u32, value);
+
+        self.cpu.data_bank = destination;
+        self.cpu.set_register_x_value(register_x.wrapping_add(step));
+        self.cpu.set_register_y_value(register_y.wrapping_add(step));
+
+        let count = self.cpu.accumulator_16().wrapping_sub(1);
+        self.cpu.set_accumulator_16(count);
+
+        if count != 0xFFFF {
+            self.cpu.program_counter = self.cpu.program_counter.wrapping_sub(1);
+        }
+    }
+}
+
+// Returns the sum with the carry out above the register width, and the overflow flag
+fn decimal_sum(
+    accumulator: i32,
+    value: i32,
+    carry: i32,
+    width: Width,
+    subtract: bool,
+) -> (i32, bool) {
+    let mut result = 0;
+    let mut carry = carry;
+    let mut overflow = false;
+
+    for digit in 0..width.digits() {
+        let shift = digit * 4;
+        let mut sum = ((accumulator >> shift) & 0x0F) + ((value >> shift) & 0x0F) + carry;
+
+        if digit == width.digits() - 1 {
+            let unadjusted = result | (sum << shift);
+            overflow =
+                !(accumulator ^ value) & (accumulator ^ unadjusted) & width.sign() as i32 != 0;
+        }
+
+        if subtract {
+            if sum <= 0x0F {
+                sum -= 0x06;
+            }
+        } else if sum > 0x09 {
+            sum += 0x06;
+        }
+
+        carry = (sum > 0x0F) as i32;
+        result |= (sum & 0x0F) << shift;
+    }
+
+    (result | (carry << (width.digits() * 4)), overflow)
+}
+
+impl Cpu {
+    fn accumulator_width(&self) -> Width {
+        if self.is_accumulator_16bit() {
+            Width::Word
+        } else {
+            Width::Byte
+        }
+    }
+
+    fn index_width(&self) -> Width {
+        if self.is_index_16bit() {
+            Width::Word
+        } else {
+            Width::Byte
+        }
+    }
+
+    // An 8-bit accumulator leaves B untouched
+    fn accumulator_value(&self) -> u16 {
+        match self.accumulator_width() {
+            Width::Byte => self.accumulator as u16,
+            Width::Word => self.accumulator_16(),
+        }
+    }
+
+    fn set_accumulator_value(&mut self, value: u16) {
+        match self.accumulator_width() {
+            Width::Byte => self.accumulator = value as u8,
+            Width::Word => self.set_accumulator_16(value),
+        }
+    }
+
+    fn set_register_x_value(&mut self, value: u16) {
+        let [low, high] = value.to_le_bytes();
+
+        self.register_x = low;
+
+        if self.is_index_16bit() {
+            self.register_x_high = high;
+        }
+    }
+
+    fn set_register_y_value(&mut self, value: u16) {
+        let [low, high] = value.to_le_bytes();
+
+        self.register_y = low;
+
+        if self.is_index_16bit() {
+            self.register_y_high = high;
+        }
+    }
+
+    // REP, SEP, PLP and RTI may switch the index registers to 8 bits, which clears their high bytes.
+    // In emulation mode bits 4 and 5 are Break and the constant 1, as on the 6502.
+    fn set_status_65c816(&mut self, status: u8) {
+        if self.emulation {
+            self.set_status_from_stack(status);
+            return;
+        }
+
+        self.status = status;
+
+        if !self.is_index_16bit() {
+            self.register_x_high = 0;
+            self.register_y_high = 0;
+        }
+    }
+
+    fn update_zero_and_negative_flags_for(&mut self, value: u16, width: Width) {
+        self.update_flag(&StatusFlag::Zero, value & width.mask() == 0);
+        self.update_flag(&StatusFlag::Negative, value & width.sign() != 0);
+    }
+
+    fn compare_sized(&mut self, register: u16, value: u16, width: Width) {
+        self.update_flag(&StatusFlag::Carry, register >= value);
+        self.update_zero_and_negative_flags_for(register.wrapping_sub(value), width);
+    }
+
+    fn shift_left_sized(&mut self, value: u16, width: Width) -> u16 {
+        let result = (value << 1) & width.mask();
+
+        self.update_flag(&StatusFlag::Carry, value & width.sign() != 0);
+        self.update_zero_and_negative_flags_for(result, width);
+
+        result
+    }
+
+    fn shift_right_sized(&mut self, value: u16, width: Width) -> u16 {
+        let result = value >> 1;
+
+        self.update_flag(&StatusFlag::Carry, value & 1 == 1);
+        self.update_zero_and_negative_flags_for(result, width);
+
+        result
+    }
+
+    fn rotate_left_sized(&mut self, value: u16, width: Width) -> u16 {
+        let carry = self.has_flag(&StatusFlag::Carry) as u16;
+        let result = ((value << 1) | carry) & width.mask();
+
+        self.update_flag(&StatusFlag::Carry, value & width.sign() != 0);
+        self.update_zero_and_negative_flags_for(result, width);
+
+        result
+    }
+
+    fn rotate_right_sized(&mut self, value: u16, width: Width) -> u16 {
+        let carry = self.has_flag(&StatusFlag::Carry);
+        let result = (value >> 1) | if carry { width.sign() } else { 0 };
+
+        self.update_flag(&StatusFlag::Carry, value & 1 == 1);
+        self.update_zero_and_negative_flags_for(result, width);
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod wdc65c816_tests {
+    use crate::bus::{Bus, FlatMemory};
+    use crate::cpu::{Cpu, CpuVariant, Easy6502, Machine, StatusFlag};
+
+    fn run_program(nes: &mut Easy6502, program: Vec<u8>) {
+        nes.load_instructions(program);
+        nes.run_with_reset_pc(true).unwrap();
+    }
+
+    #[test]
+    fn native_mode_switch_test() {
+        let mut nes = Easy6502::with_variant(CpuVariant::Wdc65C816);
+
+        run_program(
+            &mut nes,
+            vec![
+                0x18, // CLC
+                0xFB, // XCE
+                0xC2, 0x30, // REP #$30
+                0xA9, 0x34, 0x12, // LDA #$1234
+                0xA2, 0xCD, 0xAB, // LDX #$ABCD
+                0xA8, // TAY
+                0xEB, // XBA
+                0xE2, 0x30, // SEP #$30
+                0x38, // SEC
+                0xFB, // XCE
+                0x4C, 0x10, 0x06, // JMP $0610
+            ],
+        );
+
+        assert_eq!(nes.cpu.accumulator_16(), 0x3412);
+        // Index registers lose their high bytes when they become 8-bit
+        assert_eq!(nes.cpu.register_x_16(), 0x00CD);
+        assert_eq!(nes.cpu.register_y_16(), 0x0034);
+        assert!(nes.cpu.emulation);
+        assert!(!nes.cpu.has_flag(&StatusFlag::Carry));
+    }
+
+    #[test]
+    fn emulation_mode_test() {
+        let mut nes = Easy6502::with_variant(CpuVariant::Wdc65C816);
+
+        run_program(
+            &mut nes,
+            vec![
+                0xC2, 0x30, // REP #$30
+                0xA9, 0x34, // LDA #$34
+                0xEA, // NOP
+            ],
+        );
+
+        // The registers stay 8-bit until the CPU enters native mode
+        assert!(!nes.cpu.is_accumulator_16bit());
+        assert_eq!(nes.cpu.accumulator, 0x34);
+        assert_eq!(nes.cpu.accumulator_b, 0x00);
+    }
+
+    #[test]
+    fn sixteen_bit_arithmetic_test() {
+        let mut nes = Easy6502::with_variant(CpuVariant::Wdc65C816);
+
+        run_program(
+            &mut nes,
+            vec![
+                0x18, // CLC
+                0xFB, // XCE
+                0xC2, 0x20, // REP #$20
+                0xA9, 0xFF, 0x80, // LDA #$80FF
+                0x18, // CLC
+                0x69, 0x01, 0x01, // ADC #$0101
+                0x85, 0x10, // STA $10
+                0x06, 0x10, // ASL $10
+                0xF8, // SED
+                0xA9, 0x99, 0x19, // LDA #$1999
+                0x69, 0x00, 0x00, // ADC #$0000 + carry
+                0x38, // SEC
+                0xE9, 0x01, 0x00, // SBC #$0001
+            ],
+        );
+
+        assert_eq!(nes.mem_read_16(0x10), 0x0400);
+        assert_eq!(nes.cpu.accumulator_16(), 0x1999);
+        assert!(nes.cpu.has_flag(&StatusFlag::Carry));
+        assert!(!nes.cpu.has_flag(&StatusFlag::Negative));
+    }
+
+    #[test]
+    fn direct_page_and_long_addressing_test() {
+        let mut nes = Easy6502::with_variant(CpuVariant::Wdc65C816);
+
+        // [$20] points to $7E0400, another byte than $0400
+        nes.mem_write_16(0x0320, 0x0400);
+        nes.mem_write_8(0x0322, 0x7E);
+        nes.mem_write_long(0x7E0400, 0x5A);
+        nes.mem_write_8(0x0400, 0xA5);
+
+        run_program(
+            &mut nes,
+            vec![
+                0x18, // CLC
+                0xFB, // XCE
+                0xC2, 0x20, // REP #$20
+                0xA9, 0x00, 0x03, // LDA #$0300
+                0x5B, // TCD
+                0xE2, 0x20, // SEP #$20
+                0xA9, 0x42, // LDA #$42
+                0xA2, 0x05, // LDX #$05
+                0x95, 0x0B, // STA $0B,X
+                0xA7, 0x20, // LDA [$20]
+                0x8F, 0x00, 0x05, 0x01, // STA $010500
+            ],
+        );
+
+        assert_eq!(nes.cpu.direct_page, 0x0300);
+        assert_eq!(nes.mem_read_8(0x0310), 0x42);
+        assert_eq!(nes.cpu.accumulator, 0x5A);
+        assert_eq!(nes.mem_read_long(0x010500), 0x5A);
+        assert_eq!(nes.mem_read_8(0x0500), 0x00);
+        assert_eq!(nes.mem_read_8(0x0400), 0xA5);
+    }
+
+    #[test]
+    fn native_cycles_test() {
+        let mut nes = Easy6502::with_variant(CpuVariant::Wdc65C816);
+
+        run_program(
+            &mut nes,
+            vec![
+                0x18, // CLC (2)
+                0xFB, // XCE (2)
+                0xC2, 0x20, // REP #$20 (3)
+                0xA9, 0x01, 0x00, // LDA #$0001 (2 + 1)
+                0x5B, // TCD (2)
+                0xA5, 0x10, // LDA $10 (3 + 1 unaligned direct page + 1)
+                0x4C, 0x0A, 0x06, // JMP $060A (3)
+            ],
+        );
+
+        assert_eq!(nes.cpu.cycles, 20);
+    }
+
+    #[test]
+    fn stack_and_subroutine_test() {
+        let mut nes = Easy6502::with_variant(CpuVariant::Wdc65C816);
+
+        nes.mem_write_8(0x0700, 0x4B); // PHK
+        nes.mem_write_8(0x0701, 0xAB); // PLB
+        nes.mem_write_8(0x0702, 0x6B); // RTL
+
+        run_program(
+            &mut nes,
+            vec![
+                0x18, // CLC
+                0xFB, // XCE
+                0xF4, 0x34, 0x12, // PEA $1234
+                0x2B, // PLD
+                0xA9, 0x99, // LDA #$99
+                0x48, // PHA
+                0xA9, 0x00, // LDA #$00
+                0xA3, 0x01, // LDA $01,S
+                0x68, // PLA
+                0x22, 0x00, 0x07, 0x00, // JSL $000700
+                0x4C, 0x12, 0x06, // JMP $0612
+            ],
+        );
+
+        assert_eq!(nes.cpu.direct_page, 0x1234);
+        assert_eq!(nes.cpu.accumulator, 0x99);
+        assert_eq!(nes.cpu.data_bank, 0x00);
+        assert_eq!(nes.cpu.stack_pointer_16(), 0x01FD);
+    }
+
+    #[test]
+    fn block_move_test() {
+        let mut nes = Easy6502::with_variant(CpuVariant::Wdc65C816);
+
+        for (offset, value) in [1, 2, 3, 4].into_iter().enumerate() {
+            nes.mem_write_8(0x0800 + offset as u16, value);
+        }
+
+        run_program(
+            &mut nes,
+            vec![
+                0x18, // CLC
+                0xFB, // XCE
+                0xC2, 0x30, // REP #$30
+                0xA9, 0x03, 0x00, // LDA #$0003
+                0xA2, 0x00, 0x08, // LDX #$0800
+                0xA0, 0x00, 0x09, // LDY #$0900
+                0x54, 0x00, 0x00, // MVN $00,$00
+                0x4C, 0x10, 0x06, // JMP $0610
+            ],
+        );
+
+        assert_eq!(nes.mem_read_16(0x0900), 0x0201);
+        assert_eq!(nes.mem_read_16(0x0902), 0x0403);
+        assert_eq!(nes.cpu.accumulator_16(), 0xFFFF);
+        assert_eq!(nes.cpu.register_x_16(), 0x0804);
+        assert_eq!(nes.cpu.register_y_16(), 0x0904);
+    }
+
+    #[test]
+    fn native_interrupt_test() {
+        let mut nes = Easy6502::with_variant(CpuVariant::Wdc65C816);
+
+        // BRK handler: JMP $0700
+        nes.mem_write_16(0xFFE6, 0x0700);
+        nes.mem_write_8(0x0700, 0x4C);
+        nes.mem_write_16(0x0701, 0x0700);
+
+        run_program(
+            &mut nes,
+            vec![
+                0x18, // CLC
+                0xFB, // XCE
+                0xF8, // SED
+                0x00, 0xFF, // BRK + signature byte
+            ],
+        );
+
+        assert_eq!(nes.cpu.program_counter, 0x0700);
+        // Program bank, return address and status without a Break flag
+        assert_eq!(nes.mem_read_8(0x01FD), 0x00);
+        assert_eq!(nes.mem_read_16(0x01FB), 0x0605);
+        assert_eq!(nes.mem_read_8(0x01FA) & 0x38, 0x38);
+        assert!(!nes.cpu.has_flag(&StatusFlag::Decimal));
+    }
+
+    // Banked RAM that records the addresses read on the bus
+    #[derive(Default)]
+    struct TracedMemory {
+        memory: FlatMemory,
+        reads: Vec<u32>,
+    }
+
+    impl Bus for TracedMemory {
+        fn write(&mut self, address: u16, data: u8) {
+            self.memory.write(address, data);
+        }
+
+        fn peek(&self, address: u16) -> u8 {
+            self.memory.peek(address)
+        }
+
+        fn read_long(&mut self, address: u32) -> u8 {
+            self.reads.push(address);
+            self.memory.read_long(address)
+        }
+
+        fn write_long(&mut self, address: u32, data: u8) {
+            self.memory.write_long(address, data);
+        }
+
+        fn peek_long(&self, address: u32) -> u8 {
+            self.memory.peek_long(address)
+        }
+    }
+
+    #[test]
+    fn pointer_reads_test() {
+        let cpu = Cpu {
+            variant: CpuVariant::Wdc65C816,
+            ..Cpu::default()
+        };
+        let mut machine = Machine::with_bus(cpu, TracedMemory::default());
+
+        machine.mem_write_16(0x0020, 0x0800);
+        machine.mem_write_16(0x0700, 0x0610);
+        machine.load_instructions(vec![
+            0xB2, 0x20, // LDA ($20)
+            0x6C, 0x00, 0x07, // JMP ($0700)
+        ]);
+        machine.set_program_counter(0x0600);
+
+        // The operand bytes, the pointers and the data are all read on the bus
+        machine.step().unwrap();
+        assert_eq!(machine.bus.reads, [0x0600, 0x0601, 0x0020, 0x0021, 0x0800]);
+
+        machine.bus.reads.clear();
+        machine.step().unwrap();
+        assert_eq!(machine.bus.reads, [0x0602, 0x0603, 0x0604, 0x0700, 0x0701]);
+        assert_eq!(machine.cpu.program_counter, 0x0610);
+    }
+}
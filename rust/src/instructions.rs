@@ -1,5 +1,6 @@
-use crate::cpu::AddressingMode;
+use crate::cpu::{AddressingMode, CpuVariant, StatusFlag};
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Instruction {
     Brk,
     // Common Load/Store opcodes
@@ -45,6 +46,7 @@ pub enum Instruction {
     Jmp,
     Jsr,
     Rts,
+    Rti,
     Bmi,
     Bpl,
     Bvs,
@@ -69,35 +71,395 @@ pub enum Instruction {
 
     // Bit operations
     Bit,
+
+    // 65C02 opcodes
+    Bra,
+    Stz,
+    Phx,
+    Plx,
+    Phy,
+    Ply,
+    Trb,
+    Tsb,
+
+    // Rockwell and WDC 65C02 bit operations, the bit number is part of the opcode
+    Rmb,
+    Smb,
+    Bbr,
+    Bbs,
+
+    // WDC 65C02 opcodes that halt the clock until an interrupt or a reset
+    Wai,
+    Stp,
+
+    // 65C816 opcodes
+    // Mode and register width control
+    Xce,
+    Rep,
+    Sep,
+    // Transfers between the 16-bit registers
+    Xba,
+    Tcd,
+    Tdc,
+    Tcs,
+    Tsc,
+    Txy,
+    Tyx,
+    // Bank and direct page registers on the stack
+    Phb,
+    Plb,
+    Phd,
+    Pld,
+    Phk,
+    // Push effective address
+    Pea,
+    Pei,
+    Per,
+    // Long jumps, calls and branches
+    Jml,
+    Jsl,
+    Rtl,
+    Brl,
+    // Block moves
+    Mvn,
+    Mvp,
+    // Co-processor interrupt and the reserved two-byte NOP
+    Cop,
+    Wdm,
+
+    // No operation
+    Nop,
+
+    // Unofficial opcodes that combine two official operations
+    Slo,
+    Rla,
+    Sre,
+    Rra,
+    Sax,
+    Lax,
+    Dcp,
+    Isc,
+    Anc,
+    Alr,
+    Arr,
+    Sbx,
+
+    // Unofficial opcodes whose result depends on the chip, see `UnstableOpcodeModel`
+    Xaa,
+    Lxa,
+    Ahx,
+    Tas,
+    Shx,
+    Shy,
+    Las,
+
+    // Unofficial opcodes that lock up the CPU
+    Jam,
+}
+
+impl Instruction {
+    pub const fn mnemonic(&self) -> &'static str {
+        match self {
+            Instruction::Brk => "BRK",
+            Instruction::Lda => "LDA",
+            Instruction::Ldx => "LDX",
+            Instruction::Ldy => "LDY",
+            Instruction::Sta => "STA",
+            Instruction::Stx => "STX",
+            Instruction::Sty => "STY",
+            Instruction::Tay => "TAY",
+            Instruction::Tya => "TYA",
+            Instruction::Tax => "TAX",
+            Instruction::Txa => "TXA",
+            Instruction::Txs => "TXS",
+            Instruction::Tsx => "TSX",
+            Instruction::Adc => "ADC",
+            Instruction::And => "AND",
+            Instruction::Sbc => "SBC",
+            Instruction::Inc => "INC",
+            Instruction::Dec => "DEC",
+            Instruction::Iny => "INY",
+            Instruction::Inx => "INX",
+            Instruction::Dey => "DEY",
+            Instruction::Dex => "DEX",
+            Instruction::Asl => "ASL",
+            Instruction::Lsr => "LSR",
+            Instruction::Cmp => "CMP",
+            Instruction::Cpx => "CPX",
+            Instruction::Cpy => "CPY",
+            Instruction::Eor => "EOR",
+            Instruction::Ror => "ROR",
+            Instruction::Ora => "ORA",
+            Instruction::Rol => "ROL",
+            Instruction::Jmp => "JMP",
+            Instruction::Jsr => "JSR",
+            Instruction::Rts => "RTS",
+            Instruction::Rti => "RTI",
+            Instruction::Bmi => "BMI",
+            Instruction::Bpl => "BPL",
+            Instruction::Bvs => "BVS",
+            Instruction::Bvc => "BVC",
+            Instruction::Bcs => "BCS",
+            Instruction::Bcc => "BCC",
+            Instruction::Beq => "BEQ",
+            Instruction::Bne => "BNE",
+            Instruction::Pha => "PHA",
+            Instruction::Php => "PHP",
+            Instruction::Pla => "PLA",
+            Instruction::Plp => "PLP",
+            Instruction::Sec => "SEC",
+            Instruction::Clc => "CLC",
+            Instruction::Clv => "CLV",
+            Instruction::Sei => "SEI",
+            Instruction::Cli => "CLI",
+            Instruction::Sed => "SED",
+            Instruction::Cld => "CLD",
+            Instruction::Bit => "BIT",
+            Instruction::Bra => "BRA",
+            Instruction::Stz => "STZ",
+            Instruction::Phx => "PHX",
+            Instruction::Plx => "PLX",
+            Instruction::Phy => "PHY",
+            Instruction::Ply => "PLY",
+            Instruction::Trb => "TRB",
+            Instruction::Tsb => "TSB",
+            Instruction::Rmb => "RMB",
+            Instruction::Smb => "SMB",
+            Instruction::Bbr => "BBR",
+            Instruction::Bbs => "BBS",
+            Instruction::Wai => "WAI",
+            Instruction::Stp => "STP",
+            Instruction::Xce => "XCE",
+            Instruction::Rep => "REP",
+            Instruction::Sep => "SEP",
+            Instruction::Xba => "XBA",
+            Instruction::Tcd => "TCD",
+            Instruction::Tdc => "TDC",
+            Instruction::Tcs => "TCS",
+            Instruction::Tsc => "TSC",
+            Instruction::Txy => "TXY",
+            Instruction::Tyx => "TYX",
+            Instruction::Phb => "PHB",
+            Instruction::Plb => "PLB",
+            Instruction::Phd => "PHD",
+            Instruction::Pld => "PLD",
+            Instruction::Phk => "PHK",
+            Instruction::Pea => "PEA",
+            Instruction::Pei => "PEI",
+            Instruction::Per => "PER",
+            Instruction::Jml => "JML",
+            Instruction::Jsl => "JSL",
+            Instruction::Rtl => "RTL",
+            Instruction::Brl => "BRL",
+            Instruction::Mvn => "MVN",
+            Instruction::Mvp => "MVP",
+            Instruction::Cop => "COP",
+            Instruction::Wdm => "WDM",
+            Instruction::Nop => "NOP",
+            Instruction::Slo => "SLO",
+            Instruction::Rla => "RLA",
+            Instruction::Sre => "SRE",
+            Instruction::Rra => "RRA",
+            Instruction::Sax => "SAX",
+            Instruction::Lax => "LAX",
+            Instruction::Dcp => "DCP",
+            Instruction::Isc => "ISC",
+            Instruction::Anc => "ANC",
+            Instruction::Alr => "ALR",
+            Instruction::Arr => "ARR",
+            Instruction::Sbx => "SBX",
+            Instruction::Xaa => "XAA",
+            Instruction::Lxa => "LXA",
+            Instruction::Ahx => "AHX",
+            Instruction::Tas => "TAS",
+            Instruction::Shx => "SHX",
+            Instruction::Shy => "SHY",
+            Instruction::Las => "LAS",
+            Instruction::Jam => "JAM",
+        }
+    }
+
+    /// Status flags the instruction may change. Interrupts set I and
+    /// the CMOS CPUs also clear D.
+    pub const fn affected_flags(&self) -> u8 {
+        const N: u8 = StatusFlag::Negative.bit_shift();
+        const V: u8 = StatusFlag::Overflow.bit_shift();
+        const M: u8 = StatusFlag::MemorySelect.bit_shift();
+        const X: u8 = StatusFlag::IndexSelect.bit_shift();
+        const D: u8 = StatusFlag::Decimal.bit_shift();
+        const I: u8 = StatusFlag::Interrupt.bit_shift();
+        const Z: u8 = StatusFlag::Zero.bit_shift();
+        const C: u8 = StatusFlag::Carry.bit_shift();
+
+        match self {
+            Instruction::Lda
+            | Instruction::Ldx
+            | Instruction::Ldy
+            | Instruction::Tay
+            | Instruction::Tya
+            | Instruction::Tax
+            | Instruction::Txa
+            | Instruction::Tsx
+            | Instruction::Inc
+            | Instruction::Dec
+            | Instruction::Iny
+            | Instruction::Inx
+            | Instruction::Dey
+            | Instruction::Dex
+            | Instruction::And
+            | Instruction::Eor
+            | Instruction::Ora
+            | Instruction::Pla
+            | Instruction::Plx
+            | Instruction::Ply
+            | Instruction::Lax
+            | Instruction::Las
+            | Instruction::Xba
+            | Instruction::Tcd
+            | Instruction::Tdc
+            | Instruction::Tsc
+            | Instruction::Txy
+            | Instruction::Tyx
+            | Instruction::Plb
+            | Instruction::Pld
+            | Instruction::Xaa
+            | Instruction::Lxa => N | Z,
+            Instruction::Asl
+            | Instruction::Lsr
+            | Instruction::Rol
+            | Instruction::Ror
+            | Instruction::Cmp
+            | Instruction::Cpx
+            | Instruction::Cpy
+            | Instruction::Slo
+            | Instruction::Rla
+            | Instruction::Sre
+            | Instruction::Dcp
+            | Instruction::Anc
+            | Instruction::Alr
+            | Instruction::Sbx => N | Z | C,
+            Instruction::Adc
+            | Instruction::Sbc
+            | Instruction::Rra
+            | Instruction::Isc
+            | Instruction::Arr => N | V | Z | C,
+            Instruction::Bit => N | V | Z,
+            Instruction::Trb | Instruction::Tsb => Z,
+            Instruction::Sec | Instruction::Clc => C,
+            Instruction::Clv => V,
+            Instruction::Sei | Instruction::Cli => I,
+            Instruction::Sed | Instruction::Cld => D,
+            Instruction::Brk | Instruction::Cop => I | D,
+            Instruction::Xce => C | M | X,
+            Instruction::Plp | Instruction::Rti | Instruction::Rep | Instruction::Sep => 0xFF,
+            _ => 0,
+        }
+    }
+
+    // Instructions that only read their operand finish one cycle earlier
+    // when indexing does not cross a page
+    const fn reads_operand(&self) -> bool {
+        matches!(
+            self,
+            Instruction::Lda
+                | Instruction::Ldx
+                | Instruction::Ldy
+                | Instruction::Adc
+                | Instruction::Sbc
+                | Instruction::And
+                | Instruction::Ora
+                | Instruction::Eor
+                | Instruction::Cmp
+                | Instruction::Bit
+                | Instruction::Lax
+                | Instruction::Las
+                | Instruction::Nop
+        )
+    }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct OpCode {
     pub code: u8,
+    pub mnemonic: &'static str,
     pub bytes: u8,
     pub cycles: u8,
     pub instruction: Instruction,
     pub address_mode: AddressingMode,
+    /// One extra cycle when the indexed address crosses a page, or when
+    /// a taken branch lands on another page (`*` in the tables below)
+    pub page_cross_penalty: bool,
+    /// Mask of the status flags the instruction may change
+    pub affected_flags: u8,
 }
 
+// Builds the table of an instruction set by decoding every byte at compile time
+macro_rules! opcode_table {
+    ($decode:path) => {{
+        let mut table = [OpCode::new(0x00, Instruction::Brk, 1, 7, AddressingMode::Implied); 256];
+        let mut code = 0;
+
+        while code < 256 {
+            table[code] = $decode(code as u8);
+            code += 1;
+        }
+
+        table
+    }};
+}
+
+/// Opcode tables indexed by the opcode byte, one per instruction set
+pub static NMOS_6502_OPCODES: [OpCode; 256] = opcode_table!(OpCode::from_byte);
+pub static ROCKWELL_R65C02_OPCODES: [OpCode; 256] = opcode_table!(OpCode::from_byte_r65c02);
+pub static WDC_W65C02S_OPCODES: [OpCode; 256] = opcode_table!(OpCode::from_byte_w65c02);
+pub static WDC_65C816_OPCODES: [OpCode; 256] = opcode_table!(OpCode::from_byte_65c816);
+
 impl OpCode {
-    fn new(
+    const fn new(
         code: u8,
         instruction: Instruction,
         bytes: u8,
         cycles: u8,
         mode: AddressingMode,
     ) -> OpCode {
+        let is_indexed = matches!(
+            mode,
+            AddressingMode::AbsoluteX
+                | AddressingMode::AbsoluteY
+                | AddressingMode::IndirectIndexedY
+        );
+
         OpCode {
             code,
+            mnemonic: instruction.mnemonic(),
             bytes,
             cycles,
             instruction,
             address_mode: mode,
+            page_cross_penalty: (is_indexed && instruction.reads_operand())
+                || matches!(mode, AddressingMode::Relative),
+            affected_flags: instruction.affected_flags(),
+        }
+    }
+
+    /// The opcode table of the instruction set of the given CPU
+    pub fn table(variant: &CpuVariant) -> &'static [OpCode; 256] {
+        match variant {
+            CpuVariant::Rockwell65C02 => &ROCKWELL_R65C02_OPCODES,
+            CpuVariant::Wdc65C02 => &WDC_W65C02S_OPCODES,
+            CpuVariant::Wdc65C816 => &WDC_65C816_OPCODES,
+            _ => &NMOS_6502_OPCODES,
         }
     }
 
+    /// Decodes an opcode using the instruction set of the given CPU
+    pub fn decode(code: u8, variant: &CpuVariant) -> &'static OpCode {
+        &OpCode::table(variant)[code as usize]
+    }
+
+    /// Decodes an opcode of the NMOS 6502 instruction set, including the unofficial opcodes
     #[rustfmt::skip]
-    pub fn from_byte(code: u8) -> OpCode {
+    pub const fn from_byte(code: u8) -> OpCode {
         match code {
             // BRK
             0x00 => OpCode::new(code, Instruction::Brk, 1, 7, AddressingMode::Implied),
@@ -118,7 +480,7 @@ impl OpCode {
             0x3D => OpCode::new(code, Instruction::And, 3, 4, AddressingMode::AbsoluteX), // *
             0x39 => OpCode::new(code, Instruction::And, 3, 4, AddressingMode::AbsoluteY), // *
             0x21 => OpCode::new(code, Instruction::And, 2, 6, AddressingMode::IndexedIndirectX),
-            0x31 => OpCode::new(code, Instruction::And, 2, 5, AddressingMode::IndexedIndirectX), // *
+            0x31 => OpCode::new(code, Instruction::And, 2, 5, AddressingMode::IndirectIndexedY), // *
             // ASL - Shift Left One Bit (Memory or Accumulator)
             0x0A => OpCode::new(code, Instruction::Asl, 1, 2, AddressingMode::Accumulator),
             0x06 => OpCode::new(code, Instruction::Asl, 2, 5, AddressingMode::ZeroPage),
@@ -203,11 +565,11 @@ impl OpCode {
             0x01 => OpCode::new(code, Instruction::Ora, 2, 6, AddressingMode::IndexedIndirectX),
             0x11 => OpCode::new(code, Instruction::Ora, 2, 5, AddressingMode::IndirectIndexedY), // *
             // ROL - Rotate One Bit Left (Memory or Accumulator)
-            0x2A => OpCode::new(code, Instruction::Rol, 1, 1, AddressingMode::Accumulator),
-            0x26 => OpCode::new(code, Instruction::Rol, 2, 2, AddressingMode::ZeroPage),
-            0x36 => OpCode::new(code, Instruction::Rol, 2, 2, AddressingMode::ZeroPageX),
-            0x2E => OpCode::new(code, Instruction::Rol, 3, 3, AddressingMode::Absolute),
-            0x3E => OpCode::new(code, Instruction::Rol, 3, 3, AddressingMode::AbsoluteX),
+            0x2A => OpCode::new(code, Instruction::Rol, 1, 2, AddressingMode::Accumulator),
+            0x26 => OpCode::new(code, Instruction::Rol, 2, 5, AddressingMode::ZeroPage),
+            0x36 => OpCode::new(code, Instruction::Rol, 2, 6, AddressingMode::ZeroPageX),
+            0x2E => OpCode::new(code, Instruction::Rol, 3, 6, AddressingMode::Absolute),
+            0x3E => OpCode::new(code, Instruction::Rol, 3, 7, AddressingMode::AbsoluteX),
             // ROR - Rotate One Bit Right (Memory or Accumulator)
             0x6A => OpCode::new(code, Instruction::Ror, 1, 2, AddressingMode::Accumulator),
             0x66 => OpCode::new(code, Instruction::Ror, 2, 5, AddressingMode::ZeroPage),
@@ -291,6 +653,8 @@ impl OpCode {
             0xD0 => OpCode::new(code, Instruction::Bne, 2, 2, AddressingMode::Relative),
             // RTS 
             0x60 => OpCode::new(code, Instruction::Rts, 1, 6, AddressingMode::Implied),
+            // RTI
+            0x40 => OpCode::new(code, Instruction::Rti, 1, 6, AddressingMode::Implied),
             // PHA 
             0x48 => OpCode::new(code, Instruction::Pha, 1, 3, AddressingMode::Implied),
             // PHP 
@@ -302,8 +666,432 @@ impl OpCode {
             // BIT 
             0x24 => OpCode::new(code, Instruction::Bit, 2,3, AddressingMode::ZeroPage),
             0x2C => OpCode::new(code, Instruction::Bit, 3, 4, AddressingMode::Absolute),
+            // NOP
+            0xEA => OpCode::new(code, Instruction::Nop, 1, 2, AddressingMode::Implied),
+
+            // Unofficial opcodes
+            // NOP - implied, immediate, zero page and absolute variants
+            0x1A | 0x3A | 0x5A | 0x7A | 0xDA | 0xFA => OpCode::new(code, Instruction::Nop, 1, 2, AddressingMode::Implied),
+            0x80 | 0x82 | 0x89 | 0xC2 | 0xE2 => OpCode::new(code, Instruction::Nop, 2, 2, AddressingMode::Immediate),
+            0x04 | 0x44 | 0x64 => OpCode::new(code, Instruction::Nop, 2, 3, AddressingMode::ZeroPage),
+            0x14 | 0x34 | 0x54 | 0x74 | 0xD4 | 0xF4 => OpCode::new(code, Instruction::Nop, 2, 4, AddressingMode::ZeroPageX),
+            0x0C => OpCode::new(code, Instruction::Nop, 3, 4, AddressingMode::Absolute),
+            0x1C | 0x3C | 0x5C | 0x7C | 0xDC | 0xFC => OpCode::new(code, Instruction::Nop, 3, 4, AddressingMode::AbsoluteX), // *
+            // SLO - ASL Memory, then OR with Accumulator
+            0x07 => OpCode::new(code, Instruction::Slo, 2, 5, AddressingMode::ZeroPage),
+            0x17 => OpCode::new(code, Instruction::Slo, 2, 6, AddressingMode::ZeroPageX),
+            0x0F => OpCode::new(code, Instruction::Slo, 3, 6, AddressingMode::Absolute),
+            0x1F => OpCode::new(code, Instruction::Slo, 3, 7, AddressingMode::AbsoluteX),
+            0x1B => OpCode::new(code, Instruction::Slo, 3, 7, AddressingMode::AbsoluteY),
+            0x03 => OpCode::new(code, Instruction::Slo, 2, 8, AddressingMode::IndexedIndirectX),
+            0x13 => OpCode::new(code, Instruction::Slo, 2, 8, AddressingMode::IndirectIndexedY),
+            // RLA - ROL Memory, then AND with Accumulator
+            0x27 => OpCode::new(code, Instruction::Rla, 2, 5, AddressingMode::ZeroPage),
+            0x37 => OpCode::new(code, Instruction::Rla, 2, 6, AddressingMode::ZeroPageX),
+            0x2F => OpCode::new(code, Instruction::Rla, 3, 6, AddressingMode::Absolute),
+            0x3F => OpCode::new(code, Instruction::Rla, 3, 7, AddressingMode::AbsoluteX),
+            0x3B => OpCode::new(code, Instruction::Rla, 3, 7, AddressingMode::AbsoluteY),
+            0x23 => OpCode::new(code, Instruction::Rla, 2, 8, AddressingMode::IndexedIndirectX),
+            0x33 => OpCode::new(code, Instruction::Rla, 2, 8, AddressingMode::IndirectIndexedY),
+            // SRE - LSR Memory, then EOR with Accumulator
+            0x47 => OpCode::new(code, Instruction::Sre, 2, 5, AddressingMode::ZeroPage),
+            0x57 => OpCode::new(code, Instruction::Sre, 2, 6, AddressingMode::ZeroPageX),
+            0x4F => OpCode::new(code, Instruction::Sre, 3, 6, AddressingMode::Absolute),
+            0x5F => OpCode::new(code, Instruction::Sre, 3, 7, AddressingMode::AbsoluteX),
+            0x5B => OpCode::new(code, Instruction::Sre, 3, 7, AddressingMode::AbsoluteY),
+            0x43 => OpCode::new(code, Instruction::Sre, 2, 8, AddressingMode::IndexedIndirectX),
+            0x53 => OpCode::new(code, Instruction::Sre, 2, 8, AddressingMode::IndirectIndexedY),
+            // RRA - ROR Memory, then ADC to Accumulator
+            0x67 => OpCode::new(code, Instruction::Rra, 2, 5, AddressingMode::ZeroPage),
+            0x77 => OpCode::new(code, Instruction::Rra, 2, 6, AddressingMode::ZeroPageX),
+            0x6F => OpCode::new(code, Instruction::Rra, 3, 6, AddressingMode::Absolute),
+            0x7F => OpCode::new(code, Instruction::Rra, 3, 7, AddressingMode::AbsoluteX),
+            0x7B => OpCode::new(code, Instruction::Rra, 3, 7, AddressingMode::AbsoluteY),
+            0x63 => OpCode::new(code, Instruction::Rra, 2, 8, AddressingMode::IndexedIndirectX),
+            0x73 => OpCode::new(code, Instruction::Rra, 2, 8, AddressingMode::IndirectIndexedY),
+            // SAX - Store Accumulator AND Index X
+            0x87 => OpCode::new(code, Instruction::Sax, 2, 3, AddressingMode::ZeroPage),
+            0x97 => OpCode::new(code, Instruction::Sax, 2, 4, AddressingMode::ZeroPageY),
+            0x8F => OpCode::new(code, Instruction::Sax, 3, 4, AddressingMode::Absolute),
+            0x83 => OpCode::new(code, Instruction::Sax, 2, 6, AddressingMode::IndexedIndirectX),
+            // LAX - Load Accumulator and Index X with Memory
+            0xA7 => OpCode::new(code, Instruction::Lax, 2, 3, AddressingMode::ZeroPage),
+            0xB7 => OpCode::new(code, Instruction::Lax, 2, 4, AddressingMode::ZeroPageY),
+            0xAF => OpCode::new(code, Instruction::Lax, 3, 4, AddressingMode::Absolute),
+            0xBF => OpCode::new(code, Instruction::Lax, 3, 4, AddressingMode::AbsoluteY), // *
+            0xA3 => OpCode::new(code, Instruction::Lax, 2, 6, AddressingMode::IndexedIndirectX),
+            0xB3 => OpCode::new(code, Instruction::Lax, 2, 5, AddressingMode::IndirectIndexedY), // *
+            // DCP - DEC Memory, then CMP with Accumulator
+            0xC7 => OpCode::new(code, Instruction::Dcp, 2, 5, AddressingMode::ZeroPage),
+            0xD7 => OpCode::new(code, Instruction::Dcp, 2, 6, AddressingMode::ZeroPageX),
+            0xCF => OpCode::new(code, Instruction::Dcp, 3, 6, AddressingMode::Absolute),
+            0xDF => OpCode::new(code, Instruction::Dcp, 3, 7, AddressingMode::AbsoluteX),
+            0xDB => OpCode::new(code, Instruction::Dcp, 3, 7, AddressingMode::AbsoluteY),
+            0xC3 => OpCode::new(code, Instruction::Dcp, 2, 8, AddressingMode::IndexedIndirectX),
+            0xD3 => OpCode::new(code, Instruction::Dcp, 2, 8, AddressingMode::IndirectIndexedY),
+            // ISC - INC Memory, then SBC from Accumulator
+            0xE7 => OpCode::new(code, Instruction::Isc, 2, 5, AddressingMode::ZeroPage),
+            0xF7 => OpCode::new(code, Instruction::Isc, 2, 6, AddressingMode::ZeroPageX),
+            0xEF => OpCode::new(code, Instruction::Isc, 3, 6, AddressingMode::Absolute),
+            0xFF => OpCode::new(code, Instruction::Isc, 3, 7, AddressingMode::AbsoluteX),
+            0xFB => OpCode::new(code, Instruction::Isc, 3, 7, AddressingMode::AbsoluteY),
+            0xE3 => OpCode::new(code, Instruction::Isc, 2, 8, AddressingMode::IndexedIndirectX),
+            0xF3 => OpCode::new(code, Instruction::Isc, 2, 8, AddressingMode::IndirectIndexedY),
+            // ANC - AND with Accumulator, then copy Negative to Carry
+            0x0B | 0x2B => OpCode::new(code, Instruction::Anc, 2, 2, AddressingMode::Immediate),
+            // ALR - AND with Accumulator, then LSR
+            0x4B => OpCode::new(code, Instruction::Alr, 2, 2, AddressingMode::Immediate),
+            // ARR - AND with Accumulator, then ROR
+            0x6B => OpCode::new(code, Instruction::Arr, 2, 2, AddressingMode::Immediate),
+            // SBX - (Accumulator AND Index X) minus Memory into Index X
+            0xCB => OpCode::new(code, Instruction::Sbx, 2, 2, AddressingMode::Immediate),
+            // USBC - same as SBC immediate
+            0xEB => OpCode::new(code, Instruction::Sbc, 2, 2, AddressingMode::Immediate),
+            // XAA - (Accumulator OR Magic) AND Index X AND Memory into Accumulator
+            0x8B => OpCode::new(code, Instruction::Xaa, 2, 2, AddressingMode::Immediate),
+            // LXA - (Accumulator OR Magic) AND Memory into Accumulator and Index X
+            0xAB => OpCode::new(code, Instruction::Lxa, 2, 2, AddressingMode::Immediate),
+            // AHX - Store Accumulator AND Index X AND (High Byte + 1)
+            0x9F => OpCode::new(code, Instruction::Ahx, 3, 5, AddressingMode::AbsoluteY),
+            0x93 => OpCode::new(code, Instruction::Ahx, 2, 6, AddressingMode::IndirectIndexedY),
+            // TAS - Accumulator AND Index X into Stack Pointer, then store like AHX
+            0x9B => OpCode::new(code, Instruction::Tas, 3, 5, AddressingMode::AbsoluteY),
+            // SHY - Store Index Y AND (High Byte + 1)
+            0x9C => OpCode::new(code, Instruction::Shy, 3, 5, AddressingMode::AbsoluteX),
+            // SHX - Store Index X AND (High Byte + 1)
+            0x9E => OpCode::new(code, Instruction::Shx, 3, 5, AddressingMode::AbsoluteY),
+            // LAS - Memory AND Stack Pointer into Accumulator, Index X and Stack Pointer
+            0xBB => OpCode::new(code, Instruction::Las, 3, 4, AddressingMode::AbsoluteY), // *
+            // JAM - freeze the CPU
+            0x02 | 0x12 | 0x22 | 0x32 | 0x42 | 0x52 | 0x62 | 0x72 | 0x92 | 0xB2 | 0xD2 | 0xF2 => {
+                OpCode::new(code, Instruction::Jam, 1, 2, AddressingMode::Implied)
+            }
+        }
+    }
+
+    /// Decodes an opcode of the WDC 65C02. The opcodes the NMOS 6502 left
+    /// undefined are either new instructions or NOPs of various lengths.
+    #[rustfmt::skip]
+    pub const fn from_byte_65c02(code: u8) -> OpCode {
+        match code {
+            // (zp) addressing for the accumulator operations
+            0x12 => OpCode::new(code, Instruction::Ora, 2, 5, AddressingMode::ZeroPageIndirect),
+            0x32 => OpCode::new(code, Instruction::And, 2, 5, AddressingMode::ZeroPageIndirect),
+            0x52 => OpCode::new(code, Instruction::Eor, 2, 5, AddressingMode::ZeroPageIndirect),
+            0x72 => OpCode::new(code, Instruction::Adc, 2, 5, AddressingMode::ZeroPageIndirect),
+            0x92 => OpCode::new(code, Instruction::Sta, 2, 5, AddressingMode::ZeroPageIndirect),
+            0xB2 => OpCode::new(code, Instruction::Lda, 2, 5, AddressingMode::ZeroPageIndirect),
+            0xD2 => OpCode::new(code, Instruction::Cmp, 2, 5, AddressingMode::ZeroPageIndirect),
+            0xF2 => OpCode::new(code, Instruction::Sbc, 2, 5, AddressingMode::ZeroPageIndirect),
+            // BIT - new addressing modes
+            0x89 => OpCode::new(code, Instruction::Bit, 2, 2, AddressingMode::Immediate),
+            0x34 => OpCode::new(code, Instruction::Bit, 2, 4, AddressingMode::ZeroPageX),
+            0x3C => OpCode::new(code, Instruction::Bit, 3, 4, AddressingMode::AbsoluteX), // *
+            // INC/DEC - Accumulator
+            0x1A => OpCode::new(code, Instruction::Inc, 1, 2, AddressingMode::Accumulator),
+            0x3A => OpCode::new(code, Instruction::Dec, 1, 2, AddressingMode::Accumulator),
+            // JMP - Absolute Indexed Indirect, without the page wrapping bug
+            0x6C => OpCode::new(code, Instruction::Jmp, 3, 6, AddressingMode::Indirect),
+            0x7C => OpCode::new(code, Instruction::Jmp, 3, 6, AddressingMode::AbsoluteIndexedIndirect),
+            // BRA - Branch Always
+            0x80 => OpCode::new(code, Instruction::Bra, 2, 2, AddressingMode::Relative),
+            // STZ - Store Zero in Memory
+            0x64 => OpCode::new(code, Instruction::Stz, 2, 3, AddressingMode::ZeroPage),
+            0x74 => OpCode::new(code, Instruction::Stz, 2, 4, AddressingMode::ZeroPageX),
+            0x9C => OpCode::new(code, Instruction::Stz, 3, 4, AddressingMode::Absolute),
+            0x9E => OpCode::new(code, Instruction::Stz, 3, 5, AddressingMode::AbsoluteX),
+            // PHX, PLX, PHY, PLY
+            0xDA => OpCode::new(code, Instruction::Phx, 1, 3, AddressingMode::Implied),
+            0xFA => OpCode::new(code, Instruction::Plx, 1, 4, AddressingMode::Implied),
+            0x5A => OpCode::new(code, Instruction::Phy, 1, 3, AddressingMode::Implied),
+            0x7A => OpCode::new(code, Instruction::Ply, 1, 4, AddressingMode::Implied),
+            // TRB - Test and Reset Memory Bits with Accumulator
+            0x14 => OpCode::new(code, Instruction::Trb, 2, 5, AddressingMode::ZeroPage),
+            0x1C => OpCode::new(code, Instruction::Trb, 3, 6, AddressingMode::Absolute),
+            // TSB - Test and Set Memory Bits with Accumulator
+            0x04 => OpCode::new(code, Instruction::Tsb, 2, 5, AddressingMode::ZeroPage),
+            0x0C => OpCode::new(code, Instruction::Tsb, 3, 6, AddressingMode::Absolute),
+
+            // Reserved opcodes are NOPs
+            0x02 | 0x22 | 0x42 | 0x62 | 0x82 | 0xC2 | 0xE2 => OpCode::new(code, Instruction::Nop, 2, 2, AddressingMode::Immediate),
+            0x44 => OpCode::new(code, Instruction::Nop, 2, 3, AddressingMode::ZeroPage),
+            0x54 | 0xD4 | 0xF4 => OpCode::new(code, Instruction::Nop, 2, 4, AddressingMode::ZeroPageX),
+            0x5C => OpCode::new(code, Instruction::Nop, 3, 8, AddressingMode::Absolute),
+            0xDC | 0xFC => OpCode::new(code, Instruction::Nop, 3, 4, AddressingMode::Absolute),
+            _ if code & 0x03 == 0x03 => OpCode::new(code, Instruction::Nop, 1, 1, AddressingMode::Implied),
+
+            _ => OpCode::from_byte(code),
+        }
+    }
+
+    /// Decodes an opcode of the Rockwell R65C02, which adds the bit operations to the 65C02
+    #[rustfmt::skip]
+    pub const fn from_byte_r65c02(code: u8) -> OpCode {
+        match code {
+            // RMB0-7 - Reset Memory Bit
+            0x07 | 0x17 | 0x27 | 0x37 | 0x47 | 0x57 | 0x67 | 0x77 => OpCode::new(code, Instruction::Rmb, 2, 5, AddressingMode::ZeroPage),
+            // SMB0-7 - Set Memory Bit
+            0x87 | 0x97 | 0xA7 | 0xB7 | 0xC7 | 0xD7 | 0xE7 | 0xF7 => OpCode::new(code, Instruction::Smb, 2, 5, AddressingMode::ZeroPage),
+            // BBR0-7 - Branch on Bit Reset
+            0x0F | 0x1F | 0x2F | 0x3F | 0x4F | 0x5F | 0x6F | 0x7F => OpCode::new(code, Instruction::Bbr, 3, 5, AddressingMode::ZeroPageRelative),
+            // BBS0-7 - Branch on Bit Set
+            0x8F | 0x9F | 0xAF | 0xBF | 0xCF | 0xDF | 0xEF | 0xFF => OpCode::new(code, Instruction::Bbs, 3, 5, AddressingMode::ZeroPageRelative),
 
-            _ => panic!("Opcode not found! Opcode: {:x}", code)
+            _ => OpCode::from_byte_65c02(code),
         }
     }
+
+    /// Decodes an opcode of the WDC W65C02S, which adds WAI and STP to the Rockwell set
+    #[rustfmt::skip]
+    pub const fn from_byte_w65c02(code: u8) -> OpCode {
+        match code {
+            // WAI - Wait for Interrupt
+            0xCB => OpCode::new(code, Instruction::Wai, 1, 3, AddressingMode::Implied),
+            // STP - Stop the Clock
+            0xDB => OpCode::new(code, Instruction::Stp, 1, 3, AddressingMode::Implied),
+
+            _ => OpCode::from_byte_r65c02(code),
+        }
+    }
+
+    /// Decodes an opcode of the WDC 65C816, which uses every slot left free by the 65C02.
+    /// Cycles are given for 8-bit registers, the 16-bit modes add their own penalties.
+    #[rustfmt::skip]
+    pub const fn from_byte_65c816(code: u8) -> OpCode {
+        match code {
+            // Stack Relative - sr,S
+            0x03 => OpCode::new(code, Instruction::Ora, 2, 4, AddressingMode::StackRelative),
+            0x23 => OpCode::new(code, Instruction::And, 2, 4, AddressingMode::StackRelative),
+            0x43 => OpCode::new(code, Instruction::Eor, 2, 4, AddressingMode::StackRelative),
+            0x63 => OpCode::new(code, Instruction::Adc, 2, 4, AddressingMode::StackRelative),
+            0x83 => OpCode::new(code, Instruction::Sta, 2, 4, AddressingMode::StackRelative),
+            0xA3 => OpCode::new(code, Instruction::Lda, 2, 4, AddressingMode::StackRelative),
+            0xC3 => OpCode::new(code, Instruction::Cmp, 2, 4, AddressingMode::StackRelative),
+            0xE3 => OpCode::new(code, Instruction::Sbc, 2, 4, AddressingMode::StackRelative),
+            // Stack Relative Indirect Indexed - (sr,S),Y
+            0x13 => OpCode::new(code, Instruction::Ora, 2, 7, AddressingMode::StackRelativeIndirectY),
+            0x33 => OpCode::new(code, Instruction::And, 2, 7, AddressingMode::StackRelativeIndirectY),
+            0x53 => OpCode::new(code, Instruction::Eor, 2, 7, AddressingMode::StackRelativeIndirectY),
+            0x73 => OpCode::new(code, Instruction::Adc, 2, 7, AddressingMode::StackRelativeIndirectY),
+            0x93 => OpCode::new(code, Instruction::Sta, 2, 7, AddressingMode::StackRelativeIndirectY),
+            0xB3 => OpCode::new(code, Instruction::Lda, 2, 7, AddressingMode::StackRelativeIndirectY),
+            0xD3 => OpCode::new(code, Instruction::Cmp, 2, 7, AddressingMode::StackRelativeIndirectY),
+            0xF3 => OpCode::new(code, Instruction::Sbc, 2, 7, AddressingMode::StackRelativeIndirectY),
+            // Direct Page Indirect Long - [dp]
+            0x07 => OpCode::new(code, Instruction::Ora, 2, 6, AddressingMode::ZeroPageIndirectLong),
+            0x27 => OpCode::new(code, Instruction::And, 2, 6, AddressingMode::ZeroPageIndirectLong),
+            0x47 => OpCode::new(code, Instruction::Eor, 2, 6, AddressingMode::ZeroPageIndirectLong),
+            0x67 => OpCode::new(code, Instruction::Adc, 2, 6, AddressingMode::ZeroPageIndirectLong),
+            0x87 => OpCode::new(code, Instruction::Sta, 2, 6, AddressingMode::ZeroPageIndirectLong),
+            0xA7 => OpCode::new(code, Instruction::Lda, 2, 6, AddressingMode::ZeroPageIndirectLong),
+            0xC7 => OpCode::new(code, Instruction::Cmp, 2, 6, AddressingMode::ZeroPageIndirectLong),
+            0xE7 => OpCode::new(code, Instruction::Sbc, 2, 6, AddressingMode::ZeroPageIndirectLong),
+            // Direct Page Indirect Long Indexed - [dp],Y
+            0x17 => OpCode::new(code, Instruction::Ora, 2, 6, AddressingMode::ZeroPageIndirectLongY),
+            0x37 => OpCode::new(code, Instruction::And, 2, 6, AddressingMode::ZeroPageIndirectLongY),
+            0x57 => OpCode::new(code, Instruction::Eor, 2, 6, AddressingMode::ZeroPageIndirectLongY),
+            0x77 => OpCode::new(code, Instruction::Adc, 2, 6, AddressingMode::ZeroPageIndirectLongY),
+            0x97 => OpCode::new(code, Instruction::Sta, 2, 6, AddressingMode::ZeroPageIndirectLongY),
+            0xB7 => OpCode::new(code, Instruction::Lda, 2, 6, AddressingMode::ZeroPageIndirectLongY),
+            0xD7 => OpCode::new(code, Instruction::Cmp, 2, 6, AddressingMode::ZeroPageIndirectLongY),
+            0xF7 => OpCode::new(code, Instruction::Sbc, 2, 6, AddressingMode::ZeroPageIndirectLongY),
+            // Absolute Long - long
+            0x0F => OpCode::new(code, Instruction::Ora, 4, 5, AddressingMode::AbsoluteLong),
+            0x2F => OpCode::new(code, Instruction::And, 4, 5, AddressingMode::AbsoluteLong),
+            0x4F => OpCode::new(code, Instruction::Eor, 4, 5, AddressingMode::AbsoluteLong),
+            0x6F => OpCode::new(code, Instruction::Adc, 4, 5, AddressingMode::AbsoluteLong),
+            0x8F => OpCode::new(code, Instruction::Sta, 4, 5, AddressingMode::AbsoluteLong),
+            0xAF => OpCode::new(code, Instruction::Lda, 4, 5, AddressingMode::AbsoluteLong),
+            0xCF => OpCode::new(code, Instruction::Cmp, 4, 5, AddressingMode::AbsoluteLong),
+            0xEF => OpCode::new(code, Instruction::Sbc, 4, 5, AddressingMode::AbsoluteLong),
+            // Absolute Long Indexed - long,X
+            0x1F => OpCode::new(code, Instruction::Ora, 4, 5, AddressingMode::AbsoluteLongX),
+            0x3F => OpCode::new(code, Instruction::And, 4, 5, AddressingMode::AbsoluteLongX),
+            0x5F => OpCode::new(code, Instruction::Eor, 4, 5, AddressingMode::AbsoluteLongX),
+            0x7F => OpCode::new(code, Instruction::Adc, 4, 5, AddressingMode::AbsoluteLongX),
+            0x9F => OpCode::new(code, Instruction::Sta, 4, 5, AddressingMode::AbsoluteLongX),
+            0xBF => OpCode::new(code, Instruction::Lda, 4, 5, AddressingMode::AbsoluteLongX),
+            0xDF => OpCode::new(code, Instruction::Cmp, 4, 5, AddressingMode::AbsoluteLongX),
+            0xFF => OpCode::new(code, Instruction::Sbc, 4, 5, AddressingMode::AbsoluteLongX),
+            // XCE, REP, SEP - Mode and register width control
+            0xFB => OpCode::new(code, Instruction::Xce, 1, 2, AddressingMode::Implied),
+            0xC2 => OpCode::new(code, Instruction::Rep, 2, 3, AddressingMode::Immediate),
+            0xE2 => OpCode::new(code, Instruction::Sep, 2, 3, AddressingMode::Immediate),
+            // XBA, TCD, TDC, TCS, TSC, TXY, TYX
+            0xEB => OpCode::new(code, Instruction::Xba, 1, 3, AddressingMode::Implied),
+            0x5B => OpCode::new(code, Instruction::Tcd, 1, 2, AddressingMode::Implied),
+            0x7B => OpCode::new(code, Instruction::Tdc, 1, 2, AddressingMode::Implied),
+            0x1B => OpCode::new(code, Instruction::Tcs, 1, 2, AddressingMode::Implied),
+            0x3B => OpCode::new(code, Instruction::Tsc, 1, 2, AddressingMode::Implied),
+            0x9B => OpCode::new(code, Instruction::Txy, 1, 2, AddressingMode::Implied),
+            0xBB => OpCode::new(code, Instruction::Tyx, 1, 2, AddressingMode::Implied),
+            // PHB, PLB, PHD, PLD, PHK
+            0x8B => OpCode::new(code, Instruction::Phb, 1, 3, AddressingMode::Implied),
+            0xAB => OpCode::new(code, Instruction::Plb, 1, 4, AddressingMode::Implied),
+            0x0B => OpCode::new(code, Instruction::Phd, 1, 4, AddressingMode::Implied),
+            0x2B => OpCode::new(code, Instruction::Pld, 1, 5, AddressingMode::Implied),
+            0x4B => OpCode::new(code, Instruction::Phk, 1, 3, AddressingMode::Implied),
+            // PEA, PEI, PER - Push Effective Address
+            0xF4 => OpCode::new(code, Instruction::Pea, 3, 5, AddressingMode::Absolute),
+            0xD4 => OpCode::new(code, Instruction::Pei, 2, 6, AddressingMode::ZeroPageIndirect),
+            0x62 => OpCode::new(code, Instruction::Per, 3, 6, AddressingMode::RelativeLong),
+            // JMP, JML, JSR, JSL, RTL, BRL
+            0x6C => OpCode::new(code, Instruction::Jmp, 3, 5, AddressingMode::Indirect),
+            0x5C => OpCode::new(code, Instruction::Jml, 4, 4, AddressingMode::AbsoluteLong),
+            0xDC => OpCode::new(code, Instruction::Jml, 3, 6, AddressingMode::AbsoluteIndirectLong),
+            0xFC => OpCode::new(code, Instruction::Jsr, 3, 8, AddressingMode::AbsoluteIndexedIndirect),
+            0x22 => OpCode::new(code, Instruction::Jsl, 4, 8, AddressingMode::AbsoluteLong),
+            0x6B => OpCode::new(code, Instruction::Rtl, 1, 6, AddressingMode::Implied),
+            0x82 => OpCode::new(code, Instruction::Brl, 3, 4, AddressingMode::RelativeLong),
+            // MVN, MVP - Block Move, the cycles are spent for every byte
+            0x54 => OpCode::new(code, Instruction::Mvn, 3, 7, AddressingMode::BlockMove),
+            0x44 => OpCode::new(code, Instruction::Mvp, 3, 7, AddressingMode::BlockMove),
+            // COP, WDM
+            0x02 => OpCode::new(code, Instruction::Cop, 2, 7, AddressingMode::Immediate),
+            0x42 => OpCode::new(code, Instruction::Wdm, 2, 2, AddressingMode::Immediate),
+            // WAI, STP
+            0xCB => OpCode::new(code, Instruction::Wai, 1, 3, AddressingMode::Implied),
+            0xDB => OpCode::new(code, Instruction::Stp, 1, 3, AddressingMode::Implied),
+
+            _ => OpCode::from_byte_65c02(code),
+        }
+    }
+}
+
+#[cfg(test)]
+mod opcode_tests {
+    use super::{Instruction, OpCode};
+    use crate::cpu::{CpuVariant, StatusFlag};
+
+    #[test]
+    fn decode_every_byte_test() {
+        for code in 0..=0xFF {
+            let opcode = OpCode::from_byte(code);
+
+            assert_eq!(opcode.code, code);
+            assert!((1..=3).contains(&opcode.bytes));
+        }
+    }
+
+    #[test]
+    fn cmos_has_no_unofficial_opcodes_test() {
+        for code in 0..=0xFF {
+            let opcode = OpCode::from_byte_65c02(code);
+
+            assert!(
+                !matches!(
+                    opcode.instruction,
+                    Instruction::Slo
+                        | Instruction::Rla
+                        | Instruction::Sre
+                        | Instruction::Rra
+                        | Instruction::Sax
+                        | Instruction::Lax
+                        | Instruction::Dcp
+                        | Instruction::Isc
+                        | Instruction::Anc
+                        | Instruction::Alr
+                        | Instruction::Arr
+                        | Instruction::Sbx
+                        | Instruction::Xaa
+                        | Instruction::Lxa
+                        | Instruction::Ahx
+                        | Instruction::Tas
+                        | Instruction::Shx
+                        | Instruction::Shy
+                        | Instruction::Las
+                        | Instruction::Jam
+                ),
+                "Opcode {:02X} is not defined on the 65C02",
+                code
+            );
+        }
+    }
+
+    #[test]
+    fn bit_operations_test() {
+        for code in 0..=0xFF {
+            let bit_operation = matches!(
+                OpCode::from_byte_r65c02(code).instruction,
+                Instruction::Rmb | Instruction::Smb | Instruction::Bbr | Instruction::Bbs
+            );
+
+            assert_eq!(bit_operation, code & 0x07 == 0x07, "Opcode {:02X}", code);
+        }
+
+        assert!(matches!(
+            OpCode::from_byte_r65c02(0xCB).instruction,
+            Instruction::Nop
+        ));
+        assert!(matches!(
+            OpCode::from_byte_w65c02(0xCB).instruction,
+            Instruction::Wai
+        ));
+        assert!(matches!(
+            OpCode::from_byte_w65c02(0xDB).instruction,
+            Instruction::Stp
+        ));
+    }
+
+    #[test]
+    fn wdc_65c816_opcodes_test() {
+        for code in 0..=0xFF {
+            let opcode = OpCode::from_byte_65c816(code);
+
+            // Every slot is a real instruction, the only NOP is $EA
+            assert_eq!(matches!(opcode.instruction, Instruction::Nop), code == 0xEA);
+            assert!((1..=4).contains(&opcode.bytes));
+        }
+    }
+
+    #[test]
+    fn jam_opcodes_test() {
+        let jams = (0..=0xFF)
+            .filter(|&code| matches!(OpCode::from_byte(code).instruction, Instruction::Jam))
+            .count();
+
+        assert_eq!(jams, 12);
+    }
+
+    #[test]
+    fn opcode_tables_test() {
+        for code in 0..=0xFF {
+            assert_eq!(
+                OpCode::decode(code, &CpuVariant::Ricoh2A03),
+                &OpCode::from_byte(code)
+            );
+            assert_eq!(
+                OpCode::decode(code, &CpuVariant::Rockwell65C02),
+                &OpCode::from_byte_r65c02(code)
+            );
+            assert_eq!(
+                OpCode::decode(code, &CpuVariant::Wdc65C02),
+                &OpCode::from_byte_w65c02(code)
+            );
+            assert_eq!(
+                OpCode::decode(code, &CpuVariant::Wdc65C816),
+                &OpCode::from_byte_65c816(code)
+            );
+        }
+    }
+
+    #[test]
+    fn opcode_metadata_test() {
+        let lda = OpCode::decode(0xBD, &CpuVariant::Nmos6502);
+        let sta = OpCode::decode(0x9D, &CpuVariant::Nmos6502);
+        let bne = OpCode::decode(0xD0, &CpuVariant::Nmos6502);
+
+        assert_eq!(lda.mnemonic, "LDA");
+        assert!(lda.page_cross_penalty);
+        assert_eq!(
+            lda.affected_flags,
+            StatusFlag::Negative.bit_shift() | StatusFlag::Zero.bit_shift()
+        );
+
+        assert_eq!(sta.mnemonic, "STA");
+        assert!(!sta.page_cross_penalty);
+        assert_eq!(sta.affected_flags, 0);
+
+        assert!(bne.page_cross_penalty);
+    }
 }
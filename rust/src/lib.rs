@@ -0,0 +1,4 @@
+pub mod bus;
+pub mod cpu;
+pub mod instructions;
+pub mod nes;
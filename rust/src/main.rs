@@ -1,4 +1 @@
-mod cpu;
-mod instructions;
-
 fn main() {}
@@ -0,0 +1,693 @@
+pub mod cartridge;
+pub mod mapper;
+pub mod ppu;
+
+use crate::bus::{Bus, Device, MemoryMap, Ram};
+use crate::cpu::{Cpu, CpuError, CpuVariant, Machine};
+use cartridge::{Cartridge, CartridgeError, Mirroring};
+use mapper::{Mapper, MapperId, MapperRegistry, StateError, StateReader, StateWriter};
+use ppu::Ppu;
+use std::{error, fmt, fs, io};
+
+const RAM_SIZE: usize = 0x0800;
+
+// The PPU draws 3 dots per CPU cycle
+const DOTS_PER_CYCLE: u32 = 3;
+
+/// The address space of the 2A03 in a NES:
+///
+/// | Range         | Contents                                    |
+/// |---------------|---------------------------------------------|
+/// | $0000-$1FFF   | 2 KiB of internal RAM, mirrored 4 times     |
+/// | $2000-$3FFF   | the 8 PPU registers, mirrored every 8 bytes |
+/// | $4000-$401F   | APU and I/O registers                       |
+/// | $4020-$FFFF   | the cartridge, usually PRG-RAM at $6000 and |
+/// |               | PRG-ROM at $8000 with its mapper registers  |
+///
+/// The cartridge space is open bus where the mapper does not answer.
+pub struct NesBus {
+    map: MemoryMap<Chip>,
+    ram: Ram,
+    ppu: Ppu,
+    io_registers: [u8; 0x20],
+    mapper: Option<Box<dyn Mapper>>,
+    mappers: MapperRegistry,
+}
+
+// The chips of the board that the memory map decodes the addresses to
+#[derive(Clone, Copy)]
+enum Chip {
+    Ram,
+    PpuRegisters,
+    IoRegisters,
+    Cartridge,
+}
+
+impl Default for NesBus {
+    fn default() -> Self {
+        // The cartridge is on the whole bus, under the chips of the console
+        let mut map = MemoryMap::default();
+        map.map_handle(0x0000, 0xFFFF, 0xFFFF, Chip::Cartridge);
+        map.map_handle(0x0000, 0x1FFF, 0x07FF, Chip::Ram);
+        map.map_handle(0x2000, 0x3FFF, 0x0007, Chip::PpuRegisters);
+        map.map_handle(0x4000, 0x401F, 0x001F, Chip::IoRegisters);
+
+        NesBus {
+            map,
+            ram: Ram::new(RAM_SIZE),
+            ppu: Ppu::default(),
+            io_registers: [0; 0x20],
+            mapper: None,
+            mappers: MapperRegistry::default(),
+        }
+    }
+}
+
+impl NesBus {
+    /// Plugs the cartridge in through the mapper of its board. The trainer is
+    /// copied to $7000.
+    pub fn insert_cartridge(&mut self, cartridge: Cartridge) -> Result<(), CartridgeError> {
+        self.insert_cartridge_as(&MapperId::INes(cartridge.mapper), cartridge)
+    }
+
+    /// Plugs the cartridge in through the board registered with `id`, for
+    /// boards that have no iNES number or use another one
+    pub fn insert_cartridge_as(
+        &mut self,
+        id: &MapperId,
+        mut cartridge: Cartridge,
+    ) -> Result<(), CartridgeError> {
+        let trainer = cartridge.trainer.take();
+        let mut mapper = self.mappers.create_as(id, cartridge)?;
+
+        for (address, &data) in (0x7000..).zip(trainer.iter().flatten()) {
+            mapper.cpu_write(address, data);
+        }
+
+        self.insert_mapper(mapper);
+
+        Ok(())
+    }
+
+    /// Plugs a board that was built by hand
+    pub fn insert_mapper(&mut self, mapper: Box<dyn Mapper>) {
+        self.mapper = Some(mapper);
+    }
+
+    /// Adds a board to those the cartridges can use, or replaces one
+    pub fn register_mapper(
+        &mut self,
+        id: MapperId,
+        constructor: impl Fn(Cartridge) -> Result<Box<dyn Mapper>, CartridgeError> + 'static,
+    ) {
+        self.mappers.register(id, constructor);
+    }
+
+    pub fn ppu(&self) -> &Ppu {
+        &self.ppu
+    }
+
+    /// The memory, the PPU and the cartridge registers, the CPU is saved apart
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = StateWriter::new();
+        state.write(self.ram.data());
+        self.ppu.save_state(&mut state);
+        state.write(&self.io_registers);
+        state.write(&self.map.open_bus());
+        state.write(&self.mapper.is_some());
+        if let Some(mapper) = &self.mapper {
+            mapper.save_state(&mut state);
+        }
+
+        state.into_bytes()
+    }
+
+    /// Restores a state saved with the same cartridge inserted
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let mut state = StateReader::new(data);
+        state.read(self.ram.data_mut())?;
+        self.ppu.load_state(&mut state)?;
+        state.read(&mut self.io_registers)?;
+
+        let mut open_bus = 0u8;
+        state.read(&mut open_bus)?;
+        self.map.set_open_bus(open_bus);
+
+        let mut has_mapper = false;
+        state.read(&mut has_mapper)?;
+        match (has_mapper, &mut self.mapper) {
+            (true, Some(mapper)) => mapper.load_state(&mut state)?,
+            (false, None) => {}
+            _ => return Err(StateError::InvalidValue),
+        }
+
+        state.finish()
+    }
+
+    /// Reads the pattern tables at $0000-$1FFF of the PPU address space
+    pub fn ppu_read(&mut self, address: u16) -> u8 {
+        match &mut self.mapper {
+            Some(mapper) => mapper.ppu_read(address),
+            None => 0,
+        }
+    }
+
+    pub fn ppu_peek(&self, address: u16) -> u8 {
+        self.mapper
+            .as_ref()
+            .map_or(0, |mapper| mapper.ppu_peek(address))
+    }
+
+    pub fn ppu_write(&mut self, address: u16, data: u8) {
+        if let Some(mapper) = &mut self.mapper {
+            mapper.ppu_write(address, data);
+        }
+    }
+
+    /// Lets the cartridge answer a read of the nametables at $2000-$2FFF,
+    /// `None` when they are in the console VRAM
+    pub fn nametable_read(&mut self, address: u16) -> Option<u8> {
+        self.mapper
+            .as_mut()
+            .and_then(|mapper| mapper.nametable_read(address))
+    }
+
+    pub fn nametable_peek(&self, address: u16) -> Option<u8> {
+        self.mapper
+            .as_ref()
+            .and_then(|mapper| mapper.nametable_peek(address))
+    }
+
+    /// Returns false when the write goes to the console VRAM
+    pub fn nametable_write(&mut self, address: u16, data: u8) -> bool {
+        self.mapper
+            .as_mut()
+            .is_some_and(|mapper| mapper.nametable_write(address, data))
+    }
+
+    /// The level of the sound channels on the cartridge, from 0.0 to 1.0
+    pub fn expansion_audio(&self) -> f32 {
+        self.mapper
+            .as_ref()
+            .map_or(0.0, |mapper| mapper.audio_output())
+    }
+
+    /// The nametable mirroring currently selected by the cartridge
+    pub fn mirroring(&self) -> Mirroring {
+        self.mapper
+            .as_ref()
+            .map_or(Mirroring::Horizontal, |mapper| mapper.mirroring())
+    }
+}
+
+impl Bus for NesBus {
+    fn read(&mut self, address: u16) -> u8 {
+        let (ram, ppu, mapper) = (&mut self.ram, &mut self.ppu, &mut self.mapper);
+        let io_registers = &self.io_registers;
+
+        self.map.read_with(address, |chip, offset| match chip {
+            Chip::Ram => Some(ram.read(offset)),
+            Chip::PpuRegisters => Some(ppu.read_register(offset, mapper)),
+            Chip::IoRegisters => io_register(io_registers, offset),
+            Chip::Cartridge => mapper.as_mut().and_then(|mapper| mapper.cpu_read(address)),
+        })
+    }
+
+    fn write(&mut self, address: u16, data: u8) {
+        let (ram, ppu, mapper) = (&mut self.ram, &mut self.ppu, &mut self.mapper);
+        let io_registers = &mut self.io_registers;
+
+        self.map
+            .write_with(address, data, |chip, offset| match chip {
+                Chip::Ram => ram.write(offset, data),
+                Chip::PpuRegisters => ppu.write_register(offset, data, mapper),
+                Chip::IoRegisters => io_registers[offset as usize] = data,
+                Chip::Cartridge => {}
+            });
+
+        // The cartridge is on the whole bus
+        if let Some(mapper) = &mut self.mapper {
+            mapper.cpu_write(address, data);
+        }
+    }
+
+    fn peek(&self, address: u16) -> u8 {
+        self.map.peek_with(address, |chip, offset| match chip {
+            Chip::Ram => Some(self.ram.peek(offset)),
+            Chip::PpuRegisters => Some(self.ppu.peek_register(offset, &self.mapper)),
+            Chip::IoRegisters => io_register(&self.io_registers, offset),
+            Chip::Cartridge => self
+                .mapper
+                .as_ref()
+                .and_then(|mapper| mapper.cpu_peek(address)),
+        })
+    }
+
+    // Cycle by cycle, so the mapper sees the PPU fetches as they happen
+    fn tick(&mut self, cycles: u64) {
+        for _ in 0..cycles {
+            if let Some(mapper) = &mut self.mapper {
+                mapper.tick(1);
+            }
+
+            for _ in 0..DOTS_PER_CYCLE {
+                self.ppu.step(&mut self.mapper);
+            }
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.mapper.as_ref().is_some_and(|mapper| mapper.irq())
+    }
+
+    fn take_nmi(&mut self) -> bool {
+        self.ppu.take_nmi()
+    }
+}
+
+// The APU status and the controller ports, the other registers are write-only
+fn io_register(io_registers: &[u8; 0x20], offset: u16) -> Option<u8> {
+    (0x15..=0x17)
+        .contains(&offset)
+        .then(|| io_registers[offset as usize])
+}
+
+/// A 2A03 on the NES memory map
+pub type Nes = Machine<NesBus>;
+
+// The CPU runs bus-accurate so the board is ticked on every cycle, between
+// the accesses, like the cycle counters of the FME-7 and the VRC chips expect
+impl Default for Nes {
+    fn default() -> Self {
+        let mut cpu = Cpu::default();
+        cpu.variant = CpuVariant::Ricoh2A03;
+        cpu.bus_accurate = true;
+
+        Nes::new(cpu)
+    }
+}
+
+impl Nes {
+    pub fn new(cpu: Cpu) -> Self {
+        Machine::with_bus(cpu, NesBus::default())
+    }
+
+    pub fn load_cartridge(&mut self, cartridge: Cartridge) -> Result<(), LoadError> {
+        Ok(self.bus.insert_cartridge(cartridge)?)
+    }
+
+    /// Loads the cartridge on the board registered with `id`, see
+    /// [`NesBus::register_mapper`]
+    pub fn load_cartridge_as(
+        &mut self,
+        id: &MapperId,
+        cartridge: Cartridge,
+    ) -> Result<(), LoadError> {
+        Ok(self.bus.insert_cartridge_as(id, cartridge)?)
+    }
+
+    /// Loads an iNES or NES 2.0 image. Anything else is taken as a bare
+    /// PRG-ROM dump on an NROM board, so it can be up to 32 KiB.
+    pub fn load_rom_from_bytes(&mut self, data: &[u8]) -> Result<(), LoadError> {
+        if data.starts_with(b"NES\x1A") {
+            return self.load_cartridge(Cartridge::from_bytes(data)?);
+        }
+
+        if data.len() > 0x8000 {
+            return Err(LoadError::RomTooLarge(data.len()));
+        }
+
+        self.load_cartridge(Cartridge::from_prg_rom(data.to_vec()))
+    }
+
+    pub fn load_rom_from_file(&mut self, filename: String) -> Result<(), LoadError> {
+        let data = fs::read(filename)?;
+
+        self.load_rom_from_bytes(&data)
+    }
+
+    /// Runs until the next vblank, when the PPU is done with a picture.
+    /// Returns the cycles spent.
+    pub fn run_frame(&mut self) -> Result<u64, CpuError> {
+        let cycles = self.run_until(|nes| nes.bus.ppu.scanline() < 241)?;
+        Ok(cycles + self.run_until(|nes| nes.bus.ppu.scanline() >= 241)?)
+    }
+}
+
+/// Errors raised while loading a ROM
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    Cartridge(CartridgeError),
+    /// The ROM is larger than the 32 KiB from $8000 to the end of memory
+    RomTooLarge(usize),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Io(error) => write!(f, "cannot read the ROM: {}", error),
+            LoadError::Cartridge(error) => write!(f, "invalid ROM: {}", error),
+            LoadError::RomTooLarge(size) => {
+                write!(f, "ROM of {} bytes does not fit in 32 KiB", size)
+            }
+        }
+    }
+}
+
+impl error::Error for LoadError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            LoadError::Io(error) => Some(error),
+            LoadError::Cartridge(error) => Some(error),
+            LoadError::RomTooLarge(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for LoadError {
+    fn from(error: io::Error) -> Self {
+        LoadError::Io(error)
+    }
+}
+
+impl From<CartridgeError> for LoadError {
+    fn from(error: CartridgeError) -> Self {
+        LoadError::Cartridge(error)
+    }
+}
+
+#[cfg(test)]
+mod nes_tests {
+    use super::cartridge::{Cartridge, CartridgeError, Mirroring};
+    use super::mapper::{Mapper, MapperId, StateError, StateReader, StateWriter};
+    use super::{LoadError, Nes, NesBus};
+    use crate::bus::Bus;
+    use crate::cpu::CpuVariant;
+
+    #[test]
+    fn load_rom_from_bytes_test() {
+        let mut nes = Nes::default();
+
+        assert_eq!(nes.cpu.variant, CpuVariant::Ricoh2A03);
+
+        // Nothing drives the bus without a cartridge
+        assert_eq!(nes.bus.peek(0x8000), 0);
+
+        let test_rom = [0x08; 0x8000];
+        nes.load_rom_from_bytes(&test_rom).unwrap();
+
+        assert_eq!(nes.bus.peek(0x8000), 0x08);
+        assert_eq!(nes.bus.peek(0xFFFF), 0x08);
+    }
+
+    #[test]
+    fn load_rom_errors_test() {
+        let mut nes = Nes::default();
+
+        assert!(matches!(
+            nes.load_rom_from_bytes(&[0; 0x8001]),
+            Err(LoadError::RomTooLarge(0x8001))
+        ));
+        assert!(matches!(
+            nes.load_rom_from_file(String::from("does/not/exist.nes")),
+            Err(LoadError::Io(_))
+        ));
+
+        let mut data = vec![0; 16 + 0x4000];
+        data[..8].copy_from_slice(&[b'N', b'E', b'S', 0x1A, 2, 0, 0, 0]);
+        assert!(matches!(
+            nes.load_rom_from_bytes(&data),
+            Err(LoadError::Cartridge(CartridgeError::Truncated { .. }))
+        ));
+
+        data[4] = 1;
+        data[6] = 0xF0;
+        assert!(matches!(
+            nes.load_rom_from_bytes(&data),
+            Err(LoadError::Cartridge(CartridgeError::UnsupportedMapper(15)))
+        ));
+    }
+
+    #[test]
+    fn load_rom_from_file_test() {
+        let mut nes = Nes::default();
+
+        let mut data = vec![0; 16 + 512 + 0x4000 + 0x2000];
+        data[..8].copy_from_slice(&[b'N', b'E', b'S', 0x1A, 1, 1, 0x04, 0]);
+        data[16..16 + 512].fill(0x77);
+        data[16 + 512] = 0x99;
+
+        let path = std::env::temp_dir().join("load_rom_from_file_test.nes");
+        std::fs::write(&path, &data).unwrap();
+        let result = nes.load_rom_from_file(path.to_string_lossy().into_owned());
+        std::fs::remove_file(&path).unwrap();
+        result.unwrap();
+
+        // The header is not mapped, the PRG-ROM starts after the trainer
+        assert_eq!(nes.bus.peek(0x8000), 0x99);
+        assert_eq!(nes.bus.peek(0xC000), 0x99);
+        assert_eq!(nes.bus.peek(0x7000), 0x77);
+        assert_eq!(nes.bus.peek(0x71FF), 0x77);
+        assert_eq!(nes.bus.peek(0x7200), 0x00);
+    }
+
+    #[test]
+    fn bank_switching_test() {
+        let mut nes = Nes::default();
+
+        // UxROM with 128 KiB, the reset vector is in the last bank
+        let mut cartridge = Cartridge::from_prg_rom(vec![0; 0x20000]);
+        cartridge.mapper = 2;
+        // LDA #$05; STA $8000; LDA $8000; JMP $C007
+        cartridge.prg_rom[0x1C000..0x1C00A]
+            .copy_from_slice(&[0xA9, 0x05, 0x8D, 0x00, 0x80, 0xAD, 0x00, 0x80, 0x4C, 0x08]);
+        cartridge.prg_rom[0x1C00A] = 0xC0;
+        cartridge.prg_rom[0x1FFFC..0x1FFFE].copy_from_slice(&[0x00, 0xC0]);
+        cartridge.prg_rom[0x14000] = 0x5B;
+        nes.load_cartridge(cartridge).unwrap();
+
+        nes.run_with_reset_pc(false).unwrap();
+
+        assert_eq!(nes.cpu.accumulator, 0x5B);
+    }
+
+    #[test]
+    fn ram_mirroring_test() {
+        let mut nes = Nes::default();
+
+        nes.mem_write_8(0x0012, 0x34);
+        assert_eq!(nes.mem_read_8(0x0812), 0x34);
+        assert_eq!(nes.mem_read_8(0x1012), 0x34);
+        assert_eq!(nes.mem_read_8(0x1812), 0x34);
+
+        nes.mem_write_8(0x1FFF, 0x56);
+        assert_eq!(nes.mem_read_8(0x07FF), 0x56);
+    }
+
+    #[test]
+    fn ppu_register_mirroring_test() {
+        let mut nes = Nes::default();
+
+        // PPUADDR and PPUDATA through their last mirrors
+        nes.mem_write_8(0x3FFE, 0x21);
+        nes.mem_write_8(0x3FFE, 0x08);
+        nes.mem_write_8(0x3FFF, 0x12);
+
+        nes.mem_write_8(0x200E, 0x21);
+        nes.mem_write_8(0x2006, 0x08);
+        nes.mem_read_8(0x2007);
+        assert_eq!(nes.bus.peek(0x2017), 0x12);
+        assert_eq!(nes.bus.ppu().vram_address(), 0x2109);
+
+        // The write-only registers read back the last value written
+        nes.mem_write_8(0x2000, 0x80);
+        assert_eq!(nes.bus.peek(0x2008), 0x80);
+        assert_eq!(nes.bus.ppu().ctrl(), 0x80);
+    }
+
+    #[test]
+    fn prg_rom_mirroring_test() {
+        let mut nes = Nes::default();
+
+        // NROM-128, the 16 KiB bank is seen at $8000 and $C000
+        let mut prg = vec![0; 0x4000];
+        prg[0x0000] = 0x11;
+        prg[0x3FFF] = 0x22;
+        nes.load_rom_from_bytes(&prg).unwrap();
+
+        assert_eq!(nes.mem_read_8(0x8000), 0x11);
+        assert_eq!(nes.mem_read_8(0xC000), 0x11);
+        assert_eq!(nes.mem_read_8(0xBFFF), 0x22);
+        assert_eq!(nes.mem_read_8(0xFFFF), 0x22);
+
+        // The ROM ignores writes
+        nes.mem_write_8(0x8000, 0x99);
+        assert_eq!(nes.mem_read_8(0x8000), 0x11);
+    }
+
+    #[test]
+    fn prg_ram_test() {
+        let mut nes = Nes::default();
+
+        // The PRG-RAM is on the cartridge
+        nes.mem_write_8(0x6000, 0xAB);
+        assert_eq!(nes.mem_read_8(0x6000), 0xAB);
+        assert_eq!(nes.mem_read_8(0x0000), 0x00);
+        assert_eq!(nes.mem_read_8(0x6000), 0x00);
+
+        nes.load_rom_from_bytes(&[0; 0x4000]).unwrap();
+
+        nes.mem_write_8(0x6000, 0xAB);
+        nes.mem_write_8(0x7FFF, 0xCD);
+
+        assert_eq!(nes.mem_read_8(0x6000), 0xAB);
+        assert_eq!(nes.mem_read_8(0x7FFF), 0xCD);
+    }
+
+    #[test]
+    fn open_bus_test() {
+        let mut nes = Nes::default();
+
+        // The expansion area and the write-only APU registers are not driven
+        nes.mem_write_8(0x0000, 0x5A);
+        assert_eq!(nes.mem_read_8(0x0000), 0x5A);
+        assert_eq!(nes.mem_read_8(0x5000), 0x5A);
+
+        nes.mem_write_8(0x4000, 0x3F);
+        assert_eq!(nes.mem_read_8(0x4000), 0x3F);
+        nes.mem_write_8(0x0000, 0x77);
+        assert_eq!(nes.mem_read_8(0x0000), 0x77);
+        assert_eq!(nes.mem_read_8(0x4000), 0x77);
+
+        // The APU status is readable
+        nes.mem_write_8(0x4015, 0x0F);
+        assert_eq!(nes.mem_read_8(0x0000), 0x77);
+        assert_eq!(nes.mem_read_8(0x4015), 0x0F);
+    }
+
+    #[test]
+    fn run_from_reset_vector_test() {
+        let mut nes = Nes::default();
+
+        // LDA #$42; STA $0200; JMP $8005
+        let mut prg = vec![0; 0x4000];
+        prg[..8].copy_from_slice(&[0xA9, 0x42, 0x8D, 0x00, 0x02, 0x4C, 0x05, 0x80]);
+        prg[0x3FFC..].copy_from_slice(&[0x00, 0x80, 0x00, 0x80]);
+        nes.load_rom_from_bytes(&prg).unwrap();
+
+        nes.run_with_reset_pc(false).unwrap();
+
+        assert_eq!(nes.cpu.program_counter, 0x8005);
+        assert_eq!(nes.mem_read_8(0x0A00), 0x42);
+    }
+
+    // Shows at $6000 the number of CPU cycles and at $6001 the last scanline
+    #[derive(Default)]
+    struct CountingBoard {
+        cycles: u8,
+        scanline: u8,
+    }
+
+    impl Mapper for CountingBoard {
+        fn cpu_peek(&self, address: u16) -> Option<u8> {
+            match address {
+                0x6000 => Some(self.cycles),
+                0x6001 => Some(self.scanline),
+                _ => None,
+            }
+        }
+
+        fn cpu_write(&mut self, _address: u16, _data: u8) {}
+
+        fn ppu_peek(&self, _address: u16) -> u8 {
+            0
+        }
+
+        fn ppu_write(&mut self, _address: u16, _data: u8) {}
+
+        fn mirroring(&self) -> Mirroring {
+            Mirroring::Vertical
+        }
+
+        fn tick(&mut self, cycles: u64) {
+            self.cycles = self.cycles.wrapping_add(cycles as u8);
+        }
+
+        fn scanline(&mut self, scanline: u16) {
+            self.scanline = scanline as u8;
+        }
+
+        fn save_state(&self, state: &mut StateWriter) {
+            state.write(&self.cycles);
+            state.write(&self.scanline);
+        }
+
+        fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+            state.read(&mut self.cycles)?;
+            state.read(&mut self.scanline)
+        }
+    }
+
+    #[test]
+    fn custom_mapper_test() {
+        let mut nes = Nes::default();
+        let id = MapperId::Custom("counting".to_string());
+
+        assert!(matches!(
+            nes.load_cartridge_as(&id, Cartridge::from_prg_rom(vec![0; 0x4000])),
+            Err(LoadError::Cartridge(CartridgeError::UnknownMapper(_)))
+        ));
+
+        nes.bus
+            .register_mapper(id.clone(), |_| Ok(Box::new(CountingBoard::default())));
+        nes.load_cartridge_as(&id, Cartridge::from_prg_rom(vec![0; 0x4000]))
+            .unwrap();
+        assert_eq!(nes.bus.mirroring(), Mirroring::Vertical);
+
+        // Three scanlines take 341 cycles
+        for _ in 0..341 {
+            nes.bus.tick(1);
+        }
+        assert_eq!(nes.mem_read_8(0x6000), 341u16 as u8);
+        assert_eq!(nes.mem_read_8(0x6001), 3);
+
+        // The pre-render line wraps to the first visible one
+        nes.bus.tick(259 * 341 / 3 + 1);
+        assert_eq!(nes.mem_read_8(0x6001), 0);
+    }
+
+    #[test]
+    fn save_state_test() {
+        let mut nes = Nes::default();
+        let mut cartridge =
+            Cartridge::from_prg_rom((0..0x20000).map(|i| (i / 0x4000) as u8).collect());
+        cartridge.mapper = 2;
+        nes.load_cartridge(cartridge.clone()).unwrap();
+
+        nes.mem_write_8(0x0123, 0x45);
+        nes.mem_write_8(0x6000, 0x67);
+        nes.mem_write_8(0x8000, 3);
+        let state = nes.bus.save_state();
+
+        nes.mem_write_8(0x0123, 0);
+        nes.mem_write_8(0x6000, 0);
+        nes.mem_write_8(0x8000, 1);
+
+        nes.bus.load_state(&state).unwrap();
+        assert_eq!(nes.mem_read_8(0x0123), 0x45);
+        assert_eq!(nes.mem_read_8(0x6000), 0x67);
+        assert_eq!(nes.mem_read_8(0x8000), 3);
+
+        // The state needs the same board
+        let mut bus = NesBus::default();
+        assert_eq!(bus.load_state(&state), Err(StateError::InvalidValue));
+        bus.insert_cartridge(cartridge).unwrap();
+        assert_eq!(
+            bus.load_state(&state[..state.len() - 1]),
+            Err(StateError::UnexpectedEnd)
+        );
+        bus.load_state(&state).unwrap();
+        assert_eq!(bus.peek(0x8000), 3);
+    }
+}
@@ -0,0 +1,421 @@
+use std::{error, fmt};
+
+const HEADER_SIZE: usize = 16;
+const TRAINER_SIZE: usize = 512;
+const MAGIC: [u8; 4] = *b"NES\x1A";
+
+/// Wiring of the two nametables in the console to the four the PPU can address
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mirroring {
+    /// $2000 and $2400 share a nametable, as do $2800 and $2C00, for vertical scrolling
+    Horizontal,
+    /// $2000 and $2800 share a nametable, as do $2400 and $2C00, for horizontal scrolling
+    Vertical,
+    /// The cartridge provides the RAM for all four nametables
+    FourScreen,
+    /// All four nametables are the first one
+    SingleScreenLower,
+    /// All four nametables are the second one
+    SingleScreenUpper,
+    /// The board picks the nametable of each of the four quadrants
+    Custom([u8; 4]),
+}
+
+/// The clock the game was written for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Timing {
+    Ntsc,
+    Pal,
+    /// The game runs on both NTSC and PAL consoles
+    MultipleRegion,
+    Dendy,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConsoleType {
+    Nes,
+    VsSystem,
+    PlayChoice10,
+    /// One of the extended console types of NES 2.0, e.g. a Famiclone with decimal mode
+    Extended(u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeaderFormat {
+    INes,
+    Nes20,
+}
+
+/// Errors raised while parsing an iNES or NES 2.0 file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CartridgeError {
+    /// The file does not start with "NES" followed by $1A
+    MissingMagic,
+    /// The file is shorter than the 16 bytes of the header
+    TruncatedHeader(usize),
+    /// The header announces more data than the file holds
+    Truncated { expected: usize, actual: usize },
+    /// A ROM size that does not fit in memory
+    InvalidSize,
+    /// The file has no PRG-ROM
+    NoPrgRom,
+    /// The board is not emulated
+    UnsupportedMapper(u16),
+    /// No board is registered with this name
+    UnknownMapper(String),
+}
+
+impl fmt::Display for CartridgeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CartridgeError::MissingMagic => write!(f, "not an iNES file"),
+            CartridgeError::TruncatedHeader(size) => {
+                write!(f, "file of {} bytes is too short for an iNES header", size)
+            }
+            CartridgeError::Truncated { expected, actual } => write!(
+                f,
+                "the header announces {} bytes but the file has {}",
+                expected, actual
+            ),
+            CartridgeError::InvalidSize => write!(f, "invalid ROM size in the NES 2.0 header"),
+            CartridgeError::NoPrgRom => write!(f, "the cartridge has no PRG-ROM"),
+            CartridgeError::UnsupportedMapper(mapper) => {
+                write!(f, "mapper {} is not supported", mapper)
+            }
+            CartridgeError::UnknownMapper(name) => write!(f, "no mapper is named {}", name),
+        }
+    }
+}
+
+impl error::Error for CartridgeError {}
+
+/// The contents of a cartridge as described by an iNES or NES 2.0 file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cartridge {
+    pub format: HeaderFormat,
+    pub mapper: u16,
+    pub submapper: u8,
+    pub mirroring: Mirroring,
+    /// The PRG-RAM is kept alive by a battery, the game saves in it
+    pub battery: bool,
+    /// 512 bytes loaded at $7000 before the game starts
+    pub trainer: Option<Vec<u8>>,
+    pub prg_rom: Vec<u8>,
+    pub chr_rom: Vec<u8>,
+    pub prg_ram_size: usize,
+    pub prg_nvram_size: usize,
+    pub chr_ram_size: usize,
+    pub chr_nvram_size: usize,
+    pub timing: Timing,
+    pub console_type: ConsoleType,
+}
+
+impl Cartridge {
+    /// An NROM board around a bare PRG-ROM dump, with 8 KiB of CHR-RAM
+    pub fn from_prg_rom(prg_rom: Vec<u8>) -> Self {
+        Cartridge {
+            format: HeaderFormat::INes,
+            mapper: 0,
+            submapper: 0,
+            mirroring: Mirroring::Horizontal,
+            battery: false,
+            trainer: None,
+            prg_rom,
+            chr_rom: Vec::new(),
+            prg_ram_size: 0x2000,
+            prg_nvram_size: 0,
+            chr_ram_size: 0x2000,
+            chr_nvram_size: 0,
+            timing: Timing::Ntsc,
+            console_type: ConsoleType::Nes,
+        }
+    }
+
+    /// Parses a file with an iNES or NES 2.0 header
+    pub fn from_bytes(data: &[u8]) -> Result<Self, CartridgeError> {
+        if data.len() < HEADER_SIZE {
+            if data.len() >= MAGIC.len() && data[..4] != MAGIC {
+                return Err(CartridgeError::MissingMagic);
+            }
+
+            return Err(CartridgeError::TruncatedHeader(data.len()));
+        }
+
+        let header = &data[..HEADER_SIZE];
+
+        if header[..4] != MAGIC {
+            return Err(CartridgeError::MissingMagic);
+        }
+
+        let flags_6 = header[6];
+        let flags_7 = header[7];
+        let has_trainer = flags_6 & 0b0000_0100 != 0;
+
+        let mirroring = match (flags_6 & 0b0000_1000 != 0, flags_6 & 0b0000_0001 != 0) {
+            (true, _) => Mirroring::FourScreen,
+            (false, true) => Mirroring::Vertical,
+            (false, false) => Mirroring::Horizontal,
+        };
+
+        let console_type = match flags_7 & 0b11 {
+            0 => ConsoleType::Nes,
+            1 => ConsoleType::VsSystem,
+            2 => ConsoleType::PlayChoice10,
+            _ => ConsoleType::Extended(header[13] & 0x0F),
+        };
+
+        let mut cartridge = Cartridge {
+            battery: flags_6 & 0b0000_0010 != 0,
+            mirroring,
+            console_type,
+            ..Cartridge::from_prg_rom(Vec::new())
+        };
+
+        let (prg_rom_size, chr_rom_size);
+
+        // NES 2.0 is identified by bits 2-3 of byte 7 being 0b10
+        if flags_7 & 0b0000_1100 == 0b0000_1000 {
+            cartridge.format = HeaderFormat::Nes20;
+            cartridge.mapper =
+                (flags_6 >> 4) as u16 | (flags_7 & 0xF0) as u16 | ((header[8] & 0x0F) as u16) << 8;
+            cartridge.submapper = header[8] >> 4;
+
+            prg_rom_size = nes_20_rom_size(header[4], header[9] & 0x0F, 0x4000)?;
+            chr_rom_size = nes_20_rom_size(header[5], header[9] >> 4, 0x2000)?;
+
+            cartridge.prg_ram_size = nes_20_ram_size(header[10] & 0x0F);
+            cartridge.prg_nvram_size = nes_20_ram_size(header[10] >> 4);
+            cartridge.chr_ram_size = nes_20_ram_size(header[11] & 0x0F);
+            cartridge.chr_nvram_size = nes_20_ram_size(header[11] >> 4);
+
+            cartridge.timing = match header[12] & 0b11 {
+                0 => Timing::Ntsc,
+                1 => Timing::Pal,
+                2 => Timing::MultipleRegion,
+                _ => Timing::Dendy,
+            };
+        } else {
+            // Old dumping tools wrote their name in bytes 7-15, the upper
+            // nibble of the mapper number is only trusted if they are clear
+            let mapper_high = match header[12..].iter().all(|&byte| byte == 0) {
+                true => flags_7 & 0xF0,
+                false => 0,
+            };
+            cartridge.mapper = (mapper_high | flags_6 >> 4) as u16;
+
+            prg_rom_size = header[4] as usize * 0x4000;
+            chr_rom_size = header[5] as usize * 0x2000;
+
+            // Zero means 8 KiB for compatibility
+            cartridge.prg_ram_size = header[8].max(1) as usize * 0x2000;
+            cartridge.chr_ram_size = match chr_rom_size {
+                0 => 0x2000,
+                _ => 0,
+            };
+
+            if cartridge.battery {
+                cartridge.prg_nvram_size = cartridge.prg_ram_size;
+                cartridge.prg_ram_size = 0;
+            }
+
+            cartridge.timing = match header[9] & 0b1 {
+                0 => Timing::Ntsc,
+                _ => Timing::Pal,
+            };
+        }
+
+        let trainer_size = if has_trainer { TRAINER_SIZE } else { 0 };
+        let expected = (HEADER_SIZE + trainer_size)
+            .checked_add(prg_rom_size)
+            .and_then(|size| size.checked_add(chr_rom_size))
+            .ok_or(CartridgeError::InvalidSize)?;
+
+        if data.len() < expected {
+            return Err(CartridgeError::Truncated {
+                expected,
+                actual: data.len(),
+            });
+        }
+
+        if prg_rom_size == 0 {
+            return Err(CartridgeError::NoPrgRom);
+        }
+
+        let mut rest = &data[HEADER_SIZE..];
+        let mut take = |size: usize| {
+            let (chunk, tail) = rest.split_at(size);
+            rest = tail;
+            chunk.to_vec()
+        };
+
+        cartridge.trainer = has_trainer.then(|| take(TRAINER_SIZE));
+        cartridge.prg_rom = take(prg_rom_size);
+        cartridge.chr_rom = take(chr_rom_size);
+
+        Ok(cartridge)
+    }
+}
+
+// The size is either the 12-bit count of `unit` sized banks, or, when the
+// upper nibble is $F, 2^E * (MM * 2 + 1) with the LSB byte as EEEEEEMM
+fn nes_20_rom_size(lsb: u8, msb: u8, unit: usize) -> Result<usize, CartridgeError> {
+    if msb == 0x0F {
+        let exponent = (lsb >> 2) as u32;
+        let multiplier = (lsb & 0b11) as usize * 2 + 1;
+
+        return 1usize
+            .checked_shl(exponent)
+            .and_then(|size| size.checked_mul(multiplier))
+            .ok_or(CartridgeError::InvalidSize);
+    }
+
+    Ok(((msb as usize) << 8 | lsb as usize) * unit)
+}
+
+// A shift count of 0 means no RAM, otherwise the size is 64 << count
+fn nes_20_ram_size(shift: u8) -> usize {
+    match shift {
+        0 => 0,
+        _ => 64 << shift,
+    }
+}
+
+#[cfg(test)]
+mod cartridge_tests {
+    use super::{Cartridge, CartridgeError, ConsoleType, HeaderFormat, Mirroring, Timing};
+
+    fn ines_file(header: [u8; 16], trainer: bool) -> Vec<u8> {
+        let mut data = header.to_vec();
+        let trainer_size = if trainer { 512 } else { 0 };
+        let size = trainer_size + header[4] as usize * 0x4000 + header[5] as usize * 0x2000;
+
+        data.extend((0..size).map(|index| (index / 0x2000) as u8));
+        data
+    }
+
+    #[test]
+    fn ines_header_test() {
+        // Mapper 66, 2 PRG banks, 4 CHR banks, vertical mirroring, battery
+        let header = [
+            b'N', b'E', b'S', 0x1A, 2, 4, 0x23, 0x40, 0, 1, 0, 0, 0, 0, 0, 0,
+        ];
+        let cartridge = Cartridge::from_bytes(&ines_file(header, false)).unwrap();
+
+        assert_eq!(cartridge.format, HeaderFormat::INes);
+        assert_eq!(cartridge.mapper, 66);
+        assert_eq!(cartridge.submapper, 0);
+        assert_eq!(cartridge.mirroring, Mirroring::Vertical);
+        assert!(cartridge.battery);
+        assert_eq!(cartridge.trainer, None);
+        assert_eq!(cartridge.prg_rom.len(), 0x8000);
+        assert_eq!(cartridge.chr_rom.len(), 0x8000);
+        assert_eq!(cartridge.prg_rom[0x2000], 1);
+        assert_eq!(cartridge.chr_rom[0], 4);
+        assert_eq!(cartridge.prg_nvram_size, 0x2000);
+        assert_eq!(cartridge.chr_ram_size, 0);
+        assert_eq!(cartridge.timing, Timing::Pal);
+        assert_eq!(cartridge.console_type, ConsoleType::Nes);
+    }
+
+    #[test]
+    fn ines_trainer_and_chr_ram_test() {
+        // Four-screen, trainer, no CHR-ROM, Vs. System
+        let header = [
+            b'N', b'E', b'S', 0x1A, 1, 0, 0x0C, 0x01, 0, 0, 0, 0, 0, 0, 0, 0,
+        ];
+        let cartridge = Cartridge::from_bytes(&ines_file(header, true)).unwrap();
+
+        assert_eq!(cartridge.mirroring, Mirroring::FourScreen);
+        assert_eq!(cartridge.trainer.as_ref().map(Vec::len), Some(512));
+        assert_eq!(cartridge.prg_rom.len(), 0x4000);
+        assert!(cartridge.chr_rom.is_empty());
+        assert_eq!(cartridge.prg_ram_size, 0x2000);
+        assert_eq!(cartridge.chr_ram_size, 0x2000);
+        assert_eq!(cartridge.console_type, ConsoleType::VsSystem);
+    }
+
+    #[test]
+    fn ines_dirty_header_test() {
+        // "DiskDude!" in bytes 7-15, the upper nibble of the mapper is garbage
+        let header = *b"NES\x1A\x01\x01\x10DiskDude!";
+        let cartridge = Cartridge::from_bytes(&ines_file(header, false)).unwrap();
+
+        assert_eq!(cartridge.mapper, 1);
+    }
+
+    #[test]
+    fn nes_20_header_test() {
+        // Mapper 0x1A4 submapper 3, 0x102 PRG banks, exponent CHR size,
+        // 8 KiB PRG-RAM, 32 KiB PRG-NVRAM, 8 KiB CHR-RAM, Dendy, extended console
+        let header = [
+            b'N', b'E', b'S', 0x1A, 0x02, 0x0D, 0x40, 0xAB, 0x31, 0xF1, 0x97, 0x07, 0x03, 0x05, 0,
+            0,
+        ];
+        let mut data = header.to_vec();
+        // CHR is 2^3 * (1 * 2 + 1) = 24 bytes
+        data.resize(16 + 0x102 * 0x4000 + 24, 0xEA);
+
+        let cartridge = Cartridge::from_bytes(&data).unwrap();
+
+        assert_eq!(cartridge.format, HeaderFormat::Nes20);
+        assert_eq!(cartridge.mapper, 0x1A4);
+        assert_eq!(cartridge.submapper, 3);
+        assert_eq!(cartridge.mirroring, Mirroring::Horizontal);
+        assert!(!cartridge.battery);
+        assert_eq!(cartridge.prg_rom.len(), 0x102 * 0x4000);
+        assert_eq!(cartridge.chr_rom.len(), 24);
+        assert_eq!(cartridge.prg_ram_size, 0x2000);
+        assert_eq!(cartridge.prg_nvram_size, 0x8000);
+        assert_eq!(cartridge.chr_ram_size, 0x2000);
+        assert_eq!(cartridge.chr_nvram_size, 0);
+        assert_eq!(cartridge.timing, Timing::Dendy);
+        assert_eq!(cartridge.console_type, ConsoleType::Extended(5));
+    }
+
+    #[test]
+    fn malformed_file_test() {
+        assert_eq!(
+            Cartridge::from_bytes(b"NES\x1A\x01"),
+            Err(CartridgeError::TruncatedHeader(5))
+        );
+        assert_eq!(
+            Cartridge::from_bytes(&[0; 0x4010]),
+            Err(CartridgeError::MissingMagic)
+        );
+
+        let header = [b'N', b'E', b'S', 0x1A, 2, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        let mut data = ines_file(header, false);
+        data.truncate(0x8000);
+        assert_eq!(
+            Cartridge::from_bytes(&data),
+            Err(CartridgeError::Truncated {
+                expected: 0xA010,
+                actual: 0x8000
+            })
+        );
+
+        let header = [b'N', b'E', b'S', 0x1A, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        assert_eq!(
+            Cartridge::from_bytes(&ines_file(header, false)),
+            Err(CartridgeError::NoPrgRom)
+        );
+
+        // A trainer is not a program
+        let header = [
+            b'N', b'E', b'S', 0x1A, 0, 1, 0x04, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        ];
+        assert_eq!(
+            Cartridge::from_bytes(&ines_file(header, true)),
+            Err(CartridgeError::NoPrgRom)
+        );
+
+        // 7 * 2^63 bytes of PRG-ROM
+        let header = [
+            b'N', b'E', b'S', 0x1A, 0xFF, 0, 0, 0x08, 0, 0x0F, 0, 0, 0, 0, 0, 0,
+        ];
+        assert_eq!(
+            Cartridge::from_bytes(&header),
+            Err(CartridgeError::InvalidSize)
+        );
+    }
+}
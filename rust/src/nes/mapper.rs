@@ -0,0 +1,426 @@
+mod discrete;
+mod fme7;
+mod mmc1;
+mod mmc3;
+mod mmc5;
+mod state;
+mod vrc;
+
+use super::cartridge::{Cartridge, CartridgeError, Mirroring};
+use discrete::{Board, Discrete};
+use fme7::Fme7;
+use mmc1::Mmc1;
+use mmc3::Mmc3;
+use mmc5::Mmc5;
+pub use state::{StateError, StateReader, StateValue, StateWriter};
+use std::collections::HashMap;
+use vrc::{Vrc4, Vrc6, Vrc7};
+
+/// The logic on a cartridge board that decides which banks of its chips the
+/// CPU sees at $4020-$FFFF and the PPU sees at $0000-$1FFF. Boards that are
+/// not built in implement it and join a [`MapperRegistry`].
+pub trait Mapper {
+    /// Reads as the CPU does, `None` when the cartridge does not drive the bus
+    fn cpu_read(&mut self, address: u16) -> Option<u8> {
+        self.cpu_peek(address)
+    }
+
+    /// Reads without the side effects of a CPU read, for debuggers
+    fn cpu_peek(&self, address: u16) -> Option<u8>;
+
+    /// Sees every write of the CPU, some boards snoop the PPU registers
+    fn cpu_write(&mut self, address: u16, data: u8);
+
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        self.ppu_peek(address)
+    }
+
+    fn ppu_peek(&self, address: u16) -> u8;
+
+    fn ppu_write(&mut self, address: u16, data: u8);
+
+    fn mirroring(&self) -> Mirroring;
+
+    /// Sees the reads of the nametables at $2000-$2FFF, `None` leaves them to
+    /// the console VRAM with the board mirroring
+    fn nametable_read(&mut self, address: u16) -> Option<u8> {
+        self.nametable_peek(address)
+    }
+
+    fn nametable_peek(&self, _address: u16) -> Option<u8> {
+        None
+    }
+
+    /// Returns false to leave the write to the console VRAM
+    fn nametable_write(&mut self, _address: u16, _data: u8) -> bool {
+        false
+    }
+
+    /// Called as the CPU spends its cycles, like [`crate::bus::Bus::tick`].
+    /// The NES runs its CPU bus-accurate, so that is once per cycle.
+    fn tick(&mut self, _cycles: u64) {}
+
+    /// Called as the PPU starts a scanline, 0 to 239 are visible and 261 is
+    /// the pre-render line
+    fn scanline(&mut self, _scanline: u16) {}
+
+    /// Whether the board pulls the IRQ line of the CPU
+    fn irq(&self) -> bool {
+        false
+    }
+
+    /// The level of the sound channels on the board, from 0.0 to 1.0
+    fn audio_output(&self) -> f32 {
+        0.0
+    }
+
+    /// Writes the registers and the RAM of the board
+    fn save_state(&self, state: &mut StateWriter);
+
+    /// Reads back what [`Mapper::save_state`] wrote, on a board built from
+    /// the same cartridge
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError>;
+}
+
+/// Identifies a board in a [`MapperRegistry`], by its iNES mapper number or
+/// by a name for boards that have none
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum MapperId {
+    INes(u16),
+    Custom(String),
+}
+
+type MapperConstructor = Box<dyn Fn(Cartridge) -> Result<Box<dyn Mapper>, CartridgeError>>;
+
+/// Builds the board of a cartridge. The default registry knows the boards
+/// of this crate, applications can add theirs or replace the built-in ones.
+pub struct MapperRegistry {
+    constructors: HashMap<MapperId, MapperConstructor>,
+}
+
+impl Default for MapperRegistry {
+    fn default() -> Self {
+        let mut registry = MapperRegistry::empty();
+
+        registry.register_built_in(&[0], |cartridge| {
+            Box::new(Discrete::new(Board::Nrom, cartridge))
+        });
+        registry.register_built_in(&[1, 155], |cartridge| Box::new(Mmc1::new(cartridge)));
+        registry.register_built_in(&[2], |cartridge| {
+            Box::new(Discrete::new(Board::Uxrom, cartridge))
+        });
+        registry.register_built_in(&[3], |cartridge| {
+            Box::new(Discrete::new(Board::Cnrom, cartridge))
+        });
+        registry.register_built_in(&[4], |cartridge| Box::new(Mmc3::new(cartridge)));
+        registry.register_built_in(&[5], |cartridge| Box::new(Mmc5::new(cartridge)));
+        registry.register_built_in(&[7], |cartridge| {
+            Box::new(Discrete::new(Board::Axrom, cartridge))
+        });
+        registry.register_built_in(&[11], |cartridge| {
+            Box::new(Discrete::new(Board::ColorDreams, cartridge))
+        });
+        registry.register_built_in(&[21, 22, 23, 25], |cartridge| {
+            Box::new(Vrc4::new(cartridge))
+        });
+        registry.register_built_in(&[24, 26], |cartridge| Box::new(Vrc6::new(cartridge)));
+        registry.register_built_in(&[66], |cartridge| {
+            Box::new(Discrete::new(Board::Gxrom, cartridge))
+        });
+        registry.register_built_in(&[69], |cartridge| Box::new(Fme7::new(cartridge)));
+        registry.register_built_in(&[85], |cartridge| Box::new(Vrc7::new(cartridge)));
+
+        registry
+    }
+}
+
+impl MapperRegistry {
+    /// A registry without the built-in boards
+    pub fn empty() -> Self {
+        MapperRegistry {
+            constructors: HashMap::new(),
+        }
+    }
+
+    /// Adds a board, or replaces the one registered with the same id
+    pub fn register(
+        &mut self,
+        id: MapperId,
+        constructor: impl Fn(Cartridge) -> Result<Box<dyn Mapper>, CartridgeError> + 'static,
+    ) {
+        self.constructors.insert(id, Box::new(constructor));
+    }
+
+    pub fn is_registered(&self, id: &MapperId) -> bool {
+        self.constructors.contains_key(id)
+    }
+
+    /// Builds the board for the mapper number in the header
+    pub fn create(&self, cartridge: Cartridge) -> Result<Box<dyn Mapper>, CartridgeError> {
+        self.create_as(&MapperId::INes(cartridge.mapper), cartridge)
+    }
+
+    /// Builds the board registered with `id`, whatever the header says
+    pub fn create_as(
+        &self,
+        id: &MapperId,
+        cartridge: Cartridge,
+    ) -> Result<Box<dyn Mapper>, CartridgeError> {
+        match (self.constructors.get(id), id) {
+            (Some(_), _) if cartridge.prg_rom.is_empty() => Err(CartridgeError::NoPrgRom),
+            (Some(constructor), _) => constructor(cartridge),
+            (None, MapperId::INes(mapper)) => Err(CartridgeError::UnsupportedMapper(*mapper)),
+            (None, MapperId::Custom(name)) => Err(CartridgeError::UnknownMapper(name.clone())),
+        }
+    }
+
+    fn register_built_in(
+        &mut self,
+        mappers: &[u16],
+        constructor: fn(Cartridge) -> Box<dyn Mapper>,
+    ) {
+        for &mapper in mappers {
+            self.register(MapperId::INes(mapper), move |cartridge| {
+                Ok(constructor(cartridge))
+            });
+        }
+    }
+}
+
+/// The PRG-ROM, PRG-RAM and CHR chips of a board. Bank numbers wrap around
+/// the size of the chip, like the unconnected upper lines of a bank register.
+pub struct CartridgeMemory {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+}
+
+impl CartridgeMemory {
+    pub fn new(cartridge: Cartridge) -> Self {
+        let chr_is_ram = cartridge.chr_rom.is_empty();
+        let chr = match chr_is_ram {
+            true => vec![0; (cartridge.chr_ram_size + cartridge.chr_nvram_size).max(0x2000)],
+            false => cartridge.chr_rom,
+        };
+
+        CartridgeMemory {
+            prg_rom: cartridge.prg_rom,
+            prg_ram: vec![0; cartridge.prg_ram_size + cartridge.prg_nvram_size],
+            chr,
+            chr_is_ram,
+        }
+    }
+
+    pub fn prg_rom_banks(&self, bank_size: usize) -> usize {
+        (self.prg_rom.len() / bank_size).max(1)
+    }
+
+    /// 0 on a board built without PRG-ROM
+    pub fn prg_rom(&self, bank: usize, bank_size: usize, address: u16) -> u8 {
+        self.prg_rom
+            .get(banked_index(bank, bank_size, address, self.prg_rom.len()))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// `None` on boards without PRG-RAM
+    pub fn prg_ram(&self, bank: usize, bank_size: usize, address: u16) -> Option<u8> {
+        (!self.prg_ram.is_empty())
+            .then(|| self.prg_ram[banked_index(bank, bank_size, address, self.prg_ram.len())])
+    }
+
+    pub fn write_prg_ram(&mut self, bank: usize, bank_size: usize, address: u16, data: u8) {
+        if !self.prg_ram.is_empty() {
+            let index = banked_index(bank, bank_size, address, self.prg_ram.len());
+            self.prg_ram[index] = data;
+        }
+    }
+
+    pub fn chr(&self, bank: usize, bank_size: usize, address: u16) -> u8 {
+        self.chr[banked_index(bank, bank_size, address, self.chr.len())]
+    }
+
+    /// Writes to CHR-RAM, CHR-ROM ignores them
+    pub fn write_chr(&mut self, bank: usize, bank_size: usize, address: u16, data: u8) {
+        if self.chr_is_ram {
+            let index = banked_index(bank, bank_size, address, self.chr.len());
+            self.chr[index] = data;
+        }
+    }
+
+    /// Saves the RAM chips, the ROM is left to the cartridge file
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write(self.prg_ram.as_slice());
+        if self.chr_is_ram {
+            state.write(self.chr.as_slice());
+        }
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.read(self.prg_ram.as_mut_slice())?;
+        if self.chr_is_ram {
+            state.read(self.chr.as_mut_slice())?;
+        }
+        Ok(())
+    }
+}
+
+fn banked_index(bank: usize, bank_size: usize, address: u16, size: usize) -> usize {
+    (bank * bank_size + (address as usize & (bank_size - 1))) % size.max(1)
+}
+
+#[cfg(test)]
+mod mapper_tests {
+    use super::{
+        CartridgeMemory, Mapper, MapperId, MapperRegistry, StateError, StateReader, StateWriter,
+    };
+    use crate::nes::cartridge::{Cartridge, CartridgeError, Mirroring};
+
+    // A board with a single register that selects the mirroring
+    struct MirroringBoard {
+        memory: CartridgeMemory,
+        mirroring: Mirroring,
+    }
+
+    impl Mapper for MirroringBoard {
+        fn cpu_peek(&self, address: u16) -> Option<u8> {
+            (address >= 0x8000).then(|| self.memory.prg_rom(0, 0x8000, address))
+        }
+
+        fn cpu_write(&mut self, address: u16, data: u8) {
+            if address >= 0x8000 {
+                self.mirroring = match data & 1 {
+                    0 => Mirroring::Horizontal,
+                    _ => Mirroring::Vertical,
+                };
+            }
+        }
+
+        fn ppu_peek(&self, address: u16) -> u8 {
+            self.memory.chr(0, 0x2000, address)
+        }
+
+        fn ppu_write(&mut self, _address: u16, _data: u8) {}
+
+        fn mirroring(&self) -> Mirroring {
+            self.mirroring
+        }
+
+        fn save_state(&self, state: &mut StateWriter) {
+            state.write(&self.mirroring);
+        }
+
+        fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+            state.read(&mut self.mirroring)
+        }
+    }
+
+    fn mirroring_board(cartridge: Cartridge) -> Result<Box<dyn Mapper>, CartridgeError> {
+        Ok(Box::new(MirroringBoard {
+            mirroring: cartridge.mirroring,
+            memory: CartridgeMemory::new(cartridge),
+        }))
+    }
+
+    fn cartridge(mapper: u16) -> Cartridge {
+        let mut cartridge =
+            Cartridge::from_prg_rom((0..0x40000).map(|i| (i / 0x2000) as u8).collect());
+        cartridge.mapper = mapper;
+        cartridge
+    }
+
+    #[test]
+    fn registry_test() {
+        let mut registry = MapperRegistry::default();
+        assert!(registry.is_registered(&MapperId::INes(4)));
+        assert_eq!(
+            registry.create(cartridge(15)).err(),
+            Some(CartridgeError::UnsupportedMapper(15))
+        );
+
+        let id = MapperId::Custom("mirroring".to_string());
+        assert_eq!(
+            registry.create_as(&id, cartridge(0)).err(),
+            Some(CartridgeError::UnknownMapper("mirroring".to_string()))
+        );
+
+        registry.register(id.clone(), mirroring_board);
+        let mut mapper = registry.create_as(&id, cartridge(0)).unwrap();
+        mapper.cpu_write(0x8000, 1);
+        assert_eq!(mapper.mirroring(), Mirroring::Vertical);
+
+        // A registered number replaces the built-in board
+        registry.register(MapperId::INes(0), mirroring_board);
+        let mut mapper = registry.create(cartridge(0)).unwrap();
+        mapper.cpu_write(0x8000, 1);
+        assert_eq!(mapper.mirroring(), Mirroring::Vertical);
+
+        let registry = MapperRegistry::empty();
+        assert_eq!(
+            registry.create(cartridge(0)).err(),
+            Some(CartridgeError::UnsupportedMapper(0))
+        );
+
+        // Every board needs a program
+        for mapper in [0, 69] {
+            let mut cartridge = Cartridge::from_prg_rom(Vec::new());
+            cartridge.mapper = mapper;
+            assert_eq!(
+                MapperRegistry::default().create(cartridge).err(),
+                Some(CartridgeError::NoPrgRom)
+            );
+        }
+
+        let memory = CartridgeMemory::new(Cartridge::from_prg_rom(Vec::new()));
+        assert_eq!(
+            memory.prg_rom(memory.prg_rom_banks(0x2000) - 1, 0x2000, 0xE000),
+            0
+        );
+    }
+
+    #[test]
+    fn save_state_test() {
+        for mapper in [
+            0, 1, 2, 3, 4, 5, 7, 11, 21, 22, 23, 24, 25, 26, 66, 69, 85, 155,
+        ] {
+            let registry = MapperRegistry::default();
+            let mut board = registry.create(cartridge(mapper)).unwrap();
+
+            // Scribble over the registers, the PRG-RAM and the CHR-RAM
+            let mut value = 0x5Au8;
+            for address in (0x5000..=0xFFFF).step_by(0x71) {
+                value = value.wrapping_mul(29).wrapping_add(address as u8);
+                board.cpu_write(address, value);
+                board.ppu_write(address & 0x1FFF, value);
+                board.tick(3);
+            }
+
+            let mut state = StateWriter::new();
+            board.save_state(&mut state);
+            let data = state.into_bytes();
+
+            let mut restored = registry.create(cartridge(mapper)).unwrap();
+            let mut state = StateReader::new(&data);
+            restored.load_state(&mut state).unwrap();
+            state.finish().unwrap();
+
+            for address in (0x5000..=0xFFFF).step_by(0x0F) {
+                assert_eq!(
+                    restored.cpu_peek(address),
+                    board.cpu_peek(address),
+                    "mapper {mapper} at {address:04X}"
+                );
+            }
+            for address in (0x0000..0x2000).step_by(0x07) {
+                assert_eq!(restored.ppu_peek(address), board.ppu_peek(address));
+            }
+            assert_eq!(restored.mirroring(), board.mirroring(), "mapper {mapper}");
+            assert_eq!(restored.irq(), board.irq(), "mapper {mapper}");
+
+            let mut state = StateWriter::new();
+            restored.save_state(&mut state);
+            assert_eq!(state.into_bytes(), data, "mapper {mapper}");
+        }
+    }
+}
@@ -0,0 +1,253 @@
+use super::{CartridgeMemory, Mapper, StateError, StateReader, StateWriter};
+use crate::nes::cartridge::{Cartridge, Mirroring};
+
+/// Boards made of a latch and a few logic gates, the register is written
+/// anywhere in $8000-$FFFF
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Board {
+    /// Mapper 0, no bank switching, 16 or 32 KiB of PRG-ROM
+    Nrom,
+    /// Mapper 2, 16 KiB switchable at $8000 and the last bank fixed at $C000
+    Uxrom,
+    /// Mapper 3, 8 KiB of switchable CHR
+    Cnrom,
+    /// Mapper 7, 32 KiB of switchable PRG and single-screen mirroring
+    Axrom,
+    /// Mapper 11, 32 KiB of PRG in bits 0-1 and 8 KiB of CHR in bits 4-7
+    ColorDreams,
+    /// Mapper 66, 32 KiB of PRG in bits 4-5 and 8 KiB of CHR in bits 0-1
+    Gxrom,
+}
+
+pub struct Discrete {
+    board: Board,
+    memory: CartridgeMemory,
+    prg_bank: usize,
+    chr_bank: usize,
+    mirroring: Mirroring,
+    /// The ROM drives the data bus along with the CPU during writes, so the
+    /// latch sees the AND of both values
+    bus_conflicts: bool,
+}
+
+impl Discrete {
+    pub fn new(board: Board, cartridge: Cartridge) -> Self {
+        let mirroring = match board {
+            Board::Axrom => Mirroring::SingleScreenLower,
+            _ => cartridge.mirroring,
+        };
+
+        Discrete {
+            board,
+            // Submapper 2 of the NES 2.0 discrete boards means bus conflicts
+            bus_conflicts: cartridge.submapper == 2,
+            memory: CartridgeMemory::new(cartridge),
+            prg_bank: 0,
+            chr_bank: 0,
+            mirroring,
+        }
+    }
+}
+
+impl Mapper for Discrete {
+    fn cpu_peek(&self, address: u16) -> Option<u8> {
+        match address {
+            0x6000..=0x7FFF => self.memory.prg_ram(0, 0x2000, address),
+            0x8000..=0xFFFF => Some(match self.board {
+                Board::Nrom | Board::Cnrom => self.memory.prg_rom(0, 0x8000, address),
+                Board::Uxrom if address < 0xC000 => {
+                    self.memory.prg_rom(self.prg_bank, 0x4000, address)
+                }
+                Board::Uxrom => {
+                    let last = self.memory.prg_rom_banks(0x4000) - 1;
+                    self.memory.prg_rom(last, 0x4000, address)
+                }
+                Board::Axrom | Board::ColorDreams | Board::Gxrom => {
+                    self.memory.prg_rom(self.prg_bank, 0x8000, address)
+                }
+            }),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, data: u8) {
+        if address < 0x8000 {
+            if address >= 0x6000 {
+                self.memory.write_prg_ram(0, 0x2000, address, data);
+            }
+            return;
+        }
+
+        let data = match self.bus_conflicts {
+            true => data & self.cpu_peek(address).unwrap_or(0xFF),
+            false => data,
+        };
+
+        match self.board {
+            Board::Nrom => {}
+            Board::Uxrom => self.prg_bank = data as usize,
+            Board::Cnrom => self.chr_bank = data as usize,
+            Board::Axrom => {
+                self.prg_bank = (data & 0x07) as usize;
+                self.mirroring = match data & 0x10 {
+                    0 => Mirroring::SingleScreenLower,
+                    _ => Mirroring::SingleScreenUpper,
+                };
+            }
+            Board::ColorDreams => {
+                self.prg_bank = (data & 0x03) as usize;
+                self.chr_bank = (data >> 4) as usize;
+            }
+            Board::Gxrom => {
+                self.prg_bank = ((data >> 4) & 0x03) as usize;
+                self.chr_bank = (data & 0x03) as usize;
+            }
+        }
+    }
+
+    fn ppu_peek(&self, address: u16) -> u8 {
+        self.memory.chr(self.chr_bank, 0x2000, address)
+    }
+
+    fn ppu_write(&mut self, address: u16, data: u8) {
+        self.memory.write_chr(self.chr_bank, 0x2000, address, data)
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        self.memory.save_state(state);
+        state.write(&self.prg_bank);
+        state.write(&self.chr_bank);
+        state.write(&self.mirroring);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.memory.load_state(state)?;
+        state.read(&mut self.prg_bank)?;
+        state.read(&mut self.chr_bank)?;
+        state.read(&mut self.mirroring)
+    }
+}
+
+#[cfg(test)]
+mod discrete_tests {
+    use super::super::{Mapper, MapperRegistry};
+    use crate::nes::cartridge::{Cartridge, Mirroring};
+
+    // Every 8 KiB of PRG-ROM and 1 KiB of CHR-ROM is filled with its bank number
+    fn board(mapper: u16, prg_size: usize, chr_size: usize) -> Box<dyn Mapper> {
+        let mut cartridge =
+            Cartridge::from_prg_rom((0..prg_size).map(|i| (i / 0x2000) as u8).collect());
+        cartridge.mapper = mapper;
+        cartridge.chr_rom = (0..chr_size).map(|i| (i / 0x0400) as u8).collect();
+
+        MapperRegistry::default().create(cartridge).unwrap()
+    }
+
+    #[test]
+    fn nrom_test() {
+        let mut mapper = board(0, 0x4000, 0x2000);
+
+        // 16 KiB are mirrored at $C000
+        assert_eq!(mapper.cpu_read(0x8000), Some(0));
+        assert_eq!(mapper.cpu_read(0xA000), Some(1));
+        assert_eq!(mapper.cpu_read(0xC000), Some(0));
+        assert_eq!(mapper.cpu_read(0xE000), Some(1));
+
+        mapper.cpu_write(0x6123, 0x45);
+        assert_eq!(mapper.cpu_read(0x6123), Some(0x45));
+        assert_eq!(mapper.cpu_read(0x5000), None);
+
+        // CHR-ROM ignores writes
+        mapper.ppu_write(0x0000, 0xFF);
+        assert_eq!(mapper.ppu_read(0x0000), 0);
+        assert_eq!(mapper.ppu_read(0x1C00), 7);
+    }
+
+    #[test]
+    fn uxrom_test() {
+        let mut mapper = board(2, 0x20000, 0);
+
+        assert_eq!(mapper.cpu_read(0x8000), Some(0));
+        assert_eq!(mapper.cpu_read(0xC000), Some(14));
+        assert_eq!(mapper.cpu_read(0xE000), Some(15));
+
+        mapper.cpu_write(0x8000, 3);
+        assert_eq!(mapper.cpu_read(0x8000), Some(6));
+        assert_eq!(mapper.cpu_read(0xA000), Some(7));
+        assert_eq!(mapper.cpu_read(0xC000), Some(14));
+
+        // The bank number wraps around the 8 banks of the ROM
+        mapper.cpu_write(0xFFFF, 9);
+        assert_eq!(mapper.cpu_read(0x8000), Some(2));
+
+        // CHR-RAM
+        mapper.ppu_write(0x1234, 0x56);
+        assert_eq!(mapper.ppu_read(0x1234), 0x56);
+    }
+
+    #[test]
+    fn cnrom_test() {
+        let mut mapper = board(3, 0x8000, 0x8000);
+
+        mapper.cpu_write(0x8000, 2);
+        assert_eq!(mapper.ppu_read(0x0000), 16);
+        assert_eq!(mapper.ppu_read(0x1C00), 23);
+        assert_eq!(mapper.cpu_read(0x8000), Some(0));
+    }
+
+    #[test]
+    fn axrom_test() {
+        let mut mapper = board(7, 0x40000, 0);
+
+        assert_eq!(mapper.mirroring(), Mirroring::SingleScreenLower);
+
+        mapper.cpu_write(0x8000, 0x15);
+        assert_eq!(mapper.cpu_read(0x8000), Some(20));
+        assert_eq!(mapper.cpu_read(0xE000), Some(23));
+        assert_eq!(mapper.mirroring(), Mirroring::SingleScreenUpper);
+
+        mapper.cpu_write(0x8000, 0x08);
+        assert_eq!(mapper.cpu_read(0x8000), Some(0));
+        assert_eq!(mapper.mirroring(), Mirroring::SingleScreenLower);
+    }
+
+    #[test]
+    fn gxrom_and_color_dreams_test() {
+        let mut mapper = board(66, 0x20000, 0x8000);
+
+        mapper.cpu_write(0x8000, 0x31);
+        assert_eq!(mapper.cpu_read(0x8000), Some(12));
+        assert_eq!(mapper.ppu_read(0x0000), 8);
+
+        let mut mapper = board(11, 0x20000, 0x20000);
+
+        mapper.cpu_write(0x8000, 0x52);
+        assert_eq!(mapper.cpu_read(0x8000), Some(8));
+        assert_eq!(mapper.ppu_read(0x0400), 41);
+    }
+
+    #[test]
+    fn bus_conflicts_test() {
+        let mut prg_rom = vec![0x02; 0x4000];
+        prg_rom.extend([0x11; 0x4000]);
+        prg_rom.extend([0x12; 0x4000]);
+        prg_rom.extend([0xFE; 0x4000]);
+
+        let mut cartridge = Cartridge::from_prg_rom(prg_rom);
+        cartridge.mapper = 2;
+        cartridge.submapper = 2;
+        let mut mapper = MapperRegistry::default().create(cartridge).unwrap();
+
+        // The ROM holds $02 at $8000, the latch gets $01 & $02
+        mapper.cpu_write(0x8000, 0x01);
+        assert_eq!(mapper.cpu_read(0x8000), Some(0x02));
+
+        // The fixed bank holds $FE, which lets bit 1 through
+        mapper.cpu_write(0xC000, 0x02);
+        assert_eq!(mapper.cpu_read(0x8000), Some(0x12));
+    }
+}
@@ -0,0 +1,274 @@
+use super::{CartridgeMemory, Mapper, StateError, StateReader, StateWriter};
+use crate::nes::cartridge::{Cartridge, Mirroring};
+
+/// Mapper 69, the Sunsoft FME-7 and the 5B that adds sound channels, which
+/// are not emulated. A command at $8000 selects the register that the
+/// parameter at $A000 writes.
+pub struct Fme7 {
+    memory: CartridgeMemory,
+    command: u8,
+    chr_banks: [u8; 8],
+    /// Bank of $6000 in bits 0-5, RAM instead of ROM in bit 6, RAM enable in bit 7
+    prg_6000: u8,
+    prg_banks: [u8; 3],
+    mirroring: Mirroring,
+    irq_enabled: bool,
+    counter_enabled: bool,
+    counter: u16,
+    irq_pending: bool,
+}
+
+impl Fme7 {
+    pub fn new(cartridge: Cartridge) -> Self {
+        Fme7 {
+            mirroring: cartridge.mirroring,
+            memory: CartridgeMemory::new(cartridge),
+            command: 0,
+            chr_banks: [0; 8],
+            prg_6000: 0,
+            prg_banks: [0; 3],
+            irq_enabled: false,
+            counter_enabled: false,
+            counter: 0,
+            irq_pending: false,
+        }
+    }
+
+    fn write_parameter(&mut self, data: u8) {
+        match self.command {
+            command @ 0x0..=0x7 => self.chr_banks[command as usize] = data,
+            0x8 => self.prg_6000 = data,
+            command @ 0x9..=0xB => self.prg_banks[command as usize - 0x9] = data & 0x3F,
+            0xC => {
+                self.mirroring = match data & 0b11 {
+                    0 => Mirroring::Vertical,
+                    1 => Mirroring::Horizontal,
+                    2 => Mirroring::SingleScreenLower,
+                    _ => Mirroring::SingleScreenUpper,
+                }
+            }
+            // Any write to the IRQ control acknowledges the IRQ
+            0xD => {
+                self.irq_enabled = data & 0x01 != 0;
+                self.counter_enabled = data & 0x80 != 0;
+                self.irq_pending = false;
+            }
+            0xE => self.counter = (self.counter & 0xFF00) | data as u16,
+            _ => self.counter = (self.counter & 0x00FF) | (data as u16) << 8,
+        }
+    }
+}
+
+impl Mapper for Fme7 {
+    fn cpu_peek(&self, address: u16) -> Option<u8> {
+        let bank = (self.prg_6000 & 0x3F) as usize;
+
+        match address {
+            0x6000..=0x7FFF => match self.prg_6000 & 0xC0 {
+                0xC0 => self.memory.prg_ram(bank, 0x2000, address),
+                0x40 => None,
+                _ => Some(self.memory.prg_rom(bank, 0x2000, address)),
+            },
+            0x8000..=0xDFFF => {
+                let bank = self.prg_banks[(address - 0x8000) as usize >> 13];
+                Some(self.memory.prg_rom(bank as usize, 0x2000, address))
+            }
+            0xE000..=0xFFFF => {
+                let last = self.memory.prg_rom_banks(0x2000) - 1;
+                Some(self.memory.prg_rom(last, 0x2000, address))
+            }
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, data: u8) {
+        match address {
+            0x6000..=0x7FFF if self.prg_6000 & 0xC0 == 0xC0 => {
+                let bank = (self.prg_6000 & 0x3F) as usize;
+                self.memory.write_prg_ram(bank, 0x2000, address, data);
+            }
+            0x8000..=0x9FFF => self.command = data & 0x0F,
+            0xA000..=0xBFFF => self.write_parameter(data),
+            _ => {}
+        }
+    }
+
+    fn ppu_peek(&self, address: u16) -> u8 {
+        let bank = self.chr_banks[(address >> 10) as usize & 7];
+        self.memory.chr(bank as usize, 0x0400, address)
+    }
+
+    fn ppu_write(&mut self, address: u16, data: u8) {
+        let bank = self.chr_banks[(address >> 10) as usize & 7];
+        self.memory.write_chr(bank as usize, 0x0400, address, data)
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    // The counter goes down every CPU cycle and raises the IRQ as it wraps
+    // from $0000 to $FFFF
+    fn tick(&mut self, cycles: u64) {
+        if !self.counter_enabled {
+            return;
+        }
+
+        for _ in 0..cycles {
+            self.counter = self.counter.wrapping_sub(1);
+            if self.counter == 0xFFFF && self.irq_enabled {
+                self.irq_pending = true;
+            }
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        self.memory.save_state(state);
+        state.write(&self.command);
+        state.write(&self.chr_banks);
+        state.write(&self.prg_6000);
+        state.write(&self.prg_banks);
+        state.write(&self.mirroring);
+        state.write(&self.irq_enabled);
+        state.write(&self.counter_enabled);
+        state.write(&self.counter);
+        state.write(&self.irq_pending);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.memory.load_state(state)?;
+        state.read(&mut self.command)?;
+        state.read(&mut self.chr_banks)?;
+        state.read(&mut self.prg_6000)?;
+        state.read(&mut self.prg_banks)?;
+        state.read(&mut self.mirroring)?;
+        state.read(&mut self.irq_enabled)?;
+        state.read(&mut self.counter_enabled)?;
+        state.read(&mut self.counter)?;
+        state.read(&mut self.irq_pending)
+    }
+}
+
+#[cfg(test)]
+mod fme7_tests {
+    use super::super::{Mapper, MapperRegistry};
+    use crate::nes::cartridge::{Cartridge, Mirroring};
+    use crate::nes::Nes;
+
+    // Every 8 KiB of PRG-ROM and 1 KiB of CHR-ROM is filled with its bank number
+    fn board() -> Box<dyn Mapper> {
+        let mut cartridge =
+            Cartridge::from_prg_rom((0..0x40000).map(|i| (i / 0x2000) as u8).collect());
+        cartridge.mapper = 69;
+        cartridge.chr_rom = (0..0x40000).map(|i| (i / 0x0400) as u8).collect();
+
+        MapperRegistry::default().create(cartridge).unwrap()
+    }
+
+    fn write_register(mapper: &mut Box<dyn Mapper>, command: u8, data: u8) {
+        mapper.cpu_write(0x8000, command);
+        mapper.cpu_write(0xA000, data);
+    }
+
+    #[test]
+    fn banking_test() {
+        let mut fme7 = board();
+
+        write_register(&mut fme7, 0x9, 3);
+        write_register(&mut fme7, 0xA, 4);
+        write_register(&mut fme7, 0xB, 5);
+        assert_eq!(fme7.cpu_read(0x8000), Some(3));
+        assert_eq!(fme7.cpu_read(0xA000), Some(4));
+        assert_eq!(fme7.cpu_read(0xC000), Some(5));
+        assert_eq!(fme7.cpu_read(0xE000), Some(31));
+
+        write_register(&mut fme7, 0x0, 0x21);
+        write_register(&mut fme7, 0x7, 0x37);
+        assert_eq!(fme7.ppu_read(0x0000), 0x21);
+        assert_eq!(fme7.ppu_read(0x1C00), 0x37);
+
+        write_register(&mut fme7, 0xC, 1);
+        assert_eq!(fme7.mirroring(), Mirroring::Horizontal);
+        write_register(&mut fme7, 0xC, 2);
+        assert_eq!(fme7.mirroring(), Mirroring::SingleScreenLower);
+    }
+
+    #[test]
+    fn prg_6000_test() {
+        let mut fme7 = board();
+
+        // ROM
+        write_register(&mut fme7, 0x8, 0x07);
+        assert_eq!(fme7.cpu_read(0x6000), Some(7));
+        fme7.cpu_write(0x6000, 0x42);
+        assert_eq!(fme7.cpu_read(0x6000), Some(7));
+
+        // Disabled RAM is open bus
+        write_register(&mut fme7, 0x8, 0x40);
+        assert_eq!(fme7.cpu_read(0x6000), None);
+        fme7.cpu_write(0x6000, 0x42);
+
+        write_register(&mut fme7, 0x8, 0xC0);
+        assert_eq!(fme7.cpu_read(0x6000), Some(0));
+        fme7.cpu_write(0x6000, 0x42);
+        assert_eq!(fme7.cpu_read(0x6000), Some(0x42));
+    }
+
+    #[test]
+    fn irq_counter_test() {
+        let mut fme7 = board();
+
+        write_register(&mut fme7, 0xE, 0x02);
+        write_register(&mut fme7, 0xF, 0x01);
+
+        // The counter only runs while enabled
+        fme7.tick(1000);
+        write_register(&mut fme7, 0xD, 0x81);
+        fme7.tick(0x102);
+        assert!(!fme7.irq());
+        fme7.tick(1);
+        assert!(fme7.irq());
+
+        write_register(&mut fme7, 0xD, 0x80);
+        assert!(!fme7.irq());
+
+        // The counter keeps going from $FFFF, without the IRQ
+        fme7.tick(0x10000);
+        assert!(!fme7.irq());
+    }
+
+    #[test]
+    fn cpu_irq_test() {
+        let mut prg_rom = vec![0; 0x8000];
+        // CLI; JMP $E001
+        prg_rom[0x6000..0x6004].copy_from_slice(&[0x58, 0x4C, 0x01, 0xE0]);
+        // IRQ handler: LDA #$AA; JMP $E006
+        prg_rom[0x6004..0x6009].copy_from_slice(&[0xA9, 0xAA, 0x4C, 0x06, 0xE0]);
+        prg_rom[0x7FFC..0x8000].copy_from_slice(&[0x00, 0xE0, 0x04, 0xE0]);
+
+        let mut cartridge = Cartridge::from_prg_rom(prg_rom);
+        cartridge.mapper = 69;
+        cartridge.chr_rom = vec![0; 0x2000];
+
+        let mut nes = Nes::default();
+        nes.load_cartridge(cartridge).unwrap();
+
+        // IRQ in 100 cycles
+        for (command, data) in [(0xE, 99), (0xF, 0), (0xD, 0x81)] {
+            nes.mem_write_8(0x8000, command);
+            nes.mem_write_8(0xA000, data);
+        }
+
+        nes.reset();
+        nes.run_for_cycles(80).unwrap();
+        assert_eq!(nes.cpu.accumulator, 0);
+
+        nes.run_for_cycles(40).unwrap();
+        assert_eq!(nes.cpu.accumulator, 0xAA);
+        assert_eq!(nes.cpu.program_counter, 0xE006);
+    }
+}
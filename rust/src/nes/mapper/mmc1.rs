@@ -0,0 +1,415 @@
+use super::{CartridgeMemory, Mapper, StateError, StateReader, StateWriter};
+use crate::nes::cartridge::{Cartridge, Mirroring};
+
+/// Mapper 1, the registers are loaded one bit at a time through a 5-bit
+/// shift register written at $8000-$FFFF. Bit 7 of a write resets it.
+///
+/// The CHR registers also drive the spare PRG lines on the larger boards:
+/// SOROM and SXROM select the 8 KiB PRG-RAM bank with them, SUROM and SXROM
+/// the 256 KiB half of their 512 KiB PRG-ROM.
+pub struct Mmc1 {
+    memory: CartridgeMemory,
+    shift: u8,
+    shift_count: u8,
+    control: u8,
+    chr_banks: [u8; 2],
+    prg_bank: u8,
+    /// MMC1A always enables the PRG-RAM, MMC1B uses bit 4 of the PRG register
+    has_ram_disable: bool,
+    prg_ram_size: usize,
+    prg_rom_size: usize,
+    /// Which CHR register the PPU used last, the PRG lines follow it in 4 KiB mode
+    chr_high_half: bool,
+    cycles: u64,
+    last_write_cycle: Option<u64>,
+}
+
+impl Mmc1 {
+    pub fn new(cartridge: Cartridge) -> Self {
+        Mmc1 {
+            has_ram_disable: cartridge.mapper != 155,
+            prg_ram_size: cartridge.prg_ram_size + cartridge.prg_nvram_size,
+            prg_rom_size: cartridge.prg_rom.len(),
+            memory: CartridgeMemory::new(cartridge),
+            shift: 0,
+            shift_count: 0,
+            control: 0x0C,
+            chr_banks: [0; 2],
+            prg_bank: 0,
+            chr_high_half: false,
+            cycles: 0,
+            last_write_cycle: None,
+        }
+    }
+
+    fn chr_bank_mode_4k(&self) -> bool {
+        self.control & 0x10 != 0
+    }
+
+    // The CHR register that drives the PRG lines
+    fn outer_bank_register(&self) -> u8 {
+        match self.chr_bank_mode_4k() && self.chr_high_half {
+            true => self.chr_banks[1],
+            false => self.chr_banks[0],
+        }
+    }
+
+    fn prg_rom_bank(&self, address: u16) -> usize {
+        let outer = match self.prg_rom_size > 0x40000 {
+            true => ((self.outer_bank_register() >> 4) & 1) as usize * 16,
+            false => 0,
+        };
+        let bank = (self.prg_bank & 0x0F) as usize;
+
+        // 32 KiB, or 16 KiB with the first or the last bank fixed
+        let bank = match ((self.control >> 2) & 0b11, address) {
+            (0 | 1, _) => (bank & !1) | ((address as usize >> 14) & 1),
+            (2, 0x8000..=0xBFFF) => 0,
+            (2, _) => bank,
+            (_, 0x8000..=0xBFFF) => bank,
+            (_, _) => 15,
+        };
+
+        outer | bank
+    }
+
+    fn prg_ram_bank(&self) -> usize {
+        let register = self.outer_bank_register() as usize;
+
+        match self.prg_ram_size {
+            0x4000 => (register >> 3) & 1,
+            0x8000 => (register >> 2) & 0b11,
+            _ => 0,
+        }
+    }
+
+    fn prg_ram_enabled(&self) -> bool {
+        !self.has_ram_disable || self.prg_bank & 0x10 == 0
+    }
+
+    fn chr_bank(&self, address: u16) -> (usize, usize) {
+        match (self.chr_bank_mode_4k(), address & 0x1000 != 0) {
+            (true, false) => (self.chr_banks[0] as usize, 0x1000),
+            (true, true) => (self.chr_banks[1] as usize, 0x1000),
+            (false, _) => ((self.chr_banks[0] >> 1) as usize, 0x2000),
+        }
+    }
+
+    fn write_register(&mut self, address: u16, value: u8) {
+        match address {
+            0x8000..=0x9FFF => self.control = value,
+            0xA000..=0xBFFF => self.chr_banks[0] = value,
+            0xC000..=0xDFFF => self.chr_banks[1] = value,
+            _ => self.prg_bank = value,
+        }
+    }
+}
+
+impl Mapper for Mmc1 {
+    fn cpu_peek(&self, address: u16) -> Option<u8> {
+        match address {
+            0x6000..=0x7FFF if self.prg_ram_enabled() => {
+                self.memory.prg_ram(self.prg_ram_bank(), 0x2000, address)
+            }
+            0x8000..=0xFFFF => Some(self.memory.prg_rom(
+                self.prg_rom_bank(address),
+                0x4000,
+                address,
+            )),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, data: u8) {
+        match address {
+            0x6000..=0x7FFF if self.prg_ram_enabled() => {
+                let bank = self.prg_ram_bank();
+                self.memory.write_prg_ram(bank, 0x2000, address, data);
+            }
+            0x8000..=0xFFFF => {
+                // Of the two writes of a read-modify-write instruction, only
+                // the dummy one counts, the MMC1 ignores writes on the next cycle
+                let consecutive = self.last_write_cycle == Some(self.cycles.wrapping_sub(1));
+                self.last_write_cycle = Some(self.cycles);
+
+                if consecutive {
+                    return;
+                }
+
+                if data & 0x80 != 0 {
+                    self.shift = 0;
+                    self.shift_count = 0;
+                    self.control |= 0x0C;
+                    return;
+                }
+
+                self.shift |= (data & 1) << self.shift_count;
+                self.shift_count += 1;
+
+                if self.shift_count == 5 {
+                    self.write_register(address, self.shift);
+                    self.shift = 0;
+                    self.shift_count = 0;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        self.chr_high_half = address & 0x1000 != 0;
+        self.ppu_peek(address)
+    }
+
+    fn ppu_peek(&self, address: u16) -> u8 {
+        let (bank, size) = self.chr_bank(address);
+        self.memory.chr(bank, size, address)
+    }
+
+    fn ppu_write(&mut self, address: u16, data: u8) {
+        let (bank, size) = self.chr_bank(address);
+        self.memory.write_chr(bank, size, address, data)
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match self.control & 0b11 {
+            0 => Mirroring::SingleScreenLower,
+            1 => Mirroring::SingleScreenUpper,
+            2 => Mirroring::Vertical,
+            _ => Mirroring::Horizontal,
+        }
+    }
+
+    fn tick(&mut self, cycles: u64) {
+        self.cycles = self.cycles.wrapping_add(cycles);
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        self.memory.save_state(state);
+        state.write(&self.shift);
+        state.write(&self.shift_count);
+        state.write(&self.control);
+        state.write(&self.chr_banks);
+        state.write(&self.prg_bank);
+        state.write(&self.chr_high_half);
+        state.write(&self.cycles);
+        state.write(&self.last_write_cycle);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.memory.load_state(state)?;
+        state.read(&mut self.shift)?;
+        state.read(&mut self.shift_count)?;
+        state.read(&mut self.control)?;
+        state.read(&mut self.chr_banks)?;
+        state.read(&mut self.prg_bank)?;
+        state.read(&mut self.chr_high_half)?;
+        state.read(&mut self.cycles)?;
+        state.read(&mut self.last_write_cycle)
+    }
+}
+
+#[cfg(test)]
+mod mmc1_tests {
+    use super::super::{Mapper, MapperRegistry};
+    use crate::bus::Bus;
+    use crate::nes::cartridge::{Cartridge, Mirroring};
+    use crate::nes::Nes;
+
+    // Every 16 KiB of PRG-ROM and 4 KiB of CHR-ROM is filled with its bank number
+    fn board(mapper: u16, prg_size: usize, chr_size: usize) -> Box<dyn Mapper> {
+        let mut cartridge =
+            Cartridge::from_prg_rom((0..prg_size).map(|i| (i / 0x4000) as u8).collect());
+        cartridge.mapper = mapper;
+        cartridge.chr_rom = (0..chr_size).map(|i| (i / 0x1000) as u8).collect();
+
+        MapperRegistry::default().create(cartridge).unwrap()
+    }
+
+    // The five writes of the serial protocol, each on its own instruction
+    fn write_register(mapper: &mut Box<dyn Mapper>, address: u16, value: u8) {
+        for bit in 0..5 {
+            mapper.cpu_write(address, value >> bit);
+            mapper.tick(4);
+        }
+    }
+
+    #[test]
+    fn prg_banking_modes_test() {
+        let mut mapper = board(1, 0x40000, 0x2000);
+
+        // Power on in mode 3, the last bank fixed at $C000
+        assert_eq!(mapper.cpu_read(0x8000), Some(0));
+        assert_eq!(mapper.cpu_read(0xC000), Some(15));
+
+        write_register(&mut mapper, 0xE000, 5);
+        assert_eq!(mapper.cpu_read(0x8000), Some(5));
+        assert_eq!(mapper.cpu_read(0xC000), Some(15));
+
+        // Mode 2, the first bank fixed at $8000
+        write_register(&mut mapper, 0x8000, 0b01000);
+        assert_eq!(mapper.cpu_read(0x8000), Some(0));
+        assert_eq!(mapper.cpu_read(0xC000), Some(5));
+
+        // 32 KiB mode ignores the low bit
+        write_register(&mut mapper, 0x8000, 0b00000);
+        assert_eq!(mapper.cpu_read(0x8000), Some(4));
+        assert_eq!(mapper.cpu_read(0xC000), Some(5));
+    }
+
+    #[test]
+    fn shift_register_reset_test() {
+        let mut mapper = board(1, 0x40000, 0x2000);
+
+        write_register(&mut mapper, 0x8000, 0b00000);
+
+        // Two bits in, then a reset goes back to mode 3 and a fresh shift register
+        mapper.cpu_write(0xE000, 1);
+        mapper.tick(4);
+        mapper.cpu_write(0xE000, 1);
+        mapper.tick(4);
+        mapper.cpu_write(0x8000, 0x80);
+        mapper.tick(4);
+        write_register(&mut mapper, 0xE000, 3);
+
+        assert_eq!(mapper.cpu_read(0x8000), Some(3));
+        assert_eq!(mapper.cpu_read(0xC000), Some(15));
+    }
+
+    #[test]
+    fn consecutive_writes_test() {
+        let mut mapper = board(1, 0x40000, 0x2000);
+
+        // The second write is ignored, the register needs a sixth one
+        mapper.cpu_write(0xE000, 1);
+        mapper.tick(1);
+        mapper.cpu_write(0xE000, 0);
+        mapper.tick(1);
+        for _ in 0..4 {
+            mapper.tick(1);
+            mapper.cpu_write(0xE000, 0);
+            mapper.tick(1);
+        }
+
+        assert_eq!(mapper.cpu_read(0x8000), Some(1));
+    }
+
+    #[test]
+    fn read_modify_write_test() {
+        let mut prg_rom = vec![0; 0x8000];
+        // INC $8000 writes $FF then $00, only the reset is seen
+        prg_rom[0x4000..0x4006].copy_from_slice(&[0xEE, 0x00, 0x80, 0x4C, 0x03, 0xC0]);
+        prg_rom[0x0000] = 0xFF;
+        prg_rom[0x7FFC..0x7FFE].copy_from_slice(&[0x00, 0xC0]);
+
+        let mut cartridge = Cartridge::from_prg_rom(prg_rom);
+        cartridge.mapper = 1;
+
+        let mut nes = Nes::default();
+        nes.cpu.bus_accurate = true;
+        nes.load_cartridge(cartridge).unwrap();
+
+        // Leave a bit in the shift register that the reset clears
+        nes.mem_write_8(0x8000, 0x01);
+        nes.bus.tick(2);
+        nes.run_with_reset_pc(false).unwrap();
+
+        for bit in 0..5 {
+            nes.bus.tick(2);
+            nes.mem_write_8(0x8000, 0b00011 >> bit);
+        }
+
+        assert_eq!(nes.bus.mirroring(), Mirroring::Horizontal);
+    }
+
+    #[test]
+    fn chr_banking_and_mirroring_test() {
+        let mut mapper = board(1, 0x20000, 0x20000);
+
+        assert_eq!(mapper.mirroring(), Mirroring::SingleScreenLower);
+
+        // 8 KiB mode ignores the low bit of CHR bank 0
+        write_register(&mut mapper, 0xA000, 5);
+        assert_eq!(mapper.ppu_read(0x0000), 4);
+        assert_eq!(mapper.ppu_read(0x1000), 5);
+
+        // Two 4 KiB banks
+        write_register(&mut mapper, 0x8000, 0b11110);
+        write_register(&mut mapper, 0xC000, 9);
+        assert_eq!(mapper.ppu_read(0x0000), 5);
+        assert_eq!(mapper.ppu_read(0x1000), 9);
+        assert_eq!(mapper.mirroring(), Mirroring::Vertical);
+
+        write_register(&mut mapper, 0x8000, 0b11111);
+        assert_eq!(mapper.mirroring(), Mirroring::Horizontal);
+        write_register(&mut mapper, 0x8000, 0b11101);
+        assert_eq!(mapper.mirroring(), Mirroring::SingleScreenUpper);
+    }
+
+    #[test]
+    fn prg_ram_test() {
+        let mut mapper = board(1, 0x20000, 0x2000);
+
+        mapper.cpu_write(0x6000, 0x42);
+        assert_eq!(mapper.cpu_read(0x6000), Some(0x42));
+
+        // Bit 4 of the PRG register disables the RAM on MMC1B
+        write_register(&mut mapper, 0xE000, 0x10);
+        assert_eq!(mapper.cpu_read(0x6000), None);
+        mapper.cpu_write(0x6000, 0x43);
+
+        write_register(&mut mapper, 0xE000, 0x00);
+        assert_eq!(mapper.cpu_read(0x6000), Some(0x42));
+
+        // MMC1A ignores it
+        let mut mapper = board(155, 0x20000, 0x2000);
+        write_register(&mut mapper, 0xE000, 0x10);
+        mapper.cpu_write(0x6000, 0x44);
+        assert_eq!(mapper.cpu_read(0x6000), Some(0x44));
+    }
+
+    #[test]
+    fn surom_test() {
+        let mut mapper = board(1, 0x80000, 0);
+
+        // The last bank of the selected 256 KiB is fixed at $C000
+        assert_eq!(mapper.cpu_read(0xC000), Some(15));
+
+        write_register(&mut mapper, 0xA000, 0x10);
+        write_register(&mut mapper, 0xE000, 2);
+        assert_eq!(mapper.cpu_read(0x8000), Some(18));
+        assert_eq!(mapper.cpu_read(0xC000), Some(31));
+    }
+
+    #[test]
+    fn sxrom_prg_ram_test() {
+        let mut cartridge = Cartridge::from_prg_rom(vec![0; 0x80000]);
+        cartridge.mapper = 1;
+        cartridge.prg_ram_size = 0x8000;
+        let mut mapper = MapperRegistry::default().create(cartridge).unwrap();
+
+        for bank in 0..4 {
+            write_register(&mut mapper, 0xA000, bank << 2);
+            mapper.cpu_write(0x6000, bank);
+        }
+
+        for bank in 0..4 {
+            write_register(&mut mapper, 0xA000, bank << 2);
+            assert_eq!(mapper.cpu_read(0x6000), Some(bank));
+        }
+
+        // SOROM only has two banks, on bit 3
+        let mut cartridge = Cartridge::from_prg_rom(vec![0; 0x40000]);
+        cartridge.mapper = 1;
+        cartridge.prg_ram_size = 0x2000;
+        cartridge.prg_nvram_size = 0x2000;
+        let mut mapper = MapperRegistry::default().create(cartridge).unwrap();
+
+        mapper.cpu_write(0x6000, 0x11);
+        write_register(&mut mapper, 0xA000, 0x08);
+        assert_eq!(mapper.cpu_read(0x6000), Some(0x00));
+        mapper.cpu_write(0x6000, 0x22);
+        write_register(&mut mapper, 0xA000, 0x00);
+        assert_eq!(mapper.cpu_read(0x6000), Some(0x11));
+    }
+}
@@ -0,0 +1,480 @@
+use super::{CartridgeMemory, Mapper, StateError, StateReader, StateWriter};
+use crate::nes::cartridge::{Cartridge, Mirroring};
+
+// A12 must stay low this many CPU cycles before a rise clocks the counter,
+// which hides the short drops between the sprite pattern fetches
+const A12_FILTER_CYCLES: u64 = 3;
+
+/// How the scanline counter raises its IRQ
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IrqBehavior {
+    /// The Sharp MMC3B/C raises it on every clock that leaves the counter at 0
+    Sharp,
+    /// The NEC MMC3A only raises it when the counter goes from 1 to 0, or is
+    /// reloaded to 0 after a write to $C001
+    Nec,
+}
+
+/// Mapper 4, on the TxROM boards. Eight bank registers are selected by
+/// $8000 and written by $8001, a counter clocked by the rises of the PPU A12
+/// line raises an IRQ at a chosen scanline.
+pub struct Mmc3 {
+    memory: CartridgeMemory,
+    bank_select: u8,
+    banks: [u8; 8],
+    mirroring: Mirroring,
+    four_screen: bool,
+    prg_ram_enabled: bool,
+    prg_ram_write_protected: bool,
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_reload: bool,
+    irq_enabled: bool,
+    irq_pending: bool,
+    irq_behavior: IrqBehavior,
+    a12_high: bool,
+    a12_low_since: u64,
+    cycles: u64,
+}
+
+impl Mmc3 {
+    pub fn new(cartridge: Cartridge) -> Self {
+        // Submapper 4 is the MMC3A with the NEC behavior
+        let irq_behavior = match cartridge.submapper {
+            4 => IrqBehavior::Nec,
+            _ => IrqBehavior::Sharp,
+        };
+
+        Mmc3 {
+            mirroring: cartridge.mirroring,
+            four_screen: cartridge.mirroring == Mirroring::FourScreen,
+            memory: CartridgeMemory::new(cartridge),
+            bank_select: 0,
+            banks: [0, 2, 4, 5, 6, 7, 0, 1],
+            prg_ram_enabled: true,
+            prg_ram_write_protected: false,
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_reload: false,
+            irq_enabled: false,
+            irq_pending: false,
+            irq_behavior,
+            a12_high: false,
+            a12_low_since: 0,
+            cycles: 0,
+        }
+    }
+
+    fn prg_bank(&self, address: u16) -> usize {
+        let second_last = self.memory.prg_rom_banks(0x2000).saturating_sub(2);
+
+        match (self.bank_select & 0x40 != 0, address) {
+            (false, 0x8000..=0x9FFF) => self.banks[6] as usize,
+            (true, 0x8000..=0x9FFF) => second_last,
+            (_, 0xA000..=0xBFFF) => self.banks[7] as usize,
+            (false, 0xC000..=0xDFFF) => second_last,
+            (true, 0xC000..=0xDFFF) => self.banks[6] as usize,
+            _ => second_last + 1,
+        }
+    }
+
+    // 1 KiB bank, the inversion bit swaps the 2 KiB and 1 KiB halves
+    fn chr_bank(&self, address: u16) -> usize {
+        let address = match self.bank_select & 0x80 != 0 {
+            true => address ^ 0x1000,
+            false => address,
+        };
+        let slot = (address >> 10) as usize & 7;
+
+        match slot {
+            0..=3 => (self.banks[slot / 2] & !1) as usize + slot % 2,
+            _ => self.banks[slot - 2] as usize,
+        }
+    }
+
+    fn watch_a12(&mut self, address: u16) {
+        let a12 = address & 0x1000 != 0;
+
+        if a12 && !self.a12_high && self.cycles - self.a12_low_since >= A12_FILTER_CYCLES {
+            self.clock_irq_counter();
+        }
+
+        if !a12 && self.a12_high {
+            self.a12_low_since = self.cycles;
+        }
+
+        self.a12_high = a12;
+    }
+
+    fn clock_irq_counter(&mut self) {
+        let reloaded = self.irq_reload;
+        let previous = self.irq_counter;
+
+        if self.irq_counter == 0 || self.irq_reload {
+            self.irq_counter = self.irq_latch;
+            self.irq_reload = false;
+        } else {
+            self.irq_counter -= 1;
+        }
+
+        let raise = match self.irq_behavior {
+            IrqBehavior::Sharp => self.irq_counter == 0,
+            IrqBehavior::Nec => self.irq_counter == 0 && (previous != 0 || reloaded),
+        };
+
+        if raise && self.irq_enabled {
+            self.irq_pending = true;
+        }
+    }
+}
+
+impl Mapper for Mmc3 {
+    fn cpu_peek(&self, address: u16) -> Option<u8> {
+        match address {
+            0x6000..=0x7FFF if self.prg_ram_enabled => self.memory.prg_ram(0, 0x2000, address),
+            0x8000..=0xFFFF => Some(self.memory.prg_rom(self.prg_bank(address), 0x2000, address)),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, data: u8) {
+        match (address, address & 1) {
+            (0x6000..=0x7FFF, _) if self.prg_ram_enabled && !self.prg_ram_write_protected => {
+                self.memory.write_prg_ram(0, 0x2000, address, data);
+            }
+            (0x8000..=0x9FFF, 0) => self.bank_select = data,
+            (0x8000..=0x9FFF, _) => self.banks[(self.bank_select & 7) as usize] = data,
+            (0xA000..=0xBFFF, 0) if !self.four_screen => {
+                self.mirroring = match data & 1 {
+                    0 => Mirroring::Vertical,
+                    _ => Mirroring::Horizontal,
+                };
+            }
+            (0xA000..=0xBFFF, 0) => {}
+            (0xA000..=0xBFFF, _) => {
+                self.prg_ram_enabled = data & 0x80 != 0;
+                self.prg_ram_write_protected = data & 0x40 != 0;
+            }
+            (0xC000..=0xDFFF, 0) => self.irq_latch = data,
+            (0xC000..=0xDFFF, _) => {
+                self.irq_counter = 0;
+                self.irq_reload = true;
+            }
+            (0xE000..=0xFFFF, 0) => {
+                self.irq_enabled = false;
+                self.irq_pending = false;
+            }
+            (0xE000..=0xFFFF, _) => self.irq_enabled = true,
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        self.watch_a12(address);
+        self.ppu_peek(address)
+    }
+
+    fn ppu_peek(&self, address: u16) -> u8 {
+        self.memory.chr(self.chr_bank(address), 0x0400, address)
+    }
+
+    fn ppu_write(&mut self, address: u16, data: u8) {
+        self.watch_a12(address);
+        self.memory
+            .write_chr(self.chr_bank(address), 0x0400, address, data)
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn tick(&mut self, cycles: u64) {
+        self.cycles += cycles;
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        self.memory.save_state(state);
+        state.write(&self.bank_select);
+        state.write(&self.banks);
+        state.write(&self.mirroring);
+        state.write(&self.prg_ram_enabled);
+        state.write(&self.prg_ram_write_protected);
+        state.write(&self.irq_latch);
+        state.write(&self.irq_counter);
+        state.write(&self.irq_reload);
+        state.write(&self.irq_enabled);
+        state.write(&self.irq_pending);
+        state.write(&self.a12_high);
+        state.write(&self.a12_low_since);
+        state.write(&self.cycles);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.memory.load_state(state)?;
+        state.read(&mut self.bank_select)?;
+        state.read(&mut self.banks)?;
+        state.read(&mut self.mirroring)?;
+        state.read(&mut self.prg_ram_enabled)?;
+        state.read(&mut self.prg_ram_write_protected)?;
+        state.read(&mut self.irq_latch)?;
+        state.read(&mut self.irq_counter)?;
+        state.read(&mut self.irq_reload)?;
+        state.read(&mut self.irq_enabled)?;
+        state.read(&mut self.irq_pending)?;
+        state.read(&mut self.a12_high)?;
+        state.read(&mut self.a12_low_since)?;
+        state.read(&mut self.cycles)
+    }
+}
+
+#[cfg(test)]
+mod mmc3_tests {
+    use super::super::{Mapper, MapperRegistry};
+    use crate::bus::Bus;
+    use crate::nes::cartridge::{Cartridge, Mirroring};
+    use crate::nes::Nes;
+
+    // Every 8 KiB of PRG-ROM and 1 KiB of CHR-ROM is filled with its bank number
+    fn board(submapper: u8) -> Box<dyn Mapper> {
+        let mut cartridge =
+            Cartridge::from_prg_rom((0..0x40000).map(|i| (i / 0x2000) as u8).collect());
+        cartridge.mapper = 4;
+        cartridge.submapper = submapper;
+        cartridge.chr_rom = (0..0x40000).map(|i| (i / 0x0400) as u8).collect();
+
+        MapperRegistry::default().create(cartridge).unwrap()
+    }
+
+    // A scanline as the MMC3 sees it: background fetches with A12 low, then
+    // the sprite fetches with A12 high
+    fn scanline(mapper: &mut Box<dyn Mapper>) {
+        mapper.ppu_read(0x0000);
+        mapper.tick(85);
+        mapper.ppu_read(0x1000);
+        mapper.tick(29);
+    }
+
+    #[test]
+    fn prg_banking_test() {
+        let mut mapper = board(0);
+
+        mapper.cpu_write(0x8000, 6);
+        mapper.cpu_write(0x8001, 3);
+        mapper.cpu_write(0x8000, 7);
+        mapper.cpu_write(0x8001, 9);
+
+        assert_eq!(mapper.cpu_read(0x8000), Some(3));
+        assert_eq!(mapper.cpu_read(0xA000), Some(9));
+        assert_eq!(mapper.cpu_read(0xC000), Some(30));
+        assert_eq!(mapper.cpu_read(0xE000), Some(31));
+
+        // PRG mode 1 swaps $8000 and $C000
+        mapper.cpu_write(0x8000, 0x40);
+        assert_eq!(mapper.cpu_read(0x8000), Some(30));
+        assert_eq!(mapper.cpu_read(0xA000), Some(9));
+        assert_eq!(mapper.cpu_read(0xC000), Some(3));
+        assert_eq!(mapper.cpu_read(0xE000), Some(31));
+    }
+
+    #[test]
+    fn chr_banking_test() {
+        let mut mapper = board(0);
+
+        for (register, bank) in [(0, 9), (1, 20), (2, 40), (3, 41), (4, 42), (5, 43)] {
+            mapper.cpu_write(0x8000, register);
+            mapper.cpu_write(0x8001, bank);
+        }
+
+        // The 2 KiB banks ignore the low bit
+        let banks = [8, 9, 20, 21, 40, 41, 42, 43];
+        for (slot, &bank) in banks.iter().enumerate() {
+            assert_eq!(mapper.ppu_read(slot as u16 * 0x400), bank);
+        }
+
+        // Inversion puts the 2 KiB banks at $1000
+        mapper.cpu_write(0x8000, 0x80);
+        for (slot, &bank) in banks.iter().enumerate() {
+            assert_eq!(mapper.ppu_read((slot as u16 * 0x400) ^ 0x1000), bank);
+        }
+    }
+
+    #[test]
+    fn mirroring_and_prg_ram_test() {
+        let mut mapper = board(0);
+
+        mapper.cpu_write(0xA000, 0);
+        assert_eq!(mapper.mirroring(), Mirroring::Vertical);
+        mapper.cpu_write(0xA000, 1);
+        assert_eq!(mapper.mirroring(), Mirroring::Horizontal);
+
+        mapper.cpu_write(0x6000, 0x12);
+        assert_eq!(mapper.cpu_read(0x6000), Some(0x12));
+
+        // Write protected
+        mapper.cpu_write(0xA001, 0xC0);
+        mapper.cpu_write(0x6000, 0x34);
+        assert_eq!(mapper.cpu_read(0x6000), Some(0x12));
+
+        // Disabled
+        mapper.cpu_write(0xA001, 0x00);
+        assert_eq!(mapper.cpu_read(0x6000), None);
+    }
+
+    #[test]
+    fn scanline_irq_test() {
+        let mut mapper = board(0);
+
+        mapper.cpu_write(0xC000, 3);
+        mapper.cpu_write(0xC001, 0);
+        mapper.cpu_write(0xE001, 0);
+
+        // Reload to 3, then 2, 1, 0
+        for _ in 0..3 {
+            scanline(&mut mapper);
+            assert!(!mapper.irq());
+        }
+        scanline(&mut mapper);
+        assert!(mapper.irq());
+
+        // $E000 acknowledges and disables
+        mapper.cpu_write(0xE000, 0);
+        assert!(!mapper.irq());
+        for _ in 0..4 {
+            scanline(&mut mapper);
+        }
+        assert!(!mapper.irq());
+    }
+
+    #[test]
+    fn a12_filter_test() {
+        let mut mapper = board(0);
+
+        mapper.cpu_write(0xC000, 1);
+        mapper.cpu_write(0xC001, 0);
+        mapper.cpu_write(0xE001, 0);
+
+        // The first rise reloads the counter to 1
+        scanline(&mut mapper);
+
+        // Short drops between sprite fetches do not count
+        for _ in 0..8 {
+            mapper.ppu_read(0x2000);
+            mapper.tick(1);
+            mapper.ppu_read(0x1000);
+            mapper.tick(1);
+        }
+        assert!(!mapper.irq());
+
+        scanline(&mut mapper);
+        assert!(mapper.irq());
+    }
+
+    #[test]
+    fn sharp_and_nec_irq_test() {
+        // With a latch of 0 the Sharp MMC3 raises the IRQ on every scanline
+        let mut sharp = board(0);
+        let mut nec = board(4);
+
+        for mapper in [&mut sharp, &mut nec] {
+            mapper.cpu_write(0xC000, 0);
+            mapper.cpu_write(0xC001, 0);
+            mapper.cpu_write(0xE001, 0);
+        }
+
+        // The reload to 0 after $C001 raises it on both
+        scanline(&mut sharp);
+        scanline(&mut nec);
+        assert!(sharp.irq());
+        assert!(nec.irq());
+
+        for mapper in [&mut sharp, &mut nec] {
+            mapper.cpu_write(0xE000, 0);
+            mapper.cpu_write(0xE001, 0);
+            scanline(mapper);
+        }
+        assert!(sharp.irq());
+        assert!(!nec.irq());
+    }
+
+    #[test]
+    fn cpu_irq_test() {
+        let mut prg_rom = vec![0; 0x8000];
+        // CLI; JMP $E001
+        prg_rom[0x6000..0x6004].copy_from_slice(&[0x58, 0x4C, 0x01, 0xE0]);
+        // IRQ handler: LDA #$AA; STA $E000; JMP $E009
+        prg_rom[0x6004..0x600C].copy_from_slice(&[0xA9, 0xAA, 0x8D, 0x00, 0xE0, 0x4C, 0x09, 0xE0]);
+        prg_rom[0x7FFC..0x8000].copy_from_slice(&[0x00, 0xE0, 0x04, 0xE0]);
+
+        let mut cartridge = Cartridge::from_prg_rom(prg_rom);
+        cartridge.mapper = 4;
+        cartridge.chr_rom = vec![0; 0x2000];
+
+        let mut nes = Nes::default();
+        nes.load_cartridge(cartridge).unwrap();
+
+        nes.mem_write_8(0xC000, 0);
+        nes.mem_write_8(0xC001, 0);
+        nes.mem_write_8(0xE001, 0);
+
+        nes.reset();
+        nes.run_for_cycles(20).unwrap();
+        assert_eq!(nes.cpu.accumulator, 0);
+
+        // The next rise of A12 raises the IRQ
+        nes.bus.ppu_read(0x0000);
+        nes.run_for_cycles(10).unwrap();
+        nes.bus.ppu_read(0x1000);
+        nes.run_for_cycles(20).unwrap();
+
+        assert_eq!(nes.cpu.accumulator, 0xAA);
+        assert_eq!(nes.cpu.program_counter, 0xE009);
+    }
+
+    #[test]
+    fn rendering_irq_test() {
+        // The scanline and dot where the IRQ is raised for a PPUCTRL
+        let irq_position = |ctrl: u8| {
+            let mut cartridge = Cartridge::from_prg_rom(vec![0; 0x8000]);
+            cartridge.mapper = 4;
+            cartridge.chr_rom = vec![0; 0x2000];
+
+            let mut nes = Nes::default();
+            nes.load_cartridge(cartridge).unwrap();
+
+            nes.mem_write_8(0x2000, ctrl);
+            nes.mem_write_8(0x2001, 0x08);
+            while nes.bus.ppu().scanline() != 261 {
+                nes.bus.tick(1);
+            }
+
+            nes.mem_write_8(0xC000, 10);
+            nes.mem_write_8(0xC001, 0);
+            nes.mem_write_8(0xE001, 0);
+
+            for _ in 0..20 * 341 / 3 {
+                if nes.bus.irq() {
+                    break;
+                }
+                nes.bus.tick(1);
+            }
+            assert!(nes.bus.irq());
+            (nes.bus.ppu().scanline(), nes.bus.ppu().dot())
+        };
+
+        // The background at $1000 and the sprites at $0000, the PPU raises A12
+        // once per scanline as it fetches the first tiles of the next one
+        let (scanline, dot) = irq_position(0x10);
+        assert_eq!(scanline, 9);
+        assert!(dot > 320);
+
+        // The other way around, A12 rises with the sprite fetches, even
+        // without any sprite on the scanline
+        let (scanline, dot) = irq_position(0x08);
+        assert_eq!(scanline, 9);
+        assert!((257..=320).contains(&dot));
+    }
+}
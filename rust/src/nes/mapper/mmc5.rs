@@ -0,0 +1,1036 @@
+use super::{CartridgeMemory, Mapper, StateError, StateReader, StateValue, StateWriter};
+use crate::nes::cartridge::{Cartridge, Mirroring};
+
+// The PPU reads of one scanline, counted from the first nametable fetch at
+// dot 1: 32 tiles of 4 fetches, 8 sprites of 4 fetches, then 2 tiles of the
+// next line and the 2 dummy nametable fetches that let the MMC5 find dot 1
+const SPRITE_FETCHES: std::ops::Range<u32> = 128..160;
+const PREFETCHES: std::ops::Range<u32> = 160..168;
+
+// The PPU stopped rendering when it has not read anything for this many cycles
+const IDLE_CYCLES: u64 = 3;
+
+// The envelopes and length counters of the pulse channels run at 240 Hz
+const AUDIO_FRAME_CYCLES: u32 = 7457;
+
+const LENGTH_TABLE: [u8; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14, 12, 16, 24, 18, 48, 20, 96, 22,
+    192, 24, 72, 26, 16, 28, 32, 30,
+];
+
+const DUTY_CYCLES: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0],
+    [0, 1, 1, 0, 0, 0, 0, 0],
+    [0, 1, 1, 1, 1, 0, 0, 0],
+    [1, 0, 0, 1, 1, 1, 1, 1],
+];
+
+/// A pulse channel like those of the APU, without the sweep unit
+#[derive(Default)]
+struct Pulse {
+    enabled: bool,
+    duty: u8,
+    halt: bool,
+    constant_volume: bool,
+    volume: u8,
+    period: u16,
+    timer: u16,
+    step: u8,
+    length: u8,
+    envelope_start: bool,
+    envelope_divider: u8,
+    envelope_decay: u8,
+}
+
+impl Pulse {
+    fn write(&mut self, register: u16, data: u8) {
+        match register {
+            0 => {
+                self.duty = data >> 6;
+                self.halt = data & 0x20 != 0;
+                self.constant_volume = data & 0x10 != 0;
+                self.volume = data & 0x0F;
+            }
+            2 => self.period = (self.period & 0x0700) | data as u16,
+            3 => {
+                self.period = (self.period & 0x00FF) | ((data as u16 & 0x07) << 8);
+                if self.enabled {
+                    self.length = LENGTH_TABLE[(data >> 3) as usize];
+                }
+                self.step = 0;
+                self.envelope_start = true;
+            }
+            _ => {}
+        }
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.length = 0;
+        }
+    }
+
+    fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period;
+            self.step = (self.step + 1) & 7;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn clock_frame(&mut self) {
+        if self.envelope_start {
+            self.envelope_start = false;
+            self.envelope_decay = 15;
+            self.envelope_divider = self.volume;
+        } else if self.envelope_divider == 0 {
+            self.envelope_divider = self.volume;
+            if self.envelope_decay > 0 {
+                self.envelope_decay -= 1;
+            } else if self.halt {
+                self.envelope_decay = 15;
+            }
+        } else {
+            self.envelope_divider -= 1;
+        }
+
+        if !self.halt && self.length > 0 {
+            self.length -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.length == 0 || DUTY_CYCLES[self.duty as usize][self.step as usize] == 0 {
+            return 0;
+        }
+
+        match self.constant_volume {
+            true => self.volume,
+            false => self.envelope_decay,
+        }
+    }
+}
+
+impl StateValue for Pulse {
+    fn save(&self, state: &mut StateWriter) {
+        state.write(&self.enabled);
+        state.write(&self.duty);
+        state.write(&self.halt);
+        state.write(&self.constant_volume);
+        state.write(&self.volume);
+        state.write(&self.period);
+        state.write(&self.timer);
+        state.write(&self.step);
+        state.write(&self.length);
+        state.write(&self.envelope_start);
+        state.write(&self.envelope_divider);
+        state.write(&self.envelope_decay);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.read(&mut self.enabled)?;
+        state.read(&mut self.duty)?;
+        state.read(&mut self.halt)?;
+        state.read(&mut self.constant_volume)?;
+        state.read(&mut self.volume)?;
+        state.read(&mut self.period)?;
+        state.read(&mut self.timer)?;
+        state.read(&mut self.step)?;
+        state.read(&mut self.length)?;
+        state.read(&mut self.envelope_start)?;
+        state.read(&mut self.envelope_divider)?;
+        state.read(&mut self.envelope_decay)
+    }
+}
+
+/// Mapper 5, the ExROM boards. Besides the PRG and CHR banks in four sizes,
+/// the MMC5 watches the PPU bus to find the scanlines, which drives its IRQ,
+/// the vertical split and the tile attributes taken from its 1 KiB ExRAM.
+pub struct Mmc5 {
+    memory: CartridgeMemory,
+    prg_mode: u8,
+    chr_mode: u8,
+    prg_ram_protect: [u8; 2],
+    exram_mode: u8,
+    nametable_mapping: u8,
+    fill_tile: u8,
+    fill_attribute: u8,
+    /// $5113-$5117, bit 7 selects ROM on $5114-$5116
+    prg_banks: [u8; 5],
+    /// $5120-$5127, used by the sprites in 8x16 mode
+    sprite_chr_banks: [u16; 8],
+    /// $5128-$512B, used by the background in 8x16 mode
+    background_chr_banks: [u16; 4],
+    chr_upper: u8,
+    background_set_written_last: bool,
+    exram: [u8; 0x400],
+    split_control: u8,
+    split_scroll: u8,
+    split_bank: u8,
+    irq_compare: u8,
+    irq_enabled: bool,
+    irq_pending: bool,
+    in_frame: bool,
+    scanline: u8,
+    multiplicand: u8,
+    multiplier: u8,
+    sprites_8x16: bool,
+    rendering_enabled: bool,
+    last_nametable_address: Option<u16>,
+    same_nametable_reads: u8,
+    fetches: u32,
+    idle_cycles: u64,
+    /// The ExRAM byte of the tile being fetched in extended attribute mode
+    tile_attribute: u8,
+    pulses: [Pulse; 2],
+    pcm: u8,
+    pcm_read_mode: bool,
+    pcm_irq_enabled: bool,
+    pcm_irq_pending: bool,
+    audio_cycles: u32,
+}
+
+impl Mmc5 {
+    pub fn new(cartridge: Cartridge) -> Self {
+        Mmc5 {
+            memory: CartridgeMemory::new(cartridge),
+            prg_mode: 3,
+            chr_mode: 3,
+            prg_ram_protect: [0; 2],
+            exram_mode: 0,
+            nametable_mapping: 0,
+            fill_tile: 0,
+            fill_attribute: 0,
+            prg_banks: [0, 0xFF, 0xFF, 0xFF, 0xFF],
+            sprite_chr_banks: [0; 8],
+            background_chr_banks: [0; 4],
+            chr_upper: 0,
+            background_set_written_last: false,
+            exram: [0; 0x400],
+            split_control: 0,
+            split_scroll: 0,
+            split_bank: 0,
+            irq_compare: 0,
+            irq_enabled: false,
+            irq_pending: false,
+            in_frame: false,
+            scanline: 0,
+            multiplicand: 0xFF,
+            multiplier: 0xFF,
+            sprites_8x16: false,
+            rendering_enabled: false,
+            last_nametable_address: None,
+            same_nametable_reads: 0,
+            fetches: 0,
+            idle_cycles: 0,
+            tile_attribute: 0,
+            pulses: [Pulse::default(), Pulse::default()],
+            pcm: 0,
+            pcm_read_mode: false,
+            pcm_irq_enabled: false,
+            pcm_irq_pending: false,
+            audio_cycles: 0,
+        }
+    }
+
+    // Whether the register is ROM or RAM, and the 8 KiB bank it selects
+    fn prg_bank(&self, address: u16) -> (bool, usize) {
+        let (register, size) = match (self.prg_mode, address) {
+            (_, 0x6000..=0x7FFF) => (0, 0x2000),
+            (0, _) => (4, 0x8000),
+            (1, 0x8000..=0xBFFF) | (2, 0x8000..=0xBFFF) => (2, 0x4000),
+            (1, _) => (4, 0x4000),
+            (2, 0xC000..=0xDFFF) => (3, 0x2000),
+            (2, _) => (4, 0x2000),
+            _ => (((address - 0x8000) >> 13) as usize + 1, 0x2000),
+        };
+
+        let value = self.prg_banks[register];
+        let is_rom = register == 4 || (register > 0 && value & 0x80 != 0);
+
+        // The larger banks ignore the low bits of the register
+        let banks_per_window = size / 0x2000;
+        let bank = (value & 0x7F) as usize & !(banks_per_window - 1);
+        let bank = bank | ((address as usize >> 13) & (banks_per_window - 1));
+
+        match is_rom {
+            true => (true, bank),
+            false => (false, bank & 0x07),
+        }
+    }
+
+    fn prg_ram_writable(&self) -> bool {
+        self.prg_ram_protect == [0b10, 0b01]
+    }
+
+    fn sprite_fetch(&self) -> bool {
+        SPRITE_FETCHES.contains(&self.fetches)
+    }
+
+    // The 1, 2, 4 or 8 KiB bank from the sprite or background registers
+    fn chr_bank(&self, address: u16, background: bool) -> (usize, usize) {
+        let size = 0x2000 >> self.chr_mode;
+        let slot = address as usize / size;
+        let register = (slot + 1) * (8 >> self.chr_mode) - 1;
+
+        match background {
+            true => (self.background_chr_banks[register & 3] as usize, size),
+            false => (self.sprite_chr_banks[register] as usize, size),
+        }
+    }
+
+    fn rendering(&self) -> bool {
+        self.in_frame && self.rendering_enabled
+    }
+
+    // The screen column of the tile being fetched, the first two tiles of a
+    // line are fetched at the end of the previous one
+    fn tile_column(&self) -> Option<(u8, bool)> {
+        match self.fetches {
+            0..=127 => Some((self.fetches as u8 / 4 + 2, false)),
+            160..=167 => Some(((self.fetches - PREFETCHES.start) as u8 / 4, true)),
+            _ => None,
+        }
+    }
+
+    // The column and the line in the split region of the tile being fetched
+    fn split_position(&self) -> Option<(u8, u8)> {
+        if self.split_control & 0x80 == 0 || self.exram_mode >= 2 || !self.rendering() {
+            return None;
+        }
+
+        let (column, next_line) = self.tile_column()?;
+        let split_tile = self.split_control & 0x1F;
+        let in_split = match self.split_control & 0x40 != 0 {
+            true => column >= split_tile,
+            false => column < split_tile,
+        };
+
+        if !in_split || column >= 32 {
+            return None;
+        }
+
+        let line = self.scanline as u16 + next_line as u16 + self.split_scroll as u16;
+
+        Some((column, (line % 240) as u8))
+    }
+
+    // Finds dot 1 of a scanline from the three identical nametable fetches
+    // that surround the end of the previous one
+    fn watch_nametable_fetch(&mut self, address: u16) {
+        self.idle_cycles = 0;
+        self.fetches += 1;
+
+        if self.last_nametable_address == Some(address) {
+            self.same_nametable_reads += 1;
+        } else {
+            self.same_nametable_reads = 0;
+        }
+        self.last_nametable_address = Some(address);
+
+        if self.same_nametable_reads == 2 {
+            self.fetches = 0;
+
+            if self.in_frame {
+                self.scanline = self.scanline.wrapping_add(1);
+                if self.scanline == self.irq_compare {
+                    self.irq_pending = true;
+                }
+            } else {
+                self.in_frame = true;
+                self.scanline = 0;
+            }
+        }
+    }
+
+    fn end_frame(&mut self) {
+        self.in_frame = false;
+        self.last_nametable_address = None;
+        self.same_nametable_reads = 0;
+    }
+
+    // The nametable source of a quadrant: CIRAM page 0 or 1, ExRAM, or fill mode
+    fn nametable_source(&self, address: u16) -> u8 {
+        let quadrant = (address >> 10) & 3;
+
+        (self.nametable_mapping >> (quadrant * 2)) & 3
+    }
+
+    fn substituted_nametable(&self, address: u16) -> Option<u8> {
+        let offset = address & 0x3FF;
+        let is_attribute = offset >= 0x3C0;
+
+        if let Some((column, line)) = self.split_position() {
+            return Some(match is_attribute {
+                false => self.exram[(line as usize / 8) * 32 + column as usize],
+                true => {
+                    let attribute =
+                        self.exram[0x3C0 + (line as usize / 32) * 8 + column as usize / 4];
+                    let shift = ((line / 16) & 1) * 4 + ((column / 2) & 1) * 2;
+                    replicate_palette((attribute >> shift) & 3)
+                }
+            });
+        }
+
+        if is_attribute && self.exram_mode == 1 && self.rendering() {
+            return Some(replicate_palette(self.tile_attribute >> 6));
+        }
+
+        match self.nametable_source(address) {
+            2 if self.exram_mode <= 1 => Some(self.exram[offset as usize]),
+            2 => Some(0),
+            3 if is_attribute => Some(replicate_palette(self.fill_attribute)),
+            3 => Some(self.fill_tile),
+            _ => None,
+        }
+    }
+
+    fn write_register(&mut self, address: u16, data: u8) {
+        match address {
+            0x5000..=0x5003 => self.pulses[0].write(address & 3, data),
+            0x5004..=0x5007 => self.pulses[1].write(address & 3, data),
+            0x5010 => {
+                self.pcm_read_mode = data & 0x01 != 0;
+                self.pcm_irq_enabled = data & 0x80 != 0;
+            }
+            0x5011 if !self.pcm_read_mode && data != 0 => self.pcm = data,
+            0x5015 => {
+                self.pulses[0].set_enabled(data & 0x01 != 0);
+                self.pulses[1].set_enabled(data & 0x02 != 0);
+            }
+            0x5100 => self.prg_mode = data & 3,
+            0x5101 => self.chr_mode = data & 3,
+            0x5102 => self.prg_ram_protect[0] = data & 3,
+            0x5103 => self.prg_ram_protect[1] = data & 3,
+            0x5104 => self.exram_mode = data & 3,
+            0x5105 => self.nametable_mapping = data,
+            0x5106 => self.fill_tile = data,
+            0x5107 => self.fill_attribute = data & 3,
+            0x5113..=0x5117 => self.prg_banks[(address - 0x5113) as usize] = data,
+            0x5120..=0x5127 => {
+                self.sprite_chr_banks[(address - 0x5120) as usize] =
+                    data as u16 | (self.chr_upper as u16) << 8;
+                self.background_set_written_last = false;
+            }
+            0x5128..=0x512B => {
+                self.background_chr_banks[(address - 0x5128) as usize] =
+                    data as u16 | (self.chr_upper as u16) << 8;
+                self.background_set_written_last = true;
+            }
+            0x5130 => self.chr_upper = data & 3,
+            0x5200 => self.split_control = data,
+            0x5201 => self.split_scroll = data,
+            0x5202 => self.split_bank = data,
+            0x5203 => self.irq_compare = data,
+            0x5204 => self.irq_enabled = data & 0x80 != 0,
+            0x5205 => self.multiplicand = data,
+            0x5206 => self.multiplier = data,
+            0x5C00..=0x5FFF => match self.exram_mode {
+                // The ExRAM can only be written while the PPU renders in the
+                // nametable modes, it stores 0 otherwise
+                0 | 1 if self.in_frame => self.exram[address as usize & 0x3FF] = data,
+                0 | 1 => self.exram[address as usize & 0x3FF] = 0,
+                2 => self.exram[address as usize & 0x3FF] = data,
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+
+    fn irq_status(&self) -> u8 {
+        ((self.irq_pending as u8) << 7) | ((self.in_frame as u8) << 6)
+    }
+
+    fn product(&self) -> u16 {
+        self.multiplicand as u16 * self.multiplier as u16
+    }
+}
+
+// The same palette in the four quadrants of an attribute byte
+fn replicate_palette(palette: u8) -> u8 {
+    (palette & 3) * 0x55
+}
+
+impl Mapper for Mmc5 {
+    fn cpu_read(&mut self, address: u16) -> Option<u8> {
+        let data = self.cpu_peek(address);
+
+        match address {
+            0x5010 => self.pcm_irq_pending = false,
+            0x5204 => self.irq_pending = false,
+            // The NMI vector is fetched at the end of the frame
+            0xFFFA | 0xFFFB => self.end_frame(),
+            0x8000..=0xBFFF if self.pcm_read_mode => match data {
+                Some(0) => self.pcm_irq_pending = true,
+                Some(sample) => self.pcm = sample,
+                None => {}
+            },
+            _ => {}
+        }
+
+        data
+    }
+
+    fn cpu_peek(&self, address: u16) -> Option<u8> {
+        match address {
+            0x5010 => Some(((self.pcm_irq_pending && self.pcm_irq_enabled) as u8) << 7),
+            0x5015 => {
+                Some((self.pulses[0].length > 0) as u8 | ((self.pulses[1].length > 0) as u8) << 1)
+            }
+            0x5204 => Some(self.irq_status()),
+            0x5205 => Some(self.product() as u8),
+            0x5206 => Some((self.product() >> 8) as u8),
+            0x5C00..=0x5FFF if self.exram_mode >= 2 => Some(self.exram[address as usize & 0x3FF]),
+            0x6000..=0xFFFF => match self.prg_bank(address) {
+                (true, bank) => Some(self.memory.prg_rom(bank, 0x2000, address)),
+                (false, bank) => self.memory.prg_ram(bank, 0x2000, address),
+            },
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, data: u8) {
+        match address {
+            // The PPU registers are snooped for the sprite size and rendering
+            0x2000..=0x3FFF => match address & 7 {
+                0 => self.sprites_8x16 = data & 0x20 != 0,
+                1 => {
+                    self.rendering_enabled = data & 0x18 != 0;
+                    if !self.rendering_enabled {
+                        self.end_frame();
+                    }
+                }
+                _ => {}
+            },
+            0x5000..=0x5FFF => self.write_register(address, data),
+            0x6000..=0xDFFF if self.prg_ram_writable() => {
+                if let (false, bank) = self.prg_bank(address) {
+                    self.memory.write_prg_ram(bank, 0x2000, address, data);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        self.idle_cycles = 0;
+        self.fetches += 1;
+        self.last_nametable_address = None;
+
+        self.ppu_peek(address)
+    }
+
+    fn ppu_peek(&self, address: u16) -> u8 {
+        if self.rendering() {
+            if let Some((_, line)) = self.split_position() {
+                let address = (address & 0x0FF8) | (line as u16 & 7);
+                return self.memory.chr(self.split_bank as usize, 0x1000, address);
+            }
+
+            if self.exram_mode == 1 && !self.sprite_fetch() {
+                let bank = (self.tile_attribute & 0x3F) as usize | (self.chr_upper as usize) << 6;
+                return self.memory.chr(bank, 0x1000, address);
+            }
+        }
+
+        let background = match self.sprites_8x16 && self.rendering() {
+            true => !self.sprite_fetch(),
+            false => self.background_set_written_last,
+        };
+        let (bank, size) = self.chr_bank(address, background);
+
+        self.memory.chr(bank, size, address)
+    }
+
+    fn ppu_write(&mut self, address: u16, data: u8) {
+        let (bank, size) = self.chr_bank(address, self.background_set_written_last);
+
+        self.memory.write_chr(bank, size, address, data)
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        let page = |quadrant: u8| (self.nametable_mapping >> (quadrant * 2)) & 1;
+
+        Mirroring::Custom([page(0), page(1), page(2), page(3)])
+    }
+
+    fn nametable_read(&mut self, address: u16) -> Option<u8> {
+        self.watch_nametable_fetch(address);
+
+        if address & 0x3FF < 0x3C0 {
+            self.tile_attribute = self.exram[address as usize & 0x3FF];
+        }
+
+        self.nametable_peek(address)
+    }
+
+    fn nametable_peek(&self, address: u16) -> Option<u8> {
+        self.substituted_nametable(address)
+    }
+
+    fn nametable_write(&mut self, address: u16, data: u8) -> bool {
+        match self.nametable_source(address) {
+            2 => {
+                if self.exram_mode <= 1 {
+                    self.exram[address as usize & 0x3FF] = data;
+                }
+                true
+            }
+            3 => true,
+            _ => false,
+        }
+    }
+
+    fn tick(&mut self, cycles: u64) {
+        self.idle_cycles += cycles;
+        if self.idle_cycles >= IDLE_CYCLES && self.in_frame {
+            self.end_frame();
+        }
+
+        for _ in 0..cycles {
+            self.audio_cycles += 1;
+
+            // The pulse timers count every other CPU cycle
+            if self.audio_cycles.is_multiple_of(2) {
+                self.pulses.iter_mut().for_each(Pulse::clock_timer);
+            }
+
+            if self.audio_cycles == AUDIO_FRAME_CYCLES {
+                self.audio_cycles = 0;
+                self.pulses.iter_mut().for_each(Pulse::clock_frame);
+            }
+        }
+    }
+
+    fn irq(&self) -> bool {
+        (self.irq_pending && self.irq_enabled) || (self.pcm_irq_pending && self.pcm_irq_enabled)
+    }
+
+    fn audio_output(&self) -> f32 {
+        // The nonlinear mix of the APU pulse channels
+        let pulses = (self.pulses[0].output() + self.pulses[1].output()) as f32;
+        let pulse_output = if pulses > 0.0 {
+            95.88 / (8128.0 / pulses + 100.0)
+        } else {
+            0.0
+        };
+
+        pulse_output + self.pcm as f32 / 255.0 * 0.25
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        self.memory.save_state(state);
+        state.write(&self.prg_mode);
+        state.write(&self.chr_mode);
+        state.write(&self.prg_ram_protect);
+        state.write(&self.exram_mode);
+        state.write(&self.nametable_mapping);
+        state.write(&self.fill_tile);
+        state.write(&self.fill_attribute);
+        state.write(&self.prg_banks);
+        state.write(&self.sprite_chr_banks);
+        state.write(&self.background_chr_banks);
+        state.write(&self.chr_upper);
+        state.write(&self.background_set_written_last);
+        state.write(&self.exram);
+        state.write(&self.split_control);
+        state.write(&self.split_scroll);
+        state.write(&self.split_bank);
+        state.write(&self.irq_compare);
+        state.write(&self.irq_enabled);
+        state.write(&self.irq_pending);
+        state.write(&self.in_frame);
+        state.write(&self.scanline);
+        state.write(&self.multiplicand);
+        state.write(&self.multiplier);
+        state.write(&self.sprites_8x16);
+        state.write(&self.rendering_enabled);
+        state.write(&self.last_nametable_address);
+        state.write(&self.same_nametable_reads);
+        state.write(&self.fetches);
+        state.write(&self.idle_cycles);
+        state.write(&self.tile_attribute);
+        state.write(&self.pulses);
+        state.write(&self.pcm);
+        state.write(&self.pcm_read_mode);
+        state.write(&self.pcm_irq_enabled);
+        state.write(&self.pcm_irq_pending);
+        state.write(&self.audio_cycles);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.memory.load_state(state)?;
+        state.read(&mut self.prg_mode)?;
+        state.read(&mut self.chr_mode)?;
+        state.read(&mut self.prg_ram_protect)?;
+        state.read(&mut self.exram_mode)?;
+        state.read(&mut self.nametable_mapping)?;
+        state.read(&mut self.fill_tile)?;
+        state.read(&mut self.fill_attribute)?;
+        state.read(&mut self.prg_banks)?;
+        state.read(&mut self.sprite_chr_banks)?;
+        state.read(&mut self.background_chr_banks)?;
+        state.read(&mut self.chr_upper)?;
+        state.read(&mut self.background_set_written_last)?;
+        state.read(&mut self.exram)?;
+        state.read(&mut self.split_control)?;
+        state.read(&mut self.split_scroll)?;
+        state.read(&mut self.split_bank)?;
+        state.read(&mut self.irq_compare)?;
+        state.read(&mut self.irq_enabled)?;
+        state.read(&mut self.irq_pending)?;
+        state.read(&mut self.in_frame)?;
+        state.read(&mut self.scanline)?;
+        state.read(&mut self.multiplicand)?;
+        state.read(&mut self.multiplier)?;
+        state.read(&mut self.sprites_8x16)?;
+        state.read(&mut self.rendering_enabled)?;
+        state.read(&mut self.last_nametable_address)?;
+        state.read(&mut self.same_nametable_reads)?;
+        state.read(&mut self.fetches)?;
+        state.read(&mut self.idle_cycles)?;
+        state.read(&mut self.tile_attribute)?;
+        state.read(&mut self.pulses)?;
+        state.read(&mut self.pcm)?;
+        state.read(&mut self.pcm_read_mode)?;
+        state.read(&mut self.pcm_irq_enabled)?;
+        state.read(&mut self.pcm_irq_pending)?;
+        state.read(&mut self.audio_cycles)
+    }
+}
+
+#[cfg(test)]
+mod mmc5_tests {
+    use super::super::{Mapper, MapperRegistry};
+    use crate::nes::cartridge::{Cartridge, Mirroring};
+
+    // Every 8 KiB of PRG-ROM and 1 KiB of CHR-ROM is filled with its bank number
+    fn board() -> Box<dyn Mapper> {
+        let mut cartridge =
+            Cartridge::from_prg_rom((0..0x80000).map(|i| (i / 0x2000) as u8).collect());
+        cartridge.mapper = 5;
+        cartridge.prg_ram_size = 0x10000;
+        cartridge.chr_rom = (0..0x40000).map(|i| (i / 0x0400) as u8).collect();
+
+        MapperRegistry::default().create(cartridge).unwrap()
+    }
+
+    // Rendering on, and the end of the pre-render line, so the next
+    // nametable fetch of $2002 is dot 1 of scanline 0
+    fn start_frame(mapper: &mut Box<dyn Mapper>) {
+        mapper.cpu_write(0x2001, 0x18);
+        mapper.nametable_read(0x2002);
+        mapper.nametable_read(0x2002);
+    }
+
+    // The four fetches of a background tile, the nametable, the attribute
+    // and the two pattern planes
+    fn fetch_tile(mapper: &mut Box<dyn Mapper>, address: u16) -> [u8; 4] {
+        [
+            mapper.nametable_read(address).unwrap_or(0xEE),
+            mapper.nametable_read(0x23C0).unwrap_or(0xEE),
+            mapper.ppu_read(0x0000),
+            mapper.ppu_read(0x0008),
+        ]
+    }
+
+    // A whole scanline from dot 1 to the first fetch of the next one
+    fn render_line(mapper: &mut Box<dyn Mapper>) {
+        for column in 2..34 {
+            fetch_tile(mapper, 0x2000 + column % 32);
+        }
+        for _ in 0..8 {
+            mapper.nametable_read(0x2000);
+            mapper.nametable_read(0x2000);
+            mapper.ppu_read(0x1000);
+            mapper.ppu_read(0x1008);
+        }
+        fetch_tile(mapper, 0x2000);
+        fetch_tile(mapper, 0x2001);
+        mapper.nametable_read(0x2002);
+        mapper.nametable_read(0x2002);
+        mapper.tick(1);
+    }
+
+    #[test]
+    fn prg_banking_test() {
+        let mut mapper = board();
+
+        // Mode 3 on power on, $5117 is $FF
+        assert_eq!(mapper.cpu_read(0xE000), Some(63));
+
+        mapper.cpu_write(0x5114, 0x85);
+        mapper.cpu_write(0x5115, 0x86);
+        mapper.cpu_write(0x5116, 0x87);
+        mapper.cpu_write(0x5117, 0x08);
+        assert_eq!(mapper.cpu_read(0x8000), Some(5));
+        assert_eq!(mapper.cpu_read(0xA000), Some(6));
+        assert_eq!(mapper.cpu_read(0xC000), Some(7));
+        assert_eq!(mapper.cpu_read(0xE000), Some(8));
+
+        // 16 KiB at $8000, 8 KiB at $C000 and $E000
+        mapper.cpu_write(0x5100, 2);
+        assert_eq!(mapper.cpu_read(0x8000), Some(6));
+        assert_eq!(mapper.cpu_read(0xA000), Some(7));
+        assert_eq!(mapper.cpu_read(0xC000), Some(7));
+        assert_eq!(mapper.cpu_read(0xE000), Some(8));
+
+        // 16 KiB at $8000 and $C000
+        mapper.cpu_write(0x5100, 1);
+        assert_eq!(mapper.cpu_read(0xC000), Some(8));
+        assert_eq!(mapper.cpu_read(0xE000), Some(9));
+
+        // 32 KiB
+        mapper.cpu_write(0x5100, 0);
+        assert_eq!(mapper.cpu_read(0x8000), Some(8));
+        assert_eq!(mapper.cpu_read(0xE000), Some(11));
+    }
+
+    #[test]
+    fn prg_ram_test() {
+        let mut mapper = board();
+
+        // Protected until $5102 is 2 and $5103 is 1
+        mapper.cpu_write(0x6000, 0x11);
+        assert_eq!(mapper.cpu_read(0x6000), Some(0x00));
+
+        mapper.cpu_write(0x5102, 0x02);
+        mapper.cpu_write(0x5103, 0x01);
+        mapper.cpu_write(0x5113, 3);
+        mapper.cpu_write(0x6000, 0x11);
+        assert_eq!(mapper.cpu_read(0x6000), Some(0x11));
+
+        // RAM bank 3 in the ROM window at $8000
+        mapper.cpu_write(0x5114, 0x03);
+        assert_eq!(mapper.cpu_read(0x8000), Some(0x11));
+        mapper.cpu_write(0x8001, 0x22);
+        mapper.cpu_write(0x5113, 3);
+        assert_eq!(mapper.cpu_read(0x6001), Some(0x22));
+
+        // $E000 is always ROM
+        mapper.cpu_write(0xE000, 0x33);
+        assert_eq!(mapper.cpu_read(0xE000), Some(63));
+    }
+
+    #[test]
+    fn chr_banking_test() {
+        let mut mapper = board();
+
+        for (index, bank) in (0x5120..0x5128).zip(10..) {
+            mapper.cpu_write(index, bank);
+        }
+
+        // 1 KiB banks
+        assert_eq!(mapper.ppu_read(0x0000), 10);
+        assert_eq!(mapper.ppu_read(0x1C00), 17);
+
+        // 2 KiB banks from the odd registers
+        mapper.cpu_write(0x5101, 2);
+        assert_eq!(mapper.ppu_read(0x0000), 22);
+        assert_eq!(mapper.ppu_read(0x0400), 23);
+        assert_eq!(mapper.ppu_read(0x1800), 34);
+
+        // 4 KiB banks from $5123 and $5127
+        mapper.cpu_write(0x5101, 1);
+        assert_eq!(mapper.ppu_read(0x0000), 52);
+        assert_eq!(mapper.ppu_read(0x1000), 68);
+
+        // An 8 KiB bank from $5127, with the upper bits of $5130
+        mapper.cpu_write(0x5101, 0);
+        mapper.cpu_write(0x5130, 1);
+        mapper.cpu_write(0x5127, 2);
+        assert_eq!(mapper.ppu_read(0x0400), (0x102 * 8 + 1) as u8);
+    }
+
+    #[test]
+    fn sprite_and_background_sets_test() {
+        let mut mapper = board();
+
+        mapper.cpu_write(0x5101, 3);
+        mapper.cpu_write(0x5120, 1);
+        mapper.cpu_write(0x5128, 2);
+
+        // Without 8x16 sprites the set written last is used
+        assert_eq!(mapper.ppu_read(0x0000), 2);
+        mapper.cpu_write(0x5120, 1);
+        assert_eq!(mapper.ppu_read(0x0000), 1);
+
+        // With 8x16 sprites the background fetches use $5128-$512B
+        mapper.cpu_write(0x2000, 0x20);
+        start_frame(&mut mapper);
+        assert_eq!(fetch_tile(&mut mapper, 0x2002)[2], 2);
+        for column in 3..34 {
+            fetch_tile(&mut mapper, 0x2000 + column % 32);
+        }
+        mapper.nametable_read(0x2000);
+        mapper.nametable_read(0x2000);
+        assert_eq!(mapper.ppu_read(0x0000), 1);
+    }
+
+    #[test]
+    fn scanline_irq_test() {
+        let mut mapper = board();
+
+        mapper.cpu_write(0x5203, 3);
+        mapper.cpu_write(0x5204, 0x80);
+        start_frame(&mut mapper);
+        assert_eq!(mapper.cpu_peek(0x5204), Some(0x00));
+
+        for _ in 0..3 {
+            render_line(&mut mapper);
+            assert!(!mapper.irq());
+            assert_eq!(mapper.cpu_peek(0x5204), Some(0x40));
+        }
+        mapper.nametable_read(0x2002);
+        assert!(mapper.irq());
+
+        // Reading the status acknowledges the IRQ
+        assert_eq!(mapper.cpu_read(0x5204), Some(0xC0));
+        assert!(!mapper.irq());
+
+        // The PPU stops fetching in vertical blank
+        mapper.tick(3);
+        assert_eq!(mapper.cpu_read(0x5204), Some(0x00));
+    }
+
+    #[test]
+    fn multiplier_test() {
+        let mut mapper = board();
+
+        assert_eq!(mapper.cpu_read(0x5205), Some(0x01));
+        assert_eq!(mapper.cpu_read(0x5206), Some(0xFE));
+
+        mapper.cpu_write(0x5205, 200);
+        mapper.cpu_write(0x5206, 123);
+        assert_eq!(mapper.cpu_read(0x5205), Some((24600 & 0xFF) as u8));
+        assert_eq!(mapper.cpu_read(0x5206), Some((24600 >> 8) as u8));
+    }
+
+    #[test]
+    fn nametable_mapping_test() {
+        let mut mapper = board();
+
+        // CIRAM 0, CIRAM 1, ExRAM, fill mode
+        mapper.cpu_write(0x5105, 0b11_10_01_00);
+        mapper.cpu_write(0x5106, 0x42);
+        mapper.cpu_write(0x5107, 0x02);
+        assert_eq!(mapper.mirroring(), Mirroring::Custom([0, 1, 0, 1]));
+
+        assert_eq!(mapper.nametable_read(0x2000), None);
+        assert_eq!(mapper.nametable_read(0x2400), None);
+        assert_eq!(mapper.nametable_read(0x2C00), Some(0x42));
+        assert_eq!(mapper.nametable_read(0x2FC0), Some(0xAA));
+
+        // The ExRAM is written through the PPU as a nametable
+        assert!(mapper.nametable_write(0x2805, 0x77));
+        assert!(!mapper.nametable_write(0x2005, 0x77));
+        assert_eq!(mapper.nametable_read(0x2805), Some(0x77));
+
+        // In mode 2 it is CPU RAM, and reads 0 as a nametable
+        mapper.cpu_write(0x5104, 2);
+        assert_eq!(mapper.cpu_read(0x5C05), Some(0x77));
+        mapper.cpu_write(0x5C06, 0x88);
+        assert_eq!(mapper.cpu_read(0x5C06), Some(0x88));
+        assert_eq!(mapper.nametable_read(0x2805), Some(0x00));
+
+        // Mode 3 is read-only
+        mapper.cpu_write(0x5104, 3);
+        mapper.cpu_write(0x5C06, 0x99);
+        assert_eq!(mapper.cpu_read(0x5C06), Some(0x88));
+    }
+
+    #[test]
+    fn extended_attributes_test() {
+        let mut mapper = board();
+
+        mapper.cpu_write(0x5104, 2);
+        // Palette 3 and 4 KiB CHR bank 5 for the tile at $2003
+        mapper.cpu_write(0x5C03, 0xC5);
+        mapper.cpu_write(0x5104, 1);
+
+        start_frame(&mut mapper);
+        fetch_tile(&mut mapper, 0x2002);
+
+        // 4 KiB bank 5 starts with 1 KiB bank 20
+        assert_eq!(fetch_tile(&mut mapper, 0x2003), [0xEE, 0xFF, 20, 20]);
+        assert_eq!(fetch_tile(&mut mapper, 0x2004), [0xEE, 0x00, 0, 0]);
+    }
+
+    #[test]
+    fn vertical_split_test() {
+        let mut mapper = board();
+
+        mapper.cpu_write(0x5104, 2);
+        // The tile of row 1 column 20, and its attribute
+        mapper.cpu_write(0x5C00 + 32 + 20, 0x33);
+        mapper.cpu_write(0x5C00 + 0x3C0 + 5, 0b0000_0011);
+        mapper.cpu_write(0x5104, 0);
+
+        // Split on the right from column 16, scrolled by 8 lines, 4 KiB bank 2
+        mapper.cpu_write(0x5200, 0xC0 | 16);
+        mapper.cpu_write(0x5201, 8);
+        mapper.cpu_write(0x5202, 2);
+
+        start_frame(&mut mapper);
+        for column in 2..20 {
+            let [tile, ..] = fetch_tile(&mut mapper, 0x2000 + column);
+            assert_eq!(tile == 0xEE, column < 16);
+        }
+
+        // Palette 3 in the top left quadrant of the attribute byte
+        let [tile, attribute, low, _] = fetch_tile(&mut mapper, 0x2014);
+        assert_eq!(tile, 0x33);
+        assert_eq!(attribute, 0xFF);
+        assert_eq!(low, 8);
+    }
+
+    #[test]
+    fn audio_test() {
+        let mut mapper = board();
+
+        assert_eq!(mapper.audio_output(), 0.0);
+
+        // Pulse 1 at constant volume 15, 50% duty
+        mapper.cpu_write(0x5015, 0x01);
+        mapper.cpu_write(0x5000, 0xBF);
+        mapper.cpu_write(0x5002, 0x10);
+        mapper.cpu_write(0x5003, 0x08);
+        assert_eq!(mapper.cpu_read(0x5015), Some(0x01));
+
+        let levels: Vec<f32> = (0..64)
+            .map(|_| {
+                mapper.tick(8);
+                mapper.audio_output()
+            })
+            .collect();
+        assert!(levels.iter().any(|&level| level > 0.1));
+        assert!(levels.contains(&0.0));
+
+        // PCM in write mode
+        mapper.cpu_write(0x5015, 0x00);
+        mapper.cpu_write(0x5011, 0xFF);
+        assert_eq!(mapper.audio_output(), 0.25);
+    }
+
+    #[test]
+    fn pcm_read_mode_test() {
+        let mut cartridge = Cartridge::from_prg_rom(vec![0x80; 0x8000]);
+        cartridge.mapper = 5;
+        cartridge.prg_rom[0x0010] = 0x00;
+        let mut mapper = MapperRegistry::default().create(cartridge).unwrap();
+
+        mapper.cpu_write(0x5114, 0x80);
+        mapper.cpu_write(0x5010, 0x81);
+        assert_eq!(mapper.cpu_read(0x8000), Some(0x80));
+        assert_eq!(mapper.audio_output(), 0x80 as f32 / 255.0 * 0.25);
+        assert!(!mapper.irq());
+
+        // Reading a 0 raises the IRQ, $5010 acknowledges it
+        mapper.cpu_read(0x8010);
+        assert!(mapper.irq());
+        assert_eq!(mapper.cpu_read(0x5010), Some(0x80));
+        assert!(!mapper.irq());
+    }
+}
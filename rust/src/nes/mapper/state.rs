@@ -0,0 +1,315 @@
+use crate::nes::cartridge::Mirroring;
+use std::{error, fmt};
+
+/// Errors raised while restoring a save state
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StateError {
+    /// The state ends before all the values were read
+    UnexpectedEnd,
+    /// A memory of the state has another size than on the board
+    SizeMismatch { expected: usize, actual: usize },
+    /// A value that the field cannot hold
+    InvalidValue,
+    /// Bytes are left once everything was read, the state is from another board
+    TrailingBytes(usize),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StateError::UnexpectedEnd => write!(f, "the save state is truncated"),
+            StateError::SizeMismatch { expected, actual } => write!(
+                f,
+                "the save state holds {} bytes of memory where {} are expected",
+                actual, expected
+            ),
+            StateError::InvalidValue => write!(f, "the save state holds an invalid value"),
+            StateError::TrailingBytes(count) => {
+                write!(f, "{} bytes are left at the end of the save state", count)
+            }
+        }
+    }
+}
+
+impl error::Error for StateError {}
+
+/// A value that goes in a save state, as little-endian bytes
+pub trait StateValue {
+    fn save(&self, state: &mut StateWriter);
+
+    /// Reads the value in place, so arrays and memories keep their size
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError>;
+}
+
+/// Collects the values of a save state in the order they are written
+#[derive(Default)]
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        StateWriter::default()
+    }
+
+    pub fn write<T: StateValue + ?Sized>(&mut self, value: &T) {
+        value.save(self);
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+/// Gives back the values of a save state in the order they were written
+pub struct StateReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        StateReader { data }
+    }
+
+    pub fn read<T: StateValue + ?Sized>(&mut self, value: &mut T) -> Result<(), StateError> {
+        value.load(self)
+    }
+
+    pub fn read_bytes(&mut self, count: usize) -> Result<&'a [u8], StateError> {
+        if self.data.len() < count {
+            return Err(StateError::UnexpectedEnd);
+        }
+
+        let (bytes, rest) = self.data.split_at(count);
+        self.data = rest;
+
+        Ok(bytes)
+    }
+
+    /// Checks that the whole state was read
+    pub fn finish(self) -> Result<(), StateError> {
+        match self.data.len() {
+            0 => Ok(()),
+            count => Err(StateError::TrailingBytes(count)),
+        }
+    }
+}
+
+macro_rules! impl_state_value_for_int {
+    ($($int:ty),*) => {
+        $(
+            impl StateValue for $int {
+                fn save(&self, state: &mut StateWriter) {
+                    state.write_bytes(&self.to_le_bytes());
+                }
+
+                fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+                    let bytes = state.read_bytes(std::mem::size_of::<$int>())?;
+                    *self = <$int>::from_le_bytes(bytes.try_into().unwrap());
+                    Ok(())
+                }
+            }
+        )*
+    };
+}
+
+impl_state_value_for_int!(u8, u16, u32, u64, i16, i32);
+
+// Sizes and positions are saved as 64 bits whatever the platform
+impl StateValue for usize {
+    fn save(&self, state: &mut StateWriter) {
+        state.write(&(*self as u64));
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        let mut value = 0u64;
+        state.read(&mut value)?;
+        *self = usize::try_from(value).map_err(|_| StateError::InvalidValue)?;
+        Ok(())
+    }
+}
+
+impl StateValue for bool {
+    fn save(&self, state: &mut StateWriter) {
+        state.write(&(*self as u8));
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        *self = match state.read_bytes(1)?[0] {
+            0 => false,
+            1 => true,
+            _ => return Err(StateError::InvalidValue),
+        };
+        Ok(())
+    }
+}
+
+impl<T: StateValue + Default> StateValue for Option<T> {
+    fn save(&self, state: &mut StateWriter) {
+        state.write(&self.is_some());
+        if let Some(value) = self {
+            state.write(value);
+        }
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        let mut is_some = false;
+        state.read(&mut is_some)?;
+
+        *self = match is_some {
+            true => {
+                let mut value = T::default();
+                state.read(&mut value)?;
+                Some(value)
+            }
+            false => None,
+        };
+        Ok(())
+    }
+}
+
+impl<T: StateValue> StateValue for [T] {
+    fn save(&self, state: &mut StateWriter) {
+        state.write(&self.len());
+        for value in self {
+            state.write(value);
+        }
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        let mut len = 0usize;
+        state.read(&mut len)?;
+
+        if len != self.len() {
+            return Err(StateError::SizeMismatch {
+                expected: self.len(),
+                actual: len,
+            });
+        }
+
+        self.iter_mut().try_for_each(|value| state.read(value))
+    }
+}
+
+impl<T: StateValue, const N: usize> StateValue for [T; N] {
+    fn save(&self, state: &mut StateWriter) {
+        state.write(self.as_slice());
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.read(self.as_mut_slice())
+    }
+}
+
+impl StateValue for Mirroring {
+    fn save(&self, state: &mut StateWriter) {
+        let (kind, mapping) = match self {
+            Mirroring::Horizontal => (0u8, [0; 4]),
+            Mirroring::Vertical => (1, [0; 4]),
+            Mirroring::FourScreen => (2, [0; 4]),
+            Mirroring::SingleScreenLower => (3, [0; 4]),
+            Mirroring::SingleScreenUpper => (4, [0; 4]),
+            Mirroring::Custom(mapping) => (5, *mapping),
+        };
+
+        state.write(&kind);
+        state.write_bytes(&mapping);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        let kind = state.read_bytes(1)?[0];
+        let mapping = state.read_bytes(4)?;
+
+        *self = match kind {
+            0 => Mirroring::Horizontal,
+            1 => Mirroring::Vertical,
+            2 => Mirroring::FourScreen,
+            3 => Mirroring::SingleScreenLower,
+            4 => Mirroring::SingleScreenUpper,
+            5 => Mirroring::Custom(mapping.try_into().unwrap()),
+            _ => return Err(StateError::InvalidValue),
+        };
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod state_tests {
+    use super::{StateError, StateReader, StateWriter};
+    use crate::nes::cartridge::Mirroring;
+
+    #[test]
+    fn round_trip_test() {
+        let mut writer = StateWriter::new();
+        writer.write(&0x12u8);
+        writer.write(&0x3456u16);
+        writer.write(&-341i16);
+        writer.write(&u64::MAX);
+        writer.write(&true);
+        writer.write(&Some(0x2007u16));
+        writer.write(&None::<u64>);
+        writer.write(&[1u8, 2, 3]);
+        writer.write(vec![4u16; 5].as_slice());
+        writer.write(&Mirroring::Custom([0, 1, 3, 2]));
+        let data = writer.into_bytes();
+
+        let mut reader = StateReader::new(&data);
+        let (mut byte, mut word, mut signed, mut long) = (0u8, 0u16, 0i16, 0u64);
+        let (mut flag, mut some, mut none) = (false, None::<u16>, Some(1u64));
+        let (mut array, mut memory) = ([0u8; 3], vec![0u16; 5]);
+        let mut mirroring = Mirroring::Horizontal;
+
+        reader.read(&mut byte).unwrap();
+        reader.read(&mut word).unwrap();
+        reader.read(&mut signed).unwrap();
+        reader.read(&mut long).unwrap();
+        reader.read(&mut flag).unwrap();
+        reader.read(&mut some).unwrap();
+        reader.read(&mut none).unwrap();
+        reader.read(&mut array).unwrap();
+        reader.read(memory.as_mut_slice()).unwrap();
+        reader.read(&mut mirroring).unwrap();
+        reader.finish().unwrap();
+
+        assert_eq!((byte, word, signed, long), (0x12, 0x3456, -341, u64::MAX));
+        assert_eq!((flag, some, none), (true, Some(0x2007), None));
+        assert_eq!((array, memory), ([1, 2, 3], vec![4; 5]));
+        assert_eq!(mirroring, Mirroring::Custom([0, 1, 3, 2]));
+    }
+
+    #[test]
+    fn errors_test() {
+        let mut value = 0u32;
+        assert_eq!(
+            StateReader::new(&[1, 2]).read(&mut value),
+            Err(StateError::UnexpectedEnd)
+        );
+
+        let mut flag = false;
+        assert_eq!(
+            StateReader::new(&[2]).read(&mut flag),
+            Err(StateError::InvalidValue)
+        );
+
+        let mut writer = StateWriter::new();
+        writer.write(&[0u8; 4]);
+        let data = writer.into_bytes();
+        let mut memory = [0u8; 8];
+        assert_eq!(
+            StateReader::new(&data).read(&mut memory),
+            Err(StateError::SizeMismatch {
+                expected: 8,
+                actual: 4
+            })
+        );
+
+        let mut byte = 0u8;
+        let mut reader = StateReader::new(&[1, 2, 3]);
+        reader.read(&mut byte).unwrap();
+        assert_eq!(reader.finish(), Err(StateError::TrailingBytes(2)));
+    }
+}
@@ -0,0 +1,707 @@
+use super::{CartridgeMemory, Mapper, StateError, StateReader, StateValue, StateWriter};
+use crate::nes::cartridge::{Cartridge, Mirroring};
+
+// A scanline lasts 341 PPU dots, three per CPU cycle
+const SCANLINE_DOTS: i16 = 341;
+
+/// The IRQ counter shared by the VRC4, VRC6 and VRC7. It counts up from the
+/// latch to $FF, either every CPU cycle or every scanline thanks to a prescaler.
+#[derive(Default)]
+struct VrcIrq {
+    latch: u8,
+    counter: u8,
+    prescaler: i16,
+    enabled: bool,
+    enable_after_acknowledge: bool,
+    cycle_mode: bool,
+    pending: bool,
+}
+
+impl VrcIrq {
+    fn write_control(&mut self, data: u8) {
+        self.enable_after_acknowledge = data & 0x01 != 0;
+        self.enabled = data & 0x02 != 0;
+        self.cycle_mode = data & 0x04 != 0;
+        self.pending = false;
+
+        if self.enabled {
+            self.counter = self.latch;
+            self.prescaler = SCANLINE_DOTS;
+        }
+    }
+
+    fn acknowledge(&mut self) {
+        self.pending = false;
+        self.enabled = self.enable_after_acknowledge;
+    }
+
+    fn clock(&mut self, cycles: u64) {
+        for _ in 0..cycles {
+            if !self.enabled {
+                return;
+            }
+
+            if self.cycle_mode {
+                self.clock_counter();
+            } else {
+                self.prescaler -= 3;
+                if self.prescaler <= 0 {
+                    self.prescaler += SCANLINE_DOTS;
+                    self.clock_counter();
+                }
+            }
+        }
+    }
+
+    fn clock_counter(&mut self) {
+        if self.counter == 0xFF {
+            self.counter = self.latch;
+            self.pending = true;
+        } else {
+            self.counter += 1;
+        }
+    }
+}
+
+impl StateValue for VrcIrq {
+    fn save(&self, state: &mut StateWriter) {
+        state.write(&self.latch);
+        state.write(&self.counter);
+        state.write(&self.prescaler);
+        state.write(&self.enabled);
+        state.write(&self.enable_after_acknowledge);
+        state.write(&self.cycle_mode);
+        state.write(&self.pending);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.read(&mut self.latch)?;
+        state.read(&mut self.counter)?;
+        state.read(&mut self.prescaler)?;
+        state.read(&mut self.enabled)?;
+        state.read(&mut self.enable_after_acknowledge)?;
+        state.read(&mut self.cycle_mode)?;
+        state.read(&mut self.pending)
+    }
+}
+
+fn vrc_mirroring(data: u8) -> Mirroring {
+    match data & 0b11 {
+        0 => Mirroring::Vertical,
+        1 => Mirroring::Horizontal,
+        2 => Mirroring::SingleScreenLower,
+        _ => Mirroring::SingleScreenUpper,
+    }
+}
+
+// The register selected by the two CPU address lines wired to the chip.
+// Each mask may hold several lines when the header does not tell which.
+fn wired_register(address: u16, lines: [u16; 2]) -> u16 {
+    (address & 0xF000) | (address & lines[0] != 0) as u16 | ((address & lines[1] != 0) as u16) << 1
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Chip {
+    Vrc2,
+    Vrc4,
+}
+
+/// Mappers 21, 22, 23 and 25. The VRC2 and VRC4 differ in the address lines
+/// that select their registers, given by the NES 2.0 submapper. Plain iNES
+/// files get both candidate lines, games only write the addresses of theirs.
+pub struct Vrc4 {
+    memory: CartridgeMemory,
+    chip: Chip,
+    lines: [u16; 2],
+    /// The VRC2a drops the low bit of the CHR banks
+    chr_shift: u8,
+    prg_banks: [u8; 2],
+    prg_swap: bool,
+    chr_banks: [u16; 8],
+    mirroring: Mirroring,
+    irq: VrcIrq,
+}
+
+impl Vrc4 {
+    pub fn new(cartridge: Cartridge) -> Self {
+        let (chip, lines) = match (cartridge.mapper, cartridge.submapper) {
+            (21, 1) => (Chip::Vrc4, [0x02, 0x04]),
+            (21, 2) => (Chip::Vrc4, [0x40, 0x80]),
+            (21, _) => (Chip::Vrc4, [0x42, 0x84]),
+            (22, _) => (Chip::Vrc2, [0x02, 0x01]),
+            (23, 1) => (Chip::Vrc4, [0x01, 0x02]),
+            (23, 2) => (Chip::Vrc4, [0x04, 0x08]),
+            (23, 3) => (Chip::Vrc2, [0x01, 0x02]),
+            (23, _) => (Chip::Vrc4, [0x05, 0x0A]),
+            (25, 1) => (Chip::Vrc4, [0x02, 0x01]),
+            (25, 2) => (Chip::Vrc4, [0x08, 0x04]),
+            (25, 3) => (Chip::Vrc2, [0x02, 0x01]),
+            (_, _) => (Chip::Vrc4, [0x0A, 0x05]),
+        };
+
+        Vrc4 {
+            chr_shift: (cartridge.mapper == 22) as u8,
+            mirroring: cartridge.mirroring,
+            memory: CartridgeMemory::new(cartridge),
+            chip,
+            lines,
+            prg_banks: [0; 2],
+            prg_swap: false,
+            chr_banks: [0; 8],
+            irq: VrcIrq::default(),
+        }
+    }
+
+    fn prg_bank(&self, address: u16) -> usize {
+        let second_last = self.memory.prg_rom_banks(0x2000).saturating_sub(2);
+
+        match (self.prg_swap, address) {
+            (false, 0x8000..=0x9FFF) | (true, 0xC000..=0xDFFF) => self.prg_banks[0] as usize,
+            (_, 0xA000..=0xBFFF) => self.prg_banks[1] as usize,
+            (false, 0xC000..=0xDFFF) | (true, 0x8000..=0x9FFF) => second_last,
+            _ => second_last + 1,
+        }
+    }
+
+    fn chr_bank(&self, address: u16) -> usize {
+        (self.chr_banks[(address >> 10) as usize & 7] >> self.chr_shift) as usize
+    }
+}
+
+impl Mapper for Vrc4 {
+    fn cpu_peek(&self, address: u16) -> Option<u8> {
+        match address {
+            0x6000..=0x7FFF => self.memory.prg_ram(0, 0x2000, address),
+            0x8000..=0xFFFF => Some(self.memory.prg_rom(self.prg_bank(address), 0x2000, address)),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, data: u8) {
+        if address < 0x8000 {
+            if address >= 0x6000 {
+                self.memory.write_prg_ram(0, 0x2000, address, data);
+            }
+            return;
+        }
+
+        match (wired_register(address, self.lines), self.chip) {
+            (0x8000..=0x8003, _) => self.prg_banks[0] = data & 0x1F,
+            (0x9000..=0x9001, Chip::Vrc4) => self.mirroring = vrc_mirroring(data),
+            (0x9000..=0x9003, Chip::Vrc2) => self.mirroring = vrc_mirroring(data & 1),
+            (0x9002..=0x9003, Chip::Vrc4) => self.prg_swap = data & 0x02 != 0,
+            (0xA000..=0xA003, _) => self.prg_banks[1] = data & 0x1F,
+            (register @ 0xB000..=0xEFFF, chip) => {
+                // Two registers per 1 KiB bank, for the low and the high nibble
+                let slot =
+                    ((register - 0xB000) >> 12) as usize * 2 + (register & 0x02) as usize / 2;
+                let bank = &mut self.chr_banks[slot];
+                let high_mask = match chip {
+                    Chip::Vrc2 => 0x0F,
+                    Chip::Vrc4 => 0x1F,
+                };
+
+                *bank = match register & 0x01 {
+                    0 => (*bank & 0x1F0) | (data & 0x0F) as u16,
+                    _ => (*bank & 0x00F) | ((data & high_mask) as u16) << 4,
+                };
+            }
+            (0xF000, Chip::Vrc4) => self.irq.latch = (self.irq.latch & 0xF0) | (data & 0x0F),
+            (0xF001, Chip::Vrc4) => self.irq.latch = (self.irq.latch & 0x0F) | (data & 0x0F) << 4,
+            (0xF002, Chip::Vrc4) => self.irq.write_control(data),
+            (0xF003, Chip::Vrc4) => self.irq.acknowledge(),
+            _ => {}
+        }
+    }
+
+    fn ppu_peek(&self, address: u16) -> u8 {
+        self.memory.chr(self.chr_bank(address), 0x0400, address)
+    }
+
+    fn ppu_write(&mut self, address: u16, data: u8) {
+        self.memory
+            .write_chr(self.chr_bank(address), 0x0400, address, data)
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn tick(&mut self, cycles: u64) {
+        self.irq.clock(cycles);
+    }
+
+    fn irq(&self) -> bool {
+        self.irq.pending
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        self.memory.save_state(state);
+        state.write(&self.prg_banks);
+        state.write(&self.prg_swap);
+        state.write(&self.chr_banks);
+        state.write(&self.mirroring);
+        state.write(&self.irq);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.memory.load_state(state)?;
+        state.read(&mut self.prg_banks)?;
+        state.read(&mut self.prg_swap)?;
+        state.read(&mut self.chr_banks)?;
+        state.read(&mut self.mirroring)?;
+        state.read(&mut self.irq)
+    }
+}
+
+/// Mappers 24 and 26, the two differ by swapping the A0 and A1 lines
+pub struct Vrc6 {
+    memory: CartridgeMemory,
+    lines: [u16; 2],
+    prg_16k_bank: u8,
+    prg_8k_bank: u8,
+    banking_mode: u8,
+    chr_banks: [u8; 8],
+    irq: VrcIrq,
+}
+
+impl Vrc6 {
+    pub fn new(cartridge: Cartridge) -> Self {
+        let lines = match cartridge.mapper {
+            26 => [0x02, 0x01],
+            _ => [0x01, 0x02],
+        };
+
+        Vrc6 {
+            memory: CartridgeMemory::new(cartridge),
+            lines,
+            prg_16k_bank: 0,
+            prg_8k_bank: 0,
+            banking_mode: 0x80,
+            chr_banks: [0; 8],
+            irq: VrcIrq::default(),
+        }
+    }
+
+    fn prg_ram_enabled(&self) -> bool {
+        self.banking_mode & 0x80 != 0
+    }
+
+    // 1 KiB banks in mode 0, 2 KiB banks in mode 1, and a mix of both in modes 2 and 3
+    fn chr_bank(&self, address: u16) -> usize {
+        let slot = (address >> 10) as usize & 7;
+
+        match (self.banking_mode & 0b11, slot) {
+            (0, _) => self.chr_banks[slot] as usize,
+            (1, _) => (self.chr_banks[slot / 2] as usize & !1) | (slot & 1),
+            (_, 0..=3) => self.chr_banks[slot] as usize,
+            (_, _) => (self.chr_banks[4 + (slot - 4) / 2] as usize & !1) | (slot & 1),
+        }
+    }
+}
+
+impl Mapper for Vrc6 {
+    fn cpu_peek(&self, address: u16) -> Option<u8> {
+        match address {
+            0x6000..=0x7FFF if self.prg_ram_enabled() => self.memory.prg_ram(0, 0x2000, address),
+            0x8000..=0xBFFF => Some(self.memory.prg_rom(
+                self.prg_16k_bank as usize,
+                0x4000,
+                address,
+            )),
+            0xC000..=0xDFFF => Some(self.memory.prg_rom(
+                self.prg_8k_bank as usize,
+                0x2000,
+                address,
+            )),
+            0xE000..=0xFFFF => {
+                let last = self.memory.prg_rom_banks(0x2000) - 1;
+                Some(self.memory.prg_rom(last, 0x2000, address))
+            }
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, data: u8) {
+        match address {
+            0x6000..=0x7FFF if self.prg_ram_enabled() => {
+                self.memory.write_prg_ram(0, 0x2000, address, data);
+            }
+            0x8000..=0xFFFF => match wired_register(address, self.lines) {
+                0x8000..=0x8003 => self.prg_16k_bank = data & 0x0F,
+                0xB003 => self.banking_mode = data,
+                0xC000..=0xC003 => self.prg_8k_bank = data & 0x1F,
+                register @ (0xD000..=0xD003 | 0xE000..=0xE003) => {
+                    let slot = ((register - 0xD000) >> 12) as usize * 4 + (register & 3) as usize;
+                    self.chr_banks[slot] = data;
+                }
+                0xF000 => self.irq.latch = data,
+                0xF001 => self.irq.write_control(data),
+                0xF002 => self.irq.acknowledge(),
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+
+    fn ppu_peek(&self, address: u16) -> u8 {
+        self.memory.chr(self.chr_bank(address), 0x0400, address)
+    }
+
+    fn ppu_write(&mut self, address: u16, data: u8) {
+        self.memory
+            .write_chr(self.chr_bank(address), 0x0400, address, data)
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        vrc_mirroring(self.banking_mode >> 2)
+    }
+
+    fn tick(&mut self, cycles: u64) {
+        self.irq.clock(cycles);
+    }
+
+    fn irq(&self) -> bool {
+        self.irq.pending
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        self.memory.save_state(state);
+        state.write(&self.prg_16k_bank);
+        state.write(&self.prg_8k_bank);
+        state.write(&self.banking_mode);
+        state.write(&self.chr_banks);
+        state.write(&self.irq);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.memory.load_state(state)?;
+        state.read(&mut self.prg_16k_bank)?;
+        state.read(&mut self.prg_8k_bank)?;
+        state.read(&mut self.banking_mode)?;
+        state.read(&mut self.chr_banks)?;
+        state.read(&mut self.irq)
+    }
+}
+
+/// Mapper 85. The VRC7b selects its second registers with A3 and the VRC7a
+/// with A4. The FM sound channels are not emulated.
+pub struct Vrc7 {
+    memory: CartridgeMemory,
+    line: u16,
+    prg_banks: [u8; 3],
+    chr_banks: [u8; 8],
+    control: u8,
+    irq: VrcIrq,
+}
+
+impl Vrc7 {
+    pub fn new(cartridge: Cartridge) -> Self {
+        let line = match cartridge.submapper {
+            1 => 0x08,
+            2 => 0x10,
+            _ => 0x18,
+        };
+
+        Vrc7 {
+            memory: CartridgeMemory::new(cartridge),
+            line,
+            prg_banks: [0; 3],
+            chr_banks: [0; 8],
+            control: 0,
+            irq: VrcIrq::default(),
+        }
+    }
+
+    fn prg_ram_enabled(&self) -> bool {
+        self.control & 0x80 != 0
+    }
+}
+
+impl Mapper for Vrc7 {
+    fn cpu_peek(&self, address: u16) -> Option<u8> {
+        match address {
+            0x6000..=0x7FFF if self.prg_ram_enabled() => self.memory.prg_ram(0, 0x2000, address),
+            0x8000..=0xDFFF => {
+                let bank = self.prg_banks[(address - 0x8000) as usize >> 13];
+                Some(self.memory.prg_rom(bank as usize, 0x2000, address))
+            }
+            0xE000..=0xFFFF => {
+                let last = self.memory.prg_rom_banks(0x2000) - 1;
+                Some(self.memory.prg_rom(last, 0x2000, address))
+            }
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, data: u8) {
+        let second = (address & self.line != 0) as usize;
+
+        match address & 0xF000 {
+            0x6000 | 0x7000 if self.prg_ram_enabled() => {
+                self.memory.write_prg_ram(0, 0x2000, address, data);
+            }
+            0x8000 => self.prg_banks[second] = data & 0x3F,
+            0x9000 if second == 0 => self.prg_banks[2] = data & 0x3F,
+            page @ 0xA000..=0xD000 => {
+                self.chr_banks[((page - 0xA000) >> 12) as usize * 2 + second] = data;
+            }
+            0xE000 if second == 0 => self.control = data,
+            0xE000 => self.irq.latch = data,
+            0xF000 if second == 0 => self.irq.write_control(data),
+            0xF000 => self.irq.acknowledge(),
+            _ => {}
+        }
+    }
+
+    fn ppu_peek(&self, address: u16) -> u8 {
+        let bank = self.chr_banks[(address >> 10) as usize & 7];
+        self.memory.chr(bank as usize, 0x0400, address)
+    }
+
+    fn ppu_write(&mut self, address: u16, data: u8) {
+        let bank = self.chr_banks[(address >> 10) as usize & 7];
+        self.memory.write_chr(bank as usize, 0x0400, address, data)
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        vrc_mirroring(self.control)
+    }
+
+    fn tick(&mut self, cycles: u64) {
+        self.irq.clock(cycles);
+    }
+
+    fn irq(&self) -> bool {
+        self.irq.pending
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        self.memory.save_state(state);
+        state.write(&self.prg_banks);
+        state.write(&self.chr_banks);
+        state.write(&self.control);
+        state.write(&self.irq);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.memory.load_state(state)?;
+        state.read(&mut self.prg_banks)?;
+        state.read(&mut self.chr_banks)?;
+        state.read(&mut self.control)?;
+        state.read(&mut self.irq)
+    }
+}
+
+#[cfg(test)]
+mod vrc_tests {
+    use super::super::{Mapper, MapperRegistry};
+    use crate::nes::cartridge::{Cartridge, Mirroring};
+
+    // Every 8 KiB of PRG-ROM and 1 KiB of CHR-ROM is filled with its bank number
+    fn board(mapper: u16, submapper: u8) -> Box<dyn Mapper> {
+        let mut cartridge =
+            Cartridge::from_prg_rom((0..0x40000).map(|i| (i / 0x2000) as u8).collect());
+        cartridge.mapper = mapper;
+        cartridge.submapper = submapper;
+        cartridge.prg_ram_size = 0x2000;
+        cartridge.chr_rom = (0..0x40000).map(|i| (i / 0x0400) as u8).collect();
+
+        MapperRegistry::default().create(cartridge).unwrap()
+    }
+
+    #[test]
+    fn vrc4_wiring_test() {
+        // The offsets of registers 1, 2 and 3 for each board
+        let boards = [
+            (21, 1, [0x02, 0x04, 0x06]),
+            (21, 2, [0x40, 0x80, 0xC0]),
+            (21, 0, [0x40, 0x04, 0xC0]),
+            (23, 1, [0x01, 0x02, 0x03]),
+            (23, 2, [0x04, 0x08, 0x0C]),
+            (23, 0, [0x04, 0x02, 0x0C]),
+            (25, 1, [0x02, 0x01, 0x03]),
+            (25, 2, [0x08, 0x04, 0x0C]),
+            (25, 0, [0x02, 0x04, 0x0C]),
+        ];
+
+        for (mapper, submapper, [one, two, three]) in boards {
+            let mut vrc = board(mapper, submapper);
+
+            // CHR bank 0 is $B000/$B001, bank 1 is $B002/$B003
+            vrc.cpu_write(0xB000, 0x05);
+            vrc.cpu_write(0xB000 + one, 0x01);
+            vrc.cpu_write(0xB000 + two, 0x07);
+            vrc.cpu_write(0xB000 + three, 0x02);
+            assert_eq!(vrc.ppu_read(0x0000), 0x15, "mapper {mapper}.{submapper}");
+            assert_eq!(vrc.ppu_read(0x0400), 0x27, "mapper {mapper}.{submapper}");
+
+            // PRG swap mode
+            vrc.cpu_write(0x8000, 3);
+            vrc.cpu_write(0x9000 + two, 0x02);
+            assert_eq!(
+                vrc.cpu_read(0x8000),
+                Some(30),
+                "mapper {mapper}.{submapper}"
+            );
+            assert_eq!(vrc.cpu_read(0xC000), Some(3), "mapper {mapper}.{submapper}");
+        }
+    }
+
+    #[test]
+    fn vrc4_prg_and_mirroring_test() {
+        let mut vrc = board(21, 1);
+
+        vrc.cpu_write(0x8000, 4);
+        vrc.cpu_write(0xA000, 5);
+        assert_eq!(vrc.cpu_read(0x8000), Some(4));
+        assert_eq!(vrc.cpu_read(0xA000), Some(5));
+        assert_eq!(vrc.cpu_read(0xC000), Some(30));
+        assert_eq!(vrc.cpu_read(0xE000), Some(31));
+
+        vrc.cpu_write(0x9000, 1);
+        assert_eq!(vrc.mirroring(), Mirroring::Horizontal);
+        vrc.cpu_write(0x9000, 3);
+        assert_eq!(vrc.mirroring(), Mirroring::SingleScreenUpper);
+
+        vrc.cpu_write(0x6000, 0x42);
+        assert_eq!(vrc.cpu_read(0x6000), Some(0x42));
+    }
+
+    #[test]
+    fn vrc2_test() {
+        // The VRC2a ignores the low bit of the CHR banks
+        let mut vrc = board(22, 0);
+
+        vrc.cpu_write(0xB000, 0x0B);
+        vrc.cpu_write(0xB002, 0x01);
+        assert_eq!(vrc.ppu_read(0x0000), 0x0D);
+
+        // A0 and A1 are swapped, and there is a single mirroring bit
+        vrc.cpu_write(0xB001, 0x06);
+        assert_eq!(vrc.ppu_read(0x0400), 0x03);
+        vrc.cpu_write(0x9000, 0x03);
+        assert_eq!(vrc.mirroring(), Mirroring::Horizontal);
+
+        // No IRQ on the VRC2
+        vrc.cpu_write(0xF002, 0x06);
+        vrc.tick(300);
+        assert!(!vrc.irq());
+
+        let mut vrc = board(23, 3);
+        vrc.cpu_write(0xB001, 0x1F);
+        assert_eq!(vrc.ppu_read(0x0000), 0xF0);
+    }
+
+    #[test]
+    fn vrc_irq_cycle_mode_test() {
+        let mut vrc = board(21, 1);
+
+        // Latch $FC, counting every cycle
+        vrc.cpu_write(0xF000, 0x0C);
+        vrc.cpu_write(0xF002, 0x0F);
+        vrc.cpu_write(0xF004, 0x06);
+
+        vrc.tick(3);
+        assert!(!vrc.irq());
+        vrc.tick(1);
+        assert!(vrc.irq());
+
+        // The acknowledge copies the enable-after-acknowledge bit
+        vrc.cpu_write(0xF006, 0);
+        assert!(!vrc.irq());
+        vrc.tick(4);
+        assert!(!vrc.irq());
+    }
+
+    #[test]
+    fn vrc_irq_scanline_mode_test() {
+        let mut vrc = board(21, 1);
+
+        // Latch $FE, two scanlines of 113 2/3 cycles
+        vrc.cpu_write(0xF000, 0x0E);
+        vrc.cpu_write(0xF002, 0x0F);
+        vrc.cpu_write(0xF004, 0x02);
+
+        vrc.tick(227);
+        assert!(!vrc.irq());
+        vrc.tick(1);
+        assert!(vrc.irq());
+
+        // Acknowledging with A clear disables it
+        vrc.cpu_write(0xF006, 0);
+        vrc.tick(1000);
+        assert!(!vrc.irq());
+    }
+
+    #[test]
+    fn vrc6_test() {
+        for (mapper, one, two) in [(24, 0x01, 0x02), (26, 0x02, 0x01)] {
+            let mut vrc = board(mapper, 0);
+
+            vrc.cpu_write(0x8000, 3);
+            vrc.cpu_write(0xC000, 9);
+            assert_eq!(vrc.cpu_read(0x8000), Some(6));
+            assert_eq!(vrc.cpu_read(0xA000), Some(7));
+            assert_eq!(vrc.cpu_read(0xC000), Some(9));
+            assert_eq!(vrc.cpu_read(0xE000), Some(31));
+
+            // 1 KiB CHR banks, horizontal mirroring and PRG-RAM
+            vrc.cpu_write(0xB000 + one + two, 0x84);
+            vrc.cpu_write(0xD000 + one, 0x11);
+            vrc.cpu_write(0xE000 + two + one, 0x22);
+            assert_eq!(vrc.ppu_read(0x0400), 0x11);
+            assert_eq!(vrc.ppu_read(0x1C00), 0x22);
+            assert_eq!(vrc.mirroring(), Mirroring::Horizontal);
+            vrc.cpu_write(0x6000, 0x42);
+            assert_eq!(vrc.cpu_read(0x6000), Some(0x42));
+
+            // 2 KiB CHR banks
+            vrc.cpu_write(0xB000 + one + two, 0x81);
+            assert_eq!(vrc.ppu_read(0x0800), 0x10);
+            assert_eq!(vrc.ppu_read(0x0C00), 0x11);
+
+            vrc.cpu_write(0xF000, 0xFF);
+            vrc.cpu_write(0xF000 + one, 0x06);
+            vrc.tick(1);
+            assert!(vrc.irq());
+            vrc.cpu_write(0xF000 + two, 0);
+            assert!(!vrc.irq());
+        }
+    }
+
+    #[test]
+    fn vrc7_test() {
+        for (submapper, line) in [(1, 0x08), (2, 0x10), (0, 0x10)] {
+            let mut vrc = board(85, submapper);
+
+            vrc.cpu_write(0x8000, 3);
+            vrc.cpu_write(0x8000 + line, 4);
+            vrc.cpu_write(0x9000, 5);
+            assert_eq!(vrc.cpu_read(0x8000), Some(3));
+            assert_eq!(vrc.cpu_read(0xA000), Some(4));
+            assert_eq!(vrc.cpu_read(0xC000), Some(5));
+            assert_eq!(vrc.cpu_read(0xE000), Some(31));
+
+            vrc.cpu_write(0xA000, 0x10);
+            vrc.cpu_write(0xD000 + line, 0x17);
+            assert_eq!(vrc.ppu_read(0x0000), 0x10);
+            assert_eq!(vrc.ppu_read(0x1C00), 0x17);
+
+            // PRG-RAM is enabled by bit 7 of the control register
+            vrc.cpu_write(0xE000, 0x81);
+            assert_eq!(vrc.mirroring(), Mirroring::Horizontal);
+            vrc.cpu_write(0x6000, 0x42);
+            assert_eq!(vrc.cpu_read(0x6000), Some(0x42));
+            vrc.cpu_write(0xE000, 0x01);
+            assert_eq!(vrc.cpu_read(0x6000), None);
+
+            vrc.cpu_write(0xE000 + line, 0xFF);
+            vrc.cpu_write(0xF000, 0x06);
+            vrc.tick(1);
+            assert!(vrc.irq());
+            vrc.cpu_write(0xF000 + line, 0);
+            assert!(!vrc.irq());
+        }
+    }
+}
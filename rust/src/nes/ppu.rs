@@ -0,0 +1,1506 @@
+use super::cartridge::Mirroring;
+use super::mapper::{Mapper, StateError, StateReader, StateWriter};
+
+pub const SCREEN_WIDTH: usize = 256;
+pub const SCREEN_HEIGHT: usize = 240;
+
+// A scanline lasts 341 dots, a frame 262 scanlines
+const DOTS: u16 = 341;
+const SCANLINES: u16 = 262;
+const VBLANK_SCANLINE: u16 = 241;
+const PRE_RENDER_SCANLINE: u16 = 261;
+
+// PPUCTRL
+const VRAM_INCREMENT_32: u8 = 0x04;
+const SPRITE_PATTERN_TABLE: u8 = 0x08;
+const BACKGROUND_PATTERN_TABLE: u8 = 0x10;
+const SPRITE_SIZE_16: u8 = 0x20;
+const NMI_ENABLE: u8 = 0x80;
+
+// PPUMASK
+const GRAYSCALE: u8 = 0x01;
+const SHOW_BACKGROUND_LEFT: u8 = 0x02;
+const SHOW_SPRITES_LEFT: u8 = 0x04;
+const SHOW_BACKGROUND: u8 = 0x08;
+const SHOW_SPRITES: u8 = 0x10;
+
+// PPUSTATUS
+const SPRITE_OVERFLOW: u8 = 0x20;
+const SPRITE_ZERO_HIT: u8 = 0x40;
+const VBLANK: u8 = 0x80;
+
+// Sprite attributes
+const BEHIND_BACKGROUND: u8 = 0x20;
+const FLIP_HORIZONTAL: u8 = 0x40;
+const FLIP_VERTICAL: u8 = 0x80;
+
+/// The 2C02 picture processing unit, as the CPU sees it through its eight
+/// registers at $2000-$2007.
+///
+/// Its own address space holds the pattern tables of the cartridge at
+/// $0000-$1FFF, the nametables at $2000-$2FFF (mirrored up to $3EFF) and
+/// the palettes at $3F00-$3F1F (mirrored up to $3FFF). The nametables live
+/// in the 2 KiB of console VRAM, arranged by the mirroring of the cartridge,
+/// unless the mapper answers for them.
+///
+/// The picture comes out dot by dot into a framebuffer of palette entries,
+/// one byte per pixel with the 6-bit color of the NES master palette.
+pub struct Ppu {
+    ctrl: u8,
+    mask: u8,
+    status: u8,
+    oam_address: u8,
+    oam: [u8; 0x100],
+    /// The console VRAM, then the 2 KiB that four-screen boards add
+    vram: [u8; 0x1000],
+    palette: [u8; 0x20],
+    /// The current VRAM address, also the scroll position while rendering
+    v: u16,
+    /// The temporary VRAM address, copied to `v` at times
+    t: u16,
+    /// Fine X scroll
+    x: u8,
+    /// The write toggle shared by PPUSCROLL and PPUADDR
+    w: bool,
+    read_buffer: u8,
+    /// The last value on the data bus of the registers, read back from the
+    /// write-only ones
+    io_latch: u8,
+    dot: u16,
+    scanline: u16,
+    frame: u64,
+    nmi_pending: bool,
+    /// The latches filled by the fetches of the next tile
+    next_tile: u8,
+    next_attribute: u8,
+    next_pattern: [u8; 2],
+    /// Two tiles of pattern bits and their palette bits, shifted left every dot
+    pattern_shift: [u16; 2],
+    attribute_shift: [u16; 2],
+    /// The sprites found for the next scanline, 8 entries of OAM, and
+    /// whether sprite 0 is the first of them
+    secondary_oam: [u8; 0x20],
+    next_sprite_count: u8,
+    next_sprite_zero: bool,
+    /// The sprites of the current scanline, their patterns already flipped
+    sprite_count: u8,
+    sprite_zero: bool,
+    sprite_x: [u8; 8],
+    sprite_attributes: [u8; 8],
+    sprite_patterns: [[u8; 2]; 8],
+    framebuffer: Vec<u8>,
+}
+
+impl Default for Ppu {
+    fn default() -> Self {
+        Ppu {
+            ctrl: 0,
+            mask: 0,
+            status: 0,
+            oam_address: 0,
+            oam: [0; 0x100],
+            vram: [0; 0x1000],
+            palette: [0; 0x20],
+            v: 0,
+            t: 0,
+            x: 0,
+            w: false,
+            read_buffer: 0,
+            io_latch: 0,
+            dot: 0,
+            scanline: 0,
+            frame: 0,
+            nmi_pending: false,
+            next_tile: 0,
+            next_attribute: 0,
+            next_pattern: [0; 2],
+            pattern_shift: [0; 2],
+            attribute_shift: [0; 2],
+            secondary_oam: [0xFF; 0x20],
+            next_sprite_count: 0,
+            next_sprite_zero: false,
+            sprite_count: 0,
+            sprite_zero: false,
+            sprite_x: [0; 8],
+            sprite_attributes: [0; 8],
+            sprite_patterns: [[0; 2]; 8],
+            framebuffer: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT],
+        }
+    }
+}
+
+impl Ppu {
+    pub fn ctrl(&self) -> u8 {
+        self.ctrl
+    }
+
+    pub fn mask(&self) -> u8 {
+        self.mask
+    }
+
+    pub fn status(&self) -> u8 {
+        self.status
+    }
+
+    pub fn vram_address(&self) -> u16 {
+        self.v
+    }
+
+    pub fn temp_vram_address(&self) -> u16 {
+        self.t
+    }
+
+    pub fn fine_x(&self) -> u8 {
+        self.x
+    }
+
+    pub fn write_toggle(&self) -> bool {
+        self.w
+    }
+
+    pub fn oam(&self) -> &[u8; 0x100] {
+        &self.oam
+    }
+
+    pub fn palette(&self) -> &[u8; 0x20] {
+        &self.palette
+    }
+
+    pub fn dot(&self) -> u16 {
+        self.dot
+    }
+
+    pub fn scanline(&self) -> u16 {
+        self.scanline
+    }
+
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    /// The last picture, row by row, as colors of the NES master palette
+    pub fn framebuffer(&self) -> &[u8] {
+        &self.framebuffer
+    }
+
+    /// Takes the rising edge of the NMI output, vblank with NMI enabled
+    pub fn take_nmi(&mut self) -> bool {
+        std::mem::take(&mut self.nmi_pending)
+    }
+
+    /// Reads a register as the CPU does, `address` is mirrored every 8 bytes
+    pub fn read_register(&mut self, address: u16, mapper: &mut Option<Box<dyn Mapper>>) -> u8 {
+        self.io_latch = match address & 7 {
+            // Reading the status ends the vblank flag and resets the write toggle
+            2 => {
+                let data = self.peek_register(address, mapper);
+                self.status &= !VBLANK;
+                self.w = false;
+                data
+            }
+            4 => self.peek_register(address, mapper),
+            7 => {
+                let data = self.peek_register(address, mapper);
+                // The palettes answer at once, the buffer gets the nametable beneath
+                let buffered = match self.v & 0x3FFF {
+                    0x3F00..=0x3FFF => self.v & 0x2FFF,
+                    _ => self.v,
+                };
+                self.read_buffer = self.read_memory(buffered, mapper);
+                self.increment_vram_address();
+                data
+            }
+            _ => self.io_latch,
+        };
+
+        self.io_latch
+    }
+
+    /// Reads a register without clearing flags or moving the VRAM address
+    pub fn peek_register(&self, address: u16, mapper: &Option<Box<dyn Mapper>>) -> u8 {
+        match address & 7 {
+            2 => self.status & 0xE0 | self.io_latch & 0x1F,
+            // The secondary OAM is cleared with $FF at the start of the
+            // visible scanlines, and OAMDATA reads what is written
+            4 if self.scanline < SCREEN_HEIGHT as u16
+                && (1..=64).contains(&self.dot)
+                && self.rendering_enabled() =>
+            {
+                0xFF
+            }
+            4 => match self.oam_address & 3 {
+                // Bits 2-4 of the sprite attributes do not exist
+                2 => self.oam[self.oam_address as usize] & 0xE3,
+                _ => self.oam[self.oam_address as usize],
+            },
+            7 if self.v & 0x3FFF >= 0x3F00 => {
+                self.peek_memory(self.v, mapper) | self.io_latch & 0xC0
+            }
+            7 => self.read_buffer,
+            _ => self.io_latch,
+        }
+    }
+
+    pub fn write_register(&mut self, address: u16, data: u8, mapper: &mut Option<Box<dyn Mapper>>) {
+        self.io_latch = data;
+
+        match address & 7 {
+            0 => {
+                // Enabling NMI during vblank raises it at once
+                if self.ctrl & NMI_ENABLE == 0 && data & NMI_ENABLE != 0 {
+                    self.nmi_pending |= self.status & VBLANK != 0;
+                }
+                self.ctrl = data;
+                self.t = (self.t & !0x0C00) | (data as u16 & 0x03) << 10;
+            }
+            1 => self.mask = data,
+            3 => self.oam_address = data,
+            4 => {
+                self.oam[self.oam_address as usize] = data;
+                self.oam_address = self.oam_address.wrapping_add(1);
+            }
+            // Coarse X and fine X, then coarse Y and fine Y
+            5 if !self.w => {
+                self.t = (self.t & !0x001F) | data as u16 >> 3;
+                self.x = data & 0x07;
+                self.w = true;
+            }
+            5 => {
+                self.t = (self.t & 0x0C1F) | (data as u16 & 0x07) << 12 | (data as u16 >> 3) << 5;
+                self.w = false;
+            }
+            // The high byte, whose bit 14 is cleared, then the low byte
+            6 if !self.w => {
+                self.t = (self.t & 0x00FF) | (data as u16 & 0x3F) << 8;
+                self.w = true;
+            }
+            6 => {
+                self.t = (self.t & 0x7F00) | data as u16;
+                self.v = self.t;
+                self.w = false;
+            }
+            7 => {
+                self.write_memory(self.v, data, mapper);
+                self.increment_vram_address();
+            }
+            _ => {}
+        }
+    }
+
+    /// Runs one dot: the fetches of the background and the sprites, the
+    /// pixel, the vblank flag and the NMI, and the scanline notifications of
+    /// the mapper
+    pub fn step(&mut self, mapper: &mut Option<Box<dyn Mapper>>) {
+        let visible = self.scanline < SCREEN_HEIGHT as u16;
+
+        if self.rendering_enabled() && (visible || self.scanline == PRE_RENDER_SCANLINE) {
+            self.render_background(mapper);
+            self.render_sprites(mapper);
+        }
+
+        if visible && (1..=SCREEN_WIDTH as u16).contains(&self.dot) {
+            self.output_pixel();
+        }
+
+        match (self.scanline, self.dot) {
+            (VBLANK_SCANLINE, 1) => {
+                self.status |= VBLANK;
+                self.nmi_pending |= self.ctrl & NMI_ENABLE != 0;
+            }
+            (PRE_RENDER_SCANLINE, 1) => {
+                self.status &= !(VBLANK | SPRITE_ZERO_HIT | SPRITE_OVERFLOW);
+            }
+            _ => {}
+        }
+
+        self.dot += 1;
+
+        // The odd frames skip the last dot of the pre-render line while rendering
+        if self.scanline == PRE_RENDER_SCANLINE
+            && self.dot == DOTS - 1
+            && self.frame % 2 == 1
+            && self.rendering_enabled()
+        {
+            self.dot = DOTS;
+        }
+
+        if self.dot == DOTS {
+            self.dot = 0;
+            self.scanline = (self.scanline + 1) % SCANLINES;
+            if self.scanline == 0 {
+                self.frame += 1;
+            }
+
+            if let Some(mapper) = mapper {
+                mapper.scanline(self.scanline);
+            }
+        }
+    }
+
+    fn rendering_enabled(&self) -> bool {
+        self.mask & (SHOW_BACKGROUND | SHOW_SPRITES) != 0
+    }
+
+    // The fetches of the background tiles: 32 tiles for the scanline at dots
+    // 1-256, then the first two of the next one at dots 321-336. Each takes
+    // 8 dots, for the nametable, attribute and two pattern reads.
+    fn render_background(&mut self, mapper: &mut Option<Box<dyn Mapper>>) {
+        let dot = self.dot;
+
+        if (2..=257).contains(&dot) || (322..=337).contains(&dot) {
+            for shift in self
+                .pattern_shift
+                .iter_mut()
+                .chain(&mut self.attribute_shift)
+            {
+                *shift <<= 1;
+            }
+
+            if dot % 8 == 1 {
+                self.reload_shifters();
+            }
+        }
+
+        if (1..=256).contains(&dot) || (321..=336).contains(&dot) {
+            match dot % 8 {
+                1 => self.next_tile = self.read_memory(0x2000 | (self.v & 0x0FFF), mapper),
+                3 => {
+                    let address = 0x23C0
+                        | (self.v & 0x0C00)
+                        | ((self.v >> 4) & 0x38)
+                        | ((self.v >> 2) & 0x07);
+                    let shift = ((self.v >> 4) & 0x04) | (self.v & 0x02);
+                    self.next_attribute = (self.read_memory(address, mapper) >> shift) & 0x03;
+                }
+                5 => self.next_pattern[0] = self.read_memory(self.pattern_address(), mapper),
+                7 => self.next_pattern[1] = self.read_memory(self.pattern_address() + 8, mapper),
+                0 => self.increment_x(),
+                _ => {}
+            }
+        }
+
+        match dot {
+            256 => self.increment_y(),
+            257 => self.v = (self.v & !0x041F) | (self.t & 0x041F),
+            280..=304 if self.scanline == PRE_RENDER_SCANLINE => {
+                self.v = (self.v & !0x7BE0) | (self.t & 0x7BE0);
+            }
+            // The two unused nametable fetches at the end of the scanline
+            337 | 339 => {
+                self.read_memory(0x2000 | (self.v & 0x0FFF), mapper);
+            }
+            _ => {}
+        }
+    }
+
+    // The sprites of the next scanline are found at dot 65, then their
+    // patterns are fetched at dots 257-320 in 8 slots of two unused
+    // nametable reads and two pattern reads. Empty slots read tile $FF.
+    fn render_sprites(&mut self, mapper: &mut Option<Box<dyn Mapper>>) {
+        let dot = self.dot;
+
+        if dot == 65 {
+            self.evaluate_sprites();
+        }
+
+        if !(257..=320).contains(&dot) {
+            return;
+        }
+
+        self.oam_address = 0;
+        if dot == 257 {
+            self.sprite_count = self.next_sprite_count;
+            self.sprite_zero = self.next_sprite_zero;
+        }
+
+        let slot = (dot - 257) as usize / 8;
+        let [y, tile, attributes, x] = self.secondary_oam[slot * 4..slot * 4 + 4]
+            .try_into()
+            .unwrap();
+
+        match dot % 8 {
+            1 | 3 => {
+                self.read_memory(0x2000 | (self.v & 0x0FFF), mapper);
+            }
+            5 | 7 => {
+                let height = self.sprite_height();
+                let row = match self.scanline.wrapping_sub(y as u16) % height {
+                    row if attributes & FLIP_VERTICAL != 0 => height - 1 - row,
+                    row => row,
+                };
+
+                let plane = (dot % 8 == 7) as usize;
+                let address = self.sprite_pattern_address(tile, row) + 8 * plane as u16;
+                let data = self.read_memory(address, mapper);
+
+                self.sprite_patterns[slot][plane] = match attributes & FLIP_HORIZONTAL {
+                    _ if slot >= self.next_sprite_count as usize => 0,
+                    0 => data,
+                    _ => data.reverse_bits(),
+                };
+                self.sprite_attributes[slot] = attributes;
+                self.sprite_x[slot] = x;
+            }
+            _ => {}
+        }
+    }
+
+    // Copies the first 8 sprites that cover the next scanline to the
+    // secondary OAM. There are none after the pre-render line.
+    fn evaluate_sprites(&mut self) {
+        self.secondary_oam = [0xFF; 0x20];
+        self.next_sprite_count = 0;
+        self.next_sprite_zero = false;
+
+        if self.scanline == PRE_RENDER_SCANLINE {
+            return;
+        }
+
+        let (scanline, height) = (self.scanline, self.sprite_height());
+        let in_range = |y: u8| scanline.wrapping_sub(y as u16) < height;
+
+        let mut sprite = 0;
+        while sprite < 64 && self.next_sprite_count < 8 {
+            let entry = sprite * 4;
+            if in_range(self.oam[entry]) {
+                let slot = self.next_sprite_count as usize * 4;
+                self.secondary_oam[slot..slot + 4].copy_from_slice(&self.oam[entry..entry + 4]);
+                self.next_sprite_count += 1;
+                self.next_sprite_zero |= sprite == 0;
+            }
+            sprite += 1;
+        }
+
+        // Looking for a ninth sprite, the PPU also moves to the next byte of
+        // each entry and takes the tile, attributes or X as the Y coordinate
+        let mut byte = 0;
+        while sprite < 64 {
+            if in_range(self.oam[sprite * 4 + byte]) {
+                self.status |= SPRITE_OVERFLOW;
+                break;
+            }
+            sprite += 1;
+            byte = (byte + 1) % 4;
+        }
+    }
+
+    fn sprite_height(&self) -> u16 {
+        match self.ctrl & SPRITE_SIZE_16 {
+            0 => 8,
+            _ => 16,
+        }
+    }
+
+    fn pattern_address(&self) -> u16 {
+        let table = match self.ctrl & BACKGROUND_PATTERN_TABLE {
+            0 => 0x0000,
+            _ => 0x1000,
+        };
+
+        table | (self.next_tile as u16) << 4 | (self.v >> 12) & 0x07
+    }
+
+    // 8x16 sprites take their pattern table from bit 0 of the tile, and
+    // their bottom half from the next tile
+    fn sprite_pattern_address(&self, tile: u8, row: u16) -> u16 {
+        match self.ctrl & SPRITE_SIZE_16 {
+            0 => {
+                let table = match self.ctrl & SPRITE_PATTERN_TABLE {
+                    0 => 0x0000,
+                    _ => 0x1000,
+                };
+                table | (tile as u16) << 4 | row & 0x07
+            }
+            _ => {
+                let table = (tile as u16 & 1) << 12;
+                table | ((tile & 0xFE) as u16 | (row >> 3) & 1) << 4 | row & 0x07
+            }
+        }
+    }
+
+    // The next tile goes in the low byte, behind the one being drawn
+    fn reload_shifters(&mut self) {
+        for plane in 0..2 {
+            self.pattern_shift[plane] =
+                (self.pattern_shift[plane] & 0xFF00) | self.next_pattern[plane] as u16;
+
+            let bits = match (self.next_attribute >> plane) & 1 {
+                0 => 0x00,
+                _ => 0xFF,
+            };
+            self.attribute_shift[plane] = (self.attribute_shift[plane] & 0xFF00) | bits;
+        }
+    }
+
+    fn output_pixel(&mut self) {
+        let x = self.dot as usize - 1;
+        let background = self.background_pixel(x);
+        let sprite = self.sprite_pixel(x);
+
+        // Sprite 0 hits on opaque pixels whatever its priority, but never
+        // at the last column
+        if let Some((0, _, _)) = sprite {
+            if self.sprite_zero && background != 0 && x != 255 {
+                self.status |= SPRITE_ZERO_HIT;
+            }
+        }
+
+        // The first opaque sprite hides the others, even behind the background
+        let pixel = match (background, sprite) {
+            (0, Some((_, pixel, _))) | (_, Some((_, pixel, false))) => pixel,
+            _ => background,
+        };
+
+        // With rendering off, a VRAM address in the palettes shows that color
+        let address = match pixel {
+            _ if !self.rendering_enabled() && self.v & 0x3F00 == 0x3F00 => self.v,
+            0 => 0x3F00,
+            _ => 0x3F00 | pixel,
+        };
+
+        self.framebuffer[self.scanline as usize * SCREEN_WIDTH + x] = match self.mask & GRAYSCALE {
+            0 => self.palette[palette_index(address)],
+            _ => self.palette[palette_index(address)] & 0x30,
+        };
+    }
+
+    // A palette entry of the background, 0 where it is transparent
+    fn background_pixel(&self, x: usize) -> u16 {
+        if self.mask & SHOW_BACKGROUND == 0 || (x < 8 && self.mask & SHOW_BACKGROUND_LEFT == 0) {
+            return 0;
+        }
+
+        let bit = 15 - self.x;
+        let pattern =
+            ((self.pattern_shift[0] >> bit) & 1) | ((self.pattern_shift[1] >> bit) & 1) << 1;
+        let attribute =
+            ((self.attribute_shift[0] >> bit) & 1) | ((self.attribute_shift[1] >> bit) & 1) << 1;
+
+        match pattern {
+            0 => 0,
+            _ => attribute << 2 | pattern,
+        }
+    }
+
+    // The slot, palette entry and priority of the first opaque sprite
+    fn sprite_pixel(&self, x: usize) -> Option<(usize, u16, bool)> {
+        if self.mask & SHOW_SPRITES == 0 || (x < 8 && self.mask & SHOW_SPRITES_LEFT == 0) {
+            return None;
+        }
+
+        (0..self.sprite_count as usize).find_map(|slot| {
+            let column = x.checked_sub(self.sprite_x[slot] as usize)?;
+            if column >= 8 {
+                return None;
+            }
+
+            let [low, high] = self.sprite_patterns[slot];
+            let bit = 7 - column;
+            let pattern = ((low >> bit) & 1) | ((high >> bit) & 1) << 1;
+            let attributes = self.sprite_attributes[slot];
+
+            match pattern {
+                0 => None,
+                _ => Some((
+                    slot,
+                    0x10 | (attributes as u16 & 0x03) << 2 | pattern as u16,
+                    attributes & BEHIND_BACKGROUND != 0,
+                )),
+            }
+        })
+    }
+
+    // Coarse X, wrapping to the next horizontal nametable
+    fn increment_x(&mut self) {
+        if self.v & 0x001F == 31 {
+            self.v = (self.v & !0x001F) ^ 0x0400;
+        } else {
+            self.v += 1;
+        }
+    }
+
+    // Fine Y then coarse Y, wrapping to the next vertical nametable after
+    // row 29. Rows 30 and 31 are the attributes, they wrap in place.
+    fn increment_y(&mut self) {
+        if self.v & 0x7000 != 0x7000 {
+            self.v += 0x1000;
+            return;
+        }
+
+        self.v &= !0x7000;
+        let coarse_y = match (self.v >> 5) & 0x1F {
+            29 => {
+                self.v ^= 0x0800;
+                0
+            }
+            31 => 0,
+            y => y + 1,
+        };
+        self.v = (self.v & !0x03E0) | coarse_y << 5;
+    }
+
+    // While rendering, PPUDATA accesses bump both coarse X and Y
+    fn increment_vram_address(&mut self) {
+        let rendering =
+            self.scanline < SCREEN_HEIGHT as u16 || self.scanline == PRE_RENDER_SCANLINE;
+        if rendering && self.rendering_enabled() {
+            self.increment_x();
+            self.increment_y();
+            return;
+        }
+
+        let increment = match self.ctrl & VRAM_INCREMENT_32 {
+            0 => 1,
+            _ => 32,
+        };
+
+        self.v = self.v.wrapping_add(increment) & 0x7FFF;
+    }
+
+    fn read_memory(&mut self, address: u16, mapper: &mut Option<Box<dyn Mapper>>) -> u8 {
+        let address = address & 0x3FFF;
+
+        match (address, mapper) {
+            (0x0000..=0x1FFF, Some(mapper)) => mapper.ppu_read(address),
+            (0x2000..=0x3EFF, Some(mapper)) => {
+                let address = 0x2000 | address & 0x0FFF;
+                match mapper.nametable_read(address) {
+                    Some(data) => data,
+                    None => self.vram[nametable_index(address, mapper.mirroring())],
+                }
+            }
+            (_, mapper) => self.peek_memory(address, mapper),
+        }
+    }
+
+    fn peek_memory(&self, address: u16, mapper: &Option<Box<dyn Mapper>>) -> u8 {
+        let address = address & 0x3FFF;
+
+        match (address, mapper) {
+            (0x0000..=0x1FFF, Some(mapper)) => mapper.ppu_peek(address),
+            (0x0000..=0x1FFF, None) => 0,
+            (0x2000..=0x3EFF, mapper) => {
+                let address = 0x2000 | address & 0x0FFF;
+                let mirroring = mapper
+                    .as_ref()
+                    .map_or(Mirroring::Horizontal, |mapper| mapper.mirroring());
+
+                mapper
+                    .as_ref()
+                    .and_then(|mapper| mapper.nametable_peek(address))
+                    .unwrap_or(self.vram[nametable_index(address, mirroring)])
+            }
+            _ => match self.mask & GRAYSCALE {
+                0 => self.palette[palette_index(address)],
+                _ => self.palette[palette_index(address)] & 0x30,
+            },
+        }
+    }
+
+    fn write_memory(&mut self, address: u16, data: u8, mapper: &mut Option<Box<dyn Mapper>>) {
+        let address = address & 0x3FFF;
+
+        match (address, mapper) {
+            (0x0000..=0x1FFF, Some(mapper)) => mapper.ppu_write(address, data),
+            (0x0000..=0x1FFF, None) => {}
+            (0x2000..=0x3EFF, mapper) => {
+                let address = 0x2000 | address & 0x0FFF;
+                let mirroring = match mapper {
+                    Some(mapper) => match mapper.nametable_write(address, data) {
+                        true => return,
+                        false => mapper.mirroring(),
+                    },
+                    None => Mirroring::Horizontal,
+                };
+
+                self.vram[nametable_index(address, mirroring)] = data;
+            }
+            _ => self.palette[palette_index(address)] = data & 0x3F,
+        }
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write(&self.ctrl);
+        state.write(&self.mask);
+        state.write(&self.status);
+        state.write(&self.oam_address);
+        state.write(&self.oam);
+        state.write(&self.vram);
+        state.write(&self.palette);
+        state.write(&self.v);
+        state.write(&self.t);
+        state.write(&self.x);
+        state.write(&self.w);
+        state.write(&self.read_buffer);
+        state.write(&self.io_latch);
+        state.write(&self.dot);
+        state.write(&self.scanline);
+        state.write(&self.frame);
+        state.write(&self.nmi_pending);
+        state.write(&self.next_tile);
+        state.write(&self.next_attribute);
+        state.write(&self.next_pattern);
+        state.write(&self.pattern_shift);
+        state.write(&self.attribute_shift);
+        state.write(&self.secondary_oam);
+        state.write(&self.next_sprite_count);
+        state.write(&self.next_sprite_zero);
+        state.write(&self.sprite_count);
+        state.write(&self.sprite_zero);
+        state.write(&self.sprite_x);
+        state.write(&self.sprite_attributes);
+        state.write(&self.sprite_patterns);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.read(&mut self.ctrl)?;
+        state.read(&mut self.mask)?;
+        state.read(&mut self.status)?;
+        state.read(&mut self.oam_address)?;
+        state.read(&mut self.oam)?;
+        state.read(&mut self.vram)?;
+        state.read(&mut self.palette)?;
+        state.read(&mut self.v)?;
+        state.read(&mut self.t)?;
+        state.read(&mut self.x)?;
+        state.read(&mut self.w)?;
+        state.read(&mut self.read_buffer)?;
+        state.read(&mut self.io_latch)?;
+        state.read(&mut self.dot)?;
+        state.read(&mut self.scanline)?;
+        state.read(&mut self.frame)?;
+        state.read(&mut self.nmi_pending)?;
+        state.read(&mut self.next_tile)?;
+        state.read(&mut self.next_attribute)?;
+        state.read(&mut self.next_pattern)?;
+        state.read(&mut self.pattern_shift)?;
+        state.read(&mut self.attribute_shift)?;
+        state.read(&mut self.secondary_oam)?;
+        state.read(&mut self.next_sprite_count)?;
+        state.read(&mut self.next_sprite_zero)?;
+        state.read(&mut self.sprite_count)?;
+        state.read(&mut self.sprite_zero)?;
+        state.read(&mut self.sprite_x)?;
+        state.read(&mut self.sprite_attributes)?;
+        state.read(&mut self.sprite_patterns)
+    }
+}
+
+// The offset in VRAM of a nametable address for the mirroring of the board
+fn nametable_index(address: u16, mirroring: Mirroring) -> usize {
+    let quadrant = (address >> 10) as usize & 3;
+    let page = match mirroring {
+        Mirroring::Horizontal => quadrant >> 1,
+        Mirroring::Vertical => quadrant & 1,
+        Mirroring::SingleScreenLower => 0,
+        Mirroring::SingleScreenUpper => 1,
+        Mirroring::FourScreen => quadrant,
+        Mirroring::Custom(pages) => pages[quadrant] as usize & 1,
+    };
+
+    page * 0x400 + (address as usize & 0x3FF)
+}
+
+// The backdrop entries of the sprite palettes are those of the background
+fn palette_index(address: u16) -> usize {
+    match address as usize & 0x1F {
+        index if index & 0x13 == 0x10 => index & 0x0F,
+        index => index,
+    }
+}
+
+#[cfg(test)]
+mod ppu_tests {
+    use super::{nametable_index, SCREEN_HEIGHT, SCREEN_WIDTH};
+    use crate::bus::Bus;
+    use crate::nes::cartridge::{Cartridge, Mirroring};
+    use crate::nes::Nes;
+
+    // Cycles from the power-up to the vblank flag, at dot 1 of scanline 241
+    const VBLANK_CYCLES: u64 = (241 * 341 + 2u64).div_ceil(3);
+
+    fn console(mirroring: Mirroring) -> Nes {
+        let mut cartridge = Cartridge::from_prg_rom(vec![0; 0x8000]);
+        cartridge.mirroring = mirroring;
+
+        let mut nes = Nes::default();
+        nes.load_cartridge(cartridge).unwrap();
+        nes
+    }
+
+    fn set_address(nes: &mut Nes, address: u16) {
+        nes.mem_write_8(0x2006, (address >> 8) as u8);
+        nes.mem_write_8(0x2006, address as u8);
+    }
+
+    fn write_data(nes: &mut Nes, address: u16, data: &[u8]) {
+        set_address(nes, address);
+        for &byte in data {
+            nes.mem_write_8(0x2007, byte);
+        }
+    }
+
+    // The palettes are read at once, the rest through the read buffer
+    fn read_data(nes: &mut Nes, address: u16) -> u8 {
+        set_address(nes, address);
+        if address < 0x3F00 {
+            nes.mem_read_8(0x2007);
+        }
+        nes.mem_read_8(0x2007)
+    }
+
+    #[test]
+    fn scroll_and_address_test() {
+        let mut nes = console(Mirroring::Horizontal);
+
+        nes.mem_write_8(0x2000, 0x00);
+        nes.mem_read_8(0x2002);
+        assert_eq!(nes.bus.ppu().temp_vram_address(), 0x0000);
+
+        nes.mem_write_8(0x2005, 0x7D);
+        assert_eq!(nes.bus.ppu().temp_vram_address(), 0x000F);
+        assert_eq!(nes.bus.ppu().fine_x(), 5);
+        assert!(nes.bus.ppu().write_toggle());
+
+        nes.mem_write_8(0x2005, 0x5E);
+        assert_eq!(nes.bus.ppu().temp_vram_address(), 0x616F);
+        assert!(!nes.bus.ppu().write_toggle());
+
+        nes.mem_write_8(0x2006, 0x3D);
+        assert_eq!(nes.bus.ppu().temp_vram_address(), 0x3D6F);
+        nes.mem_write_8(0x2006, 0xF0);
+        assert_eq!(nes.bus.ppu().temp_vram_address(), 0x3DF0);
+        assert_eq!(nes.bus.ppu().vram_address(), 0x3DF0);
+
+        // PPUCTRL picks the nametable, a status read resets the toggle
+        nes.mem_write_8(0x2000, 0x02);
+        assert_eq!(nes.bus.ppu().temp_vram_address(), 0x39F0);
+        nes.mem_write_8(0x2006, 0x12);
+        nes.mem_read_8(0x2002);
+        nes.mem_write_8(0x2006, 0x34);
+        assert!(nes.bus.ppu().write_toggle());
+        assert_eq!(nes.bus.ppu().temp_vram_address(), 0x34F0);
+    }
+
+    #[test]
+    fn status_test() {
+        let mut nes = console(Mirroring::Horizontal);
+
+        nes.bus.tick(VBLANK_CYCLES - 1);
+        assert_eq!(nes.bus.peek(0x2002) & 0x80, 0);
+        nes.bus.tick(1);
+        assert_eq!(nes.bus.ppu().scanline(), 241);
+        assert_eq!(nes.bus.peek(0x2002) & 0x80, 0x80);
+
+        // The pre-render line clears the flag, 20 scanlines later
+        nes.bus.tick(20 * 341 / 3 - 1);
+        assert_eq!(nes.bus.peek(0x2002) & 0x80, 0x80);
+        nes.bus.tick(2);
+        assert_eq!(nes.bus.ppu().scanline(), 261);
+        assert_eq!(nes.bus.peek(0x2002) & 0x80, 0);
+
+        while nes.bus.peek(0x2002) & 0x80 == 0 {
+            nes.bus.tick(1);
+        }
+        assert_eq!(nes.bus.ppu().frame(), 1);
+
+        // The low bits come from the data bus of the registers
+        nes.mem_write_8(0x2005, 0x1F);
+        assert!(nes.bus.ppu().write_toggle());
+        assert_eq!(nes.mem_read_8(0x2002), 0x9F);
+        assert_eq!(nes.mem_read_8(0x2002), 0x1F);
+        assert!(!nes.bus.ppu().write_toggle());
+    }
+
+    #[test]
+    fn read_buffer_test() {
+        let mut nes = console(Mirroring::Vertical);
+
+        write_data(&mut nes, 0x2000, &[0x11, 0x22, 0x33]);
+
+        // Reads are one behind, through the buffer
+        set_address(&mut nes, 0x2000);
+        nes.mem_write_8(0x2001, 0);
+        assert_eq!(nes.mem_read_8(0x2007), 0x00);
+        assert_eq!(nes.mem_read_8(0x2007), 0x11);
+        assert_eq!(nes.mem_read_8(0x2007), 0x22);
+        assert_eq!(nes.bus.peek(0x2007), 0x33);
+        assert_eq!(nes.bus.ppu().vram_address(), 0x2003);
+
+        // Going down the nametable by 32
+        nes.mem_write_8(0x2000, 0x04);
+        write_data(&mut nes, 0x2040, &[0x44, 0x55]);
+        assert_eq!(nes.bus.ppu().vram_address(), 0x2080);
+        nes.mem_write_8(0x2000, 0x00);
+        assert_eq!(read_data(&mut nes, 0x2060), 0x55);
+
+        // The palettes answer at once and the nametable beneath fills the buffer
+        write_data(&mut nes, 0x2F00, &[0x66]);
+        write_data(&mut nes, 0x3F00, &[0x2A]);
+        set_address(&mut nes, 0x3F00);
+        assert_eq!(nes.mem_read_8(0x2007), 0x2A);
+        set_address(&mut nes, 0x0000);
+        assert_eq!(nes.mem_read_8(0x2007), 0x66);
+    }
+
+    #[test]
+    fn pattern_tables_test() {
+        let mut nes = console(Mirroring::Vertical);
+
+        // The CHR-RAM of the cartridge
+        write_data(&mut nes, 0x1234, &[0x77]);
+        assert_eq!(read_data(&mut nes, 0x1234), 0x77);
+        assert_eq!(nes.bus.ppu_peek(0x1234), 0x77);
+    }
+
+    #[test]
+    fn palette_test() {
+        let mut nes = console(Mirroring::Vertical);
+
+        write_data(&mut nes, 0x3F00, &[0x0F, 0x01, 0x02, 0x03, 0x04]);
+        write_data(&mut nes, 0x3F11, &[0x21, 0x22, 0x23, 0x24]);
+
+        // The backdrop entries of the sprite palettes mirror the background ones
+        assert_eq!(read_data(&mut nes, 0x3F10), 0x0F);
+        assert_eq!(read_data(&mut nes, 0x3F04), 0x24);
+        write_data(&mut nes, 0x3F1C, &[0x1C]);
+        assert_eq!(read_data(&mut nes, 0x3F0C), 0x1C);
+        assert_eq!(read_data(&mut nes, 0x3F11), 0x21);
+
+        // Mirrored every 32 bytes, with 6 bits per entry
+        assert_eq!(read_data(&mut nes, 0x3F22), 0x02);
+        // The upper bits are those left on the data bus by the PPUADDR write
+        assert_eq!(read_data(&mut nes, 0x3FE2), 0xC2);
+        write_data(&mut nes, 0x3F25, &[0xFF]);
+        assert_eq!(nes.bus.ppu().palette()[0x05], 0x3F);
+
+        // Grayscale keeps the luminance
+        nes.mem_write_8(0x2001, 0x01);
+        assert_eq!(read_data(&mut nes, 0x3F11), 0x20);
+        nes.mem_write_8(0x2001, 0x00);
+        assert_eq!(read_data(&mut nes, 0x3F11), 0x21);
+    }
+
+    #[test]
+    fn nametable_mirroring_test() {
+        // The page each quadrant ends up in
+        let boards = [
+            (Mirroring::Horizontal, [0, 0, 1, 1]),
+            (Mirroring::Vertical, [0, 1, 0, 1]),
+            (Mirroring::SingleScreenLower, [0, 0, 0, 0]),
+            (Mirroring::SingleScreenUpper, [1, 1, 1, 1]),
+            (Mirroring::FourScreen, [0, 1, 2, 3]),
+            (Mirroring::Custom([1, 0, 0, 1]), [1, 0, 0, 1]),
+        ];
+
+        for (mirroring, pages) in boards {
+            for (quadrant, page) in pages.into_iter().enumerate() {
+                let address = 0x2000 + quadrant as u16 * 0x400 + 0x123;
+                assert_eq!(nametable_index(address, mirroring), page * 0x400 + 0x123);
+            }
+        }
+
+        let mut nes = console(Mirroring::Horizontal);
+        write_data(&mut nes, 0x2000, &[0x01]);
+        write_data(&mut nes, 0x2800, &[0x02]);
+        assert_eq!(read_data(&mut nes, 0x2400), 0x01);
+        assert_eq!(read_data(&mut nes, 0x2C00), 0x02);
+
+        // $3000-$3EFF mirrors the nametables
+        assert_eq!(read_data(&mut nes, 0x3800), 0x02);
+
+        let mut nes = console(Mirroring::Vertical);
+        write_data(&mut nes, 0x2000, &[0x01]);
+        write_data(&mut nes, 0x2400, &[0x02]);
+        assert_eq!(read_data(&mut nes, 0x2800), 0x01);
+        assert_eq!(read_data(&mut nes, 0x2C00), 0x02);
+    }
+
+    #[test]
+    fn oam_test() {
+        let mut nes = console(Mirroring::Horizontal);
+
+        nes.mem_write_8(0x2003, 0xFE);
+        for data in [0x10, 0x20, 0xFF, 0x30] {
+            nes.mem_write_8(0x2004, data);
+        }
+
+        // The address wraps and is not moved by reads
+        assert_eq!(nes.bus.ppu().oam()[0xFE..], [0x10, 0x20]);
+        assert_eq!(nes.bus.ppu().oam()[..2], [0xFF, 0x30]);
+        assert_eq!(nes.mem_read_8(0x2004), 0x00);
+
+        nes.mem_write_8(0x2003, 0x01);
+        assert_eq!(nes.mem_read_8(0x2004), 0x30);
+
+        // Bits 2-4 of the attributes read back as 0
+        nes.mem_write_8(0x2003, 0x02);
+        nes.mem_write_8(0x2004, 0xFF);
+        nes.mem_write_8(0x2003, 0x02);
+        assert_eq!(nes.mem_read_8(0x2004), 0xE3);
+    }
+
+    #[test]
+    fn vblank_nmi_test() {
+        let mut prg_rom = vec![0; 0x8000];
+        // LDA #$80; STA $2000; JMP $8005
+        prg_rom[..8].copy_from_slice(&[0xA9, 0x80, 0x8D, 0x00, 0x20, 0x4C, 0x05, 0x80]);
+        // NMI handler: INC $00; RTI
+        prg_rom[0x10..0x13].copy_from_slice(&[0xE6, 0x00, 0x40]);
+        prg_rom[0x7FFA..].copy_from_slice(&[0x10, 0x80, 0x00, 0x80, 0x00, 0x80]);
+
+        let mut nes = Nes::default();
+        nes.load_cartridge(Cartridge::from_prg_rom(prg_rom))
+            .unwrap();
+        nes.reset();
+
+        nes.run_for_cycles(VBLANK_CYCLES - 10).unwrap();
+        assert_eq!(nes.mem_read_8(0x0000), 0);
+        nes.run_for_cycles(20).unwrap();
+        assert_eq!(nes.mem_read_8(0x0000), 1);
+
+        // One NMI per frame
+        nes.run_for_cycles(262 * 341 / 3).unwrap();
+        assert_eq!(nes.mem_read_8(0x0000), 2);
+
+        // Enabling NMI during vblank raises it again
+        nes.mem_write_8(0x2000, 0x00);
+        nes.mem_write_8(0x2000, 0x80);
+        nes.run_for_cycles(10).unwrap();
+        assert_eq!(nes.mem_read_8(0x0000), 3);
+    }
+
+    // Tile 1 is solid in color 1, tile 2 in color 2, tile 3 has its first
+    // column in color 3. The four background palettes are different.
+    fn background_console(mirroring: Mirroring) -> Nes {
+        let mut nes = console(mirroring);
+
+        write_data(&mut nes, 0x0010, &[0xFF; 8]);
+        write_data(&mut nes, 0x0028, &[0xFF; 8]);
+        write_data(&mut nes, 0x0030, &[0x80; 16]);
+        write_data(
+            &mut nes,
+            0x3F00,
+            &[0x0F, 0x11, 0x12, 0x13, 0x0F, 0x21, 0x22, 0x23],
+        );
+
+        nes
+    }
+
+    fn fill_nametable(nes: &mut Nes, address: u16, tile: u8) {
+        write_data(nes, address, &[tile; 0x3C0]);
+        write_data(nes, address + 0x3C0, &[0; 0x40]);
+    }
+
+    fn set_scroll(nes: &mut Nes, nametable: u8, x: u8, y: u8) {
+        nes.mem_read_8(0x2002);
+        nes.mem_write_8(0x2000, nametable);
+        nes.mem_write_8(0x2005, x);
+        nes.mem_write_8(0x2005, y);
+    }
+
+    fn run_until(nes: &mut Nes, scanline: u16, dot: u16) {
+        while nes.bus.ppu().scanline() != scanline || nes.bus.ppu().dot() < dot {
+            nes.bus.tick(1);
+        }
+    }
+
+    // A whole picture, from the pre-render line to the end of line 239
+    fn render_frame(nes: &mut Nes) {
+        run_until(nes, 261, 0);
+        run_until(nes, 240, 0);
+    }
+
+    fn pixel(nes: &Nes, x: usize, y: usize) -> u8 {
+        nes.bus.ppu().framebuffer()[y * SCREEN_WIDTH + x]
+    }
+
+    #[test]
+    fn background_test() {
+        let mut nes = background_console(Mirroring::Horizontal);
+        fill_nametable(&mut nes, 0x2000, 1);
+        write_data(&mut nes, 0x2043, &[3]);
+        // The top left and bottom right 16x16 pixels use palette 1
+        write_data(&mut nes, 0x23C0, &[0b01_00_00_01]);
+
+        set_scroll(&mut nes, 0, 0, 0);
+        nes.mem_write_8(0x2001, 0x0A);
+        render_frame(&mut nes);
+
+        assert_eq!(
+            nes.bus.ppu().framebuffer().len(),
+            SCREEN_WIDTH * SCREEN_HEIGHT
+        );
+        assert_eq!(pixel(&nes, 0, 0), 0x21);
+        assert_eq!(pixel(&nes, 15, 15), 0x21);
+        assert_eq!(pixel(&nes, 16, 0), 0x11);
+        assert_eq!(pixel(&nes, 0, 16), 0x11);
+        assert_eq!(pixel(&nes, 31, 31), 0x21);
+        assert_eq!(pixel(&nes, 32, 0), 0x11);
+        assert_eq!(pixel(&nes, 255, 239), 0x11);
+
+        // Tile 3 at row 2 and column 3
+        assert_eq!(pixel(&nes, 24, 16), 0x23);
+        assert_eq!(pixel(&nes, 24, 23), 0x23);
+        assert_eq!(pixel(&nes, 25, 16), 0x0F);
+
+        // Without the left column, and without the background
+        nes.mem_write_8(0x2001, 0x08);
+        render_frame(&mut nes);
+        assert_eq!(pixel(&nes, 7, 100), 0x0F);
+        assert_eq!(pixel(&nes, 8, 100), 0x11);
+
+        nes.mem_write_8(0x2001, 0x00);
+        render_frame(&mut nes);
+        assert_eq!(pixel(&nes, 100, 100), 0x0F);
+
+        // The VRAM address in the palettes shows that color
+        set_address(&mut nes, 0x3F02);
+        render_frame(&mut nes);
+        assert_eq!(pixel(&nes, 100, 100), 0x12);
+    }
+
+    #[test]
+    fn fine_scroll_test() {
+        let mut nes = background_console(Mirroring::Vertical);
+        fill_nametable(&mut nes, 0x2000, 0);
+        fill_nametable(&mut nes, 0x2400, 2);
+        write_data(&mut nes, 0x2043, &[3]);
+
+        set_scroll(&mut nes, 0, 3, 5);
+        nes.mem_write_8(0x2001, 0x0A);
+        render_frame(&mut nes);
+
+        assert_eq!(pixel(&nes, 20, 11), 0x0F);
+        assert_eq!(pixel(&nes, 21, 11), 0x13);
+        assert_eq!(pixel(&nes, 21, 10), 0x0F);
+        assert_eq!(pixel(&nes, 21, 18), 0x13);
+        assert_eq!(pixel(&nes, 21, 19), 0x0F);
+
+        // Coarse and fine X cross into the next nametable
+        set_scroll(&mut nes, 0, 250, 0);
+        render_frame(&mut nes);
+        assert_eq!(pixel(&nes, 5, 0), 0x0F);
+        assert_eq!(pixel(&nes, 6, 0), 0x12);
+        assert_eq!(pixel(&nes, 255, 239), 0x12);
+
+        // Starting from the second nametable wraps back to the first
+        set_scroll(&mut nes, 1, 0, 0);
+        render_frame(&mut nes);
+        assert_eq!(pixel(&nes, 255, 0), 0x12);
+        set_scroll(&mut nes, 1, 8, 0);
+        render_frame(&mut nes);
+        assert_eq!(pixel(&nes, 247, 0), 0x12);
+        assert_eq!(pixel(&nes, 248, 0), 0x0F);
+    }
+
+    #[test]
+    fn vertical_scroll_test() {
+        let mut nes = background_console(Mirroring::Horizontal);
+        fill_nametable(&mut nes, 0x2000, 1);
+        fill_nametable(&mut nes, 0x2800, 2);
+
+        // Row 29 is followed by the row 0 of the nametable below
+        set_scroll(&mut nes, 0, 0, 16);
+        nes.mem_write_8(0x2001, 0x0A);
+        render_frame(&mut nes);
+        assert_eq!(pixel(&nes, 0, 223), 0x11);
+        assert_eq!(pixel(&nes, 0, 224), 0x12);
+
+        // Scrolling into the attribute rows shows them as tiles, then wraps
+        // to the top of the same nametable
+        write_data(&mut nes, 0x23C0, &[1; 0x40]);
+        set_scroll(&mut nes, 0, 0, 240);
+        render_frame(&mut nes);
+        assert_eq!(pixel(&nes, 0, 0), 0x11);
+        assert_eq!(pixel(&nes, 0, 16), 0x21);
+    }
+
+    #[test]
+    fn scroll_split_test() {
+        let mut nes = background_console(Mirroring::Vertical);
+        fill_nametable(&mut nes, 0x2000, 1);
+        fill_nametable(&mut nes, 0x2400, 2);
+
+        set_scroll(&mut nes, 0, 0, 0);
+        nes.mem_write_8(0x2001, 0x0A);
+        run_until(&mut nes, 261, 0);
+
+        // A status bar on top, then the playfield scrolled by half a screen.
+        // The horizontal scroll is taken at dot 257.
+        run_until(&mut nes, 119, 300);
+        set_scroll(&mut nes, 0, 128, 0);
+        run_until(&mut nes, 240, 0);
+
+        for x in [0, 127, 128, 255] {
+            assert_eq!(pixel(&nes, x, 0), 0x11);
+            assert_eq!(pixel(&nes, x, 120), 0x11);
+        }
+        assert_eq!(pixel(&nes, 127, 121), 0x11);
+        assert_eq!(pixel(&nes, 128, 121), 0x12);
+        assert_eq!(pixel(&nes, 255, 239), 0x12);
+
+        // PPUADDR sets the whole scroll at once, the vertical one included.
+        // $2400 is the top of the second nametable.
+        run_until(&mut nes, 261, 0);
+        set_scroll(&mut nes, 0, 0, 0);
+        run_until(&mut nes, 99, 260);
+        set_address(&mut nes, 0x2400);
+        run_until(&mut nes, 240, 0);
+
+        assert_eq!(pixel(&nes, 200, 99), 0x11);
+        assert_eq!(pixel(&nes, 0, 100), 0x12);
+        assert_eq!(pixel(&nes, 255, 239), 0x12);
+    }
+
+    #[test]
+    fn odd_frame_test() {
+        let mut nes = console(Mirroring::Horizontal);
+
+        let frame_dots = |nes: &mut Nes| {
+            let mut dots = 0;
+            let frame = nes.bus.ppu().frame();
+            while nes.bus.ppu().frame() == frame {
+                nes.bus.ppu.step(&mut nes.bus.mapper);
+                dots += 1;
+            }
+            dots
+        };
+
+        frame_dots(&mut nes);
+        assert_eq!(frame_dots(&mut nes), 341 * 262);
+        assert_eq!(frame_dots(&mut nes), 341 * 262);
+
+        // While rendering, the odd frames are one dot shorter
+        nes.mem_write_8(0x2001, 0x08);
+        assert_eq!(frame_dots(&mut nes), 341 * 262 - 1);
+        assert_eq!(frame_dots(&mut nes), 341 * 262);
+        assert_eq!(frame_dots(&mut nes), 341 * 262 - 1);
+    }
+
+    #[test]
+    fn run_frame_test() {
+        let mut prg_rom = vec![0; 0x8000];
+        // JMP $8000
+        prg_rom[..3].copy_from_slice(&[0x4C, 0x00, 0x80]);
+        prg_rom[0x7FFC..0x7FFE].copy_from_slice(&[0x00, 0x80]);
+
+        let mut nes = Nes::default();
+        nes.load_cartridge(Cartridge::from_prg_rom(prg_rom))
+            .unwrap();
+        nes.reset();
+
+        nes.run_frame().unwrap();
+        assert_eq!(nes.bus.ppu().scanline(), 241);
+        assert_eq!(nes.bus.ppu().frame(), 0);
+
+        let cycles = nes.run_frame().unwrap();
+        assert_eq!(nes.bus.ppu().frame(), 1);
+        assert!((29778..29784).contains(&cycles));
+    }
+
+    // Tile 4 has a single pixel in color 1, at its top left corner. The
+    // sprite palettes 0 and 1 are different.
+    fn sprite_console(tile: u8) -> Nes {
+        let mut nes = background_console(Mirroring::Horizontal);
+        write_data(&mut nes, 0x0040, &[0x80]);
+        write_data(
+            &mut nes,
+            0x3F11,
+            &[0x15, 0x16, 0x17, 0x0F, 0x25, 0x26, 0x27],
+        );
+        fill_nametable(&mut nes, 0x2000, tile);
+        set_scroll(&mut nes, 0, 0, 0);
+        nes
+    }
+
+    // Every sprite is placed below the screen but the given ones
+    fn write_oam(nes: &mut Nes, sprites: &[[u8; 4]]) {
+        let mut oam = [0xFF; 0x100];
+        for (entry, sprite) in oam.chunks_mut(4).zip(sprites) {
+            entry.copy_from_slice(sprite);
+        }
+
+        nes.mem_write_8(0x2003, 0);
+        for data in oam {
+            nes.mem_write_8(0x2004, data);
+        }
+    }
+
+    #[test]
+    fn sprite_test() {
+        let mut nes = sprite_console(0);
+
+        // Sprites are drawn one line below their Y coordinate
+        write_oam(
+            &mut nes,
+            &[
+                [31, 1, 0x01, 16],
+                [99, 4, 0x40, 100],
+                [99, 4, 0xC0, 120],
+                [149, 1, 0x00, 0],
+            ],
+        );
+        nes.mem_write_8(0x2001, 0x1E);
+        render_frame(&mut nes);
+
+        assert_eq!(pixel(&nes, 16, 32), 0x25);
+        assert_eq!(pixel(&nes, 23, 39), 0x25);
+        assert_eq!(pixel(&nes, 24, 32), 0x0F);
+        assert_eq!(pixel(&nes, 16, 31), 0x0F);
+        assert_eq!(pixel(&nes, 16, 40), 0x0F);
+
+        // Flipped horizontally, then both ways
+        assert_eq!(pixel(&nes, 100, 100), 0x0F);
+        assert_eq!(pixel(&nes, 107, 100), 0x15);
+        assert_eq!(pixel(&nes, 127, 100), 0x0F);
+        assert_eq!(pixel(&nes, 127, 107), 0x15);
+
+        assert_eq!(pixel(&nes, 0, 150), 0x15);
+
+        // Without the sprites of the left column, and without sprites
+        nes.mem_write_8(0x2001, 0x1A);
+        render_frame(&mut nes);
+        assert_eq!(pixel(&nes, 7, 150), 0x0F);
+        assert_eq!(pixel(&nes, 16, 32), 0x25);
+
+        nes.mem_write_8(0x2001, 0x0A);
+        render_frame(&mut nes);
+        assert_eq!(pixel(&nes, 16, 32), 0x0F);
+    }
+
+    #[test]
+    fn tall_sprite_test() {
+        let mut nes = sprite_console(0);
+        // Tiles $04 and $05 of the second pattern table, in colors 1 and 2
+        write_data(&mut nes, 0x1040, &[0xFF; 8]);
+        write_data(&mut nes, 0x1058, &[0xFF; 8]);
+
+        // An odd tile takes the pair from the second pattern table
+        write_oam(&mut nes, &[[49, 0x05, 0x00, 16], [49, 0x05, 0x80, 32]]);
+        set_scroll(&mut nes, 0x20, 0, 0);
+        nes.mem_write_8(0x2001, 0x1E);
+        render_frame(&mut nes);
+
+        assert_eq!(pixel(&nes, 16, 50), 0x15);
+        assert_eq!(pixel(&nes, 16, 57), 0x15);
+        assert_eq!(pixel(&nes, 16, 58), 0x16);
+        assert_eq!(pixel(&nes, 16, 65), 0x16);
+        assert_eq!(pixel(&nes, 16, 66), 0x0F);
+
+        // Flipped vertically, the two tiles swap
+        assert_eq!(pixel(&nes, 32, 50), 0x16);
+        assert_eq!(pixel(&nes, 32, 65), 0x15);
+    }
+
+    #[test]
+    fn sprite_priority_test() {
+        // The background is opaque on the first column of each tile only
+        let mut nes = sprite_console(3);
+
+        write_oam(
+            &mut nes,
+            &[
+                [31, 1, 0x20, 16],
+                [31, 1, 0x01, 16],
+                [31, 1, 0x21, 40],
+                [31, 1, 0x00, 64],
+            ],
+        );
+        nes.mem_write_8(0x2001, 0x1E);
+        render_frame(&mut nes);
+
+        // Behind the background, a sprite shows through its transparent pixels
+        assert_eq!(pixel(&nes, 40, 32), 0x13);
+        assert_eq!(pixel(&nes, 41, 32), 0x25);
+        assert_eq!(pixel(&nes, 64, 32), 0x15);
+
+        // The first sprite wins over the next ones, and puts them behind the
+        // background along with it
+        assert_eq!(pixel(&nes, 16, 32), 0x13);
+        assert_eq!(pixel(&nes, 17, 32), 0x15);
+    }
+
+    #[test]
+    fn sprite_overflow_test() {
+        let mut nes = sprite_console(0);
+        nes.mem_write_8(0x2001, 0x1E);
+
+        // A ninth sprite is dropped
+        let sprites: Vec<_> = (0..9).map(|i| [49, 1, 0x00, i * 10]).collect();
+        write_oam(&mut nes, &sprites);
+        render_frame(&mut nes);
+        assert_eq!(pixel(&nes, 70, 50), 0x15);
+        assert_eq!(pixel(&nes, 80, 50), 0x0F);
+        assert_eq!(nes.bus.ppu().status() & 0x20, 0x20);
+
+        write_oam(&mut nes, &sprites[..8]);
+        render_frame(&mut nes);
+        assert_eq!(nes.bus.ppu().status() & 0x20, 0);
+
+        // Past eight sprites, the tile of the tenth one is taken as its Y
+        let mut sprites = sprites[..8].to_vec();
+        sprites.extend([[0xFF, 0xFF, 0xFF, 0xFF], [0xFF, 49, 0x00, 0]]);
+        write_oam(&mut nes, &sprites);
+        render_frame(&mut nes);
+        assert_eq!(nes.bus.ppu().status() & 0x20, 0x20);
+
+        // And a tenth sprite on the scanline is missed
+        sprites[9] = [49, 0, 0x00, 0];
+        write_oam(&mut nes, &sprites);
+        render_frame(&mut nes);
+        assert_eq!(nes.bus.ppu().status() & 0x20, 0);
+    }
+
+    #[test]
+    fn sprite_zero_hit_test() {
+        let mut nes = sprite_console(1);
+        let hit = |nes: &Nes| nes.bus.ppu().status() & 0x40 != 0;
+
+        write_oam(&mut nes, &[[99, 1, 0x00, 50]]);
+        nes.mem_write_8(0x2001, 0x1E);
+        run_until(&mut nes, 261, 0);
+        run_until(&mut nes, 100, 45);
+        assert!(!hit(&nes));
+        run_until(&mut nes, 100, 55);
+        assert!(hit(&nes));
+
+        // Reading the status leaves the flag until the pre-render line
+        nes.mem_read_8(0x2002);
+        assert!(hit(&nes));
+        run_until(&mut nes, 261, 2);
+        assert!(!hit(&nes));
+
+        // Behind the background too
+        write_oam(&mut nes, &[[99, 1, 0x20, 50]]);
+        render_frame(&mut nes);
+        assert!(hit(&nes));
+
+        // Only sprite 0
+        write_oam(&mut nes, &[[0xFF, 1, 0x00, 0], [99, 1, 0x00, 50]]);
+        render_frame(&mut nes);
+        assert!(!hit(&nes));
+
+        // Never at the last column
+        write_oam(&mut nes, &[[99, 1, 0x00, 255]]);
+        render_frame(&mut nes);
+        assert!(!hit(&nes));
+
+        // Not in the left column while either of them is hidden there
+        write_oam(&mut nes, &[[99, 1, 0x00, 0]]);
+        for (mask, expected) in [(0x1E, true), (0x1C, false), (0x1A, false), (0x18, false)] {
+            nes.mem_write_8(0x2001, mask);
+            render_frame(&mut nes);
+            assert_eq!(hit(&nes), expected);
+        }
+
+        // Not on a transparent background
+        nes.mem_write_8(0x2001, 0x00);
+        fill_nametable(&mut nes, 0x2000, 0);
+        set_scroll(&mut nes, 0, 0, 0);
+        write_oam(&mut nes, &[[99, 1, 0x00, 50]]);
+        nes.mem_write_8(0x2001, 0x1E);
+        render_frame(&mut nes);
+        assert!(!hit(&nes));
+    }
+}